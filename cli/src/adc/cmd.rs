@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use clap::{Args, Subcommand};
+
+pub use mecha_adc_ctl::{AdcControl, AdcReading, AdcWindow};
+
+use crate::{
+    configs::BaseConfig,
+    output_message::{Message, StdOut, ADC},
+};
+
+#[derive(Debug, Args)]
+pub struct Adc {
+    #[command(subcommand)]
+    command: AdcCommands,
+}
+
+#[derive(Debug, Subcommand)]
+enum AdcCommands {
+    #[command(about = "List the configured adc channels")]
+    List,
+    #[command(about = "Read adc channels in millivolts")]
+    Read(ReadArgs),
+    #[command(about = "Read adc channels continuously")]
+    Watch(WatchArgs),
+    #[command(about = "Get or set the adc sampling frequency")]
+    Frequency(FrequencyArgs),
+}
+
+#[derive(Debug, Args)]
+struct WindowArgs {
+    #[arg(long, default_value_t = 1, help = "Samples averaged per reading")]
+    samples: usize,
+    #[arg(long, default_value_t = 100, help = "Interval between samples")]
+    interval_ms: u64,
+}
+
+impl WindowArgs {
+    fn window(&self) -> AdcWindow {
+        AdcWindow {
+            samples: self.samples,
+            interval: Duration::from_millis(self.interval_ms),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+struct ReadArgs {
+    #[arg(help = "Channel name, e.g. voltage0, all channels when omitted")]
+    channel: Option<String>,
+    #[command(flatten)]
+    window: WindowArgs,
+}
+
+#[derive(Debug, Args)]
+struct WatchArgs {
+    #[arg(help = "Channel names, all channels when omitted")]
+    channels: Vec<String>,
+    #[command(flatten)]
+    window: WindowArgs,
+}
+
+#[derive(Debug, Args)]
+struct FrequencyArgs {
+    #[arg(help = "Sampling frequency to set in Hz")]
+    frequency: Option<f64>,
+}
+
+fn print_reading(reading: &AdcReading) {
+    let message = if reading.samples > 1 {
+        format!(
+            "{}: {:.2} mV (min: {:.2}, max: {:.2}, samples: {})",
+            reading.channel,
+            reading.millivolts,
+            reading.min_millivolts,
+            reading.max_millivolts,
+            reading.samples
+        )
+    } else {
+        format!("{}: {:.2} mV", reading.channel, reading.millivolts)
+    };
+    StdOut::info(&message, Some(ADC));
+}
+
+impl Adc {
+    pub async fn execute(&self, config: &BaseConfig) -> Result<()> {
+        let adc = AdcControl::new(
+            &config.interfaces.adc.channels(),
+            config.interfaces.adc.sampling_frequency.as_deref(),
+        );
+
+        match &self.command {
+            AdcCommands::List => {
+                for channel in adc.channels()? {
+                    StdOut::info(
+                        &format!(
+                            "{}: {} ({} mV per count)",
+                            channel.name(),
+                            channel.path().display(),
+                            channel.scale()
+                        ),
+                        Some(ADC),
+                    );
+                }
+            }
+            AdcCommands::Read(args) => {
+                let channels = match &args.channel {
+                    Some(channel) => vec![adc.channel(channel)?],
+                    None => adc.channels()?,
+                };
+                for channel in channels {
+                    print_reading(&channel.read_window(&args.window.window())?);
+                }
+            }
+            AdcCommands::Watch(args) => {
+                let mut readings = adc.stream(&args.channels, &args.window.window())?;
+                while let Some(reading) = readings.next().await {
+                    print_reading(&reading?);
+                }
+            }
+            AdcCommands::Frequency(args) => match args.frequency {
+                Some(frequency) => {
+                    adc.set_sampling_frequency(frequency)?;
+                    StdOut::success(&format!("Sampling frequency set to {} Hz", frequency));
+                }
+                None => StdOut::info(
+                    &format!("Sampling frequency: {} Hz", adc.sampling_frequency()?),
+                    Some(ADC),
+                ),
+            },
+        }
+        Ok(())
+    }
+}
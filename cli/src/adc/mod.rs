@@ -0,0 +1,2 @@
+mod cmd;
+pub use cmd::Adc;
@@ -17,6 +17,10 @@ pub struct Interfaces {
     pub motion_sensor: Gyroscope,
     pub led: Led,
     pub battery: Battery,
+    #[serde(default)]
+    pub adc: Adc,
+    #[serde(default)]
+    pub wifi: Wifi,
 }
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct Display {
@@ -28,6 +32,9 @@ pub struct Gyroscope {
     pub x_axis: String,
     pub y_axis: String,
     pub z_axis: String,
+    // directory of the per-device calibration files
+    #[serde(default)]
+    pub calibration_dir: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
@@ -35,6 +42,9 @@ pub struct Led {
     pub red_led: String,
     pub green_led: String,
     pub blue_led: String,
+    // brightness path of a multicolor led, used instead of the three leds above
+    #[serde(default)]
+    pub multicolor_led: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
@@ -42,3 +52,40 @@ pub struct Battery {
     pub device: String,
     pub current: String,
 }
+
+// IIO voltage inputs the carrier board routes analog sensors to
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct Adc {
+    #[serde(default)]
+    pub channel_1: Option<String>,
+    #[serde(default)]
+    pub channel_2: Option<String>,
+    // `sampling_frequency` attribute of the adc
+    #[serde(default)]
+    pub sampling_frequency: Option<String>,
+}
+
+impl Adc {
+    pub fn channels(&self) -> Vec<&str> {
+        [&self.channel_1, &self.channel_2]
+            .into_iter()
+            .filter_map(|channel| channel.as_deref())
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct Wifi {
+    // interface used when none is given, the first wireless one otherwise
+    #[serde(default)]
+    pub interface: Option<String>,
+    // directory of the wpa_supplicant control sockets
+    #[serde(default)]
+    pub control_dir: Option<String>,
+    // dnsmasq serving DHCP on hotspots, `dnsmasq` from the PATH when not set
+    #[serde(default)]
+    pub dnsmasq: Option<String>,
+    // networkd or netlink, who applies IP configuration, networkd when not set
+    #[serde(default)]
+    pub ip_backend: Option<String>,
+}
@@ -1,10 +1,16 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use anyhow::{bail, Result};
 use clap::{Args, Subcommand};
 
 // use crate::led::led_interface::LedManagerClient;
-pub use mecha_led_ctl::{LedControl, LedctlError, LedctlErrorCodes};
+pub use mecha_led_ctl::{
+    list_leds, Keyframe, LedControl, LedDevice, LedPattern, LedPatternEngine, LedTrigger,
+    LedctlError, LedctlErrorCodes, PatternBackend, RgbColor,
+};
 
-use crate::output_message::{Message, StdOut, LED_COLOR, LIGHT_OFF};
+use crate::output_message::{Message, StdOut, LED, LED_COLOR, LIGHT_OFF};
 
 use crate::configs::BaseConfig;
 
@@ -19,9 +25,15 @@ pub struct Led {
 #[derive(Debug, Subcommand)]
 enum LedCommands {
     #[command(about = "Set Led")]
-    SetLed(LedArgs),
+    SetLed(LedColorArgs),
     #[command(about = "Clear Led")]
     ClearLed(LedArgs),
+    #[command(about = "Play a Led pattern")]
+    Pattern(LedPatternArgs),
+    #[command(about = "List Leds and their triggers")]
+    List,
+    #[command(about = "Set a kernel trigger on a Led")]
+    Trigger(LedTriggerArgs),
 }
 
 #[derive(Debug, Args)]
@@ -30,28 +42,220 @@ struct LedArgs {
     rgb: String,
 }
 
+#[derive(Debug, Args)]
+struct LedColorArgs {
+    #[arg(required = true, help = "Color as r,g,b (0-255), #rrggbb, 0xrrggbb or a name")]
+    color: String,
+}
+
+#[derive(Debug, Args)]
+struct LedPatternArgs {
+    #[command(subcommand)]
+    command: LedPatternCommands,
+}
+
+#[derive(Debug, Subcommand)]
+enum LedPatternCommands {
+    #[command(about = "Blink a color on and off")]
+    Blink {
+        #[arg(required = true, help = "Color as r,g,b, #rrggbb or a name")]
+        rgb: String,
+        #[arg(long, default_value_t = 500)]
+        on_ms: u64,
+        #[arg(long, default_value_t = 500)]
+        off_ms: u64,
+        #[arg(
+            long,
+            default_value_t = 0,
+            help = "Times to play, 0 plays until stopped"
+        )]
+        repeat: u32,
+    },
+    #[command(about = "Fade a color in and out")]
+    Breathe {
+        #[arg(required = true, help = "Color as r,g,b, #rrggbb or a name")]
+        rgb: String,
+        #[arg(long, default_value_t = 2000)]
+        period_ms: u64,
+        #[arg(
+            long,
+            default_value_t = 0,
+            help = "Times to play, 0 plays until stopped"
+        )]
+        repeat: u32,
+    },
+    #[command(about = "Cycle through colors")]
+    Cycle {
+        #[arg(required = true, num_args = 1.., help = "Colors as r,g,b, #rrggbb or a name")]
+        colors: Vec<String>,
+        #[arg(long, default_value_t = 500)]
+        interval_ms: u64,
+        #[arg(
+            long,
+            default_value_t = 0,
+            help = "Times to play, 0 plays until stopped"
+        )]
+        repeat: u32,
+    },
+    #[command(about = "Play a sequence of keyframes")]
+    Sequence {
+        #[arg(required = true, num_args = 1.., help = "Keyframes as color:duration_ms")]
+        keyframes: Vec<String>,
+        #[arg(
+            long,
+            default_value_t = 0,
+            help = "Times to play, 0 plays until stopped"
+        )]
+        repeat: u32,
+    },
+    #[command(about = "Stop the running pattern")]
+    Stop,
+}
+
+#[derive(Debug, Args)]
+struct LedTriggerArgs {
+    #[arg(required = true, help = "Led name as shown by `led list`")]
+    led: String,
+    #[arg(
+        required = true,
+        help = "Trigger such as none, heartbeat, timer, netdev, mmc0 or default-on"
+    )]
+    trigger: String,
+    #[arg(long, default_value_t = 500, help = "timer: on time in milliseconds")]
+    delay_on_ms: u32,
+    #[arg(long, default_value_t = 500, help = "timer: off time in milliseconds")]
+    delay_off_ms: u32,
+    #[arg(long, help = "netdev: network device to follow, e.g. wlan0")]
+    device: Option<String>,
+    #[arg(long, help = "netdev: light while the link is up")]
+    link: bool,
+    #[arg(long, help = "netdev: blink on receive")]
+    rx: bool,
+    #[arg(long, help = "netdev: blink on transmit")]
+    tx: bool,
+    #[arg(long, help = "netdev: blink interval in milliseconds")]
+    interval_ms: Option<u32>,
+}
+
+impl LedTriggerArgs {
+    fn trigger(&self) -> Result<LedTrigger> {
+        let trigger = match self.trigger.as_str() {
+            "none" => LedTrigger::None,
+            "default-on" => LedTrigger::DefaultOn,
+            "heartbeat" => LedTrigger::Heartbeat,
+            "timer" => LedTrigger::Timer {
+                delay_on: self.delay_on_ms,
+                delay_off: self.delay_off_ms,
+            },
+            "netdev" => LedTrigger::Netdev {
+                device: match &self.device {
+                    Some(device) => device.clone(),
+                    None => bail!("netdev trigger requires --device"),
+                },
+                link: self.link,
+                rx: self.rx,
+                tx: self.tx,
+                interval: self.interval_ms,
+            },
+            name => LedTrigger::Other(name.to_string()),
+        };
+        Ok(trigger)
+    }
+}
+
+// Parse a `color:duration_ms` keyframe
+fn parse_keyframe(keyframe: &str) -> Result<Keyframe> {
+    let (color, duration) = match keyframe.rsplit_once(':') {
+        Some(parts) => parts,
+        None => return Err(anyhow::anyhow!("Invalid keyframe {}", keyframe)),
+    };
+    Ok(Keyframe::new(
+        color.parse::<RgbColor>()?,
+        Duration::from_millis(duration.trim().parse::<u64>()?),
+    ))
+}
+
+impl LedPatternCommands {
+    // Pattern to play and its repeat count, `None` for the stop command
+    fn pattern(&self) -> Result<Option<(LedPattern, Option<u32>)>> {
+        let (pattern, repeat) = match self {
+            LedPatternCommands::Blink {
+                rgb,
+                on_ms,
+                off_ms,
+                repeat,
+            } => (
+                LedPattern::Blink {
+                    color: rgb.parse::<RgbColor>()?,
+                    on: Duration::from_millis(*on_ms),
+                    off: Duration::from_millis(*off_ms),
+                },
+                *repeat,
+            ),
+            LedPatternCommands::Breathe {
+                rgb,
+                period_ms,
+                repeat,
+            } => (
+                LedPattern::Breathe {
+                    color: rgb.parse::<RgbColor>()?,
+                    period: Duration::from_millis(*period_ms),
+                },
+                *repeat,
+            ),
+            LedPatternCommands::Cycle {
+                colors,
+                interval_ms,
+                repeat,
+            } => (
+                LedPattern::ColorCycle {
+                    colors: colors
+                        .iter()
+                        .map(|color| color.parse::<RgbColor>())
+                        .collect::<Result<_>>()?,
+                    interval: Duration::from_millis(*interval_ms),
+                },
+                *repeat,
+            ),
+            LedPatternCommands::Sequence { keyframes, repeat } => (
+                LedPattern::Sequence {
+                    keyframes: keyframes
+                        .iter()
+                        .map(|keyframe| parse_keyframe(keyframe))
+                        .collect::<Result<_>>()?,
+                },
+                *repeat,
+            ),
+            LedPatternCommands::Stop => return Ok(None),
+        };
+
+        let repeat = match repeat {
+            0 => None,
+            repeat => Some(repeat),
+        };
+        Ok(Some((pattern, repeat)))
+    }
+}
+
 impl Led {
     pub async fn execute(&self, config: &BaseConfig) -> Result<()> {
         //device led path
-        let red_led_path = config.interfaces.led.blue_led.clone();
-        let green_led_path = config.interfaces.led.red_led.clone();
-        let blue_led_path = config.interfaces.led.green_led.clone();
+        let red_led_path = config.interfaces.led.red_led.clone();
+        let green_led_path = config.interfaces.led.green_led.clone();
+        let blue_led_path = config.interfaces.led.blue_led.clone();
 
-        let led = LedControl::new(&red_led_path, &green_led_path, &blue_led_path);
+        let led = Arc::new(match &config.interfaces.led.multicolor_led {
+            Some(multicolor_led_path) => LedControl::new_multicolor(multicolor_led_path),
+            None => LedControl::new(&red_led_path, &green_led_path, &blue_led_path),
+        });
 
         match &self.command {
             LedCommands::SetLed(args) => {
-                let rgb_values: Vec<&str> = args.rgb.split(',').collect();
-                if rgb_values.len() != 3 {
-                    return Err(anyhow::anyhow!("Invalid RGB values"));
-                }
-                let red = rgb_values[0].parse::<u8>()? != 0;
-                let green = rgb_values[1].parse::<u8>()? != 0;
-                let blue = rgb_values[2].parse::<u8>()? != 0;
+                let color = args.color.parse::<RgbColor>()?;
 
-                let _ = match led.set_led(red as u8, green as u8, blue as u8) {
+                let _ = match led.set_led(color.red, color.green, color.blue) {
                     Ok(_) => {
-                        StdOut::info("Led set", Some(LED_COLOR));
+                        StdOut::info(&format!("Led set to {}", color.to_hex()), Some(LED_COLOR));
                     }
                     Err(e) => {
                         bail!(LedctlError::new(
@@ -82,6 +286,57 @@ impl Led {
                     }
                 };
             }
+            LedCommands::Pattern(args) => {
+                let engine = LedPatternEngine::new(led.clone());
+
+                let (pattern, repeat) = match args.command.pattern()? {
+                    Some(pattern) => pattern,
+                    None => {
+                        engine.stop().await?;
+                        StdOut::info("Led pattern stopped", Some(LIGHT_OFF));
+                        return Ok(());
+                    }
+                };
+
+                match engine.start(pattern, repeat).await? {
+                    PatternBackend::Kernel => {
+                        StdOut::info("Led pattern running on kernel trigger", Some(LED));
+                    }
+                    PatternBackend::Software => {
+                        StdOut::info("Playing led pattern, press Ctrl+C to stop", Some(LED));
+                        engine.wait().await;
+                    }
+                }
+            }
+            LedCommands::List => {
+                for led in list_leds()? {
+                    StdOut::info(
+                        &format!(
+                            "{} color: {} function: {} brightness: {}/{} trigger: {}",
+                            led.name,
+                            led.color.as_deref().unwrap_or("-"),
+                            led.function.as_deref().unwrap_or("-"),
+                            led.brightness,
+                            led.max_brightness,
+                            led.trigger.as_deref().unwrap_or("-"),
+                        ),
+                        Some(LED),
+                    );
+                    StdOut::message(&format!(
+                        "    available triggers: {}",
+                        led.available_triggers.join(" ")
+                    ));
+                }
+            }
+            LedCommands::Trigger(args) => {
+                let trigger = args.trigger()?;
+                LedDevice::new(&args.led).set_trigger(&trigger)?;
+                StdOut::success(&format!(
+                    "Led {} trigger set to {}",
+                    args.led,
+                    trigger.name()
+                ));
+            }
         }
         Ok(())
     }
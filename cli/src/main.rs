@@ -34,6 +34,9 @@ pub use cpu_governanace::CpuGoverner;
 mod motion_sensor;
 pub use motion_sensor::MotionSensor;
 
+mod adc;
+pub use adc::Adc;
+
 mod output_message;
 
 #[derive(Parser, Debug)]
@@ -68,6 +71,8 @@ enum Mecha {
     CpuGoverner(CpuGoverner),
     #[command(about = "Device motion sensor utility")]
     MotionSensor(MotionSensor),
+    #[command(about = "Device adc utility")]
+    Adc(Adc),
 }
 
 #[tokio::main]
@@ -93,7 +98,7 @@ async fn main() -> Result<()> {
             }
         },
 
-        Mecha::Network(network) => match network.execute().await {
+        Mecha::Network(network) => match network.execute(&config).await {
             Ok(_) => {}
             Err(e) => {
                 println!("Error: {}", e);
@@ -134,6 +139,13 @@ async fn main() -> Result<()> {
                 println!("Error: {}", e);
             }
         },
+
+        Mecha::Adc(adc) => match adc.execute(&config).await {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Error: {}", e);
+            }
+        },
     }
     Ok(())
 }
@@ -1,7 +1,13 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
 use anyhow::Result;
 use clap::{Args, Subcommand};
 
-pub use mecha_motion_sensor_ctl::MotionSensorControl;
+pub use mecha_motion_sensor_ctl::{
+    AvailableValues, CalibrationConfig, ChannelType, FusionConfig, GestureConfig,
+    MotionSensorControl, PowerControl, Vector3, CALIBRATION_DIR,
+};
 
 use crate::{
     configs::BaseConfig,
@@ -20,6 +26,115 @@ enum MotionSensorCommands {
     Value,
     #[command(about = "Get motion sensor event")]
     Event,
+    #[command(about = "Get all motion sensor channels in SI units")]
+    Channels,
+    #[command(about = "Watch screen orientation changes and gestures")]
+    Watch(WatchArgs),
+    #[command(about = "Calibrate the motion sensor and save the result for the device")]
+    Calibrate(CalibrateArgs),
+    #[command(about = "Get sampling frequency, oversampling ratio and power control")]
+    Attributes,
+    #[command(about = "Set sampling frequency, oversampling ratio or power control")]
+    Set(SetArgs),
+}
+
+#[derive(Debug, Args)]
+struct SetArgs {
+    #[arg(long, help = "Sampling frequency in Hz")]
+    sampling_frequency: Option<f64>,
+    #[arg(long, help = "Oversampling ratio")]
+    oversampling_ratio: Option<u32>,
+    #[arg(long, help = "Runtime power management: auto or on")]
+    power_control: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct WatchArgs {
+    #[arg(long, help = "Also print roll, pitch and yaw of every sample")]
+    attitude: bool,
+}
+
+#[derive(Debug, Args)]
+struct CalibrateArgs {
+    #[arg(long, help = "Directory to save the calibration file in")]
+    dir: Option<String>,
+    #[arg(long, default_value_t = 100, help = "Samples averaged per measurement")]
+    samples: usize,
+}
+
+// Print `prompt` and wait for the user to press enter
+fn wait_for_enter(prompt: &str) -> Result<()> {
+    print!("{} ", prompt);
+    io::stdout().flush()?;
+    io::stdin().lock().read_line(&mut String::new())?;
+    Ok(())
+}
+
+// Measure `measure` until it succeeds, asking the user to retry on failure
+fn measure_with_retry<F>(prompt: &str, mut measure: F) -> Result<Vector3>
+where
+    F: FnMut() -> Result<Vector3>,
+{
+    wait_for_enter(prompt)?;
+    loop {
+        match measure() {
+            Ok(measured) => return Ok(measured),
+            Err(err) => {
+                StdOut::warn(&err.to_string());
+                wait_for_enter("Hold the device still and press Enter to retry")?;
+            }
+        }
+    }
+}
+
+fn calibrate(motion_sensor: &MotionSensorControl, directory: &Path, samples: usize) -> Result<()> {
+    let mut calibrator = motion_sensor.calibrator(&CalibrationConfig {
+        samples,
+        ..Default::default()
+    })?;
+
+    if calibrator.needs_gyro_bias() {
+        let Vector3 { x, y, z } = measure_with_retry(
+            "Place the device on a still surface and press Enter",
+            || calibrator.measure_gyro_bias(),
+        )?;
+        StdOut::info(
+            &format!(
+                "gyroscope bias: x: {:.5}, y: {:.5}, z: {:.5} rad/s",
+                x, y, z
+            ),
+            Some(MOTION),
+        );
+    }
+    for pose in calibrator.remaining_poses() {
+        let Vector3 { x, y, z } = measure_with_retry(
+            &format!(
+                "Hold the device with the {} and press Enter",
+                pose.description()
+            ),
+            || calibrator.measure_pose(pose),
+        )?;
+        StdOut::info(
+            &format!("{:?}: x: {:.3}, y: {:.3}, z: {:.3} m/s^2", pose, x, y, z),
+            Some(MOTION),
+        );
+    }
+
+    let calibration = calibrator.finish()?;
+    motion_sensor.set_calibration(calibration.clone());
+    let path = motion_sensor.save_calibration(directory)?;
+    for (name, Vector3 { x, y, z }) in [
+        ("accel offset", calibration.accel_offset),
+        ("accel scale", calibration.accel_scale),
+        ("gyro bias", calibration.gyro_bias),
+    ] {
+        StdOut::info(
+            &format!("{}: x: {:.5}, y: {:.5}, z: {:.5}", name, x, y, z),
+            Some(MOTION),
+        );
+    }
+    StdOut::success(&format!("Calibration saved to {}", path.display()));
+    Ok(())
 }
 
 impl MotionSensor {
@@ -29,6 +144,17 @@ impl MotionSensor {
         let z_axis_path = config.interfaces.motion_sensor.z_axis.clone();
 
         let motion_sensor = MotionSensorControl::new(&x_axis_path, &y_axis_path, &z_axis_path);
+        let calibration_dir = config
+            .interfaces
+            .motion_sensor
+            .calibration_dir
+            .clone()
+            .unwrap_or_else(|| CALIBRATION_DIR.to_string());
+        if !matches!(self.command, MotionSensorCommands::Calibrate(_)) {
+            if let Err(err) = motion_sensor.load_calibration(Path::new(&calibration_dir)) {
+                StdOut::warn(&format!("Unable to load calibration: {}", err));
+            }
+        }
 
         match &self.command {
             MotionSensorCommands::Value => match motion_sensor.read_motion_sensor_value() {
@@ -48,6 +174,118 @@ impl MotionSensor {
                 ),
                 Err(err) => return Err(err.into()),
             },
+            MotionSensorCommands::Channels => {
+                let readings = motion_sensor.read_channels()?;
+                let vectors = [
+                    (ChannelType::Accel, readings.accel),
+                    (ChannelType::Anglvel, readings.anglvel),
+                    (ChannelType::Magn, readings.magn),
+                ];
+                for (channel_type, vector) in vectors {
+                    if let Some(Vector3 { x, y, z }) = vector {
+                        StdOut::info(
+                            &format!(
+                                "{}: x: {:.4}, y: {:.4}, z: {:.4} {}",
+                                channel_type.prefix(),
+                                x,
+                                y,
+                                z,
+                                channel_type.unit()
+                            ),
+                            Some(MOTION),
+                        );
+                    }
+                }
+                if let Some(temp) = readings.temp {
+                    StdOut::info(
+                        &format!("temp: {:.2} {}", temp, ChannelType::Temp.unit()),
+                        Some(MOTION),
+                    );
+                }
+            }
+            MotionSensorCommands::Watch(args) => {
+                let mut orientation = motion_sensor.watch_orientation(&FusionConfig::default())?;
+                let mut gestures = motion_sensor.watch_gestures(&GestureConfig::default())?;
+                loop {
+                    tokio::select! {
+                        update = orientation.next() => {
+                            let update = match update {
+                                Some(update) => update?,
+                                None => break,
+                            };
+                            if update.orientation_changed {
+                                StdOut::info(
+                                    &format!("orientation: {:?}", update.orientation),
+                                    Some(MOTION),
+                                );
+                            }
+                            if args.attitude {
+                                StdOut::info(
+                                    &format!(
+                                        "roll: {:.1}, pitch: {:.1}, yaw: {:.1} deg",
+                                        update.attitude.roll.to_degrees(),
+                                        update.attitude.pitch.to_degrees(),
+                                        update.attitude.yaw.to_degrees()
+                                    ),
+                                    Some(MOTION),
+                                );
+                            }
+                        }
+                        event = gestures.next() => {
+                            let event = match event {
+                                Some(event) => event?,
+                                None => break,
+                            };
+                            StdOut::info(&format!("gesture: {:?}", event.gesture), Some(MOTION));
+                        }
+                    }
+                }
+            }
+            MotionSensorCommands::Attributes => {
+                for attribute in motion_sensor.attributes()? {
+                    let available = match attribute.available()? {
+                        Some(AvailableValues::List(values)) => values.join(", "),
+                        Some(AvailableValues::Range { min, step, max }) => {
+                            format!("{} to {} in steps of {}", min, max, step)
+                        }
+                        None => "not reported".to_string(),
+                    };
+                    StdOut::info(
+                        &format!(
+                            "{}: {} (available: {})",
+                            attribute.name,
+                            attribute.read()?,
+                            available
+                        ),
+                        Some(MOTION),
+                    );
+                }
+                // not every device supports runtime power management
+                let power_control = match motion_sensor.power_control() {
+                    Ok(power_control) => power_control.as_str(),
+                    Err(_) => "unknown",
+                };
+                StdOut::info(&format!("power control: {}", power_control), Some(MOTION));
+            }
+            MotionSensorCommands::Set(args) => {
+                if let Some(frequency) = args.sampling_frequency {
+                    motion_sensor.set_sampling_frequency(frequency)?;
+                    StdOut::success(&format!("Sampling frequency set to {} Hz", frequency));
+                }
+                if let Some(ratio) = args.oversampling_ratio {
+                    motion_sensor.set_oversampling_ratio(ratio)?;
+                    StdOut::success(&format!("Oversampling ratio set to {}", ratio));
+                }
+                if let Some(power_control) = &args.power_control {
+                    let power_control = power_control.parse::<PowerControl>()?;
+                    motion_sensor.set_power_control(power_control)?;
+                    StdOut::success(&format!("Power control set to {}", power_control.as_str()));
+                }
+            }
+            MotionSensorCommands::Calibrate(args) => {
+                let directory = args.dir.clone().unwrap_or(calibration_dir);
+                calibrate(&motion_sensor, Path::new(&directory), args.samples)?;
+            }
         }
         Ok(())
     }
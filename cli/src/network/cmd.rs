@@ -1,15 +1,35 @@
 #![deny(clippy::all)]
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use clap::{Args, Subcommand};
 
+use crate::configs::BaseConfig;
 pub use crate::network::{NetworkError, NetworkErrorCodes};
 use crate::output_message::{Message, StdOut, WIFI};
-pub use mecha_network_ctl::wireless_network::WirelessNetworkControl;
+use mecha_network_ctl::connectivity::{
+    ConnectivityChecker, ConnectivityConfig, ConnectivityReport,
+};
+use mecha_network_ctl::hotspot::{Band, HotspotConfig, DEFAULT_HOTSPOT_SSID};
+use mecha_network_ctl::interfaces::{WirelessInterfaces, DEFAULT_CONTROL_DIR};
+use mecha_network_ctl::ipconfig::{DhcpLease, IpBackend, IpConfig, IpConfigurator, IpMethod};
+use mecha_network_ctl::links::{Link, Links};
+use mecha_network_ctl::profile::{EapCredentials, NetworkProfile, Security, SecurityKind};
+use mecha_network_ctl::rfkill::{RadioType, Rfkill};
+use mecha_network_ctl::scan::ScannedNetwork;
+use mecha_network_ctl::session::WifiEvent;
+use mecha_network_ctl::status::WifiStatus;
+use mecha_network_ctl::wifi_uri::WifiUri;
+use mecha_network_ctl::wps::{WpsMethod, WpsProgress, DEFAULT_WPS_TIMEOUT};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
 
 #[derive(Debug, Args)]
 #[command(name = "network")]
 pub struct Network {
+    // wireless interface to use instead of the configured one
+    #[arg(short, long, global = true)]
+    interface: Option<String>,
+
     #[command(subcommand)]
     command: NetworkCommand,
 }
@@ -17,23 +37,303 @@ pub struct Network {
 #[derive(Debug, Subcommand)]
 enum NetworkCommand {
     #[command(about = "Scan for wireless networks")]
-    Scan,
+    Scan {
+        // list every access point under its network
+        #[arg(long)]
+        bssids: bool,
+    },
 
-    #[command(about = "Add a wireless network")]
+    #[command(about = "Save a wireless network without connecting to it")]
     Add(WirelessAddArgs),
 
-    #[command(about = "Remove a wireless network")]
+    #[command(about = "Forget a saved wireless network")]
     Remove(WirelessRemoveArgs),
 
     #[command(about = "Connect to a wireless network")]
-    Connect(WirelessConnectArgs),
+    Connect(WirelessNetworkArgs),
+
+    #[command(about = "Join a wireless network from a WIFI: URI such as QR codes hold")]
+    JoinUri { uri: String },
+
+    #[command(about = "Drop the current connection, the network stays saved")]
+    Disconnect,
+
+    #[command(about = "Join the saved networks again after a disconnect")]
+    Reconnect,
+
+    #[command(about = "Block or unblock the Wi-Fi and Bluetooth radios")]
+    Radio(RadioArgs),
+
+    #[command(about = "List the saved wireless networks")]
+    Saved,
+
+    #[command(about = "Change the password of a saved wireless network")]
+    SetPassword(WirelessPasswordArgs),
+
+    #[command(about = "Set the priority of a saved wireless network")]
+    Priority(WirelessPriorityArgs),
+
+    #[command(about = "Join a saved wireless network automatically or only on request")]
+    AutoConnect(WirelessAutoConnectArgs),
 
     #[command(about = "Status of current network")]
     Status,
+
+    #[command(about = "Watch connection events of the wireless network")]
+    Watch,
+
+    #[command(about = "Run an access point for provisioning")]
+    Hotspot(HotspotArgs),
+
+    #[command(about = "Configure DHCP or static addressing of any interface")]
+    Ip(IpArgs),
+
+    #[command(about = "List every network interface or bring one up or down")]
+    Interfaces(InterfacesArgs),
+
+    #[command(about = "Check whether the device reaches the internet")]
+    Connectivity {
+        #[arg(long, help = "Plain http url answering 204 when online")]
+        url: Option<String>,
+    },
+
+    #[command(about = "Join a wireless network with WPS push-button or PIN")]
+    Wps(WpsArgs),
+}
+
+#[derive(Debug, Args)]
+struct WpsArgs {
+    #[command(subcommand)]
+    command: WpsCommands,
+
+    #[arg(long, global = true, help = "Only use this access point")]
+    bssid: Option<String>,
+
+    #[arg(long, global = true, help = "Seconds to wait for the access point")]
+    timeout: Option<u64>,
+}
+
+#[derive(Debug, Subcommand)]
+enum WpsCommands {
+    #[command(about = "Press the WPS button on the router, then run this")]
+    Pbc,
+    #[command(about = "Enter the PIN on the router, one is generated when left out")]
+    Pin { pin: Option<String> },
+}
+
+#[derive(Debug, Args)]
+struct InterfacesArgs {
+    #[command(subcommand)]
+    command: Option<InterfacesCommands>,
+}
+
+#[derive(Debug, Subcommand)]
+enum InterfacesCommands {
+    #[command(about = "List the interfaces, the default")]
+    List,
+    #[command(about = "Show one interface with its counters")]
+    Show { name: String },
+    #[command(about = "Bring an interface up")]
+    Up { name: String },
+    #[command(about = "Bring an interface down")]
+    Down { name: String },
+}
+
+impl InterfacesArgs {
+    fn execute(&self, links: &Links) -> Result<()> {
+        let link = match self.command.as_ref().unwrap_or(&InterfacesCommands::List) {
+            InterfacesCommands::List => {
+                links
+                    .list()?
+                    .iter()
+                    .for_each(|link| StdOut::info(&describe_link(link), Some(WIFI)));
+                return Ok(());
+            }
+            InterfacesCommands::Show { name } => links.get(name)?,
+            InterfacesCommands::Up { name } => links.set_up(name, true)?,
+            InterfacesCommands::Down { name } => links.set_up(name, false)?,
+        };
+        StdOut::info(&describe_link(&link), Some(WIFI));
+        let counters = &link.counters;
+        let mut lines = vec![
+            format!(
+                "RX: {} bytes, {} packets, {} errors, {} dropped",
+                counters.rx_bytes, counters.rx_packets, counters.rx_errors, counters.rx_dropped
+            ),
+            format!(
+                "TX: {} bytes, {} packets, {} errors, {} dropped",
+                counters.tx_bytes, counters.tx_packets, counters.tx_errors, counters.tx_dropped
+            ),
+        ];
+        for address in &link.addresses {
+            lines.push(format!("Address: {}", address));
+        }
+        lines.iter().for_each(|line| StdOut::info(line, Some(WIFI)));
+        Ok(())
+    }
+}
+
+#[derive(Debug, Args)]
+struct IpArgs {
+    #[command(subcommand)]
+    command: IpCommands,
+}
+
+#[derive(Debug, Subcommand)]
+enum IpCommands {
+    #[command(about = "Show addresses, gateways, DNS and the DHCP lease")]
+    Show,
+    #[command(about = "Get addresses from DHCP")]
+    Dhcp(IpDnsArgs),
+    #[command(about = "Set static addresses")]
+    Static {
+        #[arg(
+            long = "address",
+            required = true,
+            help = "Address/prefix, may be repeated"
+        )]
+        addresses: Vec<String>,
+        #[arg(long = "gateway", help = "Default gateway, one per address family")]
+        gateways: Vec<String>,
+        #[command(flatten)]
+        dns: IpDnsArgs,
+    },
+    #[command(about = "Show the DHCP lease")]
+    Lease,
+}
+
+#[derive(Debug, Args)]
+struct IpDnsArgs {
+    #[arg(long, help = "Name server, may be repeated")]
+    dns: Vec<String>,
+    #[arg(long = "search", help = "Search domain, may be repeated")]
+    search_domains: Vec<String>,
+}
+
+impl IpArgs {
+    fn execute(&self, ip_config: &IpConfigurator, interface: &str) -> Result<()> {
+        let config = match &self.command {
+            IpCommands::Show => {
+                let status = ip_config.status(interface)?;
+                let method = status
+                    .method
+                    .map(|method| method.to_string())
+                    .unwrap_or_else(|| String::from("not managed"));
+                StdOut::info(
+                    &format!("Interface: {} ({})", status.interface, method),
+                    Some(WIFI),
+                );
+                let lines = status
+                    .addresses
+                    .iter()
+                    .map(|address| format!("Address: {}", address))
+                    .chain(status.gateways.iter().map(|ip| format!("Gateway: {}", ip)))
+                    .chain(status.dns.iter().map(|ip| format!("DNS: {}", ip)));
+                lines.for_each(|line| StdOut::info(&line, Some(WIFI)));
+                if let Some(lease) = &status.lease {
+                    describe_lease(lease);
+                }
+                return Ok(());
+            }
+            IpCommands::Lease => {
+                match ip_config.lease(interface)? {
+                    Some(lease) => describe_lease(&lease),
+                    None => StdOut::info("No DHCP lease", Some(WIFI)),
+                }
+                return Ok(());
+            }
+            IpCommands::Dhcp(dns) => {
+                IpConfig::from_args(IpMethod::Dhcp, &[], &[], &dns.dns, &dns.search_domains)?
+            }
+            IpCommands::Static {
+                addresses,
+                gateways,
+                dns,
+            } => IpConfig::from_args(
+                IpMethod::Static,
+                addresses,
+                gateways,
+                &dns.dns,
+                &dns.search_domains,
+            )?,
+        };
+        ip_config.apply(interface, &config)?;
+        StdOut::success(&format!(
+            "Configured {} with {} addressing",
+            interface, config.method
+        ));
+        Ok(())
+    }
+}
+
+#[derive(Debug, Args)]
+struct RadioArgs {
+    #[command(subcommand)]
+    command: RadioCommands,
+}
+
+#[derive(Debug, Subcommand)]
+enum RadioCommands {
+    #[command(about = "List the radios and whether they are blocked")]
+    List,
+    #[command(about = "Switch radios off")]
+    Block {
+        #[arg(default_value = "all", help = "wifi, bluetooth or all")]
+        radio: String,
+    },
+    #[command(about = "Switch radios back on")]
+    Unblock {
+        #[arg(default_value = "all", help = "wifi, bluetooth or all")]
+        radio: String,
+    },
+}
+
+#[derive(Debug, Args)]
+struct HotspotArgs {
+    #[command(subcommand)]
+    command: HotspotCommands,
+}
+
+#[derive(Debug, Subcommand)]
+enum HotspotCommands {
+    #[command(about = "Start the hotspot, replacing a running one")]
+    Start {
+        #[arg(long, default_value = DEFAULT_HOTSPOT_SSID)]
+        ssid: String,
+        #[arg(long, help = "WPA2 passphrase, the hotspot is open without one")]
+        password: Option<String>,
+        #[arg(long, default_value = "2.4", help = "2.4 or 5 GHz")]
+        band: String,
+        #[arg(long, help = "Channel, 6 on 2.4 GHz and 36 on 5 GHz by default")]
+        channel: Option<u32>,
+        #[arg(
+            long,
+            help = "Hand out addresses without redirecting DNS to the device"
+        )]
+        dhcp_only: bool,
+    },
+    #[command(about = "Stop the hotspot and rejoin the saved networks")]
+    Stop,
+    #[command(about = "List the stations connected to the hotspot")]
+    Clients,
 }
 
 #[derive(Debug, Args)]
 struct WirelessAddArgs {
+    #[command(flatten)]
+    network: WirelessNetworkArgs,
+
+    // higher priorities are joined first when several networks are in range
+    #[arg(long)]
+    priority: Option<i32>,
+
+    // only join the network when asked to
+    #[arg(long)]
+    manual: bool,
+}
+
+#[derive(Debug, Args)]
+struct WirelessPasswordArgs {
     #[arg(required = true)]
     ssid: String,
 
@@ -42,80 +342,253 @@ struct WirelessAddArgs {
 }
 
 #[derive(Debug, Args)]
-struct WirelessRemoveArgs {
+struct WirelessPriorityArgs {
+    #[arg(required = true)]
+    ssid: String,
+
+    #[arg(required = true, allow_negative_numbers = true)]
+    priority: i32,
+}
+
+#[derive(Debug, Args)]
+struct WirelessAutoConnectArgs {
     #[arg(required = true)]
     ssid: String,
+
+    #[arg(required = true, value_parser = ["on", "off"])]
+    state: String,
 }
 
 #[derive(Debug, Args)]
-struct WirelessConnectArgs {
+struct WirelessRemoveArgs {
     #[arg(required = true)]
     ssid: String,
+}
 
+#[derive(Debug, Args)]
+struct WirelessNetworkArgs {
     #[arg(required = true)]
-    password: String,
+    ssid: String,
+
+    // passphrase of WPA-PSK and SAE networks, or the PEAP password
+    password: Option<String>,
+
+    // open, wpa-psk, sae, wpa-psk-sae, peap or tls, taken from the scan when not given
+    #[arg(long)]
+    security: Option<String>,
+
+    // the network does not broadcast its ssid
+    #[arg(long)]
+    hidden: bool,
+
+    #[arg(long)]
+    identity: Option<String>,
+
+    #[arg(long)]
+    anonymous_identity: Option<String>,
+
+    #[arg(long)]
+    ca_cert: Option<String>,
+
+    #[arg(long)]
+    client_cert: Option<String>,
+
+    #[arg(long)]
+    private_key: Option<String>,
+
+    #[arg(long)]
+    private_key_password: Option<String>,
+
+    // domain the server certificate must be issued for
+    #[arg(long)]
+    domain: Option<String>,
+}
+
+impl WirelessNetworkArgs {
+    // `None` when the security is taken from the scan results
+    fn profile(&self) -> Result<Option<NetworkProfile>> {
+        let kind = match &self.security {
+            Some(security) => security.parse::<SecurityKind>()?,
+            None => return Ok(None),
+        };
+        let password = self.password.clone().unwrap_or_default();
+        let eap = EapCredentials {
+            identity: self.identity.clone().unwrap_or_default(),
+            anonymous_identity: self.anonymous_identity.clone(),
+            password: self.password.clone(),
+            ca_cert: self.ca_cert.clone(),
+            client_cert: self.client_cert.clone(),
+            private_key: self.private_key.clone(),
+            private_key_password: self.private_key_password.clone(),
+            domain_suffix_match: self.domain.clone(),
+        };
+        let security = Security::from_kind(kind, &password, eap);
+        Ok(Some(
+            NetworkProfile::new(&self.ssid, security).hidden(self.hidden),
+        ))
+    }
 }
 
 impl Network {
-    pub async fn execute(&self) -> Result<()> {
-        let network_module = WirelessNetworkControl::new();
+    pub async fn execute(&self, config: &BaseConfig) -> Result<()> {
+        let wifi = &config.interfaces.wifi;
+        let interfaces = WirelessInterfaces::new(
+            wifi.control_dir.as_deref().unwrap_or(DEFAULT_CONTROL_DIR),
+            wifi.interface.as_deref(),
+        );
+        // these apply to any interface, not only wireless ones
         match &self.command {
-            NetworkCommand::Scan => {
-                let _scan_results = match network_module.scan_wireless_network().await {
-                    Ok(scan_results) => {
-                        scan_results.iter().for_each(|network| {
-                            StdOut::info(
-                                &format!(
-                                    "Network SSID: {}, Signal Strength: {}, GHz: {}",
-                                    network.name, network.signal, network.frequency
-                                ),
-                                Some(WIFI),
-                            );
-                        });
-                        scan_results
-                    }
-                    Err(e) => {
-                        return Err(e);
-                    }
+            NetworkCommand::Ip(args) => {
+                let ip_backend = match &wifi.ip_backend {
+                    Some(ip_backend) => ip_backend.parse::<IpBackend>()?,
+                    None => IpBackend::Networkd,
                 };
+                let interface = match self.interface.as_deref() {
+                    Some(interface) if !interface.is_empty() => interface.to_string(),
+                    _ => interfaces.default_interface(),
+                };
+                return args.execute(&IpConfigurator::new(ip_backend), &interface);
+            }
+            NetworkCommand::Interfaces(args) => return args.execute(&Links::default()),
+            NetworkCommand::Connectivity { url } => {
+                let mut config = ConnectivityConfig::default();
+                if let Some(url) = url {
+                    config.probe_url = url.clone();
+                }
+                let report = ConnectivityChecker::new(config)?.check();
+                describe_connectivity(&report)
+                    .iter()
+                    .for_each(|line| StdOut::info(line, Some(WIFI)));
+                return Ok(());
+            }
+            _ => {}
+        }
+        let network_module = interfaces.control(self.interface.as_deref())?;
+        match &self.command {
+            NetworkCommand::Scan { bssids } => {
+                let networks = network_module.scan_networks().await?;
+                if networks.is_empty() {
+                    StdOut::info("No wireless networks found", Some(WIFI));
+                }
+                scan_table(&networks, *bssids)
+                    .iter()
+                    .for_each(|line| StdOut::message(line));
             }
             NetworkCommand::Add(args) => {
-                let ssid = &args.ssid;
-                let psk = &args.password;
-                let _add_wireless_network = match WirelessNetworkControl::connect_wireless_network(
-                    ssid.as_str(),
-                    psk.as_str(),
-                )
-                .await
-                {
-                    Ok(()) => (StdOut::success(&format!("Added network: {}", ssid))),
-                    Err(e) => {
-                        return Err(e);
+                let network = &args.network;
+                let profile = match network.profile()? {
+                    Some(profile) => profile,
+                    None => {
+                        let password = network.password.as_deref().unwrap_or_default();
+                        network_module
+                            .resolve_profile(&network.ssid, password, network.hidden)
+                            .await?
                     }
                 };
+                let network_id = network_module
+                    .save_network(&profile, args.priority, !args.manual)
+                    .await?;
+                StdOut::success(&format!(
+                    "Saved network {} with id {}",
+                    network.ssid, network_id
+                ));
             }
             NetworkCommand::Remove(args) => {
-                //take ssid form arg and convert it into i32
-                let network_id = args.ssid.parse::<usize>().unwrap();
-
-                // use args and use remove_wireless_network
-                let _ = match WirelessNetworkControl::remove_wireless_network(network_id).await {
-                    Ok(remove_results) => {
-                        StdOut::success(&format!("Removed network: {:?}", remove_results));
-                        remove_results
+                network_module.remove_saved_network(&args.ssid).await?;
+                StdOut::success(&format!("Removed network: {}", args.ssid));
+            }
+            NetworkCommand::Disconnect => {
+                network_module.disconnect_wireless_network().await?;
+                StdOut::success("Disconnected from the wireless network");
+            }
+            NetworkCommand::Reconnect => {
+                network_module.reconnect_wireless_network().await?;
+                StdOut::success("Reconnecting to the saved wireless networks");
+            }
+            NetworkCommand::Radio(args) => {
+                let rfkill = Rfkill::default();
+                let radios = match &args.command {
+                    RadioCommands::List => rfkill.list()?,
+                    RadioCommands::Block { radio } => {
+                        rfkill.set_blocked(radio.parse::<RadioType>()?, true)?
                     }
-                    Err(e) => {
-                        return Err(e);
+                    RadioCommands::Unblock { radio } => {
+                        rfkill.set_blocked(radio.parse::<RadioType>()?, false)?
                     }
                 };
+                radios.iter().for_each(|radio| {
+                    let state = match (radio.soft_blocked, radio.hard_blocked) {
+                        (_, true) => "blocked by hardware switch",
+                        (true, false) => "blocked",
+                        (false, false) => "on",
+                    };
+                    StdOut::info(
+                        &format!(
+                            "Radio {} ({} {}): {}",
+                            radio.id, radio.kind, radio.device, state
+                        ),
+                        Some(WIFI),
+                    )
+                });
+            }
+            NetworkCommand::Saved => {
+                let networks = network_module.saved_networks().await?;
+                if networks.is_empty() {
+                    StdOut::info("No saved networks", Some(WIFI));
+                }
+                networks.iter().for_each(|network| {
+                    StdOut::info(
+                        &format!(
+                            "Network SSID: {}, Priority: {}, Auto connect: {}{}",
+                            network.ssid,
+                            network.priority,
+                            if network.auto_connect { "on" } else { "off" },
+                            if network.current { " (connected)" } else { "" }
+                        ),
+                        Some(WIFI),
+                    )
+                });
+            }
+            NetworkCommand::SetPassword(args) => {
+                network_module
+                    .update_network_passphrase(&args.ssid, &args.password)
+                    .await?;
+                StdOut::success(&format!("Updated password of {}", args.ssid));
+            }
+            NetworkCommand::Priority(args) => {
+                network_module
+                    .set_network_priority(&args.ssid, args.priority)
+                    .await?;
+                StdOut::success(&format!(
+                    "Priority of {} set to {}",
+                    args.ssid, args.priority
+                ));
+            }
+            NetworkCommand::AutoConnect(args) => {
+                let enabled = args.state == "on";
+                network_module.set_auto_connect(&args.ssid, enabled).await?;
+                StdOut::success(&format!(
+                    "Auto connect of {} turned {}",
+                    args.ssid, args.state
+                ));
             }
             NetworkCommand::Connect(args) => {
-                let _ = match WirelessNetworkControl::connect_wireless_network(
-                    &args.ssid,
-                    &args.password,
-                )
-                .await
-                {
+                let password = args.password.as_deref().unwrap_or_default();
+                let connect = match args.profile()? {
+                    Some(profile) => network_module.connect_profile(&profile).await,
+                    None if args.hidden => {
+                        let profile = network_module
+                            .resolve_profile(&args.ssid, password, true)
+                            .await?;
+                        network_module.connect_profile(&profile).await
+                    }
+                    None => {
+                        network_module
+                            .connect_wireless_network(&args.ssid, password)
+                            .await
+                    }
+                };
+                let _ = match connect {
                     Ok(connect_results) => {
                         StdOut::success(&format!("Connected to network: {:?}", connect_results));
                         connect_results
@@ -126,17 +599,314 @@ impl Network {
                 };
             }
 
+            NetworkCommand::JoinUri { uri } => {
+                let profile = network_module.connect_uri(uri).await?;
+                StdOut::success(&format!("Connected to network: {}", profile.ssid));
+            }
+
             NetworkCommand::Status => {
-                let connected = WirelessNetworkControl::wireless_network_status().await;
+                let status = network_module.wifi_status().await?;
 
-                if connected {
-                    StdOut::success("Wireless network is connected.");
+                if status.connected() {
+                    StdOut::success(&format!(
+                        "Wireless network is connected to {}.",
+                        status.ssid.as_deref().unwrap_or("unknown")
+                    ));
                 } else {
                     println!("Wireless network is not connected.");
                 }
+                describe_status(&status)
+                    .iter()
+                    .for_each(|line| StdOut::info(line, Some(WIFI)));
+            }
+            NetworkCommand::Watch => {
+                let mut events = network_module.watch_events().await?;
+                loop {
+                    let event = match events.recv().await {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(missed)) => {
+                            StdOut::warn(&format!("missed {} events", missed));
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+                    StdOut::info(&describe_event(&event), Some(WIFI));
+                }
+            }
+            NetworkCommand::Wps(args) => {
+                let method = match &args.command {
+                    WpsCommands::Pbc => WpsMethod::PushButton,
+                    WpsCommands::Pin { pin } => WpsMethod::Pin(pin.clone()),
+                };
+                let timeout = args
+                    .timeout
+                    .map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_WPS_TIMEOUT);
+                let mut progress = network_module
+                    .connect_wps(&method, args.bssid.as_deref(), timeout)
+                    .await?;
+                while let Some(step) = progress.recv().await {
+                    match &step {
+                        WpsProgress::Connected { .. } => StdOut::success(&step.to_string()),
+                        WpsProgress::Failed { reason } => bail!(NetworkError::new(
+                            NetworkErrorCodes::UnableToConnectWifi,
+                            format!("WPS failed: {}", reason),
+                        )),
+                        _ => StdOut::info(&step.to_string(), Some(WIFI)),
+                    }
+                }
             }
+            NetworkCommand::Hotspot(args) => match &args.command {
+                HotspotCommands::Start {
+                    ssid,
+                    password,
+                    band,
+                    channel,
+                    dhcp_only,
+                } => {
+                    let band = band.parse::<Band>()?;
+                    let mut hotspot = HotspotConfig {
+                        ssid: ssid.clone(),
+                        passphrase: password.clone(),
+                        band,
+                        channel: channel.unwrap_or_else(|| band.default_channel()),
+                        captive: !dhcp_only,
+                        ..Default::default()
+                    };
+                    if let Some(dnsmasq) = &wifi.dnsmasq {
+                        hotspot.dnsmasq = Some(dnsmasq.into());
+                    }
+                    let address = network_module.start_hotspot(&hotspot).await?;
+                    StdOut::success(&format!(
+                        "Hotspot {} started, the device is at {}",
+                        hotspot.ssid, address
+                    ));
+                    StdOut::info("Join with this URI or its QR code:", Some(WIFI));
+                    StdOut::message(&WifiUri::for_hotspot(&hotspot).to_string());
+                }
+                HotspotCommands::Stop => {
+                    network_module.stop_hotspot().await?;
+                    StdOut::success("Hotspot stopped");
+                }
+                HotspotCommands::Clients => {
+                    let clients = network_module.hotspot_clients().await?;
+                    if clients.is_empty() {
+                        StdOut::info("No clients connected", Some(WIFI));
+                    }
+                    clients.iter().for_each(|client| {
+                        StdOut::info(
+                            &format!(
+                                "Client: {}, IP: {}, Hostname: {}",
+                                client.mac,
+                                client
+                                    .ip
+                                    .map(|ip| ip.to_string())
+                                    .unwrap_or_else(|| String::from("none")),
+                                client.hostname.as_deref().unwrap_or("unknown")
+                            ),
+                            Some(WIFI),
+                        )
+                    });
+                }
+            },
+            // handled before a supplicant session is opened
+            NetworkCommand::Ip(_)
+            | NetworkCommand::Interfaces(_)
+            | NetworkCommand::Connectivity { .. } => {}
         }
 
         Ok(())
     }
 }
+
+// Networks as a table, the connected one marked with `*`
+fn scan_table(networks: &[ScannedNetwork], bssids: bool) -> Vec<String> {
+    let width = networks
+        .iter()
+        .map(|network| network.ssid.chars().count())
+        .max()
+        .unwrap_or_default()
+        .clamp(4, 32);
+    let mut lines = vec![format!(
+        "  {:<width$}  {:<11}  {:>7}  {:>7}  {:<14}  {}",
+        "SSID",
+        "SECURITY",
+        "SIGNAL",
+        "QUALITY",
+        "BANDS",
+        "NOTES",
+        width = width
+    )];
+    for network in networks {
+        let bands: Vec<String> = network
+            .bands()
+            .iter()
+            .map(|band| band.to_string())
+            .collect();
+        let mut notes = Vec::new();
+        if network.saved {
+            notes.push("saved");
+        }
+        if network.wps() {
+            notes.push("wps");
+        }
+        lines.push(format!(
+            "{} {:<width$}  {:<11}  {:>3} dBm  {:>6}%  {:<14}  {}",
+            if network.current { "*" } else { " " },
+            network.ssid,
+            network.security().to_string(),
+            network.signal(),
+            network.quality(),
+            bands.join(", "),
+            notes.join(", "),
+            width = width
+        ));
+        if !bssids {
+            continue;
+        }
+        for bss in &network.bss {
+            let channel = bss
+                .channel
+                .map(|channel| format!("channel {}", channel))
+                .unwrap_or_else(|| String::from("unknown channel"));
+            lines.push(format!(
+                "    {}  {} MHz, {}  {} dBm  {}",
+                bss.bssid, bss.frequency, channel, bss.signal, bss.flags
+            ));
+        }
+    }
+    lines
+}
+
+fn describe_connectivity(report: &ConnectivityReport) -> Vec<String> {
+    let yes_no = |value: bool| if value { "yes" } else { "no" };
+    let mut lines = vec![
+        format!("Connectivity: {}", report.state),
+        format!("Default route: {}", yes_no(report.default_route)),
+        format!("DNS: {}", yes_no(report.dns_resolved)),
+        format!("TCP: {}", yes_no(report.tcp_reachable)),
+    ];
+    if let Some(http) = &report.http {
+        lines.push(format!("HTTP probe: {}", http.status));
+    }
+    if let Some(portal_url) = report.portal_url() {
+        lines.push(format!("Portal: {}", portal_url));
+    }
+    if let Some(latency) = report.latency {
+        lines.push(format!("Latency: {} ms", latency.as_millis()));
+    }
+    lines
+}
+
+fn describe_link(link: &Link) -> String {
+    let mut line = format!(
+        "{}: {} {}, state {}",
+        link.name,
+        link.kind,
+        if link.up { "up" } else { "down" },
+        link.operstate
+    );
+    if let Some(carrier) = link.carrier {
+        line.push_str(if carrier { ", carrier" } else { ", no carrier" });
+    }
+    if let Some(speed) = link.speed {
+        line.push_str(&format!(", {} Mbit/s", speed));
+    }
+    if let Some(duplex) = &link.duplex {
+        line.push_str(&format!(" {} duplex", duplex));
+    }
+    if let Some(mac) = &link.mac {
+        line.push_str(&format!(", MAC {}", mac));
+    }
+    line.push_str(&format!(", MTU {}", link.mtu));
+    line
+}
+
+fn describe_lease(lease: &DhcpLease) {
+    let mut lines = Vec::new();
+    if let Some(address) = &lease.address {
+        lines.push(format!("Leased address: {}", address));
+    }
+    if let Some(router) = lease.router {
+        lines.push(format!("Router: {}", router));
+    }
+    if let Some(server) = lease.server {
+        lines.push(format!("DHCP server: {}", server));
+    }
+    for dns in &lease.dns {
+        lines.push(format!("Leased DNS: {}", dns));
+    }
+    if let Some(domain) = &lease.domain {
+        lines.push(format!("Domain: {}", domain));
+    }
+    if let Some(lifetime) = lease.lifetime {
+        lines.push(format!("Lease time: {} s", lifetime));
+    }
+    lines.iter().for_each(|line| StdOut::info(line, Some(WIFI)));
+}
+
+fn describe_status(status: &WifiStatus) -> Vec<String> {
+    let mut lines = vec![format!(
+        "Interface: {} ({})",
+        status.interface,
+        if status.enabled { "up" } else { "down" }
+    )];
+    lines.push(format!(
+        "State: {}",
+        status.wpa_state.as_deref().unwrap_or("no supplicant")
+    ));
+    if let Some(bssid) = &status.bssid {
+        lines.push(format!("BSSID: {}", bssid));
+    }
+    if let Some(frequency) = status.frequency {
+        let channel = status
+            .channel
+            .map(|channel| format!(", channel {}", channel))
+            .unwrap_or_default();
+        lines.push(format!("Frequency: {} MHz{}", frequency, channel));
+    }
+    if let Some(rssi) = status.rssi {
+        lines.push(format!("Signal: {} dBm", rssi));
+    }
+    if let Some(link_speed) = status.link_speed {
+        lines.push(format!("Link speed: {} Mbit/s", link_speed));
+    }
+    if let Some(key_mgmt) = &status.key_mgmt {
+        lines.push(format!("Security: {}", key_mgmt));
+    }
+    for address in status.ipv4_addresses.iter().chain(&status.ipv6_addresses) {
+        lines.push(format!("Address: {}", address));
+    }
+    if let Some(route) = &status.default_route {
+        lines.push(format!(
+            "Default route: {} via {}",
+            route
+                .gateway
+                .map(|gateway| gateway.to_string())
+                .unwrap_or_else(|| String::from("direct")),
+            route.interface.as_deref().unwrap_or("unknown")
+        ));
+    }
+    lines
+}
+
+fn describe_event(event: &WifiEvent) -> String {
+    let ssid = |ssid: &Option<String>| ssid.clone().unwrap_or_else(|| String::from("unknown"));
+    match event {
+        WifiEvent::Connected { ssid: name, bssid } => format!(
+            "Connected to {} ({})",
+            ssid(name),
+            bssid.as_deref().unwrap_or("unknown")
+        ),
+        WifiEvent::Disconnected => String::from("Disconnected"),
+        WifiEvent::ScanResults(results) => format!("Scan found {} networks", results.len()),
+        WifiEvent::AuthFailure { ssid: name, reason } => {
+            format!("Authentication with {} failed: {}", ssid(name), reason)
+        }
+        WifiEvent::WrongKey { ssid: name } => format!("Wrong password for {}", ssid(name)),
+        WifiEvent::NetworkNotFound { ssid: name } => {
+            format!("Network {} not found", ssid(name))
+        }
+        WifiEvent::Wps(event) => format!("WPS: {}", WpsProgress::from(event.clone())),
+    }
+}
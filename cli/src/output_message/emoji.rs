@@ -40,3 +40,5 @@ pub static LED_COLOR: Emoji = Emoji("🎨 ", "");
 pub static LIGHT_OFF: Emoji = Emoji("🚫 ", "");
 //motion
 pub static MOTION: Emoji = Emoji("🎳 ", "");
+//adc
+pub static ADC: Emoji = Emoji("📈 ", "");
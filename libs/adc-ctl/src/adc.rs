@@ -0,0 +1,434 @@
+use crate::{AdcError, AdcErrorCodes};
+use anyhow::{bail, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio_stream::Stream;
+use tracing::{error as trace_error, info, instrument, trace};
+
+// Reads averaged into one reading, `samples` reads taken `interval` apart
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdcWindow {
+    pub samples: usize,
+    pub interval: Duration,
+}
+
+impl Default for AdcWindow {
+    fn default() -> Self {
+        AdcWindow {
+            samples: 1,
+            interval: Duration::from_millis(100),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdcReading {
+    pub channel: String,
+    // mean over the window
+    pub millivolts: f64,
+    pub min_millivolts: f64,
+    pub max_millivolts: f64,
+    pub samples: usize,
+    // nanoseconds since the epoch, at the end of the window
+    pub timestamp: i64,
+}
+
+impl AdcReading {
+    fn from_samples(channel: &str, samples: &[f64]) -> Self {
+        let count = samples.len().max(1) as f64;
+        AdcReading {
+            channel: channel.to_string(),
+            millivolts: samples.iter().sum::<f64>() / count,
+            min_millivolts: samples.iter().copied().fold(f64::INFINITY, f64::min),
+            max_millivolts: samples.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            samples: samples.len(),
+            timestamp: now_ns(),
+        }
+    }
+}
+
+fn now_ns() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos() as i64)
+        .unwrap_or_default()
+}
+
+// A voltage input of an IIO ADC, `in_voltage<N>_raw`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdcChannel {
+    name: String,
+    raw_path: PathBuf,
+    // millivolts per count
+    scale: f64,
+    offset: f64,
+}
+
+impl AdcChannel {
+    // Channel of a `_raw` attribute, scale and offset are read from the
+    // channel's own attributes or the ones shared by all voltage channels
+    #[instrument]
+    pub fn from_path(raw_path: &Path) -> Result<Self> {
+        trace!(task = "adc_channel", "init");
+        let file_name = raw_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name = match file_name
+            .strip_prefix("in_")
+            .and_then(|name| name.strip_suffix("_raw"))
+        {
+            Some(name) if name.starts_with("voltage") => name.to_string(),
+            _ => bail!(AdcError::new(
+                AdcErrorCodes::UnknownChannel,
+                format!("{} is not an adc voltage channel", raw_path.display()),
+            )),
+        };
+
+        let device_path = raw_path.parent().unwrap_or_else(|| Path::new(""));
+        let attribute = |attribute: &str, default: f64| -> Result<f64> {
+            for path in [
+                device_path.join(format!("in_{}_{}", name, attribute)),
+                device_path.join(format!("in_voltage_{}", attribute)),
+            ] {
+                if path.exists() {
+                    return read_number(&path);
+                }
+            }
+            Ok(default)
+        };
+
+        Ok(AdcChannel {
+            scale: attribute("scale", 1.0)?,
+            offset: attribute("offset", 0.0)?,
+            name,
+            raw_path: raw_path.to_path_buf(),
+        })
+    }
+
+    // e.g. `voltage0`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.raw_path
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    pub fn read_raw(&self) -> Result<f64> {
+        read_number(&self.raw_path)
+    }
+
+    pub fn read_millivolts(&self) -> Result<f64> {
+        Ok((self.read_raw()? + self.offset) * self.scale)
+    }
+
+    pub fn read_window(&self, window: &AdcWindow) -> Result<AdcReading> {
+        let mut readings = read_windows(std::slice::from_ref(self), window)?;
+        Ok(readings.remove(0))
+    }
+}
+
+// Read a window of every channel, the channels are sampled in turn so their
+// windows cover the same time
+fn read_windows(channels: &[AdcChannel], window: &AdcWindow) -> Result<Vec<AdcReading>> {
+    let samples = window.samples.max(1);
+    let mut values = vec![Vec::new(); channels.len()];
+    for sample in 0..samples {
+        if sample > 0 {
+            std::thread::sleep(window.interval);
+        }
+        for (channel, values) in channels.iter().zip(values.iter_mut()) {
+            values.push(channel.read_millivolts()?);
+        }
+    }
+    Ok(channels
+        .iter()
+        .zip(values)
+        .map(|(channel, values)| AdcReading::from_samples(channel.name(), &values))
+        .collect())
+}
+
+// The configured ADC inputs and the sampling frequency attribute of their
+// device
+#[derive(Debug, Clone, Default)]
+pub struct AdcControl {
+    channel_paths: Vec<String>,
+    sampling_frequency_path: Option<String>,
+}
+
+impl AdcControl {
+    pub fn new(channel_paths: &[&str], sampling_frequency_path: Option<&str>) -> Self {
+        trace!(task = "adc_control instance", "init");
+        AdcControl {
+            channel_paths: channel_paths.iter().map(|path| path.to_string()).collect(),
+            sampling_frequency_path: sampling_frequency_path.map(str::to_string),
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub fn channels(&self) -> Result<Vec<AdcChannel>> {
+        trace!(task = "adc_channels", "init");
+        self.channel_paths
+            .iter()
+            .map(|path| AdcChannel::from_path(Path::new(path)))
+            .collect()
+    }
+
+    // Channel by its name, e.g. `voltage1`, or `_raw` path
+    pub fn channel(&self, name: &str) -> Result<AdcChannel> {
+        match self
+            .channels()?
+            .into_iter()
+            .find(|channel| channel.name() == name || channel.path() == Path::new(name))
+        {
+            Some(channel) => Ok(channel),
+            None => bail!(AdcError::new(
+                AdcErrorCodes::UnknownChannel,
+                format!("unknown adc channel: {}", name),
+            )),
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub fn read(&self, name: &str, window: &AdcWindow) -> Result<AdcReading> {
+        trace!(task = "adc_read", "init");
+        let reading = self.channel(name)?.read_window(window)?;
+        info!(
+            task = "adc_read",
+            "{}: {} mV (min {}, max {})",
+            reading.channel,
+            reading.millivolts,
+            reading.min_millivolts,
+            reading.max_millivolts
+        );
+        Ok(reading)
+    }
+
+    fn sampling_frequency_path(&self) -> Result<&str> {
+        match &self.sampling_frequency_path {
+            Some(path) => Ok(path),
+            None => bail!(AdcError::new(
+                AdcErrorCodes::NoSamplingFrequency,
+                "no sampling frequency attribute is configured".to_string(),
+            )),
+        }
+    }
+
+    // Hz
+    pub fn sampling_frequency(&self) -> Result<f64> {
+        read_number(Path::new(self.sampling_frequency_path()?))
+    }
+
+    #[instrument(skip(self))]
+    pub fn set_sampling_frequency(&self, frequency: f64) -> Result<()> {
+        trace!(task = "set_sampling_frequency", "init");
+        if !frequency.is_finite() || frequency <= 0.0 {
+            bail!(AdcError::new(
+                AdcErrorCodes::InvalidSamplingFrequency,
+                format!("sampling frequency must be above 0 Hz, got {}", frequency),
+            ));
+        }
+        let path = self.sampling_frequency_path()?;
+        match fs::write(path, frequency.to_string()) {
+            Ok(_) => {
+                info!(
+                    task = "set_sampling_frequency",
+                    "sampling frequency set to {}", frequency
+                );
+                Ok(())
+            }
+            Err(e) => {
+                trace_error!(
+                    task = "set_sampling_frequency",
+                    "unable to write {}: {}",
+                    path,
+                    e
+                );
+                bail!(AdcError::new(
+                    AdcErrorCodes::UnableToWriteFile,
+                    format!("unable to write {}: {}", path, e),
+                ))
+            }
+        }
+    }
+
+    // Read a window of `names` (every channel when empty) continuously, one
+    // reading per channel and window
+    #[instrument(skip(self))]
+    pub fn stream(&self, names: &[String], window: &AdcWindow) -> Result<AdcReadingStream> {
+        trace!(task = "adc_stream", "init");
+        let channels = if names.is_empty() {
+            self.channels()?
+        } else {
+            names
+                .iter()
+                .map(|name| self.channel(name))
+                .collect::<Result<Vec<AdcChannel>>>()?
+        };
+        Ok(AdcReadingStream::start(channels, *window))
+    }
+}
+
+// Stream of ADC readings, reading stops once the stream is dropped
+pub struct AdcReadingStream {
+    receiver: mpsc::Receiver<Result<AdcReading>>,
+}
+
+impl AdcReadingStream {
+    fn start(channels: Vec<AdcChannel>, window: AdcWindow) -> Self {
+        let (sender, receiver) = mpsc::channel(16);
+        std::thread::spawn(move || 'read: loop {
+            match read_windows(&channels, &window) {
+                Ok(readings) => {
+                    for reading in readings {
+                        if sender.blocking_send(Ok(reading)).is_err() {
+                            break 'read;
+                        }
+                    }
+                }
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(e));
+                    break;
+                }
+            }
+            std::thread::sleep(window.interval);
+        });
+        AdcReadingStream { receiver }
+    }
+
+    pub async fn next(&mut self) -> Option<Result<AdcReading>> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for AdcReadingStream {
+    type Item = Result<AdcReading>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+fn read_number(path: &Path) -> Result<f64> {
+    let value = match fs::read_to_string(path) {
+        Ok(value) => value,
+        Err(e) => {
+            trace_error!(
+                task = "read_number",
+                "unable to read {}: {}",
+                path.display(),
+                e
+            );
+            bail!(AdcError::new(
+                AdcErrorCodes::UnableToOpenFile,
+                format!("unable to read {}: {}", path.display(), e),
+            ))
+        }
+    };
+    match value.trim().parse::<f64>() {
+        Ok(value) => Ok(value),
+        Err(e) => bail!(AdcError::new(
+            AdcErrorCodes::UnableToParseValue,
+            format!("unable to parse {}: {}", path.display(), e),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn write(path: &Path, attribute: &str, value: &str) {
+        fs::write(path.join(attribute), value).unwrap();
+    }
+
+    fn create_adc(dir: &Path) -> AdcControl {
+        write(dir, "in_voltage_scale", "0.5");
+        write(dir, "in_voltage1_scale", "2");
+        write(dir, "in_voltage0_raw", "1000\n");
+        write(dir, "in_voltage1_raw", "300\n");
+        write(dir, "in_voltage0_sampling_frequency", "1000");
+        let path = |name: &str| dir.join(name).display().to_string();
+        AdcControl::new(
+            &[&path("in_voltage0_raw"), &path("in_voltage1_raw")],
+            Some(&path("in_voltage0_sampling_frequency")),
+        )
+    }
+
+    #[test]
+    fn test_channels() {
+        let dir = TempDir::new("adc").unwrap();
+        let adc = create_adc(dir.path());
+
+        let channels = adc.channels().unwrap();
+        assert_eq!(channels[0].name(), "voltage0");
+        assert_eq!(channels[0].scale(), 0.5);
+        assert_eq!(channels[1].scale(), 2.0);
+        assert_eq!(channels[0].read_millivolts().unwrap(), 500.0);
+        assert_eq!(
+            adc.channel("voltage1").unwrap().read_millivolts().unwrap(),
+            600.0
+        );
+        assert!(adc.channel("voltage2").is_err());
+        assert!(AdcChannel::from_path(&dir.path().join("in_accel_x_raw")).is_err());
+
+        assert_eq!(adc.sampling_frequency().unwrap(), 1000.0);
+        adc.set_sampling_frequency(500.0).unwrap();
+        assert_eq!(adc.sampling_frequency().unwrap(), 500.0);
+        assert!(adc.set_sampling_frequency(f64::NAN).is_err());
+        assert!(adc.set_sampling_frequency(-1.0).is_err());
+        assert!(adc.set_sampling_frequency(0.0).is_err());
+        assert_eq!(adc.sampling_frequency().unwrap(), 500.0);
+        assert!(AdcControl::default().sampling_frequency().is_err());
+    }
+
+    #[test]
+    fn test_window() {
+        let reading = AdcReading::from_samples("voltage0", &[10.0, 14.0, 12.0, 8.0]);
+        assert_eq!(reading.millivolts, 11.0);
+        assert_eq!(reading.min_millivolts, 8.0);
+        assert_eq!(reading.max_millivolts, 14.0);
+        assert_eq!(reading.samples, 4);
+
+        let dir = TempDir::new("adc").unwrap();
+        let adc = create_adc(dir.path());
+        let window = AdcWindow {
+            samples: 3,
+            interval: Duration::ZERO,
+        };
+        let reading = adc.read("voltage0", &window).unwrap();
+        assert_eq!(reading.millivolts, 500.0);
+        assert_eq!(reading.samples, 3);
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let dir = TempDir::new("adc").unwrap();
+        let adc = create_adc(dir.path());
+        let window = AdcWindow {
+            samples: 2,
+            interval: Duration::from_millis(1),
+        };
+
+        let mut readings = adc.stream(&[], &window).unwrap();
+        let first = readings.next().await.unwrap().unwrap();
+        let second = readings.next().await.unwrap().unwrap();
+        assert_eq!(first.channel, "voltage0");
+        assert_eq!(second.channel, "voltage1");
+        assert_eq!(second.millivolts, 600.0);
+
+        let mut readings = adc.stream(&["voltage1".to_string()], &window).unwrap();
+        assert_eq!(readings.next().await.unwrap().unwrap().channel, "voltage1");
+        assert!(adc.stream(&["voltage5".to_string()], &window).is_err());
+    }
+}
@@ -0,0 +1,40 @@
+#[derive(Debug)]
+pub enum AdcErrorCodes {
+    UnableToOpenFile,
+    UnableToParseValue,
+    UnableToWriteFile,
+    UnknownChannel,
+    NoSamplingFrequency,
+    InvalidSamplingFrequency,
+}
+
+impl std::fmt::Display for AdcErrorCodes {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            AdcErrorCodes::UnableToOpenFile => write!(f, "UnableToOpenFile"),
+            AdcErrorCodes::UnableToParseValue => write!(f, "UnableToParseValue"),
+            AdcErrorCodes::UnableToWriteFile => write!(f, "UnableToWriteFile"),
+            AdcErrorCodes::UnknownChannel => write!(f, "UnknownChannel"),
+            AdcErrorCodes::NoSamplingFrequency => write!(f, "NoSamplingFrequency"),
+            AdcErrorCodes::InvalidSamplingFrequency => write!(f, "InvalidSamplingFrequency"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AdcError {
+    pub code: AdcErrorCodes,
+    pub message: String,
+}
+
+impl std::fmt::Display for AdcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "(code: {:?}, message: {})", self.code, self.message)
+    }
+}
+
+impl AdcError {
+    pub fn new(code: AdcErrorCodes, message: String) -> Self {
+        AdcError { code, message }
+    }
+}
@@ -0,0 +1,6 @@
+#![deny(clippy::all)]
+mod adc;
+pub use adc::{AdcChannel, AdcControl, AdcReading, AdcReadingStream, AdcWindow};
+
+mod errors;
+pub use errors::{AdcError, AdcErrorCodes};
@@ -0,0 +1,163 @@
+use crate::errors::{LedctlError, LedctlErrorCodes};
+use crate::led::LedColor;
+use anyhow::{bail, Error, Result};
+use std::str::FromStr;
+
+// Named colors accepted when parsing a color
+const NAMED_COLORS: [(&str, RgbColor); 11] = [
+    ("off", RgbColor::new(0, 0, 0)),
+    ("black", RgbColor::new(0, 0, 0)),
+    ("white", RgbColor::new(255, 255, 255)),
+    ("red", RgbColor::new(255, 0, 0)),
+    ("green", RgbColor::new(0, 255, 0)),
+    ("blue", RgbColor::new(0, 0, 255)),
+    ("yellow", RgbColor::new(255, 255, 0)),
+    ("cyan", RgbColor::new(0, 255, 255)),
+    ("magenta", RgbColor::new(255, 0, 255)),
+    ("orange", RgbColor::new(255, 165, 0)),
+    ("purple", RgbColor::new(128, 0, 128)),
+];
+
+// RGB value written to the red, green and blue leds, each channel is scaled
+// from 0 - 255 to the `max_brightness` of its led
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RgbColor {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl RgbColor {
+    pub const OFF: RgbColor = RgbColor::new(0, 0, 0);
+
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        RgbColor { red, green, blue }
+    }
+
+    // Value of a single channel of the color
+    pub fn channel(&self, color: LedColor) -> u8 {
+        match color {
+            LedColor::Red => self.red,
+            LedColor::Green => self.green,
+            LedColor::Blue => self.blue,
+        }
+    }
+
+    // Color scaled by `numerator / denominator`, used to ramp brightness
+    pub fn scaled(&self, numerator: u32, denominator: u32) -> Self {
+        let scale = |value: u8| (value as u32 * numerator / denominator.max(1)).min(255) as u8;
+        RgbColor::new(scale(self.red), scale(self.green), scale(self.blue))
+    }
+
+    // Color as `#rrggbb`
+    pub fn to_hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+    }
+
+    fn from_hex(hex: &str) -> Option<Self> {
+        let digits = |range: std::ops::Range<usize>| u8::from_str_radix(hex.get(range)?, 16).ok();
+        match hex.len() {
+            // #rgb shorthand, each digit is repeated
+            3 => Some(RgbColor::new(
+                digits(0..1)? * 17,
+                digits(1..2)? * 17,
+                digits(2..3)? * 17,
+            )),
+            6 => Some(RgbColor::new(digits(0..2)?, digits(2..4)?, digits(4..6)?)),
+            _ => None,
+        }
+    }
+
+    fn from_values(values: &str) -> Option<Self> {
+        let values = values
+            .split(',')
+            .map(|value| value.trim().parse::<u8>().ok())
+            .collect::<Option<Vec<u8>>>()?;
+        match values[..] {
+            [red, green, blue] => Some(RgbColor::new(red, green, blue)),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for RgbColor {
+    type Err = Error;
+
+    // Parse `#rrggbb`/`#rgb`/`0xrrggbb` hex, `r,g,b` values or a color name,
+    // hex needs its prefix so words like `bed` are not taken for colors
+    fn from_str(color: &str) -> Result<Self> {
+        let color = color.trim();
+        let hex = color
+            .strip_prefix('#')
+            .or_else(|| color.strip_prefix("0x"))
+            .or_else(|| color.strip_prefix("0X"));
+        let parsed = if color.contains(',') {
+            RgbColor::from_values(color)
+        } else if let Some(hex) = hex {
+            RgbColor::from_hex(hex)
+        } else {
+            NAMED_COLORS
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(color))
+                .map(|(_, rgb)| *rgb)
+        };
+
+        match parsed {
+            Some(rgb) => Ok(rgb),
+            None => bail!(LedctlError::new(
+                LedctlErrorCodes::InvalidLedColorError,
+                format!("invalid led color: {}", color),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex_color() {
+        assert_eq!(
+            "#ff8000".parse::<RgbColor>().unwrap(),
+            RgbColor::new(255, 128, 0)
+        );
+        assert_eq!(
+            "0x00FF7f".parse::<RgbColor>().unwrap(),
+            RgbColor::new(0, 255, 127)
+        );
+        assert_eq!(
+            "#f0a".parse::<RgbColor>().unwrap(),
+            RgbColor::new(255, 0, 170)
+        );
+    }
+
+    #[test]
+    fn test_parse_named_and_values_color() {
+        assert_eq!(
+            "Orange".parse::<RgbColor>().unwrap(),
+            RgbColor::new(255, 165, 0)
+        );
+        assert_eq!(
+            "1, 2,3".parse::<RgbColor>().unwrap(),
+            RgbColor::new(1, 2, 3)
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_color() {
+        assert!("#12345".parse::<RgbColor>().is_err());
+        assert!("1,2".parse::<RgbColor>().is_err());
+        assert!("256,0,0".parse::<RgbColor>().is_err());
+        assert!("chartreuse".parse::<RgbColor>().is_err());
+        // hex without a prefix is not a color
+        assert!("bed".parse::<RgbColor>().is_err());
+        assert!("face".parse::<RgbColor>().is_err());
+        assert!("00ff7f".parse::<RgbColor>().is_err());
+    }
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(RgbColor::new(255, 8, 0).to_hex(), "#ff0800");
+    }
+}
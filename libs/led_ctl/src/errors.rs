@@ -2,6 +2,10 @@
 pub enum LedctlErrorCodes {
     InvalidLedColorError,
     InvalidLedPathValueError,
+    InvalidLedPatternError,
+    UnableToSetLedTriggerError,
+    UnknownIndicatorStateError,
+    UnsupportedLedTriggerError,
 }
 
 #[derive(Debug)]
@@ -19,6 +23,16 @@ impl std::fmt::Display for LedctlErrorCodes {
             LedctlErrorCodes::InvalidLedColorError => {
                 write!(f, "InvalidBrightnessPathError")
             }
+            LedctlErrorCodes::InvalidLedPatternError => write!(f, "InvalidLedPatternError"),
+            LedctlErrorCodes::UnableToSetLedTriggerError => {
+                write!(f, "UnableToSetLedTriggerError")
+            }
+            LedctlErrorCodes::UnknownIndicatorStateError => {
+                write!(f, "UnknownIndicatorStateError")
+            }
+            LedctlErrorCodes::UnsupportedLedTriggerError => {
+                write!(f, "UnsupportedLedTriggerError")
+            }
         }
     }
 }
@@ -0,0 +1,254 @@
+use crate::color::RgbColor;
+use crate::errors::{LedctlError, LedctlErrorCodes};
+use crate::pattern::{LedPattern, LedPatternEngine};
+use anyhow::{bail, Result};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{info, instrument, trace};
+
+// What the leds show while a state is the highest priority active one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndicatorOutput {
+    Solid(RgbColor),
+    // played until the state is cleared or preempted
+    Pattern(LedPattern),
+}
+
+// A system state such as `booting` or `battery_low` mapped to led output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndicatorState {
+    pub name: String,
+    pub priority: u32,
+    pub output: IndicatorOutput,
+}
+
+impl IndicatorState {
+    pub fn new(name: &str, priority: u32, output: IndicatorOutput) -> Self {
+        IndicatorState {
+            name: String::from(name),
+            priority,
+            output,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct IndicatorInner {
+    // active states in the order they were raised
+    active: Vec<String>,
+    shown: Option<String>,
+}
+
+// Arbitrates the leds between asserted system states, the active state with
+// the highest priority is shown and ties go to the most recently raised one
+#[derive(Debug)]
+pub struct StatusIndicator {
+    engine: Arc<LedPatternEngine>,
+    states: Vec<IndicatorState>,
+    inner: Mutex<IndicatorInner>,
+}
+
+impl StatusIndicator {
+    pub fn new(engine: Arc<LedPatternEngine>, states: Vec<IndicatorState>) -> Self {
+        trace!(task = "status_indicator instance", "init");
+        StatusIndicator {
+            engine,
+            states,
+            inner: Mutex::new(IndicatorInner::default()),
+        }
+    }
+
+    // States configured for the indicator
+    pub fn states(&self) -> &[IndicatorState] {
+        &self.states
+    }
+
+    pub fn is_configured(&self, name: &str) -> bool {
+        self.state(name).is_some()
+    }
+
+    // Assert a state, returning the state now shown on the leds
+    #[instrument(skip(self))]
+    pub async fn raise(&self, name: &str) -> Result<Option<String>> {
+        trace!(task = "raise_indicator", "init");
+        if !self.is_configured(name) {
+            bail!(LedctlError::new(
+                LedctlErrorCodes::UnknownIndicatorStateError,
+                format!("indicator state {} is not configured", name),
+            ));
+        }
+
+        let mut inner = self.inner.lock().await;
+        if !inner.active.iter().any(|active| active == name) {
+            inner.active.push(String::from(name));
+        }
+        self.apply(&mut inner).await?;
+        info!(task = "raise_indicator", "raised indicator state {}", name);
+        Ok(inner.shown.clone())
+    }
+
+    // Withdraw a state, returning the state now shown on the leds
+    #[instrument(skip(self))]
+    pub async fn clear(&self, name: &str) -> Result<Option<String>> {
+        trace!(task = "clear_indicator", "init");
+        if !self.is_configured(name) {
+            bail!(LedctlError::new(
+                LedctlErrorCodes::UnknownIndicatorStateError,
+                format!("indicator state {} is not configured", name),
+            ));
+        }
+
+        let mut inner = self.inner.lock().await;
+        inner.active.retain(|active| active != name);
+        self.apply(&mut inner).await?;
+        info!(task = "clear_indicator", "cleared indicator state {}", name);
+        Ok(inner.shown.clone())
+    }
+
+    // State currently shown on the leds, if any
+    pub async fn shown(&self) -> Option<String> {
+        self.inner.lock().await.shown.clone()
+    }
+
+    fn state(&self, name: &str) -> Option<&IndicatorState> {
+        self.states.iter().find(|state| state.name == name)
+    }
+
+    // Show the winning state, leaving the leds alone if it is already shown
+    async fn apply(&self, inner: &mut IndicatorInner) -> Result<()> {
+        let winner = inner
+            .active
+            .iter()
+            .enumerate()
+            .filter_map(|(order, name)| self.state(name).map(|state| (state, order)))
+            .max_by_key(|(state, order)| (state.priority, *order))
+            .map(|(state, _)| state);
+
+        let winner_name = winner.map(|state| state.name.clone());
+        if winner_name == inner.shown {
+            return Ok(());
+        }
+
+        match winner {
+            None => self.engine.stop().await?,
+            Some(state) => match &state.output {
+                IndicatorOutput::Solid(color) => self.engine.set_color(*color).await?,
+                IndicatorOutput::Pattern(pattern) => {
+                    self.engine.start(pattern.clone(), None).await?;
+                }
+            },
+        }
+        inner.shown = winner_name;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::led::LedControl;
+    use std::fs;
+    use std::path::Path;
+    use std::time::Duration;
+    use tempdir::TempDir;
+
+    fn indicator(dir: &Path) -> StatusIndicator {
+        let paths: Vec<String> = ["red", "green", "blue"]
+            .iter()
+            .map(|name| dir.join(name).to_str().unwrap().to_string())
+            .collect();
+        let led = Arc::new(LedControl::new(&paths[0], &paths[1], &paths[2]));
+        let states = vec![
+            IndicatorState::new(
+                "booting",
+                10,
+                IndicatorOutput::Solid(RgbColor::new(0, 0, 255)),
+            ),
+            IndicatorState::new(
+                "error",
+                100,
+                IndicatorOutput::Solid(RgbColor::new(255, 0, 0)),
+            ),
+            IndicatorState::new(
+                "charging",
+                10,
+                IndicatorOutput::Pattern(LedPattern::Blink {
+                    color: RgbColor::new(0, 255, 0),
+                    on: Duration::from_millis(5),
+                    off: Duration::from_millis(5),
+                }),
+            ),
+        ];
+        StatusIndicator::new(Arc::new(LedPatternEngine::new(led)), states)
+    }
+
+    fn color(dir: &Path) -> (String, String, String) {
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        (read("red"), read("green"), read("blue"))
+    }
+
+    #[tokio::test]
+    async fn test_highest_priority_state_is_shown() {
+        let tmp_dir = TempDir::new("indicator").unwrap();
+        let indicator = indicator(tmp_dir.path());
+
+        indicator.raise("booting").await.unwrap();
+        let shown = indicator.raise("error").await.unwrap();
+        assert_eq!(shown.as_deref(), Some("error"));
+        assert_eq!(
+            color(tmp_dir.path()),
+            ("255".to_string(), "0".to_string(), "0".to_string())
+        );
+
+        // a lower priority state does not take over
+        let shown = indicator.raise("booting").await.unwrap();
+        assert_eq!(shown.as_deref(), Some("error"));
+
+        let shown = indicator.clear("error").await.unwrap();
+        assert_eq!(shown.as_deref(), Some("booting"));
+        assert_eq!(
+            color(tmp_dir.path()),
+            ("0".to_string(), "0".to_string(), "255".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_equal_priority_prefers_latest_state() {
+        let tmp_dir = TempDir::new("indicator").unwrap();
+        let indicator = indicator(tmp_dir.path());
+
+        indicator.raise("booting").await.unwrap();
+        let shown = indicator.raise("charging").await.unwrap();
+        assert_eq!(shown.as_deref(), Some("charging"));
+
+        let shown = indicator.clear("charging").await.unwrap();
+        assert_eq!(shown.as_deref(), Some("booting"));
+        assert_eq!(
+            color(tmp_dir.path()),
+            ("0".to_string(), "0".to_string(), "255".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clearing_last_state_turns_leds_off() {
+        let tmp_dir = TempDir::new("indicator").unwrap();
+        let indicator = indicator(tmp_dir.path());
+
+        indicator.raise("error").await.unwrap();
+        let shown = indicator.clear("error").await.unwrap();
+        assert_eq!(shown, None);
+        assert_eq!(indicator.shown().await, None);
+        assert_eq!(
+            color(tmp_dir.path()),
+            ("0".to_string(), "0".to_string(), "0".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_state_is_rejected() {
+        let tmp_dir = TempDir::new("indicator").unwrap();
+        let indicator = indicator(tmp_dir.path());
+        assert!(indicator.raise("update").await.is_err());
+        assert!(indicator.clear("update").await.is_err());
+    }
+}
@@ -1,21 +1,48 @@
 use crate::errors::{LedctlError, LedctlErrorCodes};
+use crate::led_device::parse_triggers;
 use anyhow::{bail, Result};
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use tracing::{error as trace_error, info, trace,instrument};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum LedColor {
     Red,
     Green,
     Blue,
 }
 
+impl LedColor {
+    pub const ALL: [LedColor; 3] = [LedColor::Red, LedColor::Green, LedColor::Blue];
+
+    // Color of a `multi_index` entry of a multicolor led
+    fn from_index_name(name: &str) -> Option<LedColor> {
+        match name {
+            "red" => Some(LedColor::Red),
+            "green" => Some(LedColor::Green),
+            "blue" => Some(LedColor::Blue),
+            _ => None,
+        }
+    }
+}
+
+// Brightness used when the led does not report its `max_brightness`
+const DEFAULT_MAX_BRIGHTNESS: u32 = 255;
+
+// Scale a 0 - 255 value to 0 - `max_brightness`, rounding up so a non zero
+// value always switches the led on
+fn scale_brightness(value: u8, max_brightness: u32) -> u32 {
+    (value as u32 * max_brightness).div_ceil(255)
+}
+
 #[derive(Debug, Default)]
 pub struct LedControl {
     red_led_path: String,
     green_led_path: String,
     blue_led_path: String,
+    // brightness path of a `leds-class-multicolor` led driving all colors
+    multicolor_led_path: Option<String>,
 }
 
 impl LedControl {
@@ -26,6 +53,16 @@ impl LedControl {
             red_led_path: String::from(red_led_path),
             green_led_path: String::from(green_led_path),
             blue_led_path: String::from(blue_led_path),
+            multicolor_led_path: None,
+        }
+    }
+
+    // Constructor for a single multicolor led using `multi_index` and `multi_intensity`
+    pub fn new_multicolor(led_path: &str) -> Self {
+        trace!(task = "led_ctrl multicolor instance", "init");
+        LedControl {
+            multicolor_led_path: Some(String::from(led_path)),
+            ..Default::default()
         }
     }
 
@@ -33,8 +70,15 @@ impl LedControl {
     #[instrument(skip(self))]
     pub fn set_led(&self, red: u8, green: u8, blue: u8) -> Result<()> {
         trace!(task = "set_led", "init");
+
+        if self.is_multicolor() {
+            self.set_multicolor(&[(LedColor::Red, red), (LedColor::Green, green), (LedColor::Blue, blue)])?;
+            info!(task = "set_led", "set led to red: {}, green: {}, blue: {}", red, green, blue);
+            return Ok(());
+        }
     
         // Set red LED
+        let red = self.scale(LedColor::Red, red);
         if let Err(e) = self.write_brightness(&self.red_led_path, &red.to_string()) {
             trace_error!(task = "set_led", "unable to write brightness value: {}", e);
             bail!(LedctlError::new(
@@ -44,6 +88,7 @@ impl LedControl {
         }
     
         // Set green LED
+        let green = self.scale(LedColor::Green, green);
         if let Err(e) = self.write_brightness(&self.green_led_path, &green.to_string()) {
             trace_error!(task = "set_led", "unable to write brightness value: {}", e);
             bail!(LedctlError::new(
@@ -53,6 +98,7 @@ impl LedControl {
         }
     
         // Set blue LED
+        let blue = self.scale(LedColor::Blue, blue);
         if let Err(e) = self.write_brightness(&self.blue_led_path, &blue.to_string()) {
             trace_error!(task = "set_led", "unable to write brightness value: {}", e);
             bail!(LedctlError::new(
@@ -70,14 +116,14 @@ impl LedControl {
     #[instrument(skip(self))]
     pub fn clear_led(&self, color: LedColor) -> Result<()> {
         trace!(task = "clear_led", "init");
-        let path = match color {
-            LedColor::Red => &self.red_led_path,
-            LedColor::Green => &self.green_led_path,
-            LedColor::Blue => &self.blue_led_path,
-        };
+        if self.is_multicolor() {
+            self.set_multicolor(&[(color, 0)])?;
+            info!(task = "clear_led", "clear led {:?}", color);
+            return Ok(());
+        }
 
         //try to write the brightness value to the file or return an error
-        if let Err(e) = self.write_brightness(path, "0") {
+        if let Err(e) = self.write_brightness(self.led_path(color), "0") {
             trace_error!(
                 task = "clear_led",
                 "unable to write brightness value: {}",
@@ -92,6 +138,189 @@ impl LedControl {
         Ok(())
     }
 
+    // Brightness path of the led driving the specified color
+    pub fn led_path(&self, color: LedColor) -> &str {
+        if let Some(path) = &self.multicolor_led_path {
+            return path;
+        }
+        match color {
+            LedColor::Red => &self.red_led_path,
+            LedColor::Green => &self.green_led_path,
+            LedColor::Blue => &self.blue_led_path,
+        }
+    }
+
+    // Function to set a single led to the given brightness
+    #[instrument(skip(self))]
+    pub fn set_channel(&self, color: LedColor, value: u8) -> Result<()> {
+        trace!(task = "set_channel", "init");
+        if self.is_multicolor() {
+            return self.set_multicolor(&[(color, value)]);
+        }
+
+        let value = self.scale(color, value);
+        if let Err(e) = self.write_brightness(self.led_path(color), &value.to_string()) {
+            trace_error!(task = "set_channel", "unable to write brightness value: {}", e);
+            bail!(LedctlError::new(
+                LedctlErrorCodes::InvalidLedPathValueError,
+                format!("unable to write brightness value: {}", e),
+            ));
+        }
+        Ok(())
+    }
+
+    // Whether all colors are driven by a single multicolor led
+    pub fn is_multicolor(&self) -> bool {
+        self.multicolor_led_path.is_some()
+    }
+
+    // Highest brightness the led accepts, read from its `max_brightness` attribute
+    pub fn max_brightness(&self, color: LedColor) -> u32 {
+        let path = self.attribute_path(color, "max_brightness");
+        match fs::read_to_string(&path).map(|value| value.trim().parse::<u32>()) {
+            Ok(Ok(max_brightness)) => max_brightness,
+            _ => {
+                trace!(
+                    task = "max_brightness",
+                    "unable to read {}, using {}",
+                    path.display(),
+                    DEFAULT_MAX_BRIGHTNESS
+                );
+                DEFAULT_MAX_BRIGHTNESS
+            }
+        }
+    }
+
+    // Brightness written to the led for a 0 - 255 color value
+    pub(crate) fn scale(&self, color: LedColor, value: u8) -> u32 {
+        scale_brightness(value, self.max_brightness(color))
+    }
+
+    // Write an already scaled brightness to the led
+    pub(crate) fn write_raw_brightness(&self, color: LedColor, value: u32) -> Result<()> {
+        if let Err(e) = self.write_brightness(self.led_path(color), &value.to_string()) {
+            trace_error!(task = "write_raw_brightness", "unable to write brightness value: {}", e);
+            bail!(LedctlError::new(
+                LedctlErrorCodes::InvalidLedPathValueError,
+                format!("unable to write brightness value: {}", e),
+            ));
+        }
+        Ok(())
+    }
+
+    // Update the given colors of a multicolor led, keeping the others. The
+    // color comes from `multi_intensity` while `brightness` is kept at its
+    // maximum, unless every color is off.
+    fn set_multicolor(&self, values: &[(LedColor, u8)]) -> Result<()> {
+        trace!(task = "set_multicolor", "init");
+        let max_brightness = self.max_brightness(LedColor::Red);
+        let index = self.read_attribute(LedColor::Red, "multi_index")?;
+        let current = self.read_attribute(LedColor::Red, "multi_intensity")?;
+
+        let mut intensity: Vec<u32> = current
+            .split_whitespace()
+            .map(|value| value.parse::<u32>().unwrap_or(0))
+            .collect();
+        intensity.resize(index.split_whitespace().count(), 0);
+
+        for (position, name) in index.split_whitespace().enumerate() {
+            let color = LedColor::from_index_name(name);
+            if let Some((_, value)) = values.iter().find(|(c, _)| Some(*c) == color) {
+                intensity[position] = scale_brightness(*value, max_brightness);
+            }
+        }
+
+        let intensity_value = intensity
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.write_attribute(LedColor::Red, "multi_intensity", &intensity_value)?;
+
+        let brightness = if intensity.iter().any(|value| *value > 0) {
+            max_brightness
+        } else {
+            0
+        };
+        self.write_raw_brightness(LedColor::Red, brightness)
+    }
+
+    fn read_attribute(&self, color: LedColor, attribute: &str) -> Result<String> {
+        let path = self.attribute_path(color, attribute);
+        match fs::read_to_string(&path) {
+            Ok(value) => Ok(value.trim().to_string()),
+            Err(e) => {
+                trace_error!(
+                    task = "read_attribute",
+                    "unable to read {}: {}",
+                    path.display(),
+                    e
+                );
+                bail!(LedctlError::new(
+                    LedctlErrorCodes::InvalidLedPathValueError,
+                    format!("unable to read {}: {}", path.display(), e),
+                ))
+            }
+        }
+    }
+
+    // Triggers the kernel offers for the led, read from the `trigger` attribute
+    pub(crate) fn available_triggers(&self, color: LedColor) -> Result<Vec<String>> {
+        trace!(task = "available_triggers", "init");
+        let path = self.attribute_path(color, "trigger");
+        let triggers = match fs::read_to_string(&path) {
+            Ok(triggers) => triggers,
+            Err(e) => {
+                trace_error!(task = "available_triggers", "unable to read triggers: {}", e);
+                bail!(LedctlError::new(
+                    LedctlErrorCodes::UnableToSetLedTriggerError,
+                    format!("unable to read triggers: {}", e),
+                ));
+            }
+        };
+
+        let (available, _) = parse_triggers(&triggers);
+        Ok(available)
+    }
+
+    pub(crate) fn supports_trigger(&self, color: LedColor, trigger: &str) -> bool {
+        self.available_triggers(color)
+            .map(|triggers| triggers.iter().any(|t| t == trigger))
+            .unwrap_or(false)
+    }
+
+    pub(crate) fn set_trigger(&self, color: LedColor, trigger: &str) -> Result<()> {
+        trace!(task = "set_trigger", "init");
+        self.write_attribute(color, "trigger", trigger)?;
+        info!(task = "set_trigger", "set {:?} led trigger to {}", color, trigger);
+        Ok(())
+    }
+
+    // Write an attribute living next to the led brightness file, e.g. `delay_on`
+    pub(crate) fn write_attribute(&self, color: LedColor, attribute: &str, value: &str) -> Result<()> {
+        let path = self.attribute_path(color, attribute);
+        if let Err(e) = fs::write(&path, value) {
+            trace_error!(
+                task = "write_attribute",
+                "unable to write {}: {}",
+                path.display(),
+                e
+            );
+            bail!(LedctlError::new(
+                LedctlErrorCodes::UnableToSetLedTriggerError,
+                format!("unable to write {}: {}", path.display(), e),
+            ));
+        }
+        Ok(())
+    }
+
+    fn attribute_path(&self, color: LedColor, attribute: &str) -> PathBuf {
+        Path::new(self.led_path(color))
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(attribute)
+    }
+
     // Private function to write to the brightness file with error handling
     fn write_brightness(&self, path: &str, value: &str) -> Result<(), io::Error> {
         trace!(task = "write_brightness", "init");
@@ -138,6 +367,55 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_set_led_scales_to_max_brightness() {
+        let tmp_dir = TempDir::new("led").unwrap();
+        let mut paths = Vec::new();
+        for (name, max_brightness) in [("red", "1"), ("green", "100"), ("blue", "255")] {
+            let led_dir = tmp_dir.path().join(name);
+            std::fs::create_dir(&led_dir).unwrap();
+            std::fs::write(led_dir.join("max_brightness"), max_brightness).unwrap();
+            paths.push(led_dir.join("brightness"));
+        }
+        let led_ctrl = LedControl::new(
+            paths[0].to_str().unwrap(),
+            paths[1].to_str().unwrap(),
+            paths[2].to_str().unwrap(),
+        );
+
+        led_ctrl.set_led(10, 255, 128).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&paths[0]).unwrap(), "1");
+        assert_eq!(std::fs::read_to_string(&paths[1]).unwrap(), "100");
+        assert_eq!(std::fs::read_to_string(&paths[2]).unwrap(), "128");
+    }
+
+    #[test]
+    fn test_set_multicolor_led() {
+        let tmp_dir = TempDir::new("led").unwrap();
+        let led_dir = tmp_dir.path();
+        std::fs::write(led_dir.join("max_brightness"), "100").unwrap();
+        std::fs::write(led_dir.join("multi_index"), "green blue red\n").unwrap();
+        std::fs::write(led_dir.join("multi_intensity"), "0 0 0\n").unwrap();
+        let brightness = led_dir.join("brightness");
+        let led_ctrl = LedControl::new_multicolor(brightness.to_str().unwrap());
+
+        led_ctrl.set_led(255, 0, 51).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(led_dir.join("multi_intensity")).unwrap(),
+            "0 20 100"
+        );
+        assert_eq!(std::fs::read_to_string(&brightness).unwrap(), "100");
+
+        led_ctrl.clear_led(LedColor::Red).unwrap();
+        led_ctrl.clear_led(LedColor::Blue).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(led_dir.join("multi_intensity")).unwrap(),
+            "0 0 0"
+        );
+        assert_eq!(std::fs::read_to_string(&brightness).unwrap(), "0");
+    }
+
     #[test]
     fn test_clear_led() {
         let tmp_dir = TempDir::new("led").unwrap();
@@ -0,0 +1,397 @@
+use crate::errors::{LedctlError, LedctlErrorCodes};
+use anyhow::{bail, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{error as trace_error, info, instrument, trace};
+
+// Directory the kernel exposes every led class device under
+pub const LED_CLASS_PATH: &str = "/sys/class/leds";
+
+// Colors used in led names, see `include/dt-bindings/leds/common.h`
+const LED_COLORS: [&str; 14] = [
+    "white", "red", "green", "blue", "amber", "violet", "yellow", "ir", "multi", "rgb", "purple",
+    "orange", "pink", "cyan",
+];
+
+// Trigger names and the selected one, parsed from `none [timer] heartbeat`
+pub(crate) fn parse_triggers(triggers: &str) -> (Vec<String>, Option<String>) {
+    let mut current = None;
+    let available = triggers
+        .split_whitespace()
+        .map(|trigger| {
+            if trigger.starts_with('[') && trigger.ends_with(']') {
+                let trigger = trigger.trim_matches(|c| c == '[' || c == ']').to_string();
+                current = Some(trigger.clone());
+                trigger
+            } else {
+                trigger.to_string()
+            }
+        })
+        .collect();
+    (available, current)
+}
+
+// Kernel led trigger together with its parameters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LedTrigger {
+    None,
+    DefaultOn,
+    Heartbeat,
+    // blink with the given on and off times in milliseconds
+    Timer {
+        delay_on: u32,
+        delay_off: u32,
+    },
+    // follow the state of a network device
+    Netdev {
+        device: String,
+        link: bool,
+        rx: bool,
+        tx: bool,
+        interval: Option<u32>,
+    },
+    // any other trigger by name, e.g. `mmc0` or `disk-activity`
+    Other(String),
+}
+
+impl LedTrigger {
+    // Name written to the `trigger` attribute
+    pub fn name(&self) -> &str {
+        match self {
+            LedTrigger::None => "none",
+            LedTrigger::DefaultOn => "default-on",
+            LedTrigger::Heartbeat => "heartbeat",
+            LedTrigger::Timer { .. } => "timer",
+            LedTrigger::Netdev { .. } => "netdev",
+            LedTrigger::Other(name) => name,
+        }
+    }
+
+    // Parameter attributes the trigger creates once selected
+    fn attributes(&self) -> Vec<(&'static str, String)> {
+        let flag = |enabled: bool| if enabled { "1" } else { "0" }.to_string();
+        match self {
+            LedTrigger::Timer {
+                delay_on,
+                delay_off,
+            } => vec![
+                ("delay_on", delay_on.to_string()),
+                ("delay_off", delay_off.to_string()),
+            ],
+            LedTrigger::Netdev {
+                device,
+                link,
+                rx,
+                tx,
+                interval,
+            } => {
+                let mut attributes = vec![
+                    ("device_name", device.clone()),
+                    ("link", flag(*link)),
+                    ("rx", flag(*rx)),
+                    ("tx", flag(*tx)),
+                ];
+                if let Some(interval) = interval {
+                    attributes.push(("interval", interval.to_string()));
+                }
+                attributes
+            }
+            _ => vec![],
+        }
+    }
+}
+
+// Details of a led class device
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LedInfo {
+    pub name: String,
+    pub device: Option<String>,
+    pub color: Option<String>,
+    pub function: Option<String>,
+    pub brightness: u32,
+    pub max_brightness: u32,
+    pub trigger: Option<String>,
+    pub available_triggers: Vec<String>,
+    pub path: PathBuf,
+}
+
+// Split a led name following the `devicename:color:function` convention
+fn parse_led_name(name: &str) -> (Option<String>, Option<String>, Option<String>) {
+    let part = |value: &str| (!value.is_empty()).then(|| value.to_string());
+    let is_color = |value: &str| LED_COLORS.contains(&value.to_lowercase().as_str());
+
+    let parts: Vec<&str> = name.split(':').collect();
+    match parts[..] {
+        [device, color, function] => (part(device), part(color), part(function)),
+        [color, function] if is_color(color) => (None, part(color), part(function)),
+        [device, function] => (part(device), None, part(function)),
+        // legacy names such as `red-led` carry the color as a word
+        _ => (
+            None,
+            name.split(['-', '_'])
+                .find(|word| is_color(word))
+                .map(|color| color.to_lowercase()),
+            None,
+        ),
+    }
+}
+
+// A single led under `/sys/class/leds`
+#[derive(Debug, Clone)]
+pub struct LedDevice {
+    path: PathBuf,
+}
+
+impl LedDevice {
+    // Led with the given name under `/sys/class/leds`
+    pub fn new(name: &str) -> Self {
+        LedDevice::from_path(Path::new(LED_CLASS_PATH).join(name))
+    }
+
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        trace!(task = "led_device instance", "init");
+        LedDevice { path: path.into() }
+    }
+
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    #[instrument(skip(self))]
+    pub fn info(&self) -> Result<LedInfo> {
+        trace!(task = "led_info", "init");
+        let name = self.name();
+        let (device, color, function) = parse_led_name(&name);
+        let (available_triggers, trigger) = match self.read_attribute("trigger") {
+            Ok(triggers) => parse_triggers(&triggers),
+            // leds without trigger support only expose brightness
+            Err(_) => (vec![], None),
+        };
+
+        Ok(LedInfo {
+            name,
+            device,
+            color,
+            function,
+            brightness: self.read_number("brightness")?,
+            max_brightness: self.read_number("max_brightness")?,
+            trigger,
+            available_triggers,
+            path: self.path.clone(),
+        })
+    }
+
+    // Select a trigger and write its parameters
+    #[instrument(skip(self))]
+    pub fn set_trigger(&self, trigger: &LedTrigger) -> Result<()> {
+        trace!(task = "set_trigger", "init");
+        let (available_triggers, _) = parse_triggers(&self.read_attribute("trigger")?);
+        if !available_triggers.iter().any(|name| name == trigger.name()) {
+            trace_error!(
+                task = "set_trigger",
+                "led {} does not support trigger {}",
+                self.name(),
+                trigger.name()
+            );
+            bail!(LedctlError::new(
+                LedctlErrorCodes::UnsupportedLedTriggerError,
+                format!(
+                    "led {} does not support trigger {}",
+                    self.name(),
+                    trigger.name()
+                ),
+            ));
+        }
+
+        self.write_attribute("trigger", trigger.name())?;
+        for (attribute, value) in trigger.attributes() {
+            self.write_attribute(attribute, &value)?;
+        }
+        info!(
+            task = "set_trigger",
+            "set led {} trigger to {:?}",
+            self.name(),
+            trigger
+        );
+        Ok(())
+    }
+
+    // Write a raw brightness value, writing 0 also removes the trigger
+    pub fn set_brightness(&self, brightness: u32) -> Result<()> {
+        self.write_attribute("brightness", &brightness.to_string())
+    }
+
+    fn read_attribute(&self, attribute: &str) -> Result<String> {
+        let path = self.path.join(attribute);
+        match fs::read_to_string(&path) {
+            Ok(value) => Ok(value.trim().to_string()),
+            Err(e) => {
+                trace_error!(
+                    task = "read_attribute",
+                    "unable to read {}: {}",
+                    path.display(),
+                    e
+                );
+                bail!(LedctlError::new(
+                    LedctlErrorCodes::InvalidLedPathValueError,
+                    format!("unable to read {}: {}", path.display(), e),
+                ))
+            }
+        }
+    }
+
+    fn read_number(&self, attribute: &str) -> Result<u32> {
+        let value = self.read_attribute(attribute)?;
+        match value.parse::<u32>() {
+            Ok(value) => Ok(value),
+            Err(e) => bail!(LedctlError::new(
+                LedctlErrorCodes::InvalidLedPathValueError,
+                format!("unable to parse {} value {}: {}", attribute, value, e),
+            )),
+        }
+    }
+
+    fn write_attribute(&self, attribute: &str, value: &str) -> Result<()> {
+        let path = self.path.join(attribute);
+        if let Err(e) = fs::write(&path, value) {
+            trace_error!(
+                task = "write_attribute",
+                "unable to write {}: {}",
+                path.display(),
+                e
+            );
+            bail!(LedctlError::new(
+                LedctlErrorCodes::UnableToSetLedTriggerError,
+                format!("unable to write {}: {}", path.display(), e),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Every led under `/sys/class/leds`
+pub fn list_leds() -> Result<Vec<LedInfo>> {
+    list_leds_in(Path::new(LED_CLASS_PATH))
+}
+
+// Every led in a led class directory, sorted by name
+#[instrument]
+pub fn list_leds_in(class_path: &Path) -> Result<Vec<LedInfo>> {
+    trace!(task = "list_leds", "init");
+    let entries = match fs::read_dir(class_path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            trace_error!(task = "list_leds", "unable to list leds: {}", e);
+            bail!(LedctlError::new(
+                LedctlErrorCodes::InvalidLedPathValueError,
+                format!("unable to list leds in {}: {}", class_path.display(), e),
+            ));
+        }
+    };
+
+    let mut leds = Vec::new();
+    for entry in entries.flatten() {
+        match LedDevice::from_path(entry.path()).info() {
+            Ok(info) => leds.push(info),
+            Err(e) => trace_error!(task = "list_leds", "skipping {:?}: {}", entry.path(), e),
+        }
+    }
+    leds.sort_by(|a, b| a.name.cmp(&b.name));
+    info!(task = "list_leds", "found {} leds", leds.len());
+    Ok(leds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn create_led(class_path: &Path, name: &str, triggers: &str) -> PathBuf {
+        let path = class_path.join(name);
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("brightness"), "0\n").unwrap();
+        fs::write(path.join("max_brightness"), "255\n").unwrap();
+        fs::write(path.join("trigger"), triggers).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_parse_led_name() {
+        assert_eq!(
+            parse_led_name("tpacpi::power"),
+            (Some("tpacpi".to_string()), None, Some("power".to_string()))
+        );
+        assert_eq!(
+            parse_led_name("green:status"),
+            (None, Some("green".to_string()), Some("status".to_string()))
+        );
+        assert_eq!(
+            parse_led_name("mmc0::"),
+            (Some("mmc0".to_string()), None, None)
+        );
+        assert_eq!(
+            parse_led_name("red-led"),
+            (None, Some("red".to_string()), None)
+        );
+    }
+
+    #[test]
+    fn test_parse_triggers() {
+        let (available, current) = parse_triggers("none [heartbeat] timer mmc0\n");
+        assert_eq!(available, vec!["none", "heartbeat", "timer", "mmc0"]);
+        assert_eq!(current.as_deref(), Some("heartbeat"));
+    }
+
+    #[test]
+    fn test_list_leds() {
+        let tmp_dir = TempDir::new("leds").unwrap();
+        create_led(tmp_dir.path(), "green:status", "[none] timer");
+        create_led(tmp_dir.path(), "blue:power", "none [default-on]");
+
+        let leds = list_leds_in(tmp_dir.path()).unwrap();
+        assert_eq!(leds.len(), 2);
+        assert_eq!(leds[0].name, "blue:power");
+        assert_eq!(leds[0].trigger.as_deref(), Some("default-on"));
+        assert_eq!(leds[1].color.as_deref(), Some("green"));
+        assert_eq!(leds[1].function.as_deref(), Some("status"));
+        assert_eq!(leds[1].max_brightness, 255);
+    }
+
+    #[test]
+    fn test_set_netdev_trigger() {
+        let tmp_dir = TempDir::new("leds").unwrap();
+        let path = create_led(tmp_dir.path(), "green:lan", "[none] netdev timer");
+        let led = LedDevice::from_path(&path);
+
+        led.set_trigger(&LedTrigger::Netdev {
+            device: "eth0".to_string(),
+            link: true,
+            rx: false,
+            tx: true,
+            interval: Some(50),
+        })
+        .unwrap();
+
+        assert_eq!(fs::read_to_string(path.join("trigger")).unwrap(), "netdev");
+        assert_eq!(fs::read_to_string(path.join("device_name")).unwrap(), "eth0");
+        assert_eq!(fs::read_to_string(path.join("link")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(path.join("rx")).unwrap(), "0");
+        assert_eq!(fs::read_to_string(path.join("interval")).unwrap(), "50");
+    }
+
+    #[test]
+    fn test_set_unsupported_trigger() {
+        let tmp_dir = TempDir::new("leds").unwrap();
+        let path = create_led(tmp_dir.path(), "green:status", "[none] timer");
+        let led = LedDevice::from_path(&path);
+
+        assert!(led.set_trigger(&LedTrigger::Heartbeat).is_err());
+        assert_eq!(
+            fs::read_to_string(path.join("trigger")).unwrap(),
+            "[none] timer"
+        );
+    }
+}
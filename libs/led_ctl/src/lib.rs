@@ -2,5 +2,17 @@
 mod led;
 pub use led::{LedControl, LedColor};
 
+mod color;
+pub use color::RgbColor;
+
+mod pattern;
+pub use pattern::{Keyframe, LedPattern, LedPatternEngine, PatternBackend};
+
+mod indicator;
+pub use indicator::{IndicatorOutput, IndicatorState, StatusIndicator};
+
+mod led_device;
+pub use led_device::{list_leds, list_leds_in, LedDevice, LedInfo, LedTrigger, LED_CLASS_PATH};
+
 mod errors;
 pub use errors::{LedctlError, LedctlErrorCodes};
\ No newline at end of file
@@ -0,0 +1,575 @@
+use crate::color::RgbColor;
+use crate::errors::{LedctlError, LedctlErrorCodes};
+use crate::led::{LedColor, LedControl};
+use anyhow::{bail, Result};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex};
+use tokio::task::AbortHandle;
+use tracing::{error as trace_error, info, instrument, trace};
+
+// number of brightness steps used for each half of a software breathe cycle
+const BREATHE_STEPS: u32 = 32;
+
+// kernel trigger used when no pattern is running
+const NO_TRIGGER: &str = "none";
+const TIMER_TRIGGER: &str = "timer";
+const PATTERN_TRIGGER: &str = "pattern";
+
+// A color held for a fixed duration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keyframe {
+    pub color: RgbColor,
+    pub duration: Duration,
+}
+
+impl Keyframe {
+    pub fn new(color: RgbColor, duration: Duration) -> Self {
+        Keyframe { color, duration }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LedPattern {
+    // switch between the color and off
+    Blink {
+        color: RgbColor,
+        on: Duration,
+        off: Duration,
+    },
+    // ramp the color up and down again over one period
+    Breathe {
+        color: RgbColor,
+        period: Duration,
+    },
+    // show each color in turn
+    ColorCycle {
+        colors: Vec<RgbColor>,
+        interval: Duration,
+    },
+    // arbitrary list of keyframes
+    Sequence {
+        keyframes: Vec<Keyframe>,
+    },
+}
+
+impl LedPattern {
+    // Check that the pattern can be played
+    pub fn validate(&self) -> Result<()> {
+        let keyframes = self.keyframes();
+        if keyframes.is_empty() {
+            bail!(LedctlError::new(
+                LedctlErrorCodes::InvalidLedPatternError,
+                "led pattern has no keyframes".to_string(),
+            ));
+        }
+        if keyframes.iter().all(|keyframe| keyframe.duration.is_zero()) {
+            bail!(LedctlError::new(
+                LedctlErrorCodes::InvalidLedPatternError,
+                "led pattern has zero duration".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Keyframes played by the software engine for one iteration of the pattern
+    pub fn keyframes(&self) -> Vec<Keyframe> {
+        match self {
+            LedPattern::Blink { color, on, off } => {
+                vec![
+                    Keyframe::new(*color, *on),
+                    Keyframe::new(RgbColor::OFF, *off),
+                ]
+            }
+            LedPattern::Breathe { color, period } => {
+                let step = *period / (2 * BREATHE_STEPS);
+                (0..BREATHE_STEPS)
+                    .chain((1..=BREATHE_STEPS).rev())
+                    .map(|level| Keyframe::new(color.scaled(level, BREATHE_STEPS), step))
+                    .collect()
+            }
+            LedPattern::ColorCycle { colors, interval } => colors
+                .iter()
+                .map(|color| Keyframe::new(*color, *interval))
+                .collect(),
+            LedPattern::Sequence { keyframes } => keyframes.clone(),
+        }
+    }
+
+    // Only color shown by the pattern besides off, if there is one
+    pub fn single_color(&self) -> Option<RgbColor> {
+        let mut colors = self
+            .keyframes()
+            .into_iter()
+            .map(|keyframe| keyframe.color)
+            .filter(|color| *color != RgbColor::OFF);
+        let first = colors.next()?;
+        match self {
+            // the ramp is generated from one color
+            LedPattern::Breathe { color, .. } => Some(*color),
+            _ => colors.all(|color| color == first).then_some(first),
+        }
+    }
+
+    // Pattern for the kernel `pattern` trigger of a single led, as
+    // `brightness duration` pairs. The kernel ramps linearly between entries,
+    // so every keyframe is written twice to hold its brightness.
+    fn kernel_pattern(&self, brightness: impl Fn(RgbColor) -> u32) -> String {
+        let entries: Vec<(u32, u128)> = match self {
+            LedPattern::Breathe { color, period } => {
+                let half = (*period / 2).as_millis();
+                vec![
+                    (brightness(RgbColor::OFF), half),
+                    (brightness(*color), half),
+                ]
+            }
+            _ => self
+                .keyframes()
+                .iter()
+                .flat_map(|keyframe| {
+                    let value = brightness(keyframe.color);
+                    [(value, keyframe.duration.as_millis()), (value, 0)]
+                })
+                .collect(),
+        };
+
+        entries
+            .iter()
+            .map(|(brightness, duration)| format!("{} {}", brightness, duration))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+// Where a started pattern is being played
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternBackend {
+    // offloaded to the kernel `timer` or `pattern` led trigger
+    Kernel,
+    // played by a task writing brightness values
+    Software,
+}
+
+#[derive(Debug)]
+struct RunningPattern {
+    abort: AbortHandle,
+    // closed once the playback task has exited
+    finished: watch::Receiver<()>,
+}
+
+// Plays led patterns, a newly started pattern preempts the running one
+#[derive(Debug)]
+pub struct LedPatternEngine {
+    led: Arc<LedControl>,
+    running: Mutex<Option<RunningPattern>>,
+}
+
+impl LedPatternEngine {
+    pub fn new(led: Arc<LedControl>) -> Self {
+        trace!(task = "led_pattern_engine instance", "init");
+        LedPatternEngine {
+            led,
+            running: Mutex::new(None),
+        }
+    }
+
+    // Start a pattern, playing it `repeat` times or until stopped when `None`
+    #[instrument(skip(self))]
+    pub async fn start(&self, pattern: LedPattern, repeat: Option<u32>) -> Result<PatternBackend> {
+        trace!(task = "start_pattern", "init");
+        pattern.validate()?;
+
+        let trigger = self.kernel_trigger(&pattern, repeat);
+
+        let mut running = self.running.lock().await;
+        Self::cancel(running.take()).await;
+        self.reset_triggers()?;
+
+        if let Some(trigger) = trigger {
+            self.offload(&pattern, repeat, trigger)?;
+            info!(
+                task = "start_pattern",
+                "pattern offloaded to {} trigger", trigger
+            );
+            return Ok(PatternBackend::Kernel);
+        }
+
+        let (finished_tx, finished_rx) = watch::channel(());
+        let led = self.led.clone();
+        let keyframes = pattern.keyframes();
+        let handle = tokio::spawn(async move {
+            Self::play(led, keyframes, repeat).await;
+            drop(finished_tx);
+        });
+
+        *running = Some(RunningPattern {
+            abort: handle.abort_handle(),
+            finished: finished_rx,
+        });
+        info!(task = "start_pattern", "pattern started in software");
+        Ok(PatternBackend::Software)
+    }
+
+    // Stop any running pattern and switch the leds off
+    #[instrument(skip(self))]
+    pub async fn stop(&self) -> Result<()> {
+        trace!(task = "stop_pattern", "init");
+        self.set_color(RgbColor::OFF).await?;
+        info!(task = "stop_pattern", "pattern stopped");
+        Ok(())
+    }
+
+    // Stop any running pattern and show a static color
+    #[instrument(skip(self))]
+    pub async fn set_color(&self, color: RgbColor) -> Result<()> {
+        trace!(task = "set_color", "init");
+        let mut running = self.running.lock().await;
+        Self::cancel(running.take()).await;
+        self.reset_triggers()?;
+        self.led.set_led(color.red, color.green, color.blue)
+    }
+
+    // Stop any running pattern and switch off the given colors, the other
+    // colors keep their current brightness
+    #[instrument(skip(self))]
+    pub async fn clear_channels(&self, colors: &[LedColor]) -> Result<()> {
+        trace!(task = "clear_channels", "init");
+        let mut running = self.running.lock().await;
+        Self::cancel(running.take()).await;
+        self.reset_triggers()?;
+        for color in colors {
+            self.led.clear_led(*color)?;
+        }
+        Ok(())
+    }
+
+    // Wait until the running software pattern has finished playing
+    pub async fn wait(&self) {
+        let finished = self
+            .running
+            .lock()
+            .await
+            .as_ref()
+            .map(|running| running.finished.clone());
+
+        if let Some(mut finished) = finished {
+            while finished.changed().await.is_ok() {}
+        }
+    }
+
+    async fn cancel(running: Option<RunningPattern>) {
+        if let Some(mut running) = running {
+            running.abort.abort();
+            // wait for the task to exit so it cannot overwrite the next pattern
+            while running.finished.changed().await.is_ok() {}
+        }
+    }
+
+    // Put every led back under manual control if a trigger is available
+    fn reset_triggers(&self) -> Result<()> {
+        for color in LedColor::ALL {
+            if self.led.supports_trigger(color, NO_TRIGGER) {
+                self.led.set_trigger(color, NO_TRIGGER)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Kernel trigger able to play the pattern on every led, if any
+    fn kernel_trigger(&self, pattern: &LedPattern, repeat: Option<u32>) -> Option<&'static str> {
+        // a multicolor led has one trigger, its color has to stay fixed
+        if self.led.is_multicolor() && pattern.single_color().is_none() {
+            return None;
+        }
+
+        let supported = |trigger: &str| {
+            LedColor::ALL
+                .iter()
+                .all(|color| self.led.supports_trigger(*color, trigger))
+        };
+
+        // the timer trigger blinks forever, it has no repeat count
+        if matches!(pattern, LedPattern::Blink { .. })
+            && repeat.is_none()
+            && supported(TIMER_TRIGGER)
+        {
+            return Some(TIMER_TRIGGER);
+        }
+        if supported(PATTERN_TRIGGER) {
+            return Some(PATTERN_TRIGGER);
+        }
+        None
+    }
+
+    // Hand the pattern to the kernel trigger on every led
+    fn offload(&self, pattern: &LedPattern, repeat: Option<u32>, trigger: &str) -> Result<()> {
+        if self.led.is_multicolor() {
+            // set the color through multi_intensity and let the trigger drive brightness
+            let color = pattern.single_color().unwrap_or_default();
+            self.led.set_led(color.red, color.green, color.blue)?;
+            let max_brightness = self.led.max_brightness(LedColor::Red);
+            return self.offload_led(LedColor::Red, pattern, repeat, trigger, |rgb| {
+                if rgb == RgbColor::OFF {
+                    0
+                } else {
+                    max_brightness
+                }
+            });
+        }
+
+        for channel in LedColor::ALL {
+            self.offload_led(channel, pattern, repeat, trigger, |rgb| {
+                self.led.scale(channel, rgb.channel(channel))
+            })?;
+        }
+        Ok(())
+    }
+
+    fn offload_led(
+        &self,
+        channel: LedColor,
+        pattern: &LedPattern,
+        repeat: Option<u32>,
+        trigger: &str,
+        brightness: impl Fn(RgbColor) -> u32,
+    ) -> Result<()> {
+        self.led.set_trigger(channel, trigger)?;
+        match pattern {
+            LedPattern::Blink { color, on, off } if trigger == TIMER_TRIGGER => {
+                self.led
+                    .write_attribute(channel, "delay_on", &on.as_millis().to_string())?;
+                self.led
+                    .write_attribute(channel, "delay_off", &off.as_millis().to_string())?;
+                // a non zero brightness sets the blink brightness of the timer trigger
+                self.led.write_raw_brightness(channel, brightness(*color))?;
+            }
+            _ => {
+                let repeat = repeat.map_or(-1, |repeat| repeat as i64);
+                self.led.write_attribute(
+                    channel,
+                    "pattern",
+                    &pattern.kernel_pattern(brightness),
+                )?;
+                self.led
+                    .write_attribute(channel, "repeat", &repeat.to_string())?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn play(led: Arc<LedControl>, keyframes: Vec<Keyframe>, repeat: Option<u32>) {
+        let mut iteration = 0;
+        while repeat.is_none_or(|repeat| iteration < repeat) {
+            for keyframe in &keyframes {
+                let color = keyframe.color;
+                if let Err(e) = led.set_led(color.red, color.green, color.blue) {
+                    trace_error!(task = "play_pattern", "unable to play pattern: {}", e);
+                    return;
+                }
+                tokio::time::sleep(keyframe.duration).await;
+            }
+            iteration += 1;
+        }
+        info!(task = "play_pattern", "pattern finished");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use tempdir::TempDir;
+
+    fn led_control(dir: &Path) -> LedControl {
+        let mut paths = Vec::new();
+        for name in ["red", "green", "blue"] {
+            let led_dir = dir.join(name);
+            fs::create_dir(&led_dir).unwrap();
+            paths.push(led_dir.join("brightness"));
+        }
+        LedControl::new(
+            paths[0].to_str().unwrap(),
+            paths[1].to_str().unwrap(),
+            paths[2].to_str().unwrap(),
+        )
+    }
+
+    fn brightness(dir: &Path, name: &str) -> String {
+        fs::read_to_string(dir.join(name).join("brightness")).unwrap()
+    }
+
+    #[test]
+    fn test_blink_keyframes() {
+        let pattern = LedPattern::Blink {
+            color: RgbColor::new(255, 0, 0),
+            on: Duration::from_millis(100),
+            off: Duration::from_millis(200),
+        };
+        assert_eq!(
+            pattern.keyframes(),
+            vec![
+                Keyframe::new(RgbColor::new(255, 0, 0), Duration::from_millis(100)),
+                Keyframe::new(RgbColor::OFF, Duration::from_millis(200)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_breathe_keyframes_ramp() {
+        let pattern = LedPattern::Breathe {
+            color: RgbColor::new(0, 128, 0),
+            period: Duration::from_millis(640),
+        };
+        let keyframes = pattern.keyframes();
+        assert_eq!(keyframes.len(), 2 * BREATHE_STEPS as usize);
+        assert_eq!(keyframes[0].color, RgbColor::OFF);
+        assert_eq!(
+            keyframes[BREATHE_STEPS as usize].color,
+            RgbColor::new(0, 128, 0)
+        );
+        assert_eq!(keyframes[0].duration, Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let pattern = LedPattern::ColorCycle {
+            colors: vec![],
+            interval: Duration::from_millis(100),
+        };
+        assert!(pattern.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_software_pattern_runs_to_completion() {
+        let tmp_dir = TempDir::new("led").unwrap();
+        let engine = LedPatternEngine::new(Arc::new(led_control(tmp_dir.path())));
+
+        let pattern = LedPattern::Sequence {
+            keyframes: vec![
+                Keyframe::new(RgbColor::new(1, 2, 3), Duration::from_millis(5)),
+                Keyframe::new(RgbColor::new(4, 5, 6), Duration::from_millis(5)),
+            ],
+        };
+        let backend = engine.start(pattern, Some(2)).await.unwrap();
+        assert_eq!(backend, PatternBackend::Software);
+
+        engine.wait().await;
+        assert_eq!(brightness(tmp_dir.path(), "red"), "4");
+        assert_eq!(brightness(tmp_dir.path(), "green"), "5");
+        assert_eq!(brightness(tmp_dir.path(), "blue"), "6");
+    }
+
+    #[tokio::test]
+    async fn test_new_pattern_preempts_running_one() {
+        let tmp_dir = TempDir::new("led").unwrap();
+        let engine = LedPatternEngine::new(Arc::new(led_control(tmp_dir.path())));
+
+        let forever = LedPattern::Blink {
+            color: RgbColor::new(9, 9, 9),
+            on: Duration::from_millis(5),
+            off: Duration::from_millis(5),
+        };
+        engine.start(forever, None).await.unwrap();
+
+        let once = LedPattern::Sequence {
+            keyframes: vec![Keyframe::new(
+                RgbColor::new(7, 0, 0),
+                Duration::from_millis(5),
+            )],
+        };
+        engine.start(once, Some(1)).await.unwrap();
+        engine.wait().await;
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(brightness(tmp_dir.path(), "red"), "7");
+        assert_eq!(brightness(tmp_dir.path(), "green"), "0");
+    }
+
+    #[tokio::test]
+    async fn test_stop_clears_leds() {
+        let tmp_dir = TempDir::new("led").unwrap();
+        let engine = LedPatternEngine::new(Arc::new(led_control(tmp_dir.path())));
+
+        let pattern = LedPattern::Blink {
+            color: RgbColor::new(1, 1, 1),
+            on: Duration::from_millis(5),
+            off: Duration::from_millis(5),
+        };
+        engine.start(pattern, None).await.unwrap();
+        engine.stop().await.unwrap();
+
+        assert_eq!(brightness(tmp_dir.path(), "red"), "0");
+        assert_eq!(brightness(tmp_dir.path(), "blue"), "0");
+    }
+
+    #[tokio::test]
+    async fn test_pattern_offloaded_to_kernel() {
+        let tmp_dir = TempDir::new("led").unwrap();
+        let led = led_control(tmp_dir.path());
+        for name in ["red", "green", "blue"] {
+            fs::write(
+                tmp_dir.path().join(name).join("trigger"),
+                "[none] timer pattern",
+            )
+            .unwrap();
+        }
+        let engine = LedPatternEngine::new(Arc::new(led));
+
+        let pattern = LedPattern::Sequence {
+            keyframes: vec![
+                Keyframe::new(RgbColor::new(255, 0, 0), Duration::from_millis(100)),
+                Keyframe::new(RgbColor::OFF, Duration::from_millis(50)),
+            ],
+        };
+        let backend = engine.start(pattern, Some(3)).await.unwrap();
+        assert_eq!(backend, PatternBackend::Kernel);
+
+        let red = tmp_dir.path().join("red");
+        assert_eq!(fs::read_to_string(red.join("trigger")).unwrap(), "pattern");
+        assert_eq!(
+            fs::read_to_string(red.join("pattern")).unwrap(),
+            "255 100 255 0 0 50 0 0"
+        );
+        assert_eq!(fs::read_to_string(red.join("repeat")).unwrap(), "3");
+    }
+
+    #[tokio::test]
+    async fn test_multicolor_pattern_offloaded_with_fixed_color() {
+        let tmp_dir = TempDir::new("led").unwrap();
+        let led_dir = tmp_dir.path();
+        fs::write(led_dir.join("trigger"), "[none] timer pattern").unwrap();
+        fs::write(led_dir.join("max_brightness"), "10").unwrap();
+        fs::write(led_dir.join("multi_index"), "red green blue").unwrap();
+        fs::write(led_dir.join("multi_intensity"), "0 0 0").unwrap();
+        let brightness = led_dir.join("brightness");
+        let engine = LedPatternEngine::new(Arc::new(LedControl::new_multicolor(
+            brightness.to_str().unwrap(),
+        )));
+
+        let pattern = LedPattern::Breathe {
+            color: RgbColor::new(255, 0, 255),
+            period: Duration::from_millis(1000),
+        };
+        let backend = engine.start(pattern, None).await.unwrap();
+        assert_eq!(backend, PatternBackend::Kernel);
+        assert_eq!(
+            fs::read_to_string(led_dir.join("multi_intensity")).unwrap(),
+            "10 0 10"
+        );
+        assert_eq!(
+            fs::read_to_string(led_dir.join("pattern")).unwrap(),
+            "0 500 10 500"
+        );
+        assert_eq!(fs::read_to_string(led_dir.join("repeat")).unwrap(), "-1");
+
+        // colors changing over time have to be played in software
+        fs::write(led_dir.join("trigger"), "[none] timer pattern").unwrap();
+        let pattern = LedPattern::ColorCycle {
+            colors: vec![RgbColor::new(255, 0, 0), RgbColor::new(0, 0, 255)],
+            interval: Duration::from_millis(5),
+        };
+        let backend = engine.start(pattern, Some(1)).await.unwrap();
+        assert_eq!(backend, PatternBackend::Software);
+    }
+}
@@ -0,0 +1,309 @@
+use crate::errors::{MotionSensorControlError, MotionSensorControlErrorCodes};
+use crate::iio_device::ChannelType;
+use anyhow::{bail, Result};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::{error as trace_error, info, instrument, trace};
+
+pub const SAMPLING_FREQUENCY: &str = "sampling_frequency";
+pub const OVERSAMPLING_RATIO: &str = "oversampling_ratio";
+
+// Values listed in an `_available` attribute, either every value or a
+// `[min step max]` range
+#[derive(Debug, Clone, PartialEq)]
+pub enum AvailableValues {
+    List(Vec<String>),
+    Range { min: f64, step: f64, max: f64 },
+}
+
+impl AvailableValues {
+    pub fn contains(&self, value: &str) -> bool {
+        match self {
+            AvailableValues::List(values) => values.iter().any(|available| {
+                match (available.parse::<f64>(), value.parse::<f64>()) {
+                    (Ok(available), Ok(value)) => (available - value).abs() < 1e-6,
+                    _ => available == value,
+                }
+            }),
+            AvailableValues::Range { min, step, max } => match value.parse::<f64>() {
+                Ok(value) if value >= *min && value <= *max => {
+                    let steps = (value - min) / step;
+                    *step <= 0.0 || (steps - steps.round()).abs() < 1e-6
+                }
+                _ => false,
+            },
+        }
+    }
+}
+
+impl FromStr for AvailableValues {
+    type Err = anyhow::Error;
+
+    fn from_str(content: &str) -> Result<Self> {
+        let content = content.trim();
+        match content
+            .strip_prefix('[')
+            .and_then(|range| range.strip_suffix(']'))
+        {
+            Some(range) => {
+                let values = range
+                    .split_whitespace()
+                    .map(f64::from_str)
+                    .collect::<Result<Vec<f64>, _>>();
+                match values.as_deref() {
+                    Ok([min, step, max]) => Ok(AvailableValues::Range {
+                        min: *min,
+                        step: *step,
+                        max: *max,
+                    }),
+                    _ => bail!(MotionSensorControlError::new(
+                        MotionSensorControlErrorCodes::UnableToParseValue,
+                        format!("invalid range of available values: {}", content),
+                    )),
+                }
+            }
+            None => Ok(AvailableValues::List(
+                content.split_whitespace().map(str::to_string).collect(),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AvailableValues {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AvailableValues::List(values) => write!(f, "{}", values.join(" ")),
+            AvailableValues::Range { min, step, max } => write!(f, "[{} {} {}]", min, step, max),
+        }
+    }
+}
+
+// A device attribute such as `sampling_frequency`, either of one channel type
+// (`in_accel_sampling_frequency`) or shared by the whole device
+#[derive(Debug, Clone, PartialEq)]
+pub struct IioAttribute {
+    pub name: String,
+    path: PathBuf,
+    available_path: Option<PathBuf>,
+}
+
+impl IioAttribute {
+    // Attribute `name` of `device_path`, the one of `channel_type` is preferred
+    // over the shared one
+    pub fn find(device_path: &Path, name: &str, channel_type: Option<ChannelType>) -> Option<Self> {
+        let mut candidates = Vec::new();
+        if let Some(channel_type) = channel_type {
+            candidates.push(format!("in_{}_{}", channel_type.prefix(), name));
+        }
+        candidates.push(name.to_string());
+
+        candidates.into_iter().find_map(|file_name| {
+            let path = device_path.join(&file_name);
+            if !path.exists() {
+                return None;
+            }
+            let available_path = device_path.join(format!("{}_available", file_name));
+            Some(IioAttribute {
+                name: file_name,
+                path,
+                available_path: available_path.exists().then_some(available_path),
+            })
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn read(&self) -> Result<String> {
+        read_attribute(&self.path)
+    }
+
+    // Values the driver accepts, `None` when it does not list them
+    pub fn available(&self) -> Result<Option<AvailableValues>> {
+        match &self.available_path {
+            Some(path) => Ok(Some(read_attribute(path)?.parse()?)),
+            None => Ok(None),
+        }
+    }
+
+    // Write `value` after checking it against the available values
+    #[instrument(skip(self))]
+    pub fn write(&self, value: &str) -> Result<()> {
+        trace!(task = "write_attribute", "init");
+        if let Some(available) = self.available()? {
+            if !available.contains(value) {
+                bail!(MotionSensorControlError::new(
+                    MotionSensorControlErrorCodes::UnsupportedAttributeValue,
+                    format!(
+                        "{} is not supported by {}, available: {}",
+                        value, self.name, available
+                    ),
+                ))
+            }
+        }
+        write_attribute(&self.path, value)?;
+        info!(task = "write_attribute", "{} set to {}", self.name, value);
+        Ok(())
+    }
+}
+
+// Runtime power management of the device, `device/power/control` of the bus
+// device the sensor sits on, or `power/control` of the iio device itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerControl {
+    // suspended by the kernel while idle
+    Auto,
+    // kept powered
+    On,
+}
+
+impl PowerControl {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PowerControl::Auto => "auto",
+            PowerControl::On => "on",
+        }
+    }
+
+    fn path(device_path: &Path) -> PathBuf {
+        // the driver of the parent device is the one suspending the sensor
+        let parent = device_path.join("device").join("power").join("control");
+        match parent.exists() {
+            true => parent,
+            false => device_path.join("power").join("control"),
+        }
+    }
+
+    pub fn read(device_path: &Path) -> Result<Self> {
+        read_attribute(&Self::path(device_path))?.parse()
+    }
+
+    #[instrument]
+    pub fn write(device_path: &Path, power_control: PowerControl) -> Result<()> {
+        trace!(task = "write_power_control", "init");
+        write_attribute(&Self::path(device_path), power_control.as_str())
+    }
+}
+
+impl FromStr for PowerControl {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim() {
+            "auto" => Ok(PowerControl::Auto),
+            "on" => Ok(PowerControl::On),
+            value => bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnsupportedAttributeValue,
+                format!("unknown power control: {}", value),
+            )),
+        }
+    }
+}
+
+fn read_attribute(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(value) => Ok(value.trim().to_string()),
+        Err(e) => {
+            trace_error!(
+                task = "read_attribute",
+                "unable to read {}: {}",
+                path.display(),
+                e
+            );
+            bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnableToOpenFile,
+                format!("unable to read {}: {}", path.display(), e),
+            ))
+        }
+    }
+}
+
+fn write_attribute(path: &Path, value: &str) -> Result<()> {
+    match fs::write(path, value) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            trace_error!(
+                task = "write_attribute",
+                "unable to write {} to {}: {}",
+                value,
+                path.display(),
+                e
+            );
+            bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnableToWriteAttribute,
+                format!("unable to write {} to {}: {}", value, path.display(), e),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_available_values() {
+        let list: AvailableValues = "12.500000 26 52 104\n".parse().unwrap();
+        assert!(list.contains("26"));
+        assert!(list.contains("12.5"));
+        assert!(!list.contains("50"));
+
+        let range: AvailableValues = "[0.5 0.5 100]".parse().unwrap();
+        assert_eq!(
+            range,
+            AvailableValues::Range {
+                min: 0.5,
+                step: 0.5,
+                max: 100.0
+            }
+        );
+        assert!(range.contains("20"));
+        assert!(!range.contains("20.25"));
+        assert!(!range.contains("200"));
+        assert!("[1 2]".parse::<AvailableValues>().is_err());
+    }
+
+    #[test]
+    fn test_attribute() {
+        let dir = TempDir::new("iio").unwrap();
+        fs::write(dir.path().join("sampling_frequency"), "100").unwrap();
+        fs::write(dir.path().join("in_accel_sampling_frequency"), "52").unwrap();
+        fs::write(
+            dir.path().join("in_accel_sampling_frequency_available"),
+            "26 52 104",
+        )
+        .unwrap();
+
+        let attribute =
+            IioAttribute::find(dir.path(), SAMPLING_FREQUENCY, Some(ChannelType::Accel)).unwrap();
+        assert_eq!(attribute.name, "in_accel_sampling_frequency");
+        assert_eq!(attribute.read().unwrap(), "52");
+        attribute.write("104").unwrap();
+        assert_eq!(attribute.read().unwrap(), "104");
+        assert!(attribute.write("100").is_err());
+
+        let shared =
+            IioAttribute::find(dir.path(), SAMPLING_FREQUENCY, Some(ChannelType::Magn)).unwrap();
+        assert_eq!(shared.name, "sampling_frequency");
+        assert_eq!(shared.available().unwrap(), None);
+        assert!(IioAttribute::find(dir.path(), OVERSAMPLING_RATIO, None).is_none());
+
+        fs::create_dir(dir.path().join("power")).unwrap();
+        PowerControl::write(dir.path(), PowerControl::On).unwrap();
+        assert_eq!(PowerControl::read(dir.path()).unwrap(), PowerControl::On);
+
+        // the parent device's power control is preferred
+        fs::create_dir_all(dir.path().join("device").join("power")).unwrap();
+        fs::write(dir.path().join("device/power/control"), "auto").unwrap();
+        assert_eq!(PowerControl::read(dir.path()).unwrap(), PowerControl::Auto);
+        PowerControl::write(dir.path(), PowerControl::On).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("device/power/control")).unwrap(),
+            "on"
+        );
+    }
+}
@@ -0,0 +1,623 @@
+use crate::detector::wait_readable;
+use crate::errors::{MotionSensorControlError, MotionSensorControlErrorCodes};
+use crate::iio_device::IioChannel;
+use anyhow::{anyhow, bail, Error, Result};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio_stream::Stream;
+use tracing::{error as trace_error, info, instrument, trace};
+
+const TIMESTAMP_CHANNEL: &str = "timestamp";
+// Number of samples requested from the character device per read
+const SAMPLES_PER_READ: usize = 16;
+// Longest wait for samples before checking whether the stream was dropped
+const READ_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Layout of a channel inside a buffered sample, parsed from the
+// `scan_elements/in_<channel>_type` descriptor, e.g. `le:s12/16>>4`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanType {
+    pub big_endian: bool,
+    pub signed: bool,
+    pub bits: u8,
+    pub storage_bits: u8,
+    pub shift: u8,
+    pub repeat: u8,
+}
+
+impl ScanType {
+    pub fn storage_bytes(&self) -> usize {
+        self.storage_bits as usize / 8
+    }
+
+    // Decode a single value from `storage_bytes` bytes
+    pub fn decode(&self, bytes: &[u8]) -> i64 {
+        let bytes = &bytes[..self.storage_bytes()];
+        let raw = if self.big_endian {
+            bytes
+                .iter()
+                .fold(0u64, |value, byte| (value << 8) | *byte as u64)
+        } else {
+            bytes
+                .iter()
+                .rev()
+                .fold(0u64, |value, byte| (value << 8) | *byte as u64)
+        };
+
+        let value = raw >> self.shift;
+        if self.bits >= 64 {
+            return value as i64;
+        }
+        let value = value & ((1u64 << self.bits) - 1);
+        if self.signed {
+            // sign extend from `bits`
+            let unused = 64 - self.bits as u32;
+            ((value << unused) as i64) >> unused
+        } else {
+            value as i64
+        }
+    }
+}
+
+impl FromStr for ScanType {
+    type Err = Error;
+
+    fn from_str(descriptor: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::InvalidScanType,
+                format!("invalid scan element type: {}", descriptor),
+            ))
+        };
+
+        let descriptor = descriptor.trim();
+        let (endianness, format) = descriptor.split_once(':').ok_or_else(invalid)?;
+        let big_endian = match endianness {
+            "be" => true,
+            "le" => false,
+            _ => bail!(invalid()),
+        };
+        let signed = match format.chars().next() {
+            Some('s') => true,
+            Some('u') => false,
+            _ => bail!(invalid()),
+        };
+        let (sizes, shift) = format[1..].split_once(">>").ok_or_else(invalid)?;
+        let (bits, storage) = sizes.split_once('/').ok_or_else(invalid)?;
+        let (storage_bits, repeat) = match storage.split_once('X') {
+            Some((storage_bits, repeat)) => (storage_bits, repeat),
+            None => (storage, "1"),
+        };
+
+        let scan_type = ScanType {
+            big_endian,
+            signed,
+            bits: bits.parse().map_err(|_| invalid())?,
+            storage_bits: storage_bits.parse().map_err(|_| invalid())?,
+            shift: shift.parse().map_err(|_| invalid())?,
+            repeat: repeat.parse().map_err(|_| invalid())?,
+        };
+        if scan_type.storage_bits == 0
+            || !scan_type.storage_bits.is_multiple_of(8)
+            || scan_type.storage_bits > 64
+            || scan_type.bits > scan_type.storage_bits
+            || scan_type.repeat == 0
+        {
+            bail!(invalid());
+        }
+        Ok(scan_type)
+    }
+}
+
+// Enabled channel and where it sits in a buffered sample
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanElement {
+    pub name: String,
+    pub index: u32,
+    pub scan_type: ScanType,
+    pub offset: usize,
+}
+
+// Compute offsets of the enabled elements, each element is aligned to its
+// storage size and the sample to the largest one. Returns the sample size.
+fn layout_elements(elements: &mut [ScanElement]) -> usize {
+    elements.sort_by_key(|element| element.index);
+    let mut offset = 0usize;
+    let mut largest = 1;
+    for element in elements.iter_mut() {
+        let storage_bytes = element.scan_type.storage_bytes();
+        offset = offset.div_ceil(storage_bytes) * storage_bytes;
+        element.offset = offset;
+        offset += storage_bytes * element.scan_type.repeat as usize;
+        largest = largest.max(storage_bytes);
+    }
+    offset.div_ceil(largest) * largest
+}
+
+// Buffered sampling options
+#[derive(Debug, Clone)]
+pub struct BufferConfig {
+    // trigger written to `trigger/current_trigger`, e.g. `iio-trig-hrtimer0`,
+    // `None` keeps the trigger already selected for the device
+    pub trigger: Option<String>,
+    // number of samples the kernel buffer holds
+    pub length: u32,
+    // character device to read, defaults to `/dev/<iio:deviceN>`
+    pub device_node: Option<PathBuf>,
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        BufferConfig {
+            trigger: None,
+            length: 128,
+            device_node: None,
+        }
+    }
+}
+
+// Converted values of one scan, all channels captured at the same time
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotionSample {
+    // nanoseconds, from the timestamp channel when the device has one
+    pub timestamp: i64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+// IIO device configured for buffered capture of a set of channels,
+// the buffer is disabled again when dropped
+#[derive(Debug)]
+pub struct IioBuffer {
+    device_path: PathBuf,
+    device_node: PathBuf,
+    elements: Vec<ScanElement>,
+    sample_bytes: usize,
+}
+
+impl IioBuffer {
+    // Enable `channels` (e.g. `anglvel_x`) plus the timestamp channel and
+    // start the buffer of the device at `device_path`
+    #[instrument]
+    pub fn enable(device_path: &Path, channels: &[String], config: &BufferConfig) -> Result<Self> {
+        trace!(task = "enable_buffer", "init");
+        let device_node = match &config.device_node {
+            Some(device_node) => device_node.clone(),
+            None => Path::new("/dev").join(device_path.file_name().unwrap_or_default()),
+        };
+        let mut buffer = IioBuffer {
+            device_path: device_path.to_path_buf(),
+            device_node,
+            elements: Vec::new(),
+            sample_bytes: 0,
+        };
+
+        // the buffer has to be stopped to change its configuration
+        buffer.write_attribute("buffer/enable", "0")?;
+        for (name, enabled) in buffer.scan_elements()? {
+            let wanted = channels.contains(&name) || name == TIMESTAMP_CHANNEL;
+            if wanted != enabled {
+                buffer.write_attribute(
+                    &format!("scan_elements/in_{}_en", name),
+                    if wanted { "1" } else { "0" },
+                )?;
+            }
+        }
+
+        let mut elements = Vec::new();
+        for channel in channels
+            .iter()
+            .map(String::as_str)
+            .chain(buffer.has_timestamp().then_some(TIMESTAMP_CHANNEL))
+        {
+            let index = buffer.read_attribute(&format!("scan_elements/in_{}_index", channel))?;
+            let scan_type = buffer.read_attribute(&format!("scan_elements/in_{}_type", channel))?;
+            elements.push(ScanElement {
+                name: channel.to_string(),
+                index: match index.parse() {
+                    Ok(index) => index,
+                    Err(e) => bail!(MotionSensorControlError::new(
+                        MotionSensorControlErrorCodes::UnableToParseValue,
+                        format!("unable to parse {} scan index: {}", channel, e),
+                    )),
+                },
+                scan_type: scan_type.parse()?,
+                offset: 0,
+            });
+        }
+        buffer.sample_bytes = layout_elements(&mut elements);
+        buffer.elements = elements;
+
+        if let Some(trigger) = &config.trigger {
+            buffer.write_attribute("trigger/current_trigger", trigger)?;
+        }
+        buffer.write_attribute("buffer/length", &config.length.to_string())?;
+        buffer.write_attribute("buffer/enable", "1")?;
+        info!(
+            task = "enable_buffer",
+            "buffer enabled on {} with {} byte samples",
+            buffer.device_path.display(),
+            buffer.sample_bytes
+        );
+        Ok(buffer)
+    }
+
+    pub fn elements(&self) -> &[ScanElement] {
+        &self.elements
+    }
+
+    pub fn sample_bytes(&self) -> usize {
+        self.sample_bytes
+    }
+
+    // Decode one packed sample into the values of the requested channels, in
+    // the order they were requested, and the timestamp
+    pub fn decode(&self, channels: &[String], sample: &[u8]) -> (Vec<i64>, Option<i64>) {
+        let value = |name: &str| {
+            self.elements
+                .iter()
+                .find(|element| element.name == name)
+                .map(|element| element.scan_type.decode(&sample[element.offset..]))
+        };
+        (
+            channels
+                .iter()
+                .map(|channel| value(channel).unwrap_or_default())
+                .collect(),
+            value(TIMESTAMP_CHANNEL),
+        )
+    }
+
+    pub fn open_device_node(&self) -> Result<File> {
+        match File::open(&self.device_node) {
+            Ok(file) => Ok(file),
+            Err(e) => {
+                trace_error!(
+                    task = "open_device_node",
+                    "unable to open {}: {}",
+                    self.device_node.display(),
+                    e
+                );
+                bail!(MotionSensorControlError::new(
+                    MotionSensorControlErrorCodes::UnableToOpenFile,
+                    format!("unable to open {}: {}", self.device_node.display(), e),
+                ))
+            }
+        }
+    }
+
+    fn has_timestamp(&self) -> bool {
+        self.device_path
+            .join(format!("scan_elements/in_{}_en", TIMESTAMP_CHANNEL))
+            .exists()
+    }
+
+    // Channel names under `scan_elements` and whether they are enabled
+    fn scan_elements(&self) -> Result<Vec<(String, bool)>> {
+        let entries = match fs::read_dir(self.device_path.join("scan_elements")) {
+            Ok(entries) => entries,
+            Err(e) => {
+                trace_error!(
+                    task = "scan_elements",
+                    "unable to list scan elements: {}",
+                    e
+                );
+                bail!(MotionSensorControlError::new(
+                    MotionSensorControlErrorCodes::UnableToConfigureBuffer,
+                    format!(
+                        "{} does not support buffered capture: {}",
+                        self.device_path.display(),
+                        e
+                    ),
+                ))
+            }
+        };
+
+        let mut elements = Vec::new();
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if let Some(name) = file_name
+                .strip_prefix("in_")
+                .and_then(|name| name.strip_suffix("_en"))
+            {
+                let enabled = self.read_attribute(&format!("scan_elements/{}", file_name))? == "1";
+                elements.push((name.to_string(), enabled));
+            }
+        }
+        Ok(elements)
+    }
+
+    fn read_attribute(&self, attribute: &str) -> Result<String> {
+        let path = self.device_path.join(attribute);
+        match fs::read_to_string(&path) {
+            Ok(value) => Ok(value.trim().to_string()),
+            Err(e) => {
+                trace_error!(
+                    task = "read_attribute",
+                    "unable to read {}: {}",
+                    path.display(),
+                    e
+                );
+                bail!(MotionSensorControlError::new(
+                    MotionSensorControlErrorCodes::UnableToOpenFile,
+                    format!("unable to read {}: {}", path.display(), e),
+                ))
+            }
+        }
+    }
+
+    fn write_attribute(&self, attribute: &str, value: &str) -> Result<()> {
+        let path = self.device_path.join(attribute);
+        if let Err(e) = fs::write(&path, value) {
+            trace_error!(
+                task = "write_attribute",
+                "unable to write {}: {}",
+                path.display(),
+                e
+            );
+            bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnableToConfigureBuffer,
+                format!("unable to write {}: {}", path.display(), e),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for IioBuffer {
+    fn drop(&mut self) {
+        let _ = self.write_attribute("buffer/enable", "0");
+    }
+}
+
+// Stream of buffered samples, reading stops and the buffer is disabled once
+// the stream is dropped
+pub struct MotionSampleStream {
+    receiver: mpsc::Receiver<Result<MotionSample>>,
+}
+
+impl MotionSampleStream {
+    // `channels` are the x, y and z channels enabled on `buffer`
+    pub(crate) fn start(buffer: IioBuffer, channels: Vec<IioChannel>) -> Result<Self> {
+        trace!(task = "motion_sample_stream", "init");
+        let names: Vec<String> = channels.iter().map(IioChannel::name).collect();
+        let mut device_node = buffer.open_device_node()?;
+        let (sender, receiver) = mpsc::channel(SAMPLES_PER_READ * 4);
+
+        std::thread::spawn(move || {
+            let sample_bytes = buffer.sample_bytes();
+            let mut data = vec![0u8; sample_bytes * SAMPLES_PER_READ];
+            let mut filled = 0;
+            'read: loop {
+                if sender.is_closed() {
+                    // stream dropped
+                    break 'read;
+                }
+                match wait_readable(
+                    &device_node,
+                    READ_POLL_INTERVAL,
+                    MotionSensorControlErrorCodes::UnableToReadBuffer,
+                ) {
+                    Ok(true) => {}
+                    Ok(false) => continue 'read,
+                    Err(e) => {
+                        trace_error!(task = "motion_sample_stream", "unable to poll: {}", e);
+                        let _ = sender.blocking_send(Err(e));
+                        break 'read;
+                    }
+                }
+                let read = match device_node.read(&mut data[filled..]) {
+                    // end of file only happens for regular files, the device node blocks
+                    Ok(0) => break 'read,
+                    Ok(read) => read,
+                    Err(e) => {
+                        trace_error!(task = "motion_sample_stream", "unable to read: {}", e);
+                        let _ = sender.blocking_send(Err(anyhow!(MotionSensorControlError::new(
+                            MotionSensorControlErrorCodes::UnableToReadBuffer,
+                            format!("unable to read buffer: {}", e),
+                        ))));
+                        break 'read;
+                    }
+                };
+                filled += read;
+
+                let complete = filled / sample_bytes * sample_bytes;
+                for sample in data[..complete].chunks_exact(sample_bytes) {
+                    let (values, timestamp) = buffer.decode(&names, sample);
+                    let value = |axis: usize| channels[axis].convert(values[axis] as f64);
+                    let sample = MotionSample {
+                        timestamp: timestamp.unwrap_or_else(now_ns),
+                        x: value(0),
+                        y: value(1),
+                        z: value(2),
+                    };
+                    if sender.blocking_send(Ok(sample)).is_err() {
+                        // stream dropped
+                        break 'read;
+                    }
+                }
+                // keep a partially read sample for the next read
+                data.copy_within(complete..filled, 0);
+                filled -= complete;
+            }
+            // disable capture before the stream reports its end
+            drop(buffer);
+        });
+
+        Ok(MotionSampleStream { receiver })
+    }
+
+    pub async fn next(&mut self) -> Option<Result<MotionSample>> {
+        self.receiver.recv().await
+    }
+
+    // Wait for the next sample outside of an async context
+    pub fn blocking_next(&mut self) -> Option<Result<MotionSample>> {
+        self.receiver.blocking_recv()
+    }
+}
+
+impl Stream for MotionSampleStream {
+    type Item = Result<MotionSample>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+pub(crate) fn now_ns() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_nanos() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    // Fake IIO device with the given `(channel, index, type)` scan elements
+    pub(crate) fn create_device(root: &Path, elements: &[(&str, u32, &str)]) -> PathBuf {
+        let device_path = root.join("iio:device1");
+        fs::create_dir_all(device_path.join("scan_elements")).unwrap();
+        fs::create_dir_all(device_path.join("buffer")).unwrap();
+        fs::create_dir_all(device_path.join("trigger")).unwrap();
+        for (name, index, scan_type) in elements {
+            let scan_elements = device_path.join("scan_elements");
+            fs::write(scan_elements.join(format!("in_{}_en", name)), "0").unwrap();
+            fs::write(
+                scan_elements.join(format!("in_{}_index", name)),
+                index.to_string(),
+            )
+            .unwrap();
+            fs::write(scan_elements.join(format!("in_{}_type", name)), scan_type).unwrap();
+        }
+        device_path
+    }
+
+    #[test]
+    fn test_parse_scan_type() {
+        assert_eq!(
+            "le:s12/16>>4".parse::<ScanType>().unwrap(),
+            ScanType {
+                big_endian: false,
+                signed: true,
+                bits: 12,
+                storage_bits: 16,
+                shift: 4,
+                repeat: 1,
+            }
+        );
+        let repeated = "be:u16/16X3>>0\n".parse::<ScanType>().unwrap();
+        assert!(repeated.big_endian);
+        assert_eq!(repeated.repeat, 3);
+        assert!("le:s12/12>>0".parse::<ScanType>().is_err());
+        assert!("xx:s16/16>>0".parse::<ScanType>().is_err());
+    }
+
+    #[test]
+    fn test_decode_scan_type() {
+        let scan_type = "le:s12/16>>4".parse::<ScanType>().unwrap();
+        // -1 in the upper 12 bits
+        assert_eq!(scan_type.decode(&[0xf0, 0xff]), -1);
+        let scan_type = "be:s16/16>>0".parse::<ScanType>().unwrap();
+        assert_eq!(scan_type.decode(&[0xff, 0x38]), -200);
+        let scan_type = "le:u10/16>>0".parse::<ScanType>().unwrap();
+        assert_eq!(scan_type.decode(&[0xff, 0xff]), 1023);
+    }
+
+    #[test]
+    fn test_layout_elements() {
+        let element = |name: &str, index, scan_type: &str| ScanElement {
+            name: name.to_string(),
+            index,
+            scan_type: scan_type.parse().unwrap(),
+            offset: 0,
+        };
+        let mut elements = vec![
+            element("timestamp", 3, "le:s64/64>>0"),
+            element("accel_x", 0, "le:s16/16>>0"),
+            element("accel_y", 1, "le:s16/16>>0"),
+            element("accel_z", 2, "le:s16/16>>0"),
+        ];
+        assert_eq!(layout_elements(&mut elements), 16);
+        let offsets: Vec<usize> = elements.iter().map(|element| element.offset).collect();
+        assert_eq!(offsets, vec![0, 2, 4, 8]);
+    }
+
+    #[tokio::test]
+    async fn test_buffered_samples() {
+        let tmp_dir = TempDir::new("iio").unwrap();
+        let device_path = create_device(
+            tmp_dir.path(),
+            &[
+                ("anglvel_x", 0, "le:s16/16>>0"),
+                ("anglvel_y", 1, "le:s16/16>>0"),
+                ("anglvel_z", 2, "le:s16/16>>0"),
+                ("temp", 3, "le:s16/16>>0"),
+                ("timestamp", 4, "le:s64/64>>0"),
+            ],
+        );
+
+        // two samples of x, y, z, padding to 8 bytes and the timestamp
+        let mut data = Vec::new();
+        for (values, timestamp) in [([1i16, -2, 3], 1000i64), ([4, 5, -6], 2000)] {
+            for value in values {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            data.extend_from_slice(&[0, 0]);
+            data.extend_from_slice(&timestamp.to_le_bytes());
+        }
+        let device_node = tmp_dir.path().join("device_node");
+        fs::write(&device_node, data).unwrap();
+        fs::write(device_path.join("in_anglvel_scale"), "0.5").unwrap();
+
+        let channels: Vec<String> = ["anglvel_x", "anglvel_y", "anglvel_z"]
+            .iter()
+            .map(|channel| channel.to_string())
+            .collect();
+        let config = BufferConfig {
+            trigger: Some("trigger0".to_string()),
+            device_node: Some(device_node),
+            ..Default::default()
+        };
+        let buffer = IioBuffer::enable(&device_path, &channels, &config).unwrap();
+        assert_eq!(buffer.sample_bytes(), 16);
+        let read = |attribute: &str| fs::read_to_string(device_path.join(attribute)).unwrap();
+        assert_eq!(read("scan_elements/in_anglvel_x_en"), "1");
+        assert_eq!(read("scan_elements/in_temp_en"), "0");
+        assert_eq!(read("scan_elements/in_timestamp_en"), "1");
+        assert_eq!(read("trigger/current_trigger"), "trigger0");
+        assert_eq!(read("buffer/length"), "128");
+        assert_eq!(read("buffer/enable"), "1");
+
+        let channels = channels
+            .iter()
+            .map(|channel| IioChannel::from_path(&device_path.join(format!("in_{}_raw", channel))))
+            .collect::<Result<Vec<IioChannel>>>()
+            .unwrap();
+        let mut stream = MotionSampleStream::start(buffer, channels).unwrap();
+        let first = stream.next().await.unwrap().unwrap();
+        assert_eq!(
+            first,
+            MotionSample {
+                timestamp: 1000,
+                x: 0.5,
+                y: -1.0,
+                z: 1.5
+            }
+        );
+        let second = stream.next().await.unwrap().unwrap();
+        assert_eq!((second.timestamp, second.z), (2000, -3.0));
+        assert!(stream.next().await.is_none());
+        assert_eq!(read("buffer/enable"), "0");
+    }
+}
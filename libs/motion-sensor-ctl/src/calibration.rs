@@ -0,0 +1,629 @@
+use crate::errors::{MotionSensorControlError, MotionSensorControlErrorCodes};
+use crate::iio_device::{ChannelType, IioChannel, IioDevice, Vector3};
+use crate::orientation::STANDARD_GRAVITY;
+use anyhow::{bail, Result};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{error as trace_error, info, instrument, trace};
+
+// Directory calibration files are kept in unless configured otherwise
+pub const CALIBRATION_DIR: &str = "/var/lib/mecha/motion-sensor";
+
+// Corrections measured for one unit, readings are corrected as
+// `(value - offset) * scale` for the accelerometer and `value - bias` for the
+// gyroscope
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calibration {
+    // m/s^2
+    pub accel_offset: Vector3,
+    pub accel_scale: Vector3,
+    // rad/s
+    pub gyro_bias: Vector3,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration {
+            accel_offset: Vector3::default(),
+            accel_scale: Vector3::new(1.0, 1.0, 1.0),
+            gyro_bias: Vector3::default(),
+        }
+    }
+}
+
+impl Calibration {
+    // Bias and gain for a channel, channels that are not calibrated are left as is
+    pub fn correction(&self, channel: &IioChannel) -> (f64, f64) {
+        let index = match channel.axis.as_deref() {
+            Some("x") => 0,
+            Some("y") => 1,
+            Some("z") => 2,
+            _ => return (0.0, 1.0),
+        };
+        match channel.channel_type {
+            ChannelType::Accel => (
+                component(&self.accel_offset, index),
+                component(&self.accel_scale, index),
+            ),
+            ChannelType::Anglvel => (component(&self.gyro_bias, index), 1.0),
+            _ => (0.0, 1.0),
+        }
+    }
+
+    // Calibration file of `device` within `directory`
+    pub fn path(directory: &Path, device: &IioDevice) -> PathBuf {
+        let name = match device.name() {
+            Some(name) => name.to_string(),
+            None => device
+                .path()
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
+        directory.join(format!("{}.calibration", name))
+    }
+
+    // Read a calibration file, `None` when there is none
+    #[instrument]
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        trace!(task = "load_calibration", "init");
+        if !path.exists() {
+            return Ok(None);
+        }
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Some(content.parse()?)),
+            Err(e) => {
+                trace_error!(
+                    task = "load_calibration",
+                    "unable to read {}: {}",
+                    path.display(),
+                    e
+                );
+                bail!(MotionSensorControlError::new(
+                    MotionSensorControlErrorCodes::UnableToOpenFile,
+                    format!("unable to read {}: {}", path.display(), e),
+                ))
+            }
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub fn save(&self, path: &Path) -> Result<()> {
+        trace!(task = "save_calibration", "init");
+        let written = match path.parent() {
+            Some(directory) => fs::create_dir_all(directory),
+            None => Ok(()),
+        }
+        .and_then(|_| fs::write(path, self.to_string()));
+        match written {
+            Ok(_) => {
+                info!(
+                    task = "save_calibration",
+                    "calibration saved to {}",
+                    path.display()
+                );
+                Ok(())
+            }
+            Err(e) => {
+                trace_error!(
+                    task = "save_calibration",
+                    "unable to write {}: {}",
+                    path.display(),
+                    e
+                );
+                bail!(MotionSensorControlError::new(
+                    MotionSensorControlErrorCodes::UnableToOpenFile,
+                    format!("unable to write {}: {}", path.display(), e),
+                ))
+            }
+        }
+    }
+}
+
+// One `<name> <x> <y> <z>` line per vector
+impl fmt::Display for Calibration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, vector) in [
+            ("accel_offset", &self.accel_offset),
+            ("accel_scale", &self.accel_scale),
+            ("gyro_bias", &self.gyro_bias),
+        ] {
+            writeln!(f, "{} {} {} {}", name, vector.x, vector.y, vector.z)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Calibration {
+    type Err = anyhow::Error;
+
+    fn from_str(content: &str) -> Result<Self> {
+        let invalid = |line: &str| {
+            MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::InvalidCalibration,
+                format!("invalid calibration line: {}", line),
+            )
+        };
+        let mut calibration = Calibration::default();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let name = fields.next().unwrap_or_default();
+            let values = match fields.map(f64::from_str).collect::<Result<Vec<f64>, _>>() {
+                Ok(values) if values.len() == 3 => values,
+                _ => bail!(invalid(line)),
+            };
+            let vector = Vector3::new(values[0], values[1], values[2]);
+            match name {
+                "accel_offset" => calibration.accel_offset = vector,
+                "accel_scale" => calibration.accel_scale = vector,
+                "gyro_bias" => calibration.gyro_bias = vector,
+                _ => bail!(invalid(line)),
+            }
+        }
+        Ok(calibration)
+    }
+}
+
+// Calibration in use by `MotionSensorControl`, offsets of channels with a
+// `calibbias` attribute are corrected by the sensor instead of in software
+#[derive(Debug, Default)]
+pub(crate) struct CalibrationState {
+    pub(crate) calibration: Calibration,
+    hardware_bias: Vec<String>,
+}
+
+impl CalibrationState {
+    // Write the offsets of `device` to `calibbias` where supported, the
+    // attribute is taken to be in raw counts of the channel as it is for most
+    // drivers
+    pub(crate) fn new(calibration: Calibration, device: Option<&IioDevice>) -> Self {
+        let mut hardware_bias = Vec::new();
+        for channel in device.map(IioDevice::channels).unwrap_or_default() {
+            let (bias, _) = calibration.correction(channel);
+            let path = match channel.calibbias_path() {
+                Some(path) => path,
+                None => continue,
+            };
+            let raw = -channel.to_raw(bias).round() as i64;
+            match fs::write(&path, raw.to_string()) {
+                Ok(_) => hardware_bias.push(channel.name()),
+                Err(e) => trace_error!(
+                    task = "calibration",
+                    "unable to write {}, correcting in software: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+        CalibrationState {
+            calibration,
+            hardware_bias,
+        }
+    }
+
+    pub(crate) fn apply(&self, channel: &mut IioChannel) {
+        let (bias, gain) = self.calibration.correction(channel);
+        if self.hardware_bias.contains(&channel.name()) {
+            channel.set_correction(0.0, gain);
+        } else {
+            channel.set_correction(bias, gain);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CalibrationConfig {
+    // samples averaged per measurement
+    pub samples: usize,
+    pub sample_interval: Duration,
+    // largest standard deviation of the samples for the device to count as
+    // held still, m/s^2 for the accelerometer and rad/s for the gyroscope
+    pub max_accel_noise: f64,
+    pub max_gyro_noise: f64,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        CalibrationConfig {
+            samples: 100,
+            sample_interval: Duration::from_millis(10),
+            max_accel_noise: 0.3,
+            max_gyro_noise: 0.05,
+        }
+    }
+}
+
+// Positions the device is held in for the accelerometer calibration, each
+// with one axis pointing straight up or down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationPose {
+    XUp,
+    XDown,
+    YUp,
+    YDown,
+    ZUp,
+    ZDown,
+}
+
+impl CalibrationPose {
+    pub const ALL: [CalibrationPose; 6] = [
+        CalibrationPose::ZUp,
+        CalibrationPose::ZDown,
+        CalibrationPose::XUp,
+        CalibrationPose::XDown,
+        CalibrationPose::YUp,
+        CalibrationPose::YDown,
+    ];
+
+    fn axis(&self) -> usize {
+        match self {
+            CalibrationPose::XUp | CalibrationPose::XDown => 0,
+            CalibrationPose::YUp | CalibrationPose::YDown => 1,
+            CalibrationPose::ZUp | CalibrationPose::ZDown => 2,
+        }
+    }
+
+    fn is_up(&self) -> bool {
+        matches!(
+            self,
+            CalibrationPose::XUp | CalibrationPose::YUp | CalibrationPose::ZUp
+        )
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            CalibrationPose::XUp => "x axis pointing up",
+            CalibrationPose::XDown => "x axis pointing down",
+            CalibrationPose::YUp => "y axis pointing up",
+            CalibrationPose::YDown => "y axis pointing down",
+            CalibrationPose::ZUp => "z axis pointing up (lying flat, face up)",
+            CalibrationPose::ZDown => "z axis pointing down (lying flat, face down)",
+        }
+    }
+}
+
+fn component(vector: &Vector3, index: usize) -> f64 {
+    match index {
+        0 => vector.x,
+        1 => vector.y,
+        _ => vector.z,
+    }
+}
+
+// Mean of the samples and the largest standard deviation of the three axes
+fn mean_and_noise(samples: &[Vector3]) -> (Vector3, f64) {
+    let count = samples.len().max(1) as f64;
+    let mean = |index| samples.iter().map(|s| component(s, index)).sum::<f64>() / count;
+    let mean = Vector3::new(mean(0), mean(1), mean(2));
+    let noise = (0..3)
+        .map(|index| {
+            let average = component(&mean, index);
+            let variance = samples
+                .iter()
+                .map(|s| (component(s, index) - average).powi(2))
+                .sum::<f64>()
+                / count;
+            variance.sqrt()
+        })
+        .fold(0.0, f64::max);
+    (mean, noise)
+}
+
+// Accelerometer offset and scale from the mean reading of every pose, an axis
+// reads +g pointing up and -g pointing down once corrected
+pub fn accel_calibration(poses: &[(CalibrationPose, Vector3)]) -> Result<(Vector3, Vector3)> {
+    let mut offset = [0.0; 3];
+    let mut scale = [1.0; 3];
+    for axis in 0..3 {
+        let reading = |up: bool| {
+            poses
+                .iter()
+                .find(|(pose, _)| pose.axis() == axis && pose.is_up() == up)
+                .map(|(_, mean)| component(mean, axis))
+        };
+        let (up, down) = match (reading(true), reading(false)) {
+            (Some(up), Some(down)) if up > down => (up, down),
+            (Some(_), Some(_)) => bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnableToCalibrate,
+                format!("opposite poses of axis {} read the same direction", axis),
+            )),
+            _ => bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnableToCalibrate,
+                format!("both poses of axis {} are needed", axis),
+            )),
+        };
+        offset[axis] = (up + down) / 2.0;
+        scale[axis] = 2.0 * STANDARD_GRAVITY / (up - down);
+    }
+    Ok((
+        Vector3::new(offset[0], offset[1], offset[2]),
+        Vector3::new(scale[0], scale[1], scale[2]),
+    ))
+}
+
+// Guided calibration of one device: the gyroscope bias is measured with the
+// device held still, the accelerometer from the six `CalibrationPose`s
+#[derive(Debug)]
+pub struct Calibrator {
+    device: IioDevice,
+    config: CalibrationConfig,
+    gyro_bias: Option<Vector3>,
+    poses: Vec<(CalibrationPose, Vector3)>,
+}
+
+impl Calibrator {
+    // Any `calibbias` the sensor applies is cleared so samples are uncorrected
+    #[instrument(skip(device))]
+    pub fn new(mut device: IioDevice, config: CalibrationConfig) -> Result<Self> {
+        trace!(task = "calibrator", "init");
+        if !device.has_channel_type(ChannelType::Accel)
+            && !device.has_channel_type(ChannelType::Anglvel)
+        {
+            bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnableToCalibrate,
+                format!(
+                    "no accelerometer or gyroscope on {}",
+                    device.path().display()
+                ),
+            ))
+        }
+        for channel in device.channels_mut() {
+            channel.set_correction(0.0, 1.0);
+            if let Some(path) = channel.calibbias_path() {
+                if let Err(e) = fs::write(&path, "0") {
+                    bail!(MotionSensorControlError::new(
+                        MotionSensorControlErrorCodes::UnableToCalibrate,
+                        format!("unable to clear {}: {}", path.display(), e),
+                    ))
+                }
+            }
+        }
+        Ok(Calibrator {
+            device,
+            config,
+            gyro_bias: None,
+            poses: Vec::new(),
+        })
+    }
+
+    pub fn needs_gyro_bias(&self) -> bool {
+        self.device.has_channel_type(ChannelType::Anglvel) && self.gyro_bias.is_none()
+    }
+
+    // Poses still to be measured, none without an accelerometer
+    pub fn remaining_poses(&self) -> Vec<CalibrationPose> {
+        if !self.device.has_channel_type(ChannelType::Accel) {
+            return Vec::new();
+        }
+        CalibrationPose::ALL
+            .into_iter()
+            .filter(|pose| !self.poses.iter().any(|(measured, _)| measured == pose))
+            .collect()
+    }
+
+    // Average `samples` readings, failing when the device was not held still
+    fn measure(&self, channel_type: ChannelType, max_noise: f64) -> Result<Vector3> {
+        let mut samples = Vec::with_capacity(self.config.samples);
+        for _ in 0..self.config.samples {
+            match self.device.read_vector(channel_type)? {
+                Some(sample) => samples.push(sample),
+                None => bail!(MotionSensorControlError::new(
+                    MotionSensorControlErrorCodes::UnknownChannel,
+                    format!(
+                        "no {} channels on {}",
+                        channel_type.prefix(),
+                        self.device.path().display()
+                    ),
+                )),
+            }
+            std::thread::sleep(self.config.sample_interval);
+        }
+        let (mean, noise) = mean_and_noise(&samples);
+        if noise > max_noise {
+            bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnableToCalibrate,
+                format!(
+                    "device moved while measuring {} (noise {:.3} {})",
+                    channel_type.prefix(),
+                    noise,
+                    channel_type.unit()
+                ),
+            ))
+        }
+        Ok(mean)
+    }
+
+    #[instrument(skip(self))]
+    pub fn measure_gyro_bias(&mut self) -> Result<Vector3> {
+        trace!(task = "measure_gyro_bias", "init");
+        let bias = self.measure(ChannelType::Anglvel, self.config.max_gyro_noise)?;
+        info!(task = "measure_gyro_bias", "gyroscope bias: {:?}", bias);
+        self.gyro_bias = Some(bias);
+        Ok(bias)
+    }
+
+    // Measure the accelerometer in `pose`, measuring a pose again replaces it
+    #[instrument(skip(self))]
+    pub fn measure_pose(&mut self, pose: CalibrationPose) -> Result<Vector3> {
+        trace!(task = "measure_pose", "init");
+        let mean = self.measure(ChannelType::Accel, self.config.max_accel_noise)?;
+
+        let axis = pose.axis();
+        let reading = component(&mean, axis);
+        let dominant =
+            (0..3).all(|other| other == axis || component(&mean, other).abs() < reading.abs());
+        let expected = if pose.is_up() { 1.0 } else { -1.0 } * STANDARD_GRAVITY;
+        if !dominant || (reading - expected).abs() > 0.5 * STANDARD_GRAVITY {
+            bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnableToCalibrate,
+                format!(
+                    "device is not held with the {}, read {:.2} {:.2} {:.2}",
+                    pose.description(),
+                    mean.x,
+                    mean.y,
+                    mean.z
+                ),
+            ))
+        }
+
+        info!(task = "measure_pose", "{:?}: {:?}", pose, mean);
+        self.poses.retain(|(measured, _)| *measured != pose);
+        self.poses.push((pose, mean));
+        Ok(mean)
+    }
+
+    // Calibration from everything measured so far
+    pub fn finish(&self) -> Result<Calibration> {
+        if self.needs_gyro_bias() {
+            bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnableToCalibrate,
+                "gyroscope bias has not been measured".to_string(),
+            ))
+        }
+        let mut calibration = Calibration {
+            gyro_bias: self.gyro_bias.unwrap_or_default(),
+            ..Default::default()
+        };
+        if self.device.has_channel_type(ChannelType::Accel) {
+            let (offset, scale) = accel_calibration(&self.poses)?;
+            calibration.accel_offset = offset;
+            calibration.accel_scale = scale;
+        }
+        Ok(calibration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn write(path: &Path, attribute: &str, value: &str) {
+        fs::write(path.join(attribute), value).unwrap();
+    }
+
+    // reading of an accelerometer with `offset` and `gain` held in `pose`
+    fn reading(pose: CalibrationPose, offset: Vector3, gain: Vector3) -> Vector3 {
+        let mut gravity = [0.0; 3];
+        gravity[pose.axis()] = if pose.is_up() { 1.0 } else { -1.0 } * STANDARD_GRAVITY;
+        Vector3::new(
+            gravity[0] * gain.x + offset.x,
+            gravity[1] * gain.y + offset.y,
+            gravity[2] * gain.z + offset.z,
+        )
+    }
+
+    #[test]
+    fn test_accel_calibration() {
+        let offset = Vector3::new(0.2, -0.1, 0.35);
+        let gain = Vector3::new(1.02, 0.97, 1.05);
+        let poses: Vec<(CalibrationPose, Vector3)> = CalibrationPose::ALL
+            .into_iter()
+            .map(|pose| (pose, reading(pose, offset, gain)))
+            .collect();
+
+        let (measured_offset, scale) = accel_calibration(&poses).unwrap();
+        assert!((measured_offset.x - 0.2).abs() < 1e-9);
+        assert!((measured_offset.y + 0.1).abs() < 1e-9);
+        assert!((measured_offset.z - 0.35).abs() < 1e-9);
+        assert!((scale.x - 1.0 / 1.02).abs() < 1e-9);
+        assert!((scale.z - 1.0 / 1.05).abs() < 1e-9);
+
+        assert!(accel_calibration(&poses[1..]).is_err());
+    }
+
+    #[test]
+    fn test_calibration_file() {
+        let calibration = Calibration {
+            accel_offset: Vector3::new(0.25, -0.125, 0.5),
+            accel_scale: Vector3::new(1.0, 0.99, 1.01),
+            gyro_bias: Vector3::new(0.001, -0.002, 0.003),
+        };
+        let dir = TempDir::new("calibration").unwrap();
+        let path = dir.path().join("lsm6dsl").join("lsm6dsl.calibration");
+        assert_eq!(Calibration::load(&path).unwrap(), None);
+
+        calibration.save(&path).unwrap();
+        assert_eq!(Calibration::load(&path).unwrap(), Some(calibration));
+
+        assert!("accel_offset 1 2".parse::<Calibration>().is_err());
+        assert!("gyro 1 2 3".parse::<Calibration>().is_err());
+    }
+
+    #[test]
+    fn test_calibration_state() {
+        let dir = TempDir::new("iio").unwrap();
+        write(dir.path(), "in_anglvel_scale", "0.001");
+        write(dir.path(), "in_anglvel_x_calibbias", "0");
+        for axis in ["x", "y", "z"] {
+            write(dir.path(), &format!("in_anglvel_{}_raw", axis), "10");
+        }
+        let mut device = IioDevice::discover(dir.path()).unwrap();
+        let calibration = Calibration {
+            gyro_bias: Vector3::new(0.004, 0.002, 0.0),
+            ..Default::default()
+        };
+
+        let state = CalibrationState::new(calibration, Some(&device));
+        // x is corrected by the sensor, y in software
+        assert_eq!(
+            fs::read_to_string(dir.path().join("in_anglvel_x_calibbias")).unwrap(),
+            "-4"
+        );
+        for channel in device.channels_mut() {
+            state.apply(channel);
+        }
+        let reading = device.read_vector(ChannelType::Anglvel).unwrap().unwrap();
+        assert!((reading.x - 0.01).abs() < 1e-9);
+        assert!((reading.y - 0.008).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_calibrator() {
+        let dir = TempDir::new("iio").unwrap();
+        write(dir.path(), "name", "lsm6dsl");
+        write(dir.path(), "in_accel_scale", "0.01");
+        write(dir.path(), "in_anglvel_scale", "0.001");
+        write(dir.path(), "in_anglvel_x_calibbias", "12");
+        for (name, value) in [
+            ("accel_x", "2"),
+            ("accel_y", "-1"),
+            ("accel_z", "1000"),
+            ("anglvel_x", "3"),
+            ("anglvel_y", "-2"),
+            ("anglvel_z", "1"),
+        ] {
+            write(dir.path(), &format!("in_{}_raw", name), value);
+        }
+
+        let device = IioDevice::discover(dir.path()).unwrap();
+        let config = CalibrationConfig {
+            samples: 3,
+            sample_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut calibrator = Calibrator::new(device, config).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("in_anglvel_x_calibbias")).unwrap(),
+            "0"
+        );
+        assert!(calibrator.needs_gyro_bias());
+        assert_eq!(calibrator.remaining_poses().len(), 6);
+        assert!(calibrator.finish().is_err());
+
+        let bias = calibrator.measure_gyro_bias().unwrap();
+        assert!((bias.x - 0.003).abs() < 1e-9);
+        assert!(!calibrator.needs_gyro_bias());
+
+        calibrator.measure_pose(CalibrationPose::ZUp).unwrap();
+        // z pointing up is not x pointing up
+        assert!(calibrator.measure_pose(CalibrationPose::XUp).is_err());
+        assert_eq!(calibrator.remaining_poses().len(), 5);
+    }
+}
@@ -0,0 +1,614 @@
+use crate::buffer::{now_ns, MotionSample, MotionSampleStream};
+use crate::errors::{MotionSensorControlError, MotionSensorControlErrorCodes};
+use crate::iio_device::{IioChannel, Vector3};
+use anyhow::{anyhow, bail, Result};
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::Stream;
+use tracing::{error as trace_error, info, instrument, trace};
+
+// _IOR('i', 0x90, int), returns a file descriptor delivering IIO events
+const IIO_GET_EVENT_FD_IOCTL: libc::c_ulong = 0x8004_6990;
+// size of `struct iio_event_data`, a u64 event id and an s64 timestamp
+const IIO_EVENT_SIZE: usize = 16;
+// longest time a watcher waits before checking whether its stream was dropped
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct MotionDetectorConfig {
+    // magnitude of the filtered signal that counts as motion, in the unit of
+    // the channel (m/s^2 for accel, rad/s for anglvel)
+    pub threshold: f64,
+    // cutoff of the high-pass filter removing gravity and sensor bias
+    pub high_pass_cutoff_hz: f64,
+    // time the threshold has to be exceeded before motion is reported
+    pub debounce: Duration,
+    // time below the threshold before the motion is reported as stopped
+    pub quiet_period: Duration,
+    // interval used when the device has no buffer to sample from
+    pub poll_interval: Duration,
+}
+
+impl Default for MotionDetectorConfig {
+    fn default() -> Self {
+        MotionDetectorConfig {
+            threshold: 0.35,
+            high_pass_cutoff_hz: 0.5,
+            debounce: Duration::from_millis(50),
+            quiet_period: Duration::from_secs(2),
+            poll_interval: Duration::from_millis(20),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionEventKind {
+    Started,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionEventSource {
+    // detected from samples by `MotionDetector`
+    Software,
+    // raised by the sensor through the IIO event interface
+    Hardware,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotionEvent {
+    pub kind: MotionEventKind,
+    pub source: MotionEventSource,
+    // nanoseconds
+    pub timestamp: i64,
+    // filtered magnitude that triggered the event, 0 for hardware events
+    pub magnitude: f64,
+}
+
+// First order high-pass filter applied to each axis
+#[derive(Debug, Default)]
+struct HighPassFilter {
+    previous: Option<(i64, Vector3, Vector3)>,
+}
+
+impl HighPassFilter {
+    fn apply(&mut self, cutoff_hz: f64, timestamp: i64, input: Vector3) -> Vector3 {
+        let output = match self.previous {
+            Some((previous_timestamp, previous_input, previous_output)) => {
+                let dt = (timestamp - previous_timestamp).max(0) as f64 / 1e9;
+                let rc = 1.0 / (2.0 * std::f64::consts::PI * cutoff_hz.max(f64::EPSILON));
+                let alpha = rc / (rc + dt);
+                let axis = |output: f64, input: f64, previous_input: f64| {
+                    alpha * (output + input - previous_input)
+                };
+                Vector3::new(
+                    axis(previous_output.x, input.x, previous_input.x),
+                    axis(previous_output.y, input.y, previous_input.y),
+                    axis(previous_output.z, input.z, previous_input.z),
+                )
+            }
+            // nothing to compare the first sample with
+            None => Vector3::default(),
+        };
+        self.previous = Some((timestamp, input, output));
+        output
+    }
+}
+
+// Turns samples or hardware events into motion started/stopped events
+#[derive(Debug)]
+pub struct MotionDetector {
+    config: MotionDetectorConfig,
+    filter: HighPassFilter,
+    moving: bool,
+    above_since: Option<i64>,
+    below_since: Option<i64>,
+    last_hardware_event: i64,
+}
+
+impl MotionDetector {
+    pub fn new(config: MotionDetectorConfig) -> Self {
+        MotionDetector {
+            config,
+            filter: HighPassFilter::default(),
+            moving: false,
+            above_since: None,
+            below_since: None,
+            last_hardware_event: 0,
+        }
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.moving
+    }
+
+    pub fn update(&mut self, sample: &MotionSample) -> Option<MotionEvent> {
+        let filtered = self.filter.apply(
+            self.config.high_pass_cutoff_hz,
+            sample.timestamp,
+            Vector3::new(sample.x, sample.y, sample.z),
+        );
+        let magnitude = filtered.magnitude();
+        let event = |kind| MotionEvent {
+            kind,
+            source: MotionEventSource::Software,
+            timestamp: sample.timestamp,
+            magnitude,
+        };
+
+        if magnitude >= self.config.threshold {
+            self.below_since = None;
+            let above_since = *self.above_since.get_or_insert(sample.timestamp);
+            if !self.moving && elapsed(above_since, sample.timestamp) >= self.config.debounce {
+                self.moving = true;
+                return Some(event(MotionEventKind::Started));
+            }
+        } else {
+            self.above_since = None;
+            if self.moving {
+                let below_since = *self.below_since.get_or_insert(sample.timestamp);
+                if elapsed(below_since, sample.timestamp) >= self.config.quiet_period {
+                    self.moving = false;
+                    self.below_since = None;
+                    return Some(event(MotionEventKind::Stopped));
+                }
+            }
+        }
+        None
+    }
+
+    // A hardware event reports motion, the sensor already applied the
+    // threshold and its own debounce period
+    pub fn hardware_event(&mut self, timestamp: i64) -> Option<MotionEvent> {
+        self.last_hardware_event = timestamp;
+        if self.moving {
+            return None;
+        }
+        self.moving = true;
+        Some(MotionEvent {
+            kind: MotionEventKind::Started,
+            source: MotionEventSource::Hardware,
+            timestamp,
+            magnitude: 0.0,
+        })
+    }
+
+    // Report the end of hardware detected motion once no event arrived for
+    // the quiet period, also returns how long until the next check is due
+    pub fn hardware_quiet(&mut self, now: i64) -> (Option<MotionEvent>, Duration) {
+        if !self.moving {
+            return (None, MAX_POLL_INTERVAL);
+        }
+        let quiet = elapsed(self.last_hardware_event, now);
+        if quiet >= self.config.quiet_period {
+            self.moving = false;
+            let event = MotionEvent {
+                kind: MotionEventKind::Stopped,
+                source: MotionEventSource::Hardware,
+                timestamp: now,
+                magnitude: 0.0,
+            };
+            return (Some(event), MAX_POLL_INTERVAL);
+        }
+        (
+            None,
+            (self.config.quiet_period - quiet).min(MAX_POLL_INTERVAL),
+        )
+    }
+}
+
+fn elapsed(since: i64, now: i64) -> Duration {
+    Duration::from_nanos((now - since).max(0) as u64)
+}
+
+// Motion event of the sensor under `events/`, e.g. `in_accel_mag_rising_en`,
+// disabled again when dropped
+#[derive(Debug)]
+pub struct IioEvents {
+    enable_path: PathBuf,
+    device_node: PathBuf,
+}
+
+impl IioEvents {
+    // Attribute enabling the magnitude rising event of a channel, if the
+    // hardware offers one
+    pub fn find(device_path: &Path, channel: &IioChannel) -> Option<PathBuf> {
+        let prefix = channel.channel_type.prefix();
+        [
+            format!("in_{}_mag_rising_en", prefix),
+            format!("in_{}_x&y&z_mag_rising_en", prefix),
+        ]
+        .iter()
+        .map(|attribute| device_path.join("events").join(attribute))
+        .find(|path| path.exists())
+    }
+
+    // Program the threshold and period and enable the event
+    #[instrument]
+    pub fn enable(
+        enable_path: &Path,
+        device_node: &Path,
+        channel: &IioChannel,
+        config: &MotionDetectorConfig,
+    ) -> Result<Self> {
+        trace!(task = "enable_events", "init");
+        let attribute = |name: &str| {
+            let file_name = enable_path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            enable_path.with_file_name(file_name.replace("_en", name))
+        };
+
+        // the event value is compared against raw counts
+        let scale = if channel.scale != 0.0 {
+            channel.scale
+        } else {
+            1.0
+        };
+        let value_path = attribute("_value");
+        if value_path.exists() {
+            write_attribute(
+                &value_path,
+                &((config.threshold / scale).round() as i64).to_string(),
+            )?;
+        }
+        let period_path = attribute("_period");
+        if period_path.exists() {
+            write_attribute(
+                &period_path,
+                &format!("{:.3}", config.debounce.as_secs_f64()),
+            )?;
+        }
+        write_attribute(enable_path, "1")?;
+        info!(
+            task = "enable_events",
+            "enabled hardware motion event {}",
+            enable_path.display()
+        );
+
+        Ok(IioEvents {
+            enable_path: enable_path.to_path_buf(),
+            device_node: device_node.to_path_buf(),
+        })
+    }
+
+    // Event file descriptor of the device node
+    fn open(&self) -> Result<File> {
+        let device_node = match File::open(&self.device_node) {
+            Ok(device_node) => device_node,
+            Err(e) => bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnableToOpenFile,
+                format!("unable to open {}: {}", self.device_node.display(), e),
+            )),
+        };
+
+        let mut event_fd: libc::c_int = -1;
+        // SAFETY: the ioctl writes a single int to `event_fd`, which outlives the call
+        let result = unsafe {
+            libc::ioctl(
+                device_node.as_raw_fd(),
+                IIO_GET_EVENT_FD_IOCTL as _,
+                &mut event_fd as *mut libc::c_int,
+            )
+        };
+        if result < 0 || event_fd < 0 {
+            bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnableToReadEvents,
+                format!(
+                    "unable to get event fd of {}: {}",
+                    self.device_node.display(),
+                    std::io::Error::last_os_error()
+                ),
+            ));
+        }
+        // SAFETY: the kernel handed us a new descriptor that nothing else owns
+        Ok(unsafe { File::from_raw_fd(event_fd) })
+    }
+}
+
+impl Drop for IioEvents {
+    fn drop(&mut self) {
+        let _ = write_attribute(&self.enable_path, "0");
+    }
+}
+
+fn write_attribute(path: &Path, value: &str) -> Result<()> {
+    if let Err(e) = fs::write(path, value) {
+        trace_error!(
+            task = "write_attribute",
+            "unable to write {}: {}",
+            path.display(),
+            e
+        );
+        bail!(MotionSensorControlError::new(
+            MotionSensorControlErrorCodes::UnableToConfigureEvents,
+            format!("unable to write {}: {}", path.display(), e),
+        ));
+    }
+    Ok(())
+}
+
+// Wait up to `timeout` for `file` to become readable, failures are reported
+// with `code`
+pub(crate) fn wait_readable(
+    file: &File,
+    timeout: Duration,
+    code: MotionSensorControlErrorCodes,
+) -> Result<bool> {
+    let mut poll_fd = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: `poll_fd` is a single valid pollfd for the duration of the call
+    let result = unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) };
+    match result {
+        0 => Ok(false),
+        result if result > 0 => Ok(true),
+        _ => {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+            bail!(MotionSensorControlError::new(
+                code,
+                format!("unable to wait for data: {}", error),
+            ))
+        }
+    }
+}
+
+// Stream of motion events, watching stops once the stream is dropped
+pub struct MotionEventStream {
+    receiver: mpsc::Receiver<Result<MotionEvent>>,
+}
+
+impl MotionEventStream {
+    // Run the detector over buffered samples
+    pub(crate) fn from_samples(
+        mut samples: MotionSampleStream,
+        mut detector: MotionDetector,
+    ) -> Self {
+        trace!(task = "motion_event_stream", "init");
+        let (sender, receiver) = mpsc::channel(16);
+        std::thread::spawn(move || {
+            while let Some(sample) = samples.blocking_next() {
+                let event = match sample {
+                    Ok(sample) => match detector.update(&sample) {
+                        Some(event) => Ok(event),
+                        None => continue,
+                    },
+                    Err(e) => Err(e),
+                };
+                if sender.blocking_send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        MotionEventStream { receiver }
+    }
+
+    // Run the detector over samples returned by `read`, called every
+    // `poll_interval`
+    pub(crate) fn from_polling<F>(
+        read: F,
+        mut detector: MotionDetector,
+        poll_interval: Duration,
+    ) -> Self
+    where
+        F: Fn() -> Result<(f64, f64, f64)> + Send + 'static,
+    {
+        trace!(task = "motion_event_stream", "init");
+        let (sender, receiver) = mpsc::channel(16);
+        std::thread::spawn(move || loop {
+            if sender.is_closed() {
+                break;
+            }
+            let event = match read() {
+                Ok((x, y, z)) => detector
+                    .update(&MotionSample {
+                        timestamp: now_ns(),
+                        x,
+                        y,
+                        z,
+                    })
+                    .map(Ok),
+                Err(e) => Some(Err(e)),
+            };
+            if let Some(event) = event {
+                let failed = event.is_err();
+                if sender.blocking_send(event).is_err() || failed {
+                    break;
+                }
+            }
+            std::thread::sleep(poll_interval);
+        });
+        MotionEventStream { receiver }
+    }
+
+    // Read motion events raised by the sensor itself
+    pub(crate) fn from_events(events: IioEvents, mut detector: MotionDetector) -> Result<Self> {
+        trace!(task = "motion_event_stream", "init");
+        let mut event_file = events.open()?;
+        let (sender, receiver) = mpsc::channel(16);
+        std::thread::spawn(move || {
+            // disables the event once watching stops
+            let _events = events;
+            let mut data = [0u8; IIO_EVENT_SIZE];
+            loop {
+                let (stopped, timeout) = detector.hardware_quiet(now_ns());
+                if let Some(stopped) = stopped {
+                    if sender.blocking_send(Ok(stopped)).is_err() {
+                        break;
+                    }
+                }
+                if sender.is_closed() {
+                    break;
+                }
+
+                match wait_readable(
+                    &event_file,
+                    timeout,
+                    MotionSensorControlErrorCodes::UnableToReadEvents,
+                ) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(e) => {
+                        let _ = sender.blocking_send(Err(e));
+                        break;
+                    }
+                }
+                if let Err(e) = event_file.read_exact(&mut data) {
+                    let _ = sender.blocking_send(Err(anyhow!(MotionSensorControlError::new(
+                        MotionSensorControlErrorCodes::UnableToReadEvents,
+                        format!("unable to read event: {}", e),
+                    ))));
+                    break;
+                }
+                let mut timestamp = [0u8; 8];
+                timestamp.copy_from_slice(&data[8..]);
+                if let Some(started) = detector.hardware_event(i64::from_ne_bytes(timestamp)) {
+                    if sender.blocking_send(Ok(started)).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(MotionEventStream { receiver })
+    }
+
+    pub async fn next(&mut self) -> Option<Result<MotionEvent>> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for MotionEventStream {
+    type Item = Result<MotionEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    const MS: i64 = 1_000_000;
+
+    fn sample(timestamp: i64, x: f64) -> MotionSample {
+        MotionSample {
+            timestamp,
+            x,
+            y: 0.0,
+            z: 9.8,
+        }
+    }
+
+    fn config() -> MotionDetectorConfig {
+        MotionDetectorConfig {
+            threshold: 1.0,
+            high_pass_cutoff_hz: 0.5,
+            debounce: Duration::from_millis(20),
+            quiet_period: Duration::from_millis(100),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_gravity_is_not_motion() {
+        let mut detector = MotionDetector::new(config());
+        for step in 0..100 {
+            // constant gravity and a small amount of noise
+            let noise = if step % 2 == 0 { 0.05 } else { -0.05 };
+            assert!(detector.update(&sample(step * 10 * MS, noise)).is_none());
+        }
+        assert!(!detector.is_moving());
+    }
+
+    #[test]
+    fn test_debounce_and_quiet_period() {
+        let mut detector = MotionDetector::new(config());
+        detector.update(&sample(0, 0.0));
+        // a single spike is shorter than the debounce time
+        assert!(detector.update(&sample(10 * MS, 5.0)).is_none());
+        assert!(detector.update(&sample(20 * MS, 5.0)).is_none());
+
+        let mut events = Vec::new();
+        let mut x = 0.0;
+        for step in 3..10 {
+            // keep shaking so the high-passed signal stays above threshold
+            x = if x > 0.0 { -5.0 } else { 5.0 };
+            events.extend(detector.update(&sample(step * 10 * MS, x)));
+        }
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, MotionEventKind::Started);
+        assert!(events[0].magnitude >= 1.0);
+
+        events.clear();
+        for step in 10..150 {
+            events.extend(detector.update(&sample(step * 10 * MS, x)));
+        }
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, MotionEventKind::Stopped);
+        assert!(events[0].timestamp >= 200 * MS);
+    }
+
+    #[test]
+    fn test_hardware_events() {
+        let mut detector = MotionDetector::new(config());
+        let started = detector.hardware_event(0).unwrap();
+        assert_eq!(started.source, MotionEventSource::Hardware);
+        assert!(detector.hardware_event(50 * MS).is_none());
+
+        let (stopped, timeout) = detector.hardware_quiet(100 * MS);
+        assert!(stopped.is_none());
+        assert_eq!(timeout, Duration::from_millis(50));
+        let (stopped, _) = detector.hardware_quiet(150 * MS);
+        assert_eq!(stopped.unwrap().kind, MotionEventKind::Stopped);
+    }
+
+    #[test]
+    fn test_enable_hardware_events() {
+        let tmp_dir = TempDir::new("iio").unwrap();
+        let device_path = tmp_dir.path();
+        let events_path = device_path.join("events");
+        fs::create_dir(&events_path).unwrap();
+        for attribute in ["en", "value", "period"] {
+            fs::write(
+                events_path.join(format!("in_accel_mag_rising_{}", attribute)),
+                "0",
+            )
+            .unwrap();
+        }
+        fs::write(device_path.join("in_accel_scale"), "0.01").unwrap();
+        let channel = IioChannel::from_path(&device_path.join("in_accel_x_raw")).unwrap();
+
+        let enable_path = IioEvents::find(device_path, &channel).unwrap();
+        let events = IioEvents::enable(
+            &enable_path,
+            &device_path.join("device_node"),
+            &channel,
+            &config(),
+        )
+        .unwrap();
+        let read = |attribute: &str| {
+            fs::read_to_string(events_path.join(format!("in_accel_mag_rising_{}", attribute)))
+                .unwrap()
+        };
+        assert_eq!(read("en"), "1");
+        assert_eq!(read("value"), "100");
+        assert_eq!(read("period"), "0.020");
+
+        drop(events);
+        assert_eq!(read("en"), "0");
+    }
+}
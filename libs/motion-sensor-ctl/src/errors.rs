@@ -5,6 +5,17 @@ pub enum MotionSensorControlErrorCodes {
     Unknown,
     UnableToOpenFile,
     UnableToParseValue,
+    UnableToConfigureBuffer,
+    UnableToReadBuffer,
+    InvalidScanType,
+    UnknownChannel,
+    UnableToConfigureEvents,
+    UnableToReadEvents,
+    UnableToCalibrate,
+    InvalidCalibration,
+    UnknownAttribute,
+    UnsupportedAttributeValue,
+    UnableToWriteAttribute,
 }
 
 impl std::fmt::Display for MotionSensorControlErrorCodes {
@@ -15,6 +26,17 @@ impl std::fmt::Display for MotionSensorControlErrorCodes {
             MotionSensorControlErrorCodes::Unknown => write!(f, "Unknown"),
             MotionSensorControlErrorCodes::UnableToOpenFile => write!(f, "UnableToOpenFile"),
             MotionSensorControlErrorCodes::UnableToParseValue => write!(f, "UnableToParseValue"),
+            MotionSensorControlErrorCodes::UnableToConfigureBuffer => write!(f, "UnableToConfigureBuffer"),
+            MotionSensorControlErrorCodes::UnableToReadBuffer => write!(f, "UnableToReadBuffer"),
+            MotionSensorControlErrorCodes::InvalidScanType => write!(f, "InvalidScanType"),
+            MotionSensorControlErrorCodes::UnknownChannel => write!(f, "UnknownChannel"),
+            MotionSensorControlErrorCodes::UnableToConfigureEvents => write!(f, "UnableToConfigureEvents"),
+            MotionSensorControlErrorCodes::UnableToReadEvents => write!(f, "UnableToReadEvents"),
+            MotionSensorControlErrorCodes::UnableToCalibrate => write!(f, "UnableToCalibrate"),
+            MotionSensorControlErrorCodes::InvalidCalibration => write!(f, "InvalidCalibration"),
+            MotionSensorControlErrorCodes::UnknownAttribute => write!(f, "UnknownAttribute"),
+            MotionSensorControlErrorCodes::UnsupportedAttributeValue => write!(f, "UnsupportedAttributeValue"),
+            MotionSensorControlErrorCodes::UnableToWriteAttribute => write!(f, "UnableToWriteAttribute"),
         }
     }
 }
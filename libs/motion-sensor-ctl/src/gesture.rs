@@ -0,0 +1,240 @@
+use crate::iio_device::Vector3;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct GestureConfig {
+    // interval the accelerometer is read at, taps need a short interval
+    pub sample_interval: Duration,
+    // change in acceleration between two samples that counts as a jolt, m/s^2
+    pub jolt_threshold: f64,
+    // jolts within this time of the first one still belong to the same tap
+    pub tap_ringing: Duration,
+    // a tap is reported once no further jolt followed for this long
+    pub tap_window: Duration,
+    // stillness required before a jolt can start a tap
+    pub tap_quiet: Duration,
+    // number of jolts within `shake_window` that make a shake
+    pub shake_count: usize,
+    pub shake_window: Duration,
+    // total acceleration below which the device is falling, m/s^2
+    pub free_fall_threshold: f64,
+    // time the acceleration has to stay below the threshold
+    pub free_fall_duration: Duration,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        GestureConfig {
+            sample_interval: Duration::from_millis(10),
+            jolt_threshold: 6.0,
+            tap_ringing: Duration::from_millis(50),
+            tap_window: Duration::from_millis(150),
+            tap_quiet: Duration::from_millis(300),
+            shake_count: 6,
+            shake_window: Duration::from_secs(1),
+            free_fall_threshold: 2.0,
+            free_fall_duration: Duration::from_millis(80),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    Tap,
+    Shake,
+    FreeFall,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GestureEvent {
+    pub gesture: Gesture,
+    // nanoseconds
+    pub timestamp: i64,
+}
+
+// Recognises taps, shakes and free fall from accelerometer samples
+#[derive(Debug)]
+pub struct GestureDetector {
+    config: GestureConfig,
+    previous: Option<Vector3>,
+    // recent jolt timestamps, oldest first
+    jolts: VecDeque<i64>,
+    pending_tap: Option<i64>,
+    last_shake: Option<i64>,
+    falling_since: Option<i64>,
+    fall_reported: bool,
+}
+
+impl GestureDetector {
+    pub fn new(config: GestureConfig) -> Self {
+        GestureDetector {
+            config,
+            previous: None,
+            jolts: VecDeque::new(),
+            pending_tap: None,
+            last_shake: None,
+            falling_since: None,
+            fall_reported: false,
+        }
+    }
+
+    pub fn update(&mut self, timestamp: i64, accel: Vector3) -> Option<GestureEvent> {
+        let event = |gesture| Some(GestureEvent { gesture, timestamp });
+
+        if let Some(event) = self.update_free_fall(timestamp, accel) {
+            return Some(event);
+        }
+
+        let jolt = match self.previous.replace(accel) {
+            Some(previous) => {
+                let change = Vector3::new(
+                    accel.x - previous.x,
+                    accel.y - previous.y,
+                    accel.z - previous.z,
+                );
+                change.magnitude() >= self.config.jolt_threshold
+            }
+            None => false,
+        };
+
+        let shake_window = nanos(self.config.shake_window);
+        while self
+            .jolts
+            .front()
+            .is_some_and(|jolt| timestamp - jolt > shake_window)
+        {
+            self.jolts.pop_front();
+        }
+
+        if jolt {
+            let last_jolt = self.jolts.back().copied();
+            self.jolts.push_back(timestamp);
+
+            let in_cooldown = self
+                .last_shake
+                .is_some_and(|last_shake| timestamp - last_shake < shake_window);
+            if self.jolts.len() >= self.config.shake_count && !in_cooldown {
+                self.jolts.clear();
+                self.pending_tap = None;
+                self.last_shake = Some(timestamp);
+                return event(Gesture::Shake);
+            }
+
+            match self.pending_tap {
+                // ringing of the same tap
+                Some(tap) if timestamp - tap <= nanos(self.config.tap_ringing) => {}
+                // a second jolt soon after makes it something other than a tap
+                Some(_) => self.pending_tap = None,
+                None => {
+                    let quiet = last_jolt
+                        .map(|last_jolt| timestamp - last_jolt >= nanos(self.config.tap_quiet))
+                        .unwrap_or(true);
+                    if quiet {
+                        self.pending_tap = Some(timestamp);
+                    }
+                }
+            }
+        }
+
+        if let Some(tap) = self.pending_tap {
+            if timestamp - tap >= nanos(self.config.tap_window) {
+                self.pending_tap = None;
+                return event(Gesture::Tap);
+            }
+        }
+        None
+    }
+
+    fn update_free_fall(&mut self, timestamp: i64, accel: Vector3) -> Option<GestureEvent> {
+        if accel.magnitude() >= self.config.free_fall_threshold {
+            self.falling_since = None;
+            self.fall_reported = false;
+            return None;
+        }
+        let falling_since = *self.falling_since.get_or_insert(timestamp);
+        if !self.fall_reported && timestamp - falling_since >= nanos(self.config.free_fall_duration)
+        {
+            self.fall_reported = true;
+            return Some(GestureEvent {
+                gesture: Gesture::FreeFall,
+                timestamp,
+            });
+        }
+        None
+    }
+}
+
+fn nanos(duration: Duration) -> i64 {
+    duration.as_nanos() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: i64 = 1_000_000;
+    const REST: Vector3 = Vector3 {
+        x: 0.0,
+        y: 0.0,
+        z: 9.8,
+    };
+
+    // feed `accel` for every 10ms step in `steps` and collect the gestures
+    fn run(
+        detector: &mut GestureDetector,
+        steps: std::ops::Range<i64>,
+        accel: impl Fn(i64) -> Vector3,
+    ) -> Vec<Gesture> {
+        steps
+            .filter_map(|step| detector.update(step * 10 * MS, accel(step)))
+            .map(|event| event.gesture)
+            .collect()
+    }
+
+    #[test]
+    fn test_tap() {
+        let mut detector = GestureDetector::new(GestureConfig::default());
+        let gestures = run(&mut detector, 0..100, |step| match step {
+            // one sharp knock followed by its rebound
+            50 => Vector3::new(0.0, 0.0, 20.0),
+            51 => Vector3::new(0.0, 0.0, 2.0),
+            _ => REST,
+        });
+        assert_eq!(gestures, vec![Gesture::Tap]);
+    }
+
+    #[test]
+    fn test_shake() {
+        let mut detector = GestureDetector::new(GestureConfig::default());
+        let gestures = run(&mut detector, 0..100, |step| {
+            if (20..60).contains(&step) && step % 5 == 0 {
+                Vector3::new(if step % 10 == 0 { 10.0 } else { -10.0 }, 0.0, 9.8)
+            } else {
+                REST
+            }
+        });
+        assert_eq!(gestures, vec![Gesture::Shake]);
+    }
+
+    #[test]
+    fn test_free_fall() {
+        let mut detector = GestureDetector::new(GestureConfig::default());
+        let gestures = run(&mut detector, 0..60, |step| {
+            if (10..40).contains(&step) {
+                Vector3::new(0.1, 0.2, 0.3)
+            } else {
+                REST
+            }
+        });
+        // the landing is a single jolt after the fall, which reads as a tap
+        assert_eq!(gestures[0], Gesture::FreeFall);
+        assert_eq!(
+            gestures
+                .iter()
+                .filter(|gesture| **gesture == Gesture::FreeFall)
+                .count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,426 @@
+use crate::errors::{MotionSensorControlError, MotionSensorControlErrorCodes};
+use anyhow::{bail, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{error as trace_error, info, instrument, trace};
+
+// Channel types read from a motion sensor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelType {
+    Accel,
+    Anglvel,
+    Magn,
+    Temp,
+}
+
+impl ChannelType {
+    pub const ALL: [ChannelType; 4] = [
+        ChannelType::Accel,
+        ChannelType::Anglvel,
+        ChannelType::Magn,
+        ChannelType::Temp,
+    ];
+
+    // Name used in IIO attributes, e.g. `in_anglvel_x_raw`
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            ChannelType::Accel => "accel",
+            ChannelType::Anglvel => "anglvel",
+            ChannelType::Magn => "magn",
+            ChannelType::Temp => "temp",
+        }
+    }
+
+    // Unit of the converted value
+    pub fn unit(&self) -> &'static str {
+        match self {
+            ChannelType::Accel => "m/s^2",
+            ChannelType::Anglvel => "rad/s",
+            ChannelType::Magn => "gauss",
+            ChannelType::Temp => "celsius",
+        }
+    }
+
+    fn from_prefix(prefix: &str) -> Option<ChannelType> {
+        // indexed channels such as `temp0` share the type of `temp`
+        let prefix = prefix.trim_end_matches(|c: char| c.is_ascii_digit());
+        ChannelType::ALL
+            .into_iter()
+            .find(|channel_type| channel_type.prefix() == prefix)
+    }
+
+    // IIO reports temperature in milli degrees, everything else in SI units
+    fn unit_factor(&self) -> f64 {
+        match self {
+            ChannelType::Temp => 0.001,
+            _ => 1.0,
+        }
+    }
+}
+
+// A single IIO input channel and the conversion of its raw value
+#[derive(Debug, Clone, PartialEq)]
+pub struct IioChannel {
+    pub channel_type: ChannelType,
+    pub axis: Option<String>,
+    pub scale: f64,
+    pub offset: f64,
+    value_path: PathBuf,
+    // `_input` attributes are already converted by the driver
+    processed: bool,
+    // calibration applied after conversion, see `Calibration`
+    bias: f64,
+    gain: f64,
+}
+
+impl IioChannel {
+    // Channel of a `in_<type>[_<axis>]_raw` or `_input` attribute, scale and
+    // offset are read from the channel's own attributes or the shared ones
+    pub fn from_path(value_path: &Path) -> Result<Self> {
+        let file_name = value_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let (name, processed) = match file_name.strip_prefix("in_") {
+            Some(name) => match (name.strip_suffix("_raw"), name.strip_suffix("_input")) {
+                (Some(name), _) => (name, false),
+                (_, Some(name)) => (name, true),
+                _ => bail!(not_a_channel(value_path)),
+            },
+            None => bail!(not_a_channel(value_path)),
+        };
+        let (prefix, axis) = match name.split_once('_') {
+            Some((prefix, axis)) => (prefix, Some(axis.to_string())),
+            None => (name, None),
+        };
+        let channel_type = match ChannelType::from_prefix(prefix) {
+            Some(channel_type) => channel_type,
+            None => bail!(not_a_channel(value_path)),
+        };
+
+        let device_path = value_path.parent().unwrap_or_else(|| Path::new(""));
+        let attribute = |attribute: &str, default: f64| -> Result<f64> {
+            if processed {
+                return Ok(default);
+            }
+            for path in [
+                device_path.join(format!("in_{}_{}", name, attribute)),
+                device_path.join(format!("in_{}_{}", prefix, attribute)),
+            ] {
+                if path.exists() {
+                    return read_number(&path);
+                }
+            }
+            Ok(default)
+        };
+
+        Ok(IioChannel {
+            channel_type,
+            scale: attribute("scale", 1.0)?,
+            offset: attribute("offset", 0.0)?,
+            axis,
+            value_path: value_path.to_path_buf(),
+            processed,
+            bias: 0.0,
+            gain: 1.0,
+        })
+    }
+
+    // Scan element name, e.g. `anglvel_x`
+    pub fn name(&self) -> String {
+        let file_name = self
+            .value_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        file_name
+            .trim_start_matches("in_")
+            .trim_end_matches("_raw")
+            .trim_end_matches("_input")
+            .to_string()
+    }
+
+    // Convert a raw count to the unit of the channel type
+    pub fn convert(&self, raw: f64) -> f64 {
+        let value = if self.processed {
+            raw * self.channel_type.unit_factor()
+        } else {
+            (raw + self.offset) * self.scale * self.channel_type.unit_factor()
+        };
+        (value - self.bias) * self.gain
+    }
+
+    // Correct converted values by subtracting `bias` and multiplying by `gain`
+    pub(crate) fn set_correction(&mut self, bias: f64, gain: f64) {
+        self.bias = bias;
+        self.gain = gain;
+    }
+
+    // `calibbias` attribute of the channel, when the driver supports one
+    pub(crate) fn calibbias_path(&self) -> Option<PathBuf> {
+        if self.processed {
+            return None;
+        }
+        let path = self
+            .value_path
+            .with_file_name(format!("in_{}_calibbias", self.name()));
+        path.exists().then_some(path)
+    }
+
+    // Raw count that corresponds to `value` in the unit of the channel type
+    pub(crate) fn to_raw(&self, value: f64) -> f64 {
+        value / (self.scale * self.channel_type.unit_factor())
+    }
+
+    pub fn read(&self) -> Result<f64> {
+        Ok(self.convert(read_number(&self.value_path)?))
+    }
+}
+
+fn not_a_channel(path: &Path) -> MotionSensorControlError {
+    MotionSensorControlError::new(
+        MotionSensorControlErrorCodes::UnknownChannel,
+        format!("{} is not a motion sensor channel", path.display()),
+    )
+}
+
+fn read_number(path: &Path) -> Result<f64> {
+    let value = match fs::read_to_string(path) {
+        Ok(value) => value,
+        Err(e) => {
+            trace_error!(
+                task = "read_number",
+                "unable to read {}: {}",
+                path.display(),
+                e
+            );
+            bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnableToOpenFile,
+                format!("unable to read {}: {}", path.display(), e),
+            ))
+        }
+    };
+    match value.trim().parse::<f64>() {
+        Ok(value) => Ok(value),
+        Err(e) => bail!(MotionSensorControlError::new(
+            MotionSensorControlErrorCodes::UnableToParseValue,
+            format!("unable to parse {}: {}", path.display(), e),
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Vector3 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Vector3 {
+    pub fn new(x: f64, y: f64, z: f64) -> Self {
+        Vector3 { x, y, z }
+    }
+
+    pub fn magnitude(&self) -> f64 {
+        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+}
+
+// Converted readings of every channel type the device offers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MotionReadings {
+    // m/s^2
+    pub accel: Option<Vector3>,
+    // rad/s
+    pub anglvel: Option<Vector3>,
+    // gauss
+    pub magn: Option<Vector3>,
+    // degrees celsius
+    pub temp: Option<f64>,
+}
+
+// IIO device directory, e.g. `/sys/bus/iio/devices/iio:device1`, and the
+// motion channels found in it
+#[derive(Debug, Clone)]
+pub struct IioDevice {
+    path: PathBuf,
+    name: Option<String>,
+    channels: Vec<IioChannel>,
+}
+
+impl IioDevice {
+    #[instrument]
+    pub fn discover(path: &Path) -> Result<Self> {
+        trace!(task = "discover", "init");
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                trace_error!(
+                    task = "discover",
+                    "unable to list {}: {}",
+                    path.display(),
+                    e
+                );
+                bail!(MotionSensorControlError::new(
+                    MotionSensorControlErrorCodes::UnableToOpenFile,
+                    format!("unable to list {}: {}", path.display(), e),
+                ))
+            }
+        };
+
+        let mut channels: Vec<IioChannel> = Vec::new();
+        for entry in entries.flatten() {
+            // not every file is a channel, e.g. `name` or `sampling_frequency`
+            let channel = match IioChannel::from_path(&entry.path()) {
+                Ok(channel) => channel,
+                Err(_) => continue,
+            };
+            // prefer the raw attribute when a channel offers both
+            match channels
+                .iter_mut()
+                .find(|existing| existing.name() == channel.name())
+            {
+                Some(existing) if existing.processed && !channel.processed => *existing = channel,
+                Some(_) => {}
+                None => channels.push(channel),
+            }
+        }
+        channels.sort_by_key(|channel| channel.name());
+
+        let name = fs::read_to_string(path.join("name"))
+            .ok()
+            .map(|name| name.trim().to_string());
+        info!(
+            task = "discover",
+            "found {} channels on {:?}",
+            channels.len(),
+            name
+        );
+        Ok(IioDevice {
+            path: path.to_path_buf(),
+            name,
+            channels,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn channels(&self) -> &[IioChannel] {
+        &self.channels
+    }
+
+    pub(crate) fn channels_mut(&mut self) -> &mut [IioChannel] {
+        &mut self.channels
+    }
+
+    pub fn channel(&self, name: &str) -> Option<&IioChannel> {
+        self.channels.iter().find(|channel| channel.name() == name)
+    }
+
+    pub fn has_channel_type(&self, channel_type: ChannelType) -> bool {
+        self.channels
+            .iter()
+            .any(|channel| channel.channel_type == channel_type)
+    }
+
+    // x, y and z of a channel type, `None` when the device does not have it
+    pub fn read_vector(&self, channel_type: ChannelType) -> Result<Option<Vector3>> {
+        let axis = |axis: &str| {
+            self.channel(&format!("{}_{}", channel_type.prefix(), axis))
+                .map(|channel| channel.read())
+                .transpose()
+        };
+        match (axis("x")?, axis("y")?, axis("z")?) {
+            (Some(x), Some(y), Some(z)) => Ok(Some(Vector3::new(x, y, z))),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn read_temperature(&self) -> Result<Option<f64>> {
+        self.channels
+            .iter()
+            .find(|channel| channel.channel_type == ChannelType::Temp)
+            .map(|channel| channel.read())
+            .transpose()
+    }
+
+    #[instrument(skip(self))]
+    pub fn read_all(&self) -> Result<MotionReadings> {
+        trace!(task = "read_all", "init");
+        Ok(MotionReadings {
+            accel: self.read_vector(ChannelType::Accel)?,
+            anglvel: self.read_vector(ChannelType::Anglvel)?,
+            magn: self.read_vector(ChannelType::Magn)?,
+            temp: self.read_temperature()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn write(path: &Path, attribute: &str, value: &str) {
+        fs::write(path.join(attribute), value).unwrap();
+    }
+
+    #[test]
+    fn test_channel_scale_and_offset() {
+        let tmp_dir = TempDir::new("iio").unwrap();
+        let path = tmp_dir.path();
+        write(path, "in_accel_x_raw", "100\n");
+        write(path, "in_accel_scale", "0.0098\n");
+        write(path, "in_accel_x_scale", "0.02\n");
+        write(path, "in_temp_raw", "-50");
+        write(path, "in_temp_offset", "2550");
+        write(path, "in_temp_scale", "10");
+
+        let accel_x = IioChannel::from_path(&path.join("in_accel_x_raw")).unwrap();
+        assert_eq!(accel_x.channel_type, ChannelType::Accel);
+        assert_eq!(accel_x.axis.as_deref(), Some("x"));
+        assert_eq!(accel_x.name(), "accel_x");
+        // the channel's own scale wins over the shared one
+        assert!((accel_x.read().unwrap() - 2.0).abs() < 1e-9);
+
+        let temp = IioChannel::from_path(&path.join("in_temp_raw")).unwrap();
+        assert!((temp.read().unwrap() - 25.0).abs() < 1e-9);
+
+        assert!(IioChannel::from_path(&path.join("in_voltage0_raw")).is_err());
+        assert!(IioChannel::from_path(&path.join("name")).is_err());
+    }
+
+    #[test]
+    fn test_discover_device() {
+        let tmp_dir = TempDir::new("iio").unwrap();
+        let path = tmp_dir.path();
+        write(path, "name", "bmi088\n");
+        for axis in ["x", "y", "z"] {
+            write(path, &format!("in_anglvel_{}_raw", axis), "1000");
+            write(path, &format!("in_accel_{}_raw", axis), "-512");
+        }
+        write(path, "in_anglvel_scale", "0.001065");
+        write(path, "in_accel_scale", "0.0191");
+        write(path, "in_temp_input", "31500");
+        write(path, "sampling_frequency", "100");
+
+        let device = IioDevice::discover(path).unwrap();
+        assert_eq!(device.name(), Some("bmi088"));
+        assert_eq!(device.channels().len(), 7);
+        assert!(!device.has_channel_type(ChannelType::Magn));
+
+        let readings = device.read_all().unwrap();
+        let anglvel = readings.anglvel.unwrap();
+        assert!((anglvel.x - 1.065).abs() < 1e-9);
+        let accel = readings.accel.unwrap();
+        assert!((accel.z + 9.7792).abs() < 1e-9);
+        assert!(readings.magn.is_none());
+        assert!((readings.temp.unwrap() - 31.5).abs() < 1e-9);
+    }
+}
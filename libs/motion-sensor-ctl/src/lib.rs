@@ -2,5 +2,37 @@
 mod motion_sensor;
 pub use motion_sensor::MotionSensorControl;
 
+mod buffer;
+pub use buffer::{BufferConfig, IioBuffer, MotionSample, MotionSampleStream, ScanElement, ScanType};
+
+mod iio_device;
+pub use iio_device::{ChannelType, IioChannel, IioDevice, MotionReadings, Vector3};
+
+mod detector;
+pub use detector::{
+    IioEvents, MotionDetector, MotionDetectorConfig, MotionEvent, MotionEventKind,
+    MotionEventSource, MotionEventStream,
+};
+
+mod orientation;
+pub use orientation::{
+    Attitude, ComplementaryFilter, FusionConfig, ImuSample, MotionStream, Orientation,
+    OrientationClassifier, OrientationTracker, OrientationUpdate,
+};
+
+mod gesture;
+pub use gesture::{Gesture, GestureConfig, GestureDetector, GestureEvent};
+
+mod calibration;
+pub use calibration::{
+    accel_calibration, Calibration, CalibrationConfig, CalibrationPose, Calibrator,
+    CALIBRATION_DIR,
+};
+
+mod attributes;
+pub use attributes::{
+    AvailableValues, IioAttribute, PowerControl, OVERSAMPLING_RATIO, SAMPLING_FREQUENCY,
+};
+
 mod errors;
 pub use errors::{MotionSensorControlError, MotionSensorControlErrorCodes};
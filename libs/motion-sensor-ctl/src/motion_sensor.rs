@@ -1,17 +1,32 @@
+use crate::attributes::{
+    IioAttribute, PowerControl, OVERSAMPLING_RATIO, SAMPLING_FREQUENCY,
+};
+use crate::buffer::{BufferConfig, IioBuffer, MotionSampleStream};
+use crate::calibration::{Calibration, CalibrationConfig, CalibrationState, Calibrator};
+use crate::detector::{IioEvents, MotionDetector, MotionDetectorConfig, MotionEventStream};
+use crate::gesture::{GestureConfig, GestureDetector, GestureEvent};
+use crate::iio_device::{ChannelType, IioChannel, IioDevice, MotionReadings, Vector3};
+use crate::orientation::{
+    FusionConfig, MotionStream, OrientationTracker, OrientationUpdate, STANDARD_GRAVITY,
+};
 use crate::errors::{MotionSensorControlError, MotionSensorControlErrorCodes};
 use anyhow::{bail, Result};
 use std::default::Default;
 use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 use tracing::{error as trace_error, info, trace,instrument};
 //allow unused import
 #[allow(unused_imports)]
 use std::io::{self, BufRead, BufReader, Read, Write};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct MotionSensorControl {
     x_axis_path: String,
     y_axis_path: String,
     z_axis_path: String,
+    // shared between clones so a new calibration applies to running streams
+    calibration: Arc<RwLock<CalibrationState>>,
 }
 
 impl MotionSensorControl {
@@ -21,6 +36,7 @@ impl MotionSensorControl {
             x_axis_path: String::from(x_path),
             y_axis_path: String::from(y_path),
             z_axis_path: String::from(z_path),
+            calibration: Arc::default(),
         }
     }
 
@@ -29,9 +45,9 @@ impl MotionSensorControl {
         trace!(task = "read_motion_sensor_value", "init");
         //read x,y,z values from the motion sensor or error using match and anyhow error
         let (x_value, y_value, z_value) = match (
-            self.read_value_from_file(&self.x_axis_path),
-            self.read_value_from_file(&self.y_axis_path),
-            self.read_value_from_file(&self.z_axis_path),
+            self.read_axis_value(&self.x_axis_path),
+            self.read_axis_value(&self.y_axis_path),
+            self.read_axis_value(&self.z_axis_path),
         ) {
             (Ok(x), Ok(y), Ok(z)) => {
                 info!(
@@ -78,6 +94,19 @@ impl MotionSensorControl {
         Ok((x_value, y_value, z_value))
     }
 
+    // Axis value with the scale and offset of its IIO channel applied, files
+    // that are not IIO channels are returned as read
+    fn read_axis_value(&self, path: &str) -> Result<f64> {
+        let value = self.read_value_from_file(path)?;
+        match IioChannel::from_path(Path::new(path)) {
+            Ok(mut channel) => {
+                self.calibration_state().apply(&mut channel);
+                Ok(channel.convert(value))
+            }
+            Err(_) => Ok(value),
+        }
+    }
+
     #[instrument]
     fn read_value_from_file(&self, path: &str) -> Result<f64> {
         trace!(task = "read_value_from_file", "init");
@@ -211,6 +240,295 @@ impl MotionSensorControl {
         Ok(())
     }
 
+    // Sample x, y and z together through the IIO buffer of the device the
+    // axis files belong to, instead of reading each `_raw` file in turn
+    #[instrument(skip(self))]
+    pub fn buffered(&self, config: &BufferConfig) -> Result<MotionSampleStream> {
+        trace!(task = "buffered", "init");
+        let device_path = match Path::new(&self.x_axis_path).parent() {
+            Some(device_path) => device_path.to_path_buf(),
+            None => {
+                bail!(MotionSensorControlError::new(
+                    MotionSensorControlErrorCodes::UnableToConfigureBuffer,
+                    format!("invalid x axis path: {}", self.x_axis_path),
+                ))
+            }
+        };
+        let channels = [&self.x_axis_path, &self.y_axis_path, &self.z_axis_path]
+            .iter()
+            .map(|path| IioChannel::from_path(Path::new(path)))
+            .collect::<Result<Vec<IioChannel>>>()?;
+        let mut channels = channels;
+        for channel in channels.iter_mut() {
+            self.calibration_state().apply(channel);
+        }
+        let names: Vec<String> = channels.iter().map(IioChannel::name).collect();
+
+        let buffer = IioBuffer::enable(&device_path, &names, config)?;
+        info!(
+            task = "buffered",
+            "buffered sampling of {:?} on {}",
+            names,
+            device_path.display()
+        );
+        MotionSampleStream::start(buffer, channels)
+    }
+
+    // IIO device the axis files belong to, with all of its motion channels
+    // and the calibration applied to them
+    #[instrument(skip(self))]
+    pub fn device(&self) -> Result<IioDevice> {
+        trace!(task = "device", "init");
+        let mut device = self.uncalibrated_device()?;
+        let state = self.calibration_state();
+        for channel in device.channels_mut() {
+            state.apply(channel);
+        }
+        Ok(device)
+    }
+
+    fn uncalibrated_device(&self) -> Result<IioDevice> {
+        match Path::new(&self.x_axis_path).parent() {
+            Some(device_path) => IioDevice::discover(device_path),
+            None => bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnableToOpenFile,
+                format!("invalid x axis path: {}", self.x_axis_path),
+            )),
+        }
+    }
+
+    // Accelerometer, gyroscope, magnetometer and temperature in SI units
+    #[instrument(skip(self))]
+    pub fn read_channels(&self) -> Result<MotionReadings> {
+        trace!(task = "read_channels", "init");
+        self.device()?.read_all()
+    }
+
+    // Watch for motion, using the sensor's own motion event when it has one,
+    // otherwise running `MotionDetector` over buffered or polled samples
+    #[instrument(skip(self))]
+    pub fn watch_motion(
+        &self,
+        config: &MotionDetectorConfig,
+        buffer: &BufferConfig,
+    ) -> Result<MotionEventStream> {
+        trace!(task = "watch_motion", "init");
+        let detector = MotionDetector::new(config.clone());
+        if let Ok(device) = self.device() {
+            let hardware_event = self.device_channel(&device).and_then(|channel| {
+                IioEvents::find(device.path(), &channel).map(|enable_path| (channel, enable_path))
+            });
+            if let Some((channel, enable_path)) = hardware_event {
+                let device_node = match &buffer.device_node {
+                    Some(device_node) => device_node.clone(),
+                    None => Path::new("/dev").join(device.path().file_name().unwrap_or_default()),
+                };
+                match IioEvents::enable(&enable_path, &device_node, &channel, config)
+                    .and_then(|events| MotionEventStream::from_events(events, detector))
+                {
+                    Ok(stream) => {
+                        info!(task = "watch_motion", "watching hardware motion events");
+                        return Ok(stream);
+                    }
+                    Err(e) => trace_error!(
+                        task = "watch_motion",
+                        "unable to use hardware motion events: {}",
+                        e
+                    ),
+                }
+            }
+        }
+
+        let detector = MotionDetector::new(config.clone());
+        match self.buffered(buffer) {
+            Ok(samples) => {
+                info!(task = "watch_motion", "detecting motion from buffered samples");
+                Ok(MotionEventStream::from_samples(samples, detector))
+            }
+            Err(e) => {
+                info!(
+                    task = "watch_motion",
+                    "buffer unavailable, polling motion sensor: {}",
+                    e
+                );
+                let motion_sensor = self.clone();
+                Ok(MotionEventStream::from_polling(
+                    move || motion_sensor.read_motion_sensor_value(),
+                    detector,
+                    config.poll_interval,
+                ))
+            }
+        }
+    }
+
+    fn calibration_state(&self) -> RwLockReadGuard<'_, CalibrationState> {
+        match self.calibration.read() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    pub fn calibration(&self) -> Calibration {
+        self.calibration_state().calibration.clone()
+    }
+
+    // Apply `calibration` to every following read, offsets are written to the
+    // sensor's `calibbias` attributes where it has them
+    #[instrument(skip(self))]
+    pub fn set_calibration(&self, calibration: Calibration) {
+        trace!(task = "set_calibration", "init");
+        let device = self.uncalibrated_device().ok();
+        let state = CalibrationState::new(calibration, device.as_ref());
+        match self.calibration.write() {
+            Ok(mut current) => *current = state,
+            Err(poisoned) => *poisoned.into_inner() = state,
+        }
+    }
+
+    // Load and apply the calibration file of the device from `directory`,
+    // `None` when the device has not been calibrated
+    #[instrument(skip(self))]
+    pub fn load_calibration(&self, directory: &Path) -> Result<Option<Calibration>> {
+        trace!(task = "load_calibration", "init");
+        let path = Calibration::path(directory, &self.uncalibrated_device()?);
+        let calibration = Calibration::load(&path)?;
+        if let Some(calibration) = &calibration {
+            info!(
+                task = "load_calibration",
+                "using calibration from {}",
+                path.display()
+            );
+            self.set_calibration(calibration.clone());
+        }
+        Ok(calibration)
+    }
+
+    // Save the calibration in use to the device's file in `directory`
+    #[instrument(skip(self))]
+    pub fn save_calibration(&self, directory: &Path) -> Result<PathBuf> {
+        trace!(task = "save_calibration", "init");
+        let path = Calibration::path(directory, &self.uncalibrated_device()?);
+        self.calibration().save(&path)?;
+        Ok(path)
+    }
+
+    // Start calibrating, the calibration in use is dropped so measurements are
+    // taken from uncorrected readings
+    #[instrument(skip(self))]
+    pub fn calibrator(&self, config: &CalibrationConfig) -> Result<Calibrator> {
+        trace!(task = "calibrator", "init");
+        self.set_calibration(Calibration::default());
+        Calibrator::new(self.uncalibrated_device()?, config.clone())
+    }
+
+    // Fuse accelerometer, gyroscope and magnetometer into attitude and
+    // screen orientation, updated every `sample_interval`
+    #[instrument(skip(self))]
+    pub fn watch_orientation(
+        &self,
+        config: &FusionConfig,
+    ) -> Result<MotionStream<OrientationUpdate>> {
+        trace!(task = "watch_orientation", "init");
+        let device = self.device()?;
+        let mut tracker = OrientationTracker::new(config);
+        info!(
+            task = "watch_orientation",
+            "tracking orientation on {}",
+            device.path().display()
+        );
+        MotionStream::from_polling(device, config.sample_interval, move |sample| {
+            tracker.update(sample)
+        })
+    }
+
+    // Detect taps, shakes and free fall from the accelerometer
+    #[instrument(skip(self))]
+    pub fn watch_gestures(&self, config: &GestureConfig) -> Result<MotionStream<GestureEvent>> {
+        trace!(task = "watch_gestures", "init");
+        let device = self.device()?;
+        let mut detector = GestureDetector::new(config.clone());
+        info!(
+            task = "watch_gestures",
+            "detecting gestures on {}",
+            device.path().display()
+        );
+        MotionStream::from_polling(device, config.sample_interval, move |sample| {
+            detector.update(sample.timestamp, sample.accel)
+        })
+    }
+
+    // Attribute `name` of the device, the one of the configured axes' channel
+    // type is preferred over the shared one
+    #[instrument(skip(self))]
+    pub fn attribute(&self, name: &str) -> Result<IioAttribute> {
+        trace!(task = "attribute", "init");
+        let device = self.uncalibrated_device()?;
+        let channel_type = IioChannel::from_path(Path::new(&self.x_axis_path))
+            .ok()
+            .map(|channel| channel.channel_type);
+        match IioAttribute::find(device.path(), name, channel_type) {
+            Some(attribute) => Ok(attribute),
+            None => bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnknownAttribute,
+                format!("{} has no {} attribute", device.path().display(), name),
+            )),
+        }
+    }
+
+    // Sampling frequency and oversampling ratio, those the device has
+    pub fn attributes(&self) -> Result<Vec<IioAttribute>> {
+        let device = self.uncalibrated_device()?;
+        let channel_type = IioChannel::from_path(Path::new(&self.x_axis_path))
+            .ok()
+            .map(|channel| channel.channel_type);
+        Ok([SAMPLING_FREQUENCY, OVERSAMPLING_RATIO]
+            .iter()
+            .filter_map(|name| IioAttribute::find(device.path(), name, channel_type))
+            .collect())
+    }
+
+    fn numeric_attribute<T: std::str::FromStr>(&self, name: &str) -> Result<T> {
+        let value = self.attribute(name)?.read()?;
+        match value.parse::<T>() {
+            Ok(value) => Ok(value),
+            Err(_) => bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnableToParseValue,
+                format!("unable to parse {}: {}", name, value),
+            )),
+        }
+    }
+
+    // Hz
+    pub fn sampling_frequency(&self) -> Result<f64> {
+        self.numeric_attribute(SAMPLING_FREQUENCY)
+    }
+
+    pub fn set_sampling_frequency(&self, frequency: f64) -> Result<()> {
+        self.attribute(SAMPLING_FREQUENCY)?.write(&frequency.to_string())
+    }
+
+    pub fn oversampling_ratio(&self) -> Result<u32> {
+        self.numeric_attribute(OVERSAMPLING_RATIO)
+    }
+
+    pub fn set_oversampling_ratio(&self, ratio: u32) -> Result<()> {
+        self.attribute(OVERSAMPLING_RATIO)?.write(&ratio.to_string())
+    }
+
+    pub fn power_control(&self) -> Result<PowerControl> {
+        PowerControl::read(self.uncalibrated_device()?.path())
+    }
+
+    pub fn set_power_control(&self, power_control: PowerControl) -> Result<()> {
+        PowerControl::write(self.uncalibrated_device()?.path(), power_control)
+    }
+
+    // Channel of the configured x axis within the discovered device
+    fn device_channel(&self, device: &IioDevice) -> Option<IioChannel> {
+        let channel = IioChannel::from_path(Path::new(&self.x_axis_path)).ok()?;
+        device.channel(&channel.name()).cloned()
+    }
+
     #[instrument(skip(self))]
     pub fn detect_motion_sensor_event(&self) -> Result<bool> {
         trace!(task = "detect_motion_sensor_event", "init");
@@ -234,10 +552,16 @@ impl MotionSensorControl {
                 ))
             }
         };
-        let mut is_motion_detected = false;
-        if x_value != 0.0 || y_value != 0.0 || z_value != 0.0 {
-            is_motion_detected = true;
-        }
+        // a single reading cannot be high-pass filtered, so take gravity off
+        // accelerometer readings and compare against the detector threshold
+        let magnitude = Vector3::new(x_value, y_value, z_value).magnitude();
+        let magnitude = match IioChannel::from_path(Path::new(&self.x_axis_path)) {
+            Ok(channel) if channel.channel_type == ChannelType::Accel => {
+                (magnitude - STANDARD_GRAVITY).abs()
+            }
+            _ => magnitude,
+        };
+        let is_motion_detected = magnitude >= MotionDetectorConfig::default().threshold;
         info!(
             task = "detect_motion_sensor_event",
             "is motion detected: {}", is_motion_detected
@@ -278,4 +602,37 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), true);
     }
+
+    #[tokio::test]
+    async fn test_buffered_motion_sensor() {
+        let tmp_dir = tempdir::TempDir::new("iio").unwrap();
+        let device_path = crate::buffer::tests::create_device(
+            tmp_dir.path(),
+            &[
+                ("accel_x", 0, "be:s16/16>>0"),
+                ("accel_y", 1, "be:s16/16>>0"),
+                ("accel_z", 2, "be:s16/16>>0"),
+            ],
+        );
+        let device_node = tmp_dir.path().join("device_node");
+        std::fs::write(&device_node, [0, 1, 0xff, 0xfe, 0, 3]).unwrap();
+
+        let path = |axis: &str| {
+            device_path
+                .join(format!("in_accel_{}_raw", axis))
+                .to_string_lossy()
+                .to_string()
+        };
+        let motion_sensor = MotionSensorControl::new(&path("x"), &path("y"), &path("z"));
+        let mut stream = motion_sensor
+            .buffered(&BufferConfig {
+                device_node: Some(device_node),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let sample = stream.next().await.unwrap().unwrap();
+        assert_eq!((sample.x, sample.y, sample.z), (1.0, -2.0, 3.0));
+        assert!(stream.next().await.is_none());
+    }
 }
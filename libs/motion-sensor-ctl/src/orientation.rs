@@ -0,0 +1,488 @@
+use crate::buffer::now_ns;
+use crate::errors::{MotionSensorControlError, MotionSensorControlErrorCodes};
+use crate::iio_device::{ChannelType, IioDevice, Vector3};
+use anyhow::{bail, Result};
+use std::f64::consts::PI;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::Stream;
+use tracing::trace;
+
+// m/s^2
+pub(crate) const STANDARD_GRAVITY: f64 = 9.80665;
+
+#[derive(Debug, Clone)]
+pub struct FusionConfig {
+    // interval the accelerometer and gyroscope are read at
+    pub sample_interval: Duration,
+    // share of the gyroscope in the complementary filter, the rest comes
+    // from the accelerometer (and magnetometer for yaw)
+    pub gyro_weight: f64,
+    // tilt from horizontal below which the device counts as flat
+    pub flat_angle_deg: f64,
+    // extra angle needed before leaving the current orientation
+    pub hysteresis_deg: f64,
+}
+
+impl Default for FusionConfig {
+    fn default() -> Self {
+        FusionConfig {
+            sample_interval: Duration::from_millis(20),
+            gyro_weight: 0.98,
+            flat_angle_deg: 20.0,
+            hysteresis_deg: 15.0,
+        }
+    }
+}
+
+// Roll, pitch and yaw in radians
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Attitude {
+    pub roll: f64,
+    pub pitch: f64,
+    pub yaw: f64,
+}
+
+// Accelerometer, gyroscope and magnetometer read at the same time
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ImuSample {
+    // nanoseconds
+    pub timestamp: i64,
+    // m/s^2
+    pub accel: Vector3,
+    // rad/s
+    pub anglvel: Option<Vector3>,
+    // gauss
+    pub magn: Option<Vector3>,
+}
+
+impl ImuSample {
+    // Read the accelerometer, and the gyroscope and magnetometer when present
+    pub fn read(device: &IioDevice) -> Result<Self> {
+        let accel = match device.read_vector(ChannelType::Accel)? {
+            Some(accel) => accel,
+            None => bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnknownChannel,
+                format!("no accelerometer on {}", device.path().display()),
+            )),
+        };
+        Ok(ImuSample {
+            timestamp: now_ns(),
+            accel,
+            anglvel: device.read_vector(ChannelType::Anglvel)?,
+            magn: device.read_vector(ChannelType::Magn)?,
+        })
+    }
+}
+
+// Wrap an angle to -pi..pi
+fn wrap(angle: f64) -> f64 {
+    (angle + PI).rem_euclid(2.0 * PI) - PI
+}
+
+// Complementary filter, the gyroscope is integrated for responsiveness and
+// pulled towards the accelerometer (and magnetometer) to cancel its drift
+#[derive(Debug)]
+pub struct ComplementaryFilter {
+    gyro_weight: f64,
+    attitude: Option<Attitude>,
+    last_timestamp: i64,
+}
+
+impl ComplementaryFilter {
+    pub fn new(gyro_weight: f64) -> Self {
+        ComplementaryFilter {
+            gyro_weight: gyro_weight.clamp(0.0, 1.0),
+            attitude: None,
+            last_timestamp: 0,
+        }
+    }
+
+    pub fn update(&mut self, sample: &ImuSample) -> Attitude {
+        let accel = sample.accel;
+        let accel_roll = accel.y.atan2(accel.z);
+        let accel_pitch = (-accel.x).atan2((accel.y * accel.y + accel.z * accel.z).sqrt());
+        let heading = sample.magn.map(|magn| {
+            // tilt compensated heading
+            let (sin_roll, cos_roll) = accel_roll.sin_cos();
+            let (sin_pitch, cos_pitch) = accel_pitch.sin_cos();
+            let x = magn.x * cos_pitch + magn.z * sin_pitch;
+            let y =
+                magn.x * sin_roll * sin_pitch + magn.y * cos_roll - magn.z * sin_roll * cos_pitch;
+            (-y).atan2(x)
+        });
+
+        let previous = match self.attitude {
+            Some(previous) => previous,
+            None => {
+                let attitude = Attitude {
+                    roll: accel_roll,
+                    pitch: accel_pitch,
+                    yaw: heading.unwrap_or_default(),
+                };
+                self.attitude = Some(attitude);
+                self.last_timestamp = sample.timestamp;
+                return attitude;
+            }
+        };
+
+        let dt = (sample.timestamp - self.last_timestamp).max(0) as f64 / 1e9;
+        self.last_timestamp = sample.timestamp;
+        let gyro = sample.anglvel.unwrap_or_default();
+        let predicted = Attitude {
+            roll: wrap(previous.roll + gyro.x * dt),
+            pitch: wrap(previous.pitch + gyro.y * dt),
+            yaw: wrap(previous.yaw + gyro.z * dt),
+        };
+
+        // without a gyroscope the accelerometer is all there is
+        let gyro_weight = if sample.anglvel.is_some() {
+            self.gyro_weight
+        } else {
+            0.0
+        };
+        // while the device is accelerated the accelerometer does not point at
+        // gravity, rely on the gyroscope alone
+        let accel_weight = if (accel.magnitude() - STANDARD_GRAVITY).abs() < 0.3 * STANDARD_GRAVITY
+        {
+            1.0 - gyro_weight
+        } else {
+            0.0
+        };
+        let blend = |predicted: f64, measured: f64, weight: f64| {
+            wrap(predicted + weight * wrap(measured - predicted))
+        };
+
+        let attitude = Attitude {
+            roll: blend(predicted.roll, accel_roll, accel_weight),
+            pitch: blend(predicted.pitch, accel_pitch, accel_weight),
+            yaw: match heading {
+                Some(heading) => blend(predicted.yaw, heading, 1.0 - gyro_weight),
+                None => predicted.yaw,
+            },
+        };
+        self.attitude = Some(attitude);
+        attitude
+    }
+}
+
+// Screen orientation, named after the edge of the device pointing up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    // +y up
+    Portrait,
+    // -y up
+    PortraitUpsideDown,
+    // +x up, device rotated counter-clockwise
+    LandscapeLeft,
+    // -x up, device rotated clockwise
+    LandscapeRight,
+    // screen facing up
+    FlatUp,
+    // screen facing down
+    FlatDown,
+}
+
+impl Orientation {
+    // Angle of gravity in the screen plane the orientation is centred on
+    fn center_deg(&self) -> Option<f64> {
+        match self {
+            Orientation::Portrait => Some(0.0),
+            Orientation::LandscapeLeft => Some(90.0),
+            Orientation::PortraitUpsideDown => Some(180.0),
+            Orientation::LandscapeRight => Some(-90.0),
+            Orientation::FlatUp | Orientation::FlatDown => None,
+        }
+    }
+
+    fn is_flat(&self) -> bool {
+        self.center_deg().is_none()
+    }
+}
+
+// Classify screen orientation from gravity, with hysteresis so holding the
+// device near a boundary does not flip the screen back and forth
+#[derive(Debug)]
+pub struct OrientationClassifier {
+    flat_angle_deg: f64,
+    hysteresis_deg: f64,
+    current: Option<Orientation>,
+}
+
+impl OrientationClassifier {
+    pub fn new(flat_angle_deg: f64, hysteresis_deg: f64) -> Self {
+        OrientationClassifier {
+            flat_angle_deg,
+            hysteresis_deg,
+            current: None,
+        }
+    }
+
+    pub fn current(&self) -> Option<Orientation> {
+        self.current
+    }
+
+    // Classify gravity measured by the accelerometer, returns the orientation
+    // when it changed
+    pub fn update(&mut self, gravity: Vector3) -> Option<Orientation> {
+        let magnitude = gravity.magnitude();
+        if magnitude < f64::EPSILON {
+            return None;
+        }
+        // angle between the screen and the horizontal plane
+        let tilt_deg = (gravity.z.abs() / magnitude).acos().to_degrees();
+        let screen_deg = gravity.x.atan2(gravity.y).to_degrees();
+
+        let flat_limit = match self.current {
+            Some(current) if current.is_flat() => self.flat_angle_deg + self.hysteresis_deg,
+            _ => self.flat_angle_deg,
+        };
+        let orientation = if tilt_deg < flat_limit {
+            if gravity.z >= 0.0 {
+                Orientation::FlatUp
+            } else {
+                Orientation::FlatDown
+            }
+        } else {
+            let keep_current = self
+                .current
+                .and_then(|current| current.center_deg().map(|center| (current, center)))
+                .filter(|(_, center)| {
+                    wrap((screen_deg - center).to_radians()).to_degrees().abs()
+                        <= 45.0 + self.hysteresis_deg
+                });
+            match keep_current {
+                Some((current, _)) => current,
+                None => match screen_deg {
+                    angle if (-45.0..45.0).contains(&angle) => Orientation::Portrait,
+                    angle if (45.0..135.0).contains(&angle) => Orientation::LandscapeLeft,
+                    angle if (-135.0..-45.0).contains(&angle) => Orientation::LandscapeRight,
+                    _ => Orientation::PortraitUpsideDown,
+                },
+            }
+        };
+
+        if self.current == Some(orientation) {
+            return None;
+        }
+        self.current = Some(orientation);
+        Some(orientation)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrientationUpdate {
+    // nanoseconds
+    pub timestamp: i64,
+    pub attitude: Attitude,
+    pub orientation: Orientation,
+    // true when the orientation differs from the previous update
+    pub orientation_changed: bool,
+}
+
+// Fuses samples into attitude and screen orientation
+#[derive(Debug)]
+pub struct OrientationTracker {
+    filter: ComplementaryFilter,
+    classifier: OrientationClassifier,
+}
+
+impl OrientationTracker {
+    pub fn new(config: &FusionConfig) -> Self {
+        OrientationTracker {
+            filter: ComplementaryFilter::new(config.gyro_weight),
+            classifier: OrientationClassifier::new(config.flat_angle_deg, config.hysteresis_deg),
+        }
+    }
+
+    pub fn update(&mut self, sample: &ImuSample) -> Option<OrientationUpdate> {
+        let attitude = self.filter.update(sample);
+        let changed = self.classifier.update(sample.accel).is_some();
+        self.classifier
+            .current()
+            .map(|orientation| OrientationUpdate {
+                timestamp: sample.timestamp,
+                attitude,
+                orientation,
+                orientation_changed: changed,
+            })
+    }
+}
+
+// Stream of values derived from polled IMU samples, polling stops once the
+// stream is dropped
+pub struct MotionStream<T> {
+    receiver: mpsc::Receiver<Result<T>>,
+}
+
+impl<T: Send + 'static> MotionStream<T> {
+    // Read a sample from `device` every `interval` and pass it to `update`,
+    // sending whatever it returns
+    pub(crate) fn from_polling<F>(
+        device: IioDevice,
+        interval: Duration,
+        mut update: F,
+    ) -> Result<Self>
+    where
+        F: FnMut(&ImuSample) -> Option<T> + Send + 'static,
+    {
+        trace!(task = "motion_stream", "init");
+        if !device.has_channel_type(ChannelType::Accel) {
+            bail!(MotionSensorControlError::new(
+                MotionSensorControlErrorCodes::UnknownChannel,
+                format!("no accelerometer on {}", device.path().display()),
+            ));
+        }
+        let (sender, receiver) = mpsc::channel(16);
+        std::thread::spawn(move || loop {
+            if sender.is_closed() {
+                break;
+            }
+            match ImuSample::read(&device) {
+                Ok(sample) => {
+                    if let Some(value) = update(&sample) {
+                        if sender.blocking_send(Ok(value)).is_err() {
+                            break;
+                        }
+                    }
+                }
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(e));
+                    break;
+                }
+            }
+            std::thread::sleep(interval);
+        });
+        Ok(MotionStream { receiver })
+    }
+}
+
+impl<T> MotionStream<T> {
+    pub async fn next(&mut self) -> Option<Result<T>> {
+        self.receiver.recv().await
+    }
+}
+
+impl<T> Stream for MotionStream<T> {
+    type Item = Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: i64 = 1_000_000;
+
+    // gravity at `screen_deg` in the screen plane and `elevation_deg` out of it
+    fn gravity_at(screen_deg: f64, elevation_deg: f64) -> Vector3 {
+        let (screen_sin, screen_cos) = screen_deg.to_radians().sin_cos();
+        let (elevation_sin, elevation_cos) = elevation_deg.to_radians().sin_cos();
+        Vector3::new(
+            STANDARD_GRAVITY * elevation_cos * screen_sin,
+            STANDARD_GRAVITY * elevation_cos * screen_cos,
+            STANDARD_GRAVITY * elevation_sin,
+        )
+    }
+
+    #[test]
+    fn test_orientation_hysteresis() {
+        let mut classifier = OrientationClassifier::new(20.0, 15.0);
+        assert_eq!(
+            classifier.update(gravity_at(0.0, 60.0)),
+            Some(Orientation::Portrait)
+        );
+        // past 45 degrees but within the hysteresis band
+        assert_eq!(classifier.update(gravity_at(55.0, 60.0)), None);
+        assert_eq!(
+            classifier.update(gravity_at(65.0, 60.0)),
+            Some(Orientation::LandscapeLeft)
+        );
+        // coming back needs to pass 45 degrees by the hysteresis again
+        assert_eq!(classifier.update(gravity_at(35.0, 60.0)), None);
+        assert_eq!(
+            classifier.update(gravity_at(-100.0, 60.0)),
+            Some(Orientation::LandscapeRight)
+        );
+        assert_eq!(
+            classifier.update(gravity_at(180.0, 45.0)),
+            Some(Orientation::PortraitUpsideDown)
+        );
+    }
+
+    #[test]
+    fn test_flat_orientation() {
+        let mut classifier = OrientationClassifier::new(20.0, 15.0);
+        assert_eq!(
+            classifier.update(gravity_at(0.0, 80.0)),
+            Some(Orientation::FlatUp)
+        );
+        // a 30 degree tilt is within the flat angle plus hysteresis
+        assert_eq!(classifier.update(gravity_at(0.0, 60.0)), None);
+        assert_eq!(
+            classifier.update(gravity_at(0.0, 50.0)),
+            Some(Orientation::Portrait)
+        );
+        assert_eq!(
+            classifier.update(gravity_at(0.0, -85.0)),
+            Some(Orientation::FlatDown)
+        );
+    }
+
+    #[test]
+    fn test_complementary_filter() {
+        let mut filter = ComplementaryFilter::new(0.98);
+        // tilted 30 degrees around x
+        let accel = Vector3::new(
+            0.0,
+            STANDARD_GRAVITY * 30f64.to_radians().sin(),
+            STANDARD_GRAVITY * 30f64.to_radians().cos(),
+        );
+        let attitude = filter.update(&ImuSample {
+            timestamp: 0,
+            accel,
+            anglvel: Some(Vector3::default()),
+            magn: None,
+        });
+        assert!((attitude.roll.to_degrees() - 30.0).abs() < 1e-6);
+        assert!(attitude.pitch.abs() < 1e-6);
+
+        // rotating about z is only seen by the gyroscope
+        let mut attitude = attitude;
+        for step in 1..=50 {
+            attitude = filter.update(&ImuSample {
+                timestamp: step * 20 * MS,
+                accel,
+                anglvel: Some(Vector3::new(0.0, 0.0, 1.0)),
+                magn: None,
+            });
+        }
+        assert!((attitude.yaw - 1.0).abs() < 1e-6);
+        assert!((attitude.roll.to_degrees() - 30.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_filter_ignores_accelerometer_while_accelerating() {
+        let mut filter = ComplementaryFilter::new(0.5);
+        let level = Vector3::new(0.0, 0.0, STANDARD_GRAVITY);
+        filter.update(&ImuSample {
+            timestamp: 0,
+            accel: level,
+            anglvel: Some(Vector3::default()),
+            magn: None,
+        });
+        // a strong sideways jolt must not be mistaken for tilt
+        let attitude = filter.update(&ImuSample {
+            timestamp: 20 * MS,
+            accel: Vector3::new(0.0, 20.0, STANDARD_GRAVITY),
+            anglvel: Some(Vector3::default()),
+            magn: None,
+        });
+        assert!(attitude.roll.abs() < 1e-9);
+    }
+}
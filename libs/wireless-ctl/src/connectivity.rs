@@ -0,0 +1,482 @@
+use anyhow::{bail, Result};
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{error as trace_error, info, trace};
+
+use crate::errors::{WirelessNetworkError, WirelessNetworkErrorCodes};
+use crate::netlink;
+
+// answers 204 with an empty body, portals answer with a redirect or a page
+pub const DEFAULT_PROBE_URL: &str = "http://connectivitycheck.gstatic.com/generate_204";
+pub const DEFAULT_TCP_ENDPOINTS: [&str; 2] = ["1.1.1.1:443", "8.8.8.8:53"];
+pub const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+// status line and headers, the body is never needed
+const MAX_RESPONSE_LEN: usize = 16 * 1024;
+const EVENT_CAPACITY: usize = 16;
+
+fn invalid_probe(message: String) -> WirelessNetworkError {
+    WirelessNetworkError::new(
+        WirelessNetworkErrorCodes::UnableToCheckConnectivity,
+        message,
+    )
+}
+
+// How far the device reaches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+    // no default route, nothing beyond the local link
+    None,
+    // a route exists but the internet does not answer
+    LocalOnly,
+    // the internet answers by address but names do not resolve
+    DnsFailure,
+    // the probe was answered by someone else, usually a login page
+    CaptivePortal,
+    Full,
+}
+
+impl fmt::Display for Connectivity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Connectivity::None => write!(f, "none"),
+            Connectivity::LocalOnly => write!(f, "local-only"),
+            Connectivity::DnsFailure => write!(f, "dns-failure"),
+            Connectivity::CaptivePortal => write!(f, "captive-portal"),
+            Connectivity::Full => write!(f, "full"),
+        }
+    }
+}
+
+// Endpoints the checker probes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectivityConfig {
+    // plain http, portals cannot intercept https without a certificate error
+    pub probe_url: String,
+    pub expected_status: u16,
+    // name resolved to test DNS, the host of `probe_url` when None
+    pub dns_host: Option<String>,
+    // host:port pairs, one answering is enough
+    pub tcp_endpoints: Vec<String>,
+    pub timeout: Duration,
+}
+
+impl Default for ConnectivityConfig {
+    fn default() -> Self {
+        ConnectivityConfig {
+            probe_url: DEFAULT_PROBE_URL.to_string(),
+            expected_status: 204,
+            dns_host: None,
+            tcp_endpoints: DEFAULT_TCP_ENDPOINTS
+                .iter()
+                .map(|endpoint| endpoint.to_string())
+                .collect(),
+            timeout: DEFAULT_PROBE_TIMEOUT,
+        }
+    }
+}
+
+// host, port and path of an http:// url
+pub fn parse_http_url(url: &str) -> Result<(String, u16, String)> {
+    let rest = match url.strip_prefix("http://") {
+        Some(rest) => rest,
+        None => bail!(invalid_probe(format!(
+            "probe url {} is not a plain http url",
+            url
+        ))),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    // IPv6 literals are bracketed, [::1]:8080
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => match bracketed.split_once(']') {
+            Some((host, port)) => (host, port.strip_prefix(':')),
+            None => bail!(invalid_probe(format!("invalid host in {}", url))),
+        },
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        Some(port) => match port.parse() {
+            Ok(port) => port,
+            Err(_) => bail!(invalid_probe(format!("invalid port in {}", url))),
+        },
+        None => 80,
+    };
+    if host.is_empty() {
+        bail!(invalid_probe(format!("no host in {}", url)))
+    }
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+// What the HTTP probe got back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpProbe {
+    pub status: u16,
+    // Location of a redirect, where a portal wants the user to log in
+    pub location: Option<String>,
+}
+
+// status code and Location header of a raw HTTP response
+pub fn parse_http_response(response: &str) -> Option<HttpProbe> {
+    let mut lines = response.split("\r\n");
+    let status_line = lines.next()?;
+    if !status_line.starts_with("HTTP/") {
+        return None;
+    }
+    let status = status_line.split_whitespace().nth(1)?.parse().ok()?;
+    let location = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("location"))
+        .map(|(_, value)| value.trim().to_string());
+    Some(HttpProbe { status, location })
+}
+
+// Result of one check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectivityReport {
+    pub state: Connectivity,
+    pub default_route: bool,
+    pub dns_resolved: bool,
+    pub tcp_reachable: bool,
+    pub http: Option<HttpProbe>,
+    // round trip of the HTTP probe
+    pub latency: Option<Duration>,
+}
+
+impl ConnectivityReport {
+    // url of the captive portal, when the portal told us
+    pub fn portal_url(&self) -> Option<&str> {
+        match self.state {
+            Connectivity::CaptivePortal => self.http.as_ref()?.location.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+// State from the probe outcomes
+//
+// An HTTP answer decides on its own: a captive portal usually also hijacks
+// DNS and TCP. Without one, a reachable TCP endpoint while names do not
+// resolve points at the DNS servers rather than the uplink.
+pub fn classify(
+    default_route: bool,
+    dns_resolved: bool,
+    tcp_reachable: bool,
+    http: Option<&HttpProbe>,
+    expected_status: u16,
+) -> Connectivity {
+    match http {
+        _ if !default_route => Connectivity::None,
+        Some(http) if http.status == expected_status => Connectivity::Full,
+        Some(_) => Connectivity::CaptivePortal,
+        None if tcp_reachable && !dns_resolved => Connectivity::DnsFailure,
+        None => Connectivity::LocalOnly,
+    }
+}
+
+// Probes DNS, TCP and HTTP to tell how far the device reaches
+#[derive(Debug, Clone, Default)]
+pub struct ConnectivityChecker {
+    config: ConnectivityConfig,
+}
+
+impl ConnectivityChecker {
+    pub fn new(config: ConnectivityConfig) -> Result<Self> {
+        parse_http_url(&config.probe_url)?;
+        Ok(ConnectivityChecker { config })
+    }
+
+    pub fn config(&self) -> &ConnectivityConfig {
+        &self.config
+    }
+
+    fn resolve(&self, host: &str, port: u16) -> Vec<SocketAddr> {
+        match (host, port).to_socket_addrs() {
+            Ok(addresses) => addresses.collect(),
+            Err(e) => {
+                trace!(
+                    task = "check_connectivity",
+                    "unable to resolve {}: {}",
+                    host,
+                    e
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    fn tcp_reachable(&self) -> bool {
+        self.config.tcp_endpoints.iter().any(|endpoint| {
+            let addresses = match endpoint.to_socket_addrs() {
+                Ok(addresses) => addresses,
+                Err(_) => return false,
+            };
+            addresses
+                .into_iter()
+                .any(|address| TcpStream::connect_timeout(&address, self.config.timeout).is_ok())
+        })
+    }
+
+    // GET the probe url, None when nothing answered
+    pub fn probe_http(&self) -> Option<HttpProbe> {
+        let (host, port, path) = parse_http_url(&self.config.probe_url).ok()?;
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: mecha-connectivity\r\n\
+             Connection: close\r\n\r\n",
+            path, host
+        );
+        self.resolve(&host, port).iter().find_map(|address| {
+            let mut stream = TcpStream::connect_timeout(address, self.config.timeout).ok()?;
+            stream.set_read_timeout(Some(self.config.timeout)).ok()?;
+            stream.set_write_timeout(Some(self.config.timeout)).ok()?;
+            stream.write_all(request.as_bytes()).ok()?;
+            let mut response = Vec::new();
+            let mut buffer = [0u8; 4096];
+            while response.len() < MAX_RESPONSE_LEN {
+                match stream.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(len) => response.extend_from_slice(&buffer[..len]),
+                    Err(_) if !response.is_empty() => break,
+                    Err(_) => return None,
+                }
+                if response.windows(4).any(|window| window == b"\r\n\r\n") {
+                    break;
+                }
+            }
+            parse_http_response(&String::from_utf8_lossy(&response))
+        })
+    }
+
+    // Run every probe, blocks for up to a few timeouts
+    pub fn check(&self) -> ConnectivityReport {
+        trace!(task = "check_connectivity", "init");
+        let default_route = netlink::default_routes()
+            .map(|routes| !routes.is_empty())
+            .unwrap_or(false);
+        let dns_host = match &self.config.dns_host {
+            Some(dns_host) => dns_host.clone(),
+            None => parse_http_url(&self.config.probe_url)
+                .map(|(host, _, _)| host)
+                .unwrap_or_default(),
+        };
+        let dns_resolved = !self.resolve(&dns_host, 80).is_empty();
+        let tcp_reachable = self.tcp_reachable();
+        let started = Instant::now();
+        let http = self.probe_http();
+        let latency = http.as_ref().map(|_| started.elapsed());
+        let state = classify(
+            default_route,
+            dns_resolved,
+            tcp_reachable,
+            http.as_ref(),
+            self.config.expected_status,
+        );
+        ConnectivityReport {
+            state,
+            default_route,
+            dns_resolved,
+            tcp_reachable,
+            http,
+            latency,
+        }
+    }
+}
+
+pub type ConnectivityReceiver = broadcast::Receiver<ConnectivityReport>;
+
+// Runs the checker periodically and publishes state changes
+#[derive(Debug, Clone)]
+pub struct ConnectivityMonitor {
+    checker: ConnectivityChecker,
+    events: broadcast::Sender<ConnectivityReport>,
+    last: Arc<Mutex<Option<ConnectivityReport>>>,
+}
+
+impl Default for ConnectivityMonitor {
+    fn default() -> Self {
+        Self::new(ConnectivityChecker::default())
+    }
+}
+
+impl ConnectivityMonitor {
+    pub fn new(checker: ConnectivityChecker) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        ConnectivityMonitor {
+            checker,
+            events,
+            last: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn subscribe(&self) -> ConnectivityReceiver {
+        self.events.subscribe()
+    }
+
+    // Report of the latest check, None before the first one finished
+    pub fn last_report(&self) -> Option<ConnectivityReport> {
+        match self.last.lock() {
+            Ok(last) => last.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    // Check now, subscribers hear about it when the state changed
+    pub async fn check(&self) -> Result<ConnectivityReport> {
+        let checker = self.checker.clone();
+        let report = match tokio::task::spawn_blocking(move || checker.check()).await {
+            Ok(report) => report,
+            Err(e) => {
+                trace_error!(task = "check_connectivity", "check failed: {}", e);
+                bail!(invalid_probe(format!("connectivity check failed: {}", e)))
+            }
+        };
+        let changed = {
+            let mut last = match self.last.lock() {
+                Ok(last) => last,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let changed = last.as_ref().map(|last| last.state) != Some(report.state);
+            *last = Some(report.clone());
+            changed
+        };
+        if changed {
+            info!(task = "check_connectivity", "connectivity {}", report.state);
+            // no subscribers is fine
+            let _ = self.events.send(report.clone());
+        }
+        Ok(report)
+    }
+
+    // Check every `interval` until the runtime shuts down
+    pub fn start(&self, interval: Duration) -> JoinHandle<()> {
+        let monitor = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = monitor.check().await {
+                    trace_error!(task = "connectivity_monitor", "{}", e);
+                }
+                tokio::time::sleep(interval).await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    // answers every connection with `response` and closes it
+    fn stand_in(response: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => break,
+                };
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        port
+    }
+
+    fn checker(port: u16) -> ConnectivityChecker {
+        ConnectivityChecker::new(ConnectivityConfig {
+            probe_url: format!("http://127.0.0.1:{}/generate_204", port),
+            tcp_endpoints: vec![format!("127.0.0.1:{}", port)],
+            timeout: Duration::from_secs(2),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_connectivity() {
+        assert_eq!(
+            parse_http_url("http://example.com:8080/check").unwrap(),
+            ("example.com".to_string(), 8080, "/check".to_string())
+        );
+        assert_eq!(parse_http_url("http://[::1]").unwrap().0, "::1");
+        assert!(parse_http_url("https://example.com/").is_err());
+
+        let online = checker(stand_in("HTTP/1.1 204 No Content\r\n\r\n"));
+        let http = online.probe_http().unwrap();
+        assert_eq!(http.status, 204);
+        assert!(online.tcp_reachable());
+        assert_eq!(
+            classify(true, true, true, Some(&http), 204),
+            Connectivity::Full
+        );
+        assert_eq!(
+            classify(false, true, true, Some(&http), 204),
+            Connectivity::None
+        );
+
+        let portal = checker(stand_in(
+            "HTTP/1.1 302 Found\r\nlocation: http://portal.lan/login\r\n\r\n",
+        ));
+        let http = portal.probe_http().unwrap();
+        assert_eq!(http.location.as_deref(), Some("http://portal.lan/login"));
+        let report = ConnectivityReport {
+            state: classify(true, true, true, Some(&http), 204),
+            default_route: true,
+            dns_resolved: true,
+            tcp_reachable: true,
+            http: Some(http),
+            latency: None,
+        };
+        assert_eq!(report.state, Connectivity::CaptivePortal);
+        assert_eq!(report.portal_url(), Some("http://portal.lan/login"));
+
+        // a closed port answers nothing
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = closed.local_addr().unwrap().port();
+        drop(closed);
+        let offline = checker(port);
+        assert!(offline.probe_http().is_none());
+        assert!(!offline.tcp_reachable());
+        assert_eq!(
+            classify(true, false, false, None, 204),
+            Connectivity::LocalOnly
+        );
+        assert_eq!(
+            classify(true, true, false, None, 204),
+            Connectivity::LocalOnly
+        );
+        // addresses answer while names do not resolve
+        assert_eq!(
+            classify(true, false, true, None, 204),
+            Connectivity::DnsFailure
+        );
+        assert_eq!(classify(false, false, true, None, 204), Connectivity::None);
+    }
+
+    #[tokio::test]
+    async fn test_monitor_publishes_changes() {
+        let monitor =
+            ConnectivityMonitor::new(checker(stand_in("HTTP/1.1 204 No Content\r\n\r\n")));
+        let mut events = monitor.subscribe();
+        let first = monitor.check().await.unwrap();
+        assert_eq!(events.try_recv().unwrap().state, first.state);
+        monitor.check().await.unwrap();
+        // same state, nothing published
+        assert!(events.try_recv().is_err());
+        assert_eq!(monitor.last_report().unwrap().state, first.state);
+    }
+}
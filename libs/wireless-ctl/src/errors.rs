@@ -10,6 +10,19 @@ pub enum WirelessNetworkErrorCodes {
     UnableToDisconnectWirelessNetwork,
     UnableToGetWirelessNetworkStatus,
     UnableToRemoveWirelessNetwork,
+    UnableToReachSupplicant,
+    WrongKey,
+    UnknownInterface,
+    UnableToQueryNetlink,
+    InvalidNetworkProfile,
+    UnableToStartHotspot,
+    HotspotNotRunning,
+    UnableToSaveNetwork,
+    UnableToConfigureIp,
+    UnableToSetInterfaceState,
+    UnableToCheckConnectivity,
+    InvalidWpsPin,
+    UnableToConnectWps,
     Unknown,
 }
 
@@ -31,6 +44,25 @@ impl std::fmt::Display for WirelessNetworkErrorCodes {
             WirelessNetworkErrorCodes::UnableToRemoveWirelessNetwork => {
                 write!(f, "UnableToRemoveWirelessNetwork")
             }
+            WirelessNetworkErrorCodes::UnableToReachSupplicant => {
+                write!(f, "UnableToReachSupplicant")
+            }
+            WirelessNetworkErrorCodes::WrongKey => write!(f, "WrongKey"),
+            WirelessNetworkErrorCodes::UnknownInterface => write!(f, "UnknownInterface"),
+            WirelessNetworkErrorCodes::UnableToQueryNetlink => write!(f, "UnableToQueryNetlink"),
+            WirelessNetworkErrorCodes::InvalidNetworkProfile => write!(f, "InvalidNetworkProfile"),
+            WirelessNetworkErrorCodes::UnableToStartHotspot => write!(f, "UnableToStartHotspot"),
+            WirelessNetworkErrorCodes::HotspotNotRunning => write!(f, "HotspotNotRunning"),
+            WirelessNetworkErrorCodes::UnableToSaveNetwork => write!(f, "UnableToSaveNetwork"),
+            WirelessNetworkErrorCodes::UnableToConfigureIp => write!(f, "UnableToConfigureIp"),
+            WirelessNetworkErrorCodes::UnableToSetInterfaceState => {
+                write!(f, "UnableToSetInterfaceState")
+            }
+            WirelessNetworkErrorCodes::UnableToCheckConnectivity => {
+                write!(f, "UnableToCheckConnectivity")
+            }
+            WirelessNetworkErrorCodes::InvalidWpsPin => write!(f, "InvalidWpsPin"),
+            WirelessNetworkErrorCodes::UnableToConnectWps => write!(f, "UnableToConnectWps"),
             WirelessNetworkErrorCodes::Unknown => write!(f, "Unknown"),
         }
     }
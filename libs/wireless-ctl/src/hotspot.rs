@@ -0,0 +1,521 @@
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use tracing::{error as trace_error, info};
+
+use crate::errors::{WirelessNetworkError, WirelessNetworkErrorCodes};
+use crate::netlink::InterfaceAddress;
+use crate::profile;
+
+// holds the state, lease and pid files of running hotspots
+pub const HOTSPOT_RUN_DIR: &str = "/run/mecha/hotspot";
+pub const DEFAULT_HOTSPOT_SSID: &str = "mecha-setup";
+pub const DEFAULT_DNSMASQ: &str = "dnsmasq";
+
+fn invalid_hotspot(message: String) -> WirelessNetworkError {
+    WirelessNetworkError::new(WirelessNetworkErrorCodes::UnableToStartHotspot, message)
+}
+
+// 20 MHz channels of the 5 GHz band an access point can use
+const FIVE_GHZ_CHANNELS: [u32; 25] = [
+    36, 40, 44, 48, 52, 56, 60, 64, 100, 104, 108, 112, 116, 120, 124, 128, 132, 136, 140, 144,
+    149, 153, 157, 161, 165,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Band {
+    TwoPointFourGhz,
+    FiveGhz,
+}
+
+impl Band {
+    // Frequency in MHz of `channel`, an error when the band has no such channel
+    pub fn frequency(&self, channel: u32) -> Result<u32> {
+        match (self, channel) {
+            (Band::TwoPointFourGhz, 1..=13) => Ok(2407 + 5 * channel),
+            (Band::TwoPointFourGhz, 14) => Ok(2484),
+            (Band::FiveGhz, channel) if FIVE_GHZ_CHANNELS.contains(&channel) => {
+                Ok(5000 + 5 * channel)
+            }
+            _ => bail!(invalid_hotspot(format!(
+                "channel {} is not in the {} band",
+                channel, self
+            ))),
+        }
+    }
+
+    pub fn default_channel(&self) -> u32 {
+        match self {
+            Band::TwoPointFourGhz => 6,
+            Band::FiveGhz => 36,
+        }
+    }
+}
+
+impl fmt::Display for Band {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Band::TwoPointFourGhz => write!(f, "2.4GHz"),
+            Band::FiveGhz => write!(f, "5GHz"),
+        }
+    }
+}
+
+impl FromStr for Band {
+    type Err = anyhow::Error;
+
+    fn from_str(band: &str) -> Result<Self> {
+        match band.trim().to_lowercase().trim_end_matches("ghz") {
+            "2.4" | "2" => Ok(Band::TwoPointFourGhz),
+            "5" => Ok(Band::FiveGhz),
+            _ => bail!(invalid_hotspot(format!(
+                "unknown band {}, expected 2.4 or 5",
+                band
+            ))),
+        }
+    }
+}
+
+// Access point run by wpa_supplicant, with addresses handed out by dnsmasq
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotspotConfig {
+    pub ssid: String,
+    // WPA2-PSK when set, open otherwise
+    pub passphrase: Option<String>,
+    pub band: Band,
+    pub channel: u32,
+    // address of the device on the hotspot network
+    pub address: Ipv4Addr,
+    pub prefix_len: u8,
+    pub dhcp_start: Ipv4Addr,
+    pub dhcp_end: Ipv4Addr,
+    // seconds
+    pub lease_time: u32,
+    // answer every DNS query with our address so clients open the setup page
+    pub captive: bool,
+    // `None` leaves address assignment to the clients
+    pub dnsmasq: Option<PathBuf>,
+}
+
+impl Default for HotspotConfig {
+    fn default() -> Self {
+        HotspotConfig {
+            ssid: DEFAULT_HOTSPOT_SSID.to_string(),
+            passphrase: None,
+            band: Band::TwoPointFourGhz,
+            channel: Band::TwoPointFourGhz.default_channel(),
+            address: Ipv4Addr::new(192, 168, 4, 1),
+            prefix_len: 24,
+            dhcp_start: Ipv4Addr::new(192, 168, 4, 10),
+            dhcp_end: Ipv4Addr::new(192, 168, 4, 100),
+            lease_time: 3600,
+            captive: true,
+            dnsmasq: Some(PathBuf::from(DEFAULT_DNSMASQ)),
+        }
+    }
+}
+
+impl HotspotConfig {
+    pub fn netmask(&self) -> Ipv4Addr {
+        let bits = u32::MAX
+            .checked_shl(32 - self.prefix_len as u32)
+            .unwrap_or(0);
+        Ipv4Addr::from(bits)
+    }
+
+    pub fn interface_address(&self) -> InterfaceAddress {
+        InterfaceAddress {
+            address: IpAddr::V4(self.address),
+            prefix_len: self.prefix_len,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        profile::validate_ssid(&self.ssid)?;
+        if let Some(passphrase) = &self.passphrase {
+            profile::validate_passphrase(passphrase)?;
+        }
+        self.band.frequency(self.channel)?;
+        if !(8..=30).contains(&self.prefix_len) {
+            bail!(invalid_hotspot(format!(
+                "prefix length {} leaves no room for clients",
+                self.prefix_len
+            )))
+        }
+        let netmask = u32::from(self.netmask());
+        let network = u32::from(self.address) & netmask;
+        let start = u32::from(self.dhcp_start);
+        let end = u32::from(self.dhcp_end);
+        if start & netmask != network || end & netmask != network || start > end {
+            bail!(invalid_hotspot(format!(
+                "DHCP range {} - {} is not inside {}/{}",
+                self.dhcp_start, self.dhcp_end, self.address, self.prefix_len
+            )))
+        }
+        let address = u32::from(self.address);
+        if (start..=end).contains(&address) {
+            bail!(invalid_hotspot(format!(
+                "DHCP range must not contain the hotspot address {}",
+                self.address
+            )))
+        }
+        Ok(())
+    }
+
+    // `SET_NETWORK` parameters of the access point network
+    pub fn network_settings(&self) -> Result<Vec<(&'static str, String)>> {
+        let frequency = self.band.frequency(self.channel)?;
+        let mut settings = vec![
+            ("ssid", profile::hex_ssid(&self.ssid)),
+            // access point
+            ("mode", String::from("2")),
+            ("frequency", frequency.to_string()),
+        ];
+        match &self.passphrase {
+            Some(passphrase) => {
+                settings.push(("key_mgmt", String::from("WPA-PSK")));
+                settings.push(("proto", String::from("RSN")));
+                settings.push(("pairwise", String::from("CCMP")));
+                settings.push(("group", String::from("CCMP")));
+                settings.push(("psk", profile::psk_value(passphrase)));
+            }
+            None => settings.push(("key_mgmt", String::from("NONE"))),
+        }
+        Ok(settings)
+    }
+
+    // Arguments of a dnsmasq serving DHCP, and DNS for captive hotspots, on `interface`
+    pub fn dnsmasq_args(&self, interface: &str, files: &HotspotFiles) -> Vec<String> {
+        let mut args = vec![
+            format!("--interface={}", interface),
+            String::from("--bind-interfaces"),
+            String::from("--except-interface=lo"),
+            format!("--listen-address={}", self.address),
+            String::from("--no-resolv"),
+            String::from("--no-hosts"),
+            String::from("--dhcp-authoritative"),
+            format!(
+                "--dhcp-range={},{},{},{}s",
+                self.dhcp_start,
+                self.dhcp_end,
+                self.netmask(),
+                self.lease_time
+            ),
+            format!("--dhcp-leasefile={}", files.leases.display()),
+            format!("--pid-file={}", files.pid.display()),
+        ];
+        match self.captive {
+            true => args.push(format!("--address=/#/{}", self.address)),
+            // DHCP only
+            false => args.push(String::from("--port=0")),
+        }
+        args
+    }
+}
+
+// Files of the hotspot on one interface
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotspotFiles {
+    pub state: PathBuf,
+    pub leases: PathBuf,
+    pub pid: PathBuf,
+}
+
+impl HotspotFiles {
+    pub fn new(run_dir: &Path, interface: &str) -> Self {
+        HotspotFiles {
+            state: run_dir.join(format!("{}.state", interface)),
+            leases: run_dir.join(format!("{}.leases", interface)),
+            pid: run_dir.join(format!("{}.pid", interface)),
+        }
+    }
+}
+
+// What is needed to switch back to station mode, kept on disk so any process
+// can stop the hotspot
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotspotState {
+    pub network_id: usize,
+    pub address: InterfaceAddress,
+    // station networks enabled before the hotspot started
+    pub enabled_networks: Vec<usize>,
+}
+
+impl HotspotState {
+    pub fn load(path: &Path) -> Option<Self> {
+        fs::read_to_string(path).ok()?.parse().ok()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for HotspotState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let enabled: Vec<String> = self
+            .enabled_networks
+            .iter()
+            .map(|network_id| network_id.to_string())
+            .collect();
+        writeln!(f, "network_id={}", self.network_id)?;
+        writeln!(f, "address={}", self.address)?;
+        writeln!(f, "enabled_networks={}", enabled.join(","))
+    }
+}
+
+impl FromStr for HotspotState {
+    type Err = anyhow::Error;
+
+    fn from_str(state: &str) -> Result<Self> {
+        let fields: HashMap<&str, &str> = state
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .collect();
+        let invalid = || anyhow!(invalid_hotspot(String::from("invalid hotspot state")));
+        let network_id = fields
+            .get("network_id")
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(invalid)?;
+        let (address, prefix_len) = fields
+            .get("address")
+            .and_then(|address| address.split_once('/'))
+            .ok_or_else(invalid)?;
+        let address = InterfaceAddress {
+            address: address.parse().map_err(|_| invalid())?,
+            prefix_len: prefix_len.parse().map_err(|_| invalid())?,
+        };
+        let enabled_networks = fields
+            .get("enabled_networks")
+            .map(|ids| ids.split(',').filter_map(|id| id.parse().ok()).collect())
+            .unwrap_or_default();
+        Ok(HotspotState {
+            network_id,
+            address,
+            enabled_networks,
+        })
+    }
+}
+
+// A station associated with the hotspot
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HotspotClient {
+    pub mac: String,
+    pub ip: Option<Ipv4Addr>,
+    pub hostname: Option<String>,
+    // seconds
+    pub connected_time: Option<u64>,
+}
+
+// Station of a `STA-FIRST`/`STA-NEXT` reply, `None` past the last one
+pub fn parse_station(reply: &str) -> Option<HotspotClient> {
+    let mut lines = reply.lines();
+    let mac = lines.next()?.trim();
+    if mac.is_empty() || mac == "FAIL" || mac.contains('=') {
+        return None;
+    }
+    let mut client = HotspotClient {
+        mac: mac.to_lowercase(),
+        ..Default::default()
+    };
+    for line in lines {
+        if let Some(("connected_time", time)) = line.trim().split_once('=') {
+            client.connected_time = time.parse().ok();
+        }
+    }
+    Some(client)
+}
+
+// (address, hostname) by MAC from a dnsmasq lease file
+pub fn parse_leases(leases: &str) -> HashMap<String, (Ipv4Addr, Option<String>)> {
+    // <expiry> <mac> <ip> <hostname or *> <client id>
+    leases
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let ip = fields.get(2)?.parse().ok()?;
+            let hostname = fields
+                .get(3)
+                .filter(|hostname| **hostname != "*")
+                .map(|hostname| hostname.to_string());
+            Some((fields.get(1)?.to_lowercase(), (ip, hostname)))
+        })
+        .collect()
+}
+
+// Run dnsmasq, it forks to the background once it is serving
+pub fn start_dnsmasq(dnsmasq: &Path, args: &[String]) -> Result<()> {
+    let output = match Command::new(dnsmasq).args(args).output() {
+        Ok(output) => output,
+        Err(e) => {
+            trace_error!(
+                task = "start_dnsmasq",
+                "unable to run {}: {}",
+                dnsmasq.display(),
+                e
+            );
+            bail!(invalid_hotspot(format!(
+                "unable to run {}: {}",
+                dnsmasq.display(),
+                e
+            )))
+        }
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        trace_error!(task = "start_dnsmasq", "dnsmasq failed: {}", stderr.trim());
+        bail!(invalid_hotspot(format!(
+            "dnsmasq failed: {}",
+            stderr.trim()
+        )))
+    }
+    Ok(())
+}
+
+// Whether the NUL separated `cmdline` of a process is the dnsmasq writing
+// `pid_file`, a stale pid may since belong to another process
+fn is_dnsmasq_of(cmdline: &[u8], pid_file: &Path) -> bool {
+    let pid_file_arg = format!("--pid-file={}", pid_file.display());
+    let mut args = cmdline
+        .split(|byte| *byte == 0)
+        .map(String::from_utf8_lossy);
+    let is_dnsmasq = match args.next() {
+        Some(program) => program
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .contains("dnsmasq"),
+        None => false,
+    };
+    is_dnsmasq && args.any(|arg| arg == pid_file_arg)
+}
+
+// Stop the dnsmasq of `pid_file`, if any
+pub fn stop_dnsmasq(pid_file: &Path) {
+    let pid = fs::read_to_string(pid_file)
+        .ok()
+        .and_then(|pid| pid.trim().parse::<libc::pid_t>().ok())
+        .filter(|pid| *pid > 0);
+    if let Some(pid) = pid {
+        let cmdline = fs::read(format!("/proc/{}/cmdline", pid)).unwrap_or_default();
+        if is_dnsmasq_of(&cmdline, pid_file) {
+            info!(task = "stop_dnsmasq", "stopping dnsmasq {}", pid);
+            // SAFETY: kill has no memory safety requirements
+            if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+                info!(
+                    task = "stop_dnsmasq",
+                    "unable to stop dnsmasq {}: {}",
+                    pid,
+                    std::io::Error::last_os_error()
+                );
+            }
+        } else {
+            info!(
+                task = "stop_dnsmasq",
+                "process {} is not the hotspot dnsmasq, leaving it running", pid
+            );
+        }
+    }
+    let _ = fs::remove_file(pid_file);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hotspot_config() {
+        let config = HotspotConfig {
+            passphrase: Some(String::from("provision me")),
+            ..Default::default()
+        };
+        config.validate().unwrap();
+        assert_eq!(config.netmask(), Ipv4Addr::new(255, 255, 255, 0));
+        let settings = config.network_settings().unwrap();
+        assert!(settings.contains(&("mode", String::from("2"))));
+        assert!(settings.contains(&("frequency", String::from("2437"))));
+        assert!(settings.contains(&("psk", String::from("\"provision me\""))));
+
+        let files = HotspotFiles::new(Path::new("/run/hotspot"), "wlan0");
+        let args = config.dnsmasq_args("wlan0", &files);
+        assert!(args.contains(&String::from(
+            "--dhcp-range=192.168.4.10,192.168.4.100,255.255.255.0,3600s"
+        )));
+        assert!(args.contains(&String::from("--address=/#/192.168.4.1")));
+        assert!(args.contains(&String::from("--pid-file=/run/hotspot/wlan0.pid")));
+
+        let invalid = |config: HotspotConfig| config.validate().is_err();
+        assert!(invalid(HotspotConfig {
+            channel: 36,
+            ..Default::default()
+        }));
+        assert!(invalid(HotspotConfig {
+            dhcp_start: Ipv4Addr::new(192, 168, 4, 1),
+            ..Default::default()
+        }));
+        assert!(invalid(HotspotConfig {
+            dhcp_end: Ipv4Addr::new(10, 0, 0, 1),
+            ..Default::default()
+        }));
+        assert!(invalid(HotspotConfig {
+            band: Band::FiveGhz,
+            channel: 37,
+            ..Default::default()
+        }));
+        assert!(invalid(HotspotConfig {
+            band: Band::FiveGhz,
+            channel: 99,
+            ..Default::default()
+        }));
+        assert_eq!(Band::FiveGhz.frequency(149).unwrap(), 5745);
+        assert_eq!("5GHz".parse::<Band>().unwrap(), Band::FiveGhz);
+        assert!("6".parse::<Band>().is_err());
+    }
+
+    #[test]
+    fn test_hotspot_state_and_clients() {
+        let state = HotspotState {
+            network_id: 4,
+            address: HotspotConfig::default().interface_address(),
+            enabled_networks: vec![0, 2],
+        };
+        assert_eq!(state.to_string().parse::<HotspotState>().unwrap(), state);
+
+        let client =
+            parse_station("AA:BB:CC:DD:EE:FF\nflags=[AUTH][ASSOC]\nconnected_time=42\n").unwrap();
+        assert_eq!(client.mac, "aa:bb:cc:dd:ee:ff");
+        assert_eq!(client.connected_time, Some(42));
+        assert_eq!(parse_station("FAIL\n"), None);
+        assert_eq!(parse_station(""), None);
+
+        let leases = parse_leases(
+            "1700000000 aa:bb:cc:dd:ee:ff 192.168.4.23 phone 01:aa:bb:cc:dd:ee:ff\n\
+             1700000100 11:22:33:44:55:66 192.168.4.24 * *\n",
+        );
+        assert_eq!(
+            leases["aa:bb:cc:dd:ee:ff"],
+            (Ipv4Addr::new(192, 168, 4, 23), Some(String::from("phone")))
+        );
+        assert_eq!(leases["11:22:33:44:55:66"].1, None);
+
+        let pid_file = Path::new("/run/hotspot/wlan0.pid");
+        let cmdline =
+            b"/usr/sbin/dnsmasq\0--keep-in-foreground\0--pid-file=/run/hotspot/wlan0.pid\0";
+        assert!(is_dnsmasq_of(cmdline, pid_file));
+        assert!(!is_dnsmasq_of(cmdline, Path::new("/run/hotspot/wlan1.pid")));
+        assert!(!is_dnsmasq_of(
+            b"/usr/bin/sleep\0--pid-file=/run/hotspot/wlan0.pid\0",
+            pid_file
+        ));
+        assert!(!is_dnsmasq_of(b"", pid_file));
+    }
+}
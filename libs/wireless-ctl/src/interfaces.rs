@@ -0,0 +1,209 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{error as trace_error, info, trace};
+
+use crate::errors::{WirelessNetworkError, WirelessNetworkErrorCodes};
+use crate::hotspot::HOTSPOT_RUN_DIR;
+use crate::wireless_network::WirelessNetworkControl;
+
+pub const SYS_CLASS_NET: &str = "/sys/class/net";
+pub const DEFAULT_CONTROL_DIR: &str = "/var/run/wpa_supplicant";
+// used when no interface is configured and none is discovered
+pub const DEFAULT_INTERFACE: &str = "wlan0";
+
+// IFF_UP in /sys/class/net/<interface>/flags
+const IFF_UP: u32 = 0x1;
+
+// Names of the wireless interfaces under `sys_class_net`, sorted
+pub fn wireless_interfaces(sys_class_net: &Path) -> Result<Vec<String>> {
+    trace!(task = "wireless_interfaces", "init");
+    let entries = match fs::read_dir(sys_class_net) {
+        Ok(entries) => entries,
+        Err(e) => {
+            trace_error!(
+                task = "wireless_interfaces",
+                "unable to read {}: {}",
+                sys_class_net.display(),
+                e
+            );
+            bail!(WirelessNetworkError::new(
+                WirelessNetworkErrorCodes::NoWirelessNetworkFound,
+                format!("unable to read {}: {}", sys_class_net.display(), e),
+            ))
+        }
+    };
+    let mut interfaces: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| is_wireless(sys_class_net, name))
+        .collect();
+    interfaces.sort();
+    Ok(interfaces)
+}
+
+// cfg80211 drivers expose `phy80211`, wireless extensions `wireless`
+pub fn is_wireless(sys_class_net: &Path, interface: &str) -> bool {
+    let path = sys_class_net.join(interface);
+    path.join("phy80211").exists() || path.join("wireless").exists()
+}
+
+// Whether the interface is administratively up
+pub fn interface_is_up(sys_class_net: &Path, interface: &str) -> bool {
+    let flags = match fs::read_to_string(sys_class_net.join(interface).join("flags")) {
+        Ok(flags) => flags,
+        Err(_) => return false,
+    };
+    let flags = flags.trim();
+    u32::from_str_radix(flags.trim_start_matches("0x"), 16)
+        .map(|flags| flags & IFF_UP != 0)
+        .unwrap_or(false)
+}
+
+pub(crate) fn valid_interface_name(interface: &str) -> bool {
+    !interface.is_empty() && interface != "." && interface != ".." && !interface.contains('/')
+}
+
+// Wireless interfaces of the device, each with its own supplicant session
+#[derive(Clone)]
+pub struct WirelessInterfaces {
+    sys_class_net: PathBuf,
+    control_dir: PathBuf,
+    run_dir: PathBuf,
+    default_interface: Option<String>,
+    controls: Arc<Mutex<HashMap<String, WirelessNetworkControl>>>,
+}
+
+impl Default for WirelessInterfaces {
+    fn default() -> Self {
+        Self::new(DEFAULT_CONTROL_DIR, None)
+    }
+}
+
+impl WirelessInterfaces {
+    // `control_dir` holds the supplicant control sockets, one per interface
+    pub fn new<P: Into<PathBuf>>(control_dir: P, default_interface: Option<&str>) -> Self {
+        WirelessInterfaces {
+            sys_class_net: PathBuf::from(SYS_CLASS_NET),
+            control_dir: control_dir.into(),
+            run_dir: PathBuf::from(HOTSPOT_RUN_DIR),
+            default_interface: default_interface.map(str::to_string),
+            controls: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // look interfaces up somewhere other than /sys/class/net
+    pub fn with_sys_class_net<P: Into<PathBuf>>(mut self, sys_class_net: P) -> Self {
+        self.sys_class_net = sys_class_net.into();
+        self
+    }
+
+    // keep hotspot state somewhere other than /run/mecha/hotspot
+    pub fn with_run_dir<P: Into<PathBuf>>(mut self, run_dir: P) -> Self {
+        self.run_dir = run_dir.into();
+        self
+    }
+
+    pub fn control_dir(&self) -> &Path {
+        &self.control_dir
+    }
+
+    pub fn list(&self) -> Result<Vec<String>> {
+        wireless_interfaces(&self.sys_class_net)
+    }
+
+    // The configured interface, else the first one discovered
+    pub fn default_interface(&self) -> String {
+        if let Some(interface) = &self.default_interface {
+            return interface.clone();
+        }
+        self.list()
+            .ok()
+            .and_then(|interfaces| interfaces.into_iter().next())
+            .unwrap_or_else(|| DEFAULT_INTERFACE.to_string())
+    }
+
+    // Control of `interface`, the default interface when it is `None` or empty
+    pub fn control(&self, interface: Option<&str>) -> Result<WirelessNetworkControl> {
+        let interface = match interface.filter(|interface| !interface.is_empty()) {
+            Some(interface) => {
+                let known = valid_interface_name(interface)
+                    && (is_wireless(&self.sys_class_net, interface)
+                        || self.control_dir.join(interface).exists());
+                if !known {
+                    trace_error!(
+                        task = "wireless_interface",
+                        "{} is not a wireless interface",
+                        interface
+                    );
+                    bail!(WirelessNetworkError::new(
+                        WirelessNetworkErrorCodes::UnknownInterface,
+                        format!("{} is not a wireless interface", interface),
+                    ))
+                }
+                interface.to_string()
+            }
+            None => self.default_interface(),
+        };
+
+        let mut controls = match self.controls.lock() {
+            Ok(controls) => controls,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let control = controls.entry(interface.clone()).or_insert_with(|| {
+            info!(task = "wireless_interface", "using interface {}", interface);
+            WirelessNetworkControl::for_interface(&self.control_dir, &interface)
+                .with_sys_class_net(&self.sys_class_net)
+                .with_run_dir(&self.run_dir)
+        });
+        Ok(control.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn add_interface(dir: &Path, name: &str, marker: Option<&str>, flags: &str) {
+        let path = dir.join(name);
+        fs::create_dir(&path).unwrap();
+        if let Some(marker) = marker {
+            fs::create_dir(path.join(marker)).unwrap();
+        }
+        fs::write(path.join("flags"), flags).unwrap();
+    }
+
+    #[test]
+    fn test_wireless_interfaces() {
+        let dir = TempDir::new("net").unwrap();
+        add_interface(dir.path(), "wlan1", Some("phy80211"), "0x1003\n");
+        add_interface(dir.path(), "eth0", None, "0x1003\n");
+        add_interface(dir.path(), "wlan0", Some("wireless"), "0x1002\n");
+
+        assert_eq!(
+            wireless_interfaces(dir.path()).unwrap(),
+            vec!["wlan0", "wlan1"]
+        );
+        assert!(interface_is_up(dir.path(), "wlan1"));
+        assert!(!interface_is_up(dir.path(), "wlan0"));
+        assert!(!interface_is_up(dir.path(), "wlan2"));
+
+        let interfaces =
+            WirelessInterfaces::new(dir.path().join("run"), None).with_sys_class_net(dir.path());
+        assert_eq!(interfaces.default_interface(), "wlan0");
+        assert_eq!(
+            interfaces.control(Some("wlan1")).unwrap().interface(),
+            "wlan1"
+        );
+        assert_eq!(interfaces.control(Some("")).unwrap().interface(), "wlan0");
+        assert!(interfaces.control(Some("eth0")).is_err());
+        assert!(interfaces.control(Some("../wlan0")).is_err());
+
+        let configured = WirelessInterfaces::new(dir.path().join("run"), Some("wlan1"))
+            .with_sys_class_net(dir.path());
+        assert_eq!(configured.control(None).unwrap().interface(), "wlan1");
+    }
+}
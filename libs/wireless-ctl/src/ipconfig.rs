@@ -0,0 +1,576 @@
+use anyhow::{anyhow, bail, Result};
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use tracing::{error as trace_error, info, trace};
+
+use crate::errors::{WirelessNetworkError, WirelessNetworkErrorCodes};
+use crate::netlink::{self, InterfaceAddress};
+
+pub const SYSTEMD_NETWORK_DIR: &str = "/etc/systemd/network";
+pub const NETWORKD_LEASE_DIR: &str = "/run/systemd/netif/leases";
+pub const RESOLV_CONF: &str = "/etc/resolv.conf";
+pub const DEFAULT_NETWORKCTL: &str = "networkctl";
+
+// drop-ins we write are named 50-mecha-<interface>.network
+const UNIT_PREFIX: &str = "50-mecha-";
+
+fn invalid_ip_config(message: String) -> WirelessNetworkError {
+    WirelessNetworkError::new(WirelessNetworkErrorCodes::UnableToConfigureIp, message)
+}
+
+// How an interface gets its addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpMethod {
+    Dhcp,
+    Static,
+}
+
+impl fmt::Display for IpMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IpMethod::Dhcp => write!(f, "dhcp"),
+            IpMethod::Static => write!(f, "static"),
+        }
+    }
+}
+
+impl FromStr for IpMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(method: &str) -> Result<Self> {
+        match method.trim().to_lowercase().as_str() {
+            "dhcp" | "auto" => Ok(IpMethod::Dhcp),
+            "static" | "manual" => Ok(IpMethod::Static),
+            _ => bail!(invalid_ip_config(format!(
+                "unknown method {}, expected dhcp or static",
+                method
+            ))),
+        }
+    }
+}
+
+// Who applies the configuration
+//
+// `Networkd` writes a systemd-networkd drop-in and lets networkd run DHCP,
+// set routes and hand DNS to resolved, the configuration survives reboots.
+// `Netlink` sets addresses and routes directly and writes resolv.conf, it
+// is for images without networkd and only supports static addressing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpBackend {
+    Networkd,
+    Netlink,
+}
+
+impl FromStr for IpBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(backend: &str) -> Result<Self> {
+        match backend.trim().to_lowercase().as_str() {
+            "networkd" | "systemd-networkd" => Ok(IpBackend::Networkd),
+            "netlink" | "rtnetlink" => Ok(IpBackend::Netlink),
+            _ => bail!(invalid_ip_config(format!(
+                "unknown ip backend {}, expected networkd or netlink",
+                backend
+            ))),
+        }
+    }
+}
+
+// Layer 3 configuration of an interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpConfig {
+    pub method: IpMethod,
+    // static addresses, IPv4 and IPv6 may be mixed
+    pub addresses: Vec<InterfaceAddress>,
+    // default gateways, at most one per family
+    pub gateways: Vec<IpAddr>,
+    // name servers, with DHCP they are used on top of the leased ones
+    pub dns: Vec<IpAddr>,
+    pub search_domains: Vec<String>,
+}
+
+impl IpConfig {
+    pub fn dhcp() -> Self {
+        IpConfig {
+            method: IpMethod::Dhcp,
+            addresses: Vec::new(),
+            gateways: Vec::new(),
+            dns: Vec::new(),
+            search_domains: Vec::new(),
+        }
+    }
+
+    pub fn static_addresses(addresses: Vec<InterfaceAddress>) -> Self {
+        IpConfig {
+            method: IpMethod::Static,
+            addresses,
+            ..Self::dhcp()
+        }
+    }
+
+    // Configuration from user input such as `192.168.1.50/24` and `1.1.1.1`
+    pub fn from_args(
+        method: IpMethod,
+        addresses: &[String],
+        gateways: &[String],
+        dns: &[String],
+        search_domains: &[String],
+    ) -> Result<Self> {
+        let ip = |value: &String| -> Result<IpAddr> {
+            value.trim().parse().map_err(|_| {
+                anyhow!(invalid_ip_config(format!("invalid ip address {}", value)))
+            })
+        };
+        let config = IpConfig {
+            method,
+            addresses: addresses
+                .iter()
+                .map(|address| address.parse())
+                .collect::<Result<_>>()?,
+            gateways: gateways.iter().map(ip).collect::<Result<_>>()?,
+            dns: dns.iter().map(ip).collect::<Result<_>>()?,
+            search_domains: search_domains.to_vec(),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self.method {
+            IpMethod::Static if self.addresses.is_empty() => {
+                bail!(invalid_ip_config(
+                    "static configuration needs at least one address".to_string()
+                ))
+            }
+            IpMethod::Dhcp if !self.addresses.is_empty() => {
+                bail!(invalid_ip_config(
+                    "addresses are only set with the static method".to_string()
+                ))
+            }
+            _ => {}
+        }
+        for gateway in &self.gateways {
+            let same_family = self
+                .gateways
+                .iter()
+                .filter(|other| other.is_ipv4() == gateway.is_ipv4())
+                .count();
+            if same_family > 1 {
+                bail!(invalid_ip_config(
+                    "only one gateway per address family is allowed".to_string()
+                ))
+            }
+            if gateway.is_unspecified() || gateway.is_multicast() {
+                bail!(invalid_ip_config(format!("invalid gateway {}", gateway)))
+            }
+        }
+        for domain in &self.search_domains {
+            if domain.is_empty() || domain.contains(char::is_whitespace) {
+                bail!(invalid_ip_config(format!(
+                    "invalid search domain {:?}",
+                    domain
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    // systemd-networkd .network unit for `interface`
+    pub fn networkd_unit(&self, interface: &str) -> String {
+        let mut unit = String::from("# managed by mecha, changes are overwritten\n");
+        unit.push_str(&format!("[Match]\nName={}\n\n[Network]\n", interface));
+        let dhcp = match self.method {
+            IpMethod::Dhcp => "yes",
+            IpMethod::Static => "no",
+        };
+        unit.push_str(&format!("DHCP={}\n", dhcp));
+        for address in &self.addresses {
+            unit.push_str(&format!("Address={}\n", address));
+        }
+        for gateway in &self.gateways {
+            unit.push_str(&format!("Gateway={}\n", gateway));
+        }
+        for dns in &self.dns {
+            unit.push_str(&format!("DNS={}\n", dns));
+        }
+        if !self.search_domains.is_empty() {
+            unit.push_str(&format!("Domains={}\n", self.search_domains.join(" ")));
+        }
+        unit
+    }
+
+    pub fn resolv_conf(&self) -> String {
+        let mut resolv_conf = String::from("# managed by mecha, changes are overwritten\n");
+        for dns in &self.dns {
+            resolv_conf.push_str(&format!("nameserver {}\n", dns));
+        }
+        if !self.search_domains.is_empty() {
+            resolv_conf.push_str(&format!("search {}\n", self.search_domains.join(" ")));
+        }
+        resolv_conf
+    }
+}
+
+// Lease networkd holds for an interface
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DhcpLease {
+    pub address: Option<InterfaceAddress>,
+    pub router: Option<IpAddr>,
+    // DHCP server that handed out the lease
+    pub server: Option<IpAddr>,
+    pub dns: Vec<IpAddr>,
+    pub domain: Option<String>,
+    // seconds the lease is valid for
+    pub lifetime: Option<u64>,
+}
+
+fn prefix_len(netmask: Ipv4Addr) -> u8 {
+    u32::from(netmask).count_ones() as u8
+}
+
+// Parses a networkd lease file, KEY=value lines
+pub fn parse_lease(contents: &str) -> DhcpLease {
+    let mut lease = DhcpLease::default();
+    let mut address: Option<Ipv4Addr> = None;
+    let mut netmask: Option<Ipv4Addr> = None;
+    for line in contents.lines() {
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) if !line.starts_with('#') => (key.trim(), value.trim()),
+            _ => continue,
+        };
+        match key {
+            "ADDRESS" => address = value.parse().ok(),
+            "NETMASK" => netmask = value.parse().ok(),
+            // networkd writes every router it was offered
+            "ROUTER" => {
+                lease.router = value
+                    .split_whitespace()
+                    .find_map(|router| router.parse().ok())
+            }
+            "SERVER_ADDRESS" => lease.server = value.parse().ok(),
+            "DNS" => {
+                lease.dns = value
+                    .split_whitespace()
+                    .filter_map(|dns| dns.parse().ok())
+                    .collect()
+            }
+            "DOMAINNAME" => lease.domain = Some(value.to_string()),
+            "LIFETIME" => lease.lifetime = value.parse().ok(),
+            _ => {}
+        }
+    }
+    lease.address = address.map(|address| InterfaceAddress {
+        address: IpAddr::V4(address),
+        prefix_len: netmask.map(prefix_len).unwrap_or(32),
+    });
+    lease
+}
+
+// Name servers of a resolv.conf
+pub fn parse_resolv_conf(contents: &str) -> Vec<IpAddr> {
+    contents
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|dns| dns.trim().parse().ok())
+        .collect()
+}
+
+// Current layer 3 state of an interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpStatus {
+    pub interface: String,
+    // method we configured, None when the interface is not managed by us
+    pub method: Option<IpMethod>,
+    pub addresses: Vec<InterfaceAddress>,
+    pub gateways: Vec<IpAddr>,
+    pub dns: Vec<IpAddr>,
+    pub lease: Option<DhcpLease>,
+}
+
+// Applies and reports IP configuration of interfaces
+#[derive(Debug, Clone)]
+pub struct IpConfigurator {
+    backend: IpBackend,
+    network_dir: PathBuf,
+    lease_dir: PathBuf,
+    resolv_conf: PathBuf,
+    networkctl: PathBuf,
+}
+
+impl Default for IpConfigurator {
+    fn default() -> Self {
+        Self::new(IpBackend::Networkd)
+    }
+}
+
+impl IpConfigurator {
+    pub fn new(backend: IpBackend) -> Self {
+        IpConfigurator {
+            backend,
+            network_dir: PathBuf::from(SYSTEMD_NETWORK_DIR),
+            lease_dir: PathBuf::from(NETWORKD_LEASE_DIR),
+            resolv_conf: PathBuf::from(RESOLV_CONF),
+            networkctl: PathBuf::from(DEFAULT_NETWORKCTL),
+        }
+    }
+
+    pub fn with_network_dir<P: Into<PathBuf>>(mut self, network_dir: P) -> Self {
+        self.network_dir = network_dir.into();
+        self
+    }
+
+    pub fn with_lease_dir<P: Into<PathBuf>>(mut self, lease_dir: P) -> Self {
+        self.lease_dir = lease_dir.into();
+        self
+    }
+
+    pub fn with_resolv_conf<P: Into<PathBuf>>(mut self, resolv_conf: P) -> Self {
+        self.resolv_conf = resolv_conf.into();
+        self
+    }
+
+    pub fn with_networkctl<P: Into<PathBuf>>(mut self, networkctl: P) -> Self {
+        self.networkctl = networkctl.into();
+        self
+    }
+
+    pub fn backend(&self) -> IpBackend {
+        self.backend
+    }
+
+    fn unit_path(&self, interface: &str) -> PathBuf {
+        self.network_dir
+            .join(format!("{}{}.network", UNIT_PREFIX, interface))
+    }
+
+    // Configure `interface`, replacing what was set before
+    pub fn apply(&self, interface: &str, config: &IpConfig) -> Result<()> {
+        trace!(task = "apply_ip_config", "init");
+        config.validate()?;
+        netlink::interface_index(interface)?;
+        match self.backend {
+            IpBackend::Networkd => self.apply_networkd(interface, config)?,
+            IpBackend::Netlink => self.apply_netlink(interface, config)?,
+        }
+        info!(
+            task = "apply_ip_config",
+            "{} configured with {}", interface, config.method
+        );
+        Ok(())
+    }
+
+    fn apply_networkd(&self, interface: &str, config: &IpConfig) -> Result<()> {
+        write_atomic(&self.unit_path(interface), &config.networkd_unit(interface))?;
+        self.networkctl(&["reload"])?;
+        self.networkctl(&["reconfigure", interface])
+    }
+
+    fn apply_netlink(&self, interface: &str, config: &IpConfig) -> Result<()> {
+        if config.method == IpMethod::Dhcp {
+            trace_error!(task = "apply_ip_config", "dhcp needs networkd");
+            bail!(invalid_ip_config(
+                "DHCP needs the networkd ip backend".to_string()
+            ))
+        }
+        for address in netlink::interface_addresses(interface)? {
+            if !config.addresses.contains(&address) && !is_link_local(&address.address) {
+                netlink::remove_address(interface, &address)?;
+            }
+        }
+        for address in &config.addresses {
+            netlink::add_address(interface, address)?;
+        }
+        for gateway in &config.gateways {
+            netlink::add_default_route(interface, *gateway)?;
+        }
+        if !config.dns.is_empty() {
+            write_atomic(&self.resolv_conf, &config.resolv_conf())?;
+        }
+        Ok(())
+    }
+
+    fn networkctl(&self, args: &[&str]) -> Result<()> {
+        let output = match Command::new(&self.networkctl).args(args).output() {
+            Ok(output) => output,
+            Err(e) => {
+                trace_error!(
+                    task = "networkctl",
+                    "unable to run {}: {}",
+                    self.networkctl.display(),
+                    e
+                );
+                bail!(invalid_ip_config(format!(
+                    "unable to run {}: {}",
+                    self.networkctl.display(),
+                    e
+                )))
+            }
+        };
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            trace_error!(
+                task = "networkctl",
+                "networkctl {} failed: {}",
+                args.join(" "),
+                stderr.trim()
+            );
+            bail!(invalid_ip_config(format!(
+                "networkctl {} failed: {}",
+                args.join(" "),
+                stderr.trim()
+            )))
+        }
+        Ok(())
+    }
+
+    // Method we configured for `interface`, read back from our drop-in
+    pub fn configured_method(&self, interface: &str) -> Option<IpMethod> {
+        let unit = fs::read_to_string(self.unit_path(interface)).ok()?;
+        match unit.lines().any(|line| line.trim() == "DHCP=yes") {
+            true => Some(IpMethod::Dhcp),
+            false => Some(IpMethod::Static),
+        }
+    }
+
+    // DHCP lease of `interface`, None when it holds none
+    pub fn lease(&self, interface: &str) -> Result<Option<DhcpLease>> {
+        trace!(task = "dhcp_lease", "init");
+        let index = netlink::interface_index(interface)?;
+        if self.backend != IpBackend::Networkd {
+            return Ok(None);
+        }
+        Ok(fs::read_to_string(self.lease_dir.join(index.to_string()))
+            .ok()
+            .map(|contents| parse_lease(&contents)))
+    }
+
+    pub fn status(&self, interface: &str) -> Result<IpStatus> {
+        trace!(task = "ip_status", "init");
+        let addresses = netlink::interface_addresses(interface)?;
+        let gateways = netlink::default_routes()?
+            .into_iter()
+            .filter(|route| route.interface.as_deref() == Some(interface))
+            .filter_map(|route| route.gateway)
+            .collect();
+        let dns = fs::read_to_string(&self.resolv_conf)
+            .map(|contents| parse_resolv_conf(&contents))
+            .unwrap_or_default();
+        Ok(IpStatus {
+            interface: interface.to_string(),
+            method: self.configured_method(interface),
+            addresses,
+            gateways,
+            dns,
+            lease: self.lease(interface)?,
+        })
+    }
+}
+
+fn is_link_local(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => address.is_link_local(),
+        IpAddr::V6(address) => (address.segments()[0] & 0xffc0) == 0xfe80,
+    }
+}
+
+// Write through a temporary file so readers never see half a file
+fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let write = || -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temporary = path.with_extension("mecha-tmp");
+        fs::write(&temporary, contents)?;
+        fs::rename(&temporary, path)
+    };
+    write().map_err(|e| {
+        trace_error!(
+            task = "write_ip_config",
+            "unable to write {}: {}",
+            path.display(),
+            e
+        );
+        anyhow!(invalid_ip_config(format!(
+            "unable to write {}: {}",
+            path.display(),
+            e
+        )))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_ip_config() {
+        let mut config = IpConfig::static_addresses(vec![
+            "192.168.1.50/24".parse().unwrap(),
+            "fd00::50/64".parse().unwrap(),
+        ]);
+        config.gateways = vec!["192.168.1.1".parse().unwrap()];
+        config.dns = vec!["1.1.1.1".parse().unwrap()];
+        config.search_domains = vec!["lan".to_string()];
+        config.validate().unwrap();
+        let unit = config.networkd_unit("wlan0");
+        assert!(unit.contains("Name=wlan0\n"));
+        assert!(unit.contains("DHCP=no\nAddress=192.168.1.50/24\nAddress=fd00::50/64\n"));
+        assert!(unit.contains("Gateway=192.168.1.1\nDNS=1.1.1.1\nDomains=lan\n"));
+        assert!(config
+            .resolv_conf()
+            .ends_with("nameserver 1.1.1.1\nsearch lan\n"));
+
+        let args = |values: &[&str]| -> Vec<String> {
+            values.iter().map(|value| value.to_string()).collect()
+        };
+        let parsed = IpConfig::from_args(
+            IpMethod::Static,
+            &args(&["192.168.1.50/24", "fd00::50/64"]),
+            &args(&["192.168.1.1"]),
+            &args(&["1.1.1.1"]),
+            &args(&["lan"]),
+        );
+        assert_eq!(parsed.unwrap(), config);
+        let invalid_dns = IpConfig::from_args(IpMethod::Dhcp, &[], &[], &args(&["one"]), &[]);
+        assert!(invalid_dns.is_err());
+
+        config.gateways.push("192.168.1.254".parse().unwrap());
+        assert!(config.validate().is_err());
+        assert!(IpConfig::static_addresses(Vec::new()).validate().is_err());
+        assert!(IpConfig::dhcp()
+            .networkd_unit("eth0")
+            .contains("DHCP=yes\n"));
+
+        let lease = parse_lease(
+            "# This is private data. Do not parse.\nADDRESS=192.168.1.23\n\
+             NETMASK=255.255.255.0\nROUTER=192.168.1.1 192.168.1.2\n\
+             SERVER_ADDRESS=192.168.1.1\nLIFETIME=86400\nDNS=192.168.1.1 9.9.9.9\n",
+        );
+        assert_eq!(lease.address.unwrap().to_string(), "192.168.1.23/24");
+        assert_eq!(lease.router, Some("192.168.1.1".parse().unwrap()));
+        assert_eq!(lease.dns.len(), 2);
+        assert_eq!(lease.lifetime, Some(86400));
+
+        let dir = TempDir::new("ipconfig").unwrap();
+        let resolv_conf = dir.path().join("resolv.conf");
+        write_atomic(&resolv_conf, &config.resolv_conf()).unwrap();
+        let dns = parse_resolv_conf(&fs::read_to_string(&resolv_conf).unwrap());
+        assert_eq!(dns, config.dns);
+
+        let networkd = IpConfigurator::default()
+            .with_network_dir(dir.path())
+            .with_lease_dir(dir.path())
+            .with_networkctl("true");
+        networkd.apply("lo", &IpConfig::dhcp()).unwrap();
+        assert_eq!(networkd.configured_method("lo"), Some(IpMethod::Dhcp));
+        assert_eq!(networkd.configured_method("eth9"), None);
+        // netlink has no DHCP client
+        let netlink = IpConfigurator::new(IpBackend::Netlink).with_resolv_conf(resolv_conf);
+        assert!(netlink.apply("lo", &IpConfig::dhcp()).is_err());
+    }
+}
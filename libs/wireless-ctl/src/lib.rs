@@ -1,2 +1,18 @@
 pub mod wireless_network;
-pub mod errors;
\ No newline at end of file
+pub mod connectivity;
+pub mod errors;
+pub mod hotspot;
+pub mod interfaces;
+pub mod ipconfig;
+pub mod links;
+pub mod netlink;
+pub mod profile;
+pub mod rfkill;
+pub mod saved;
+pub mod scan;
+pub mod session;
+pub mod status;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod wifi_uri;
+pub mod wps;
@@ -0,0 +1,318 @@
+use anyhow::{bail, Result};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{error as trace_error, info, trace};
+
+use crate::errors::{WirelessNetworkError, WirelessNetworkErrorCodes};
+use crate::interfaces::{interface_is_up, is_wireless, valid_interface_name, SYS_CLASS_NET};
+use crate::netlink::{self, InterfaceAddress};
+
+// ARPHRD_* in /sys/class/net/<interface>/type
+const ARPHRD_ETHER: u32 = 1;
+const ARPHRD_LOOPBACK: u32 = 772;
+
+// What kind of link an interface is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    Ethernet,
+    Wireless,
+    // USB device mode Ethernet such as g_ether, RNDIS or NCM
+    UsbGadget,
+    Bridge,
+    Loopback,
+    // software interfaces without a device such as veth, dummy or tun
+    Virtual,
+    Other,
+}
+
+impl fmt::Display for LinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkKind::Ethernet => write!(f, "ethernet"),
+            LinkKind::Wireless => write!(f, "wireless"),
+            LinkKind::UsbGadget => write!(f, "usb-gadget"),
+            LinkKind::Bridge => write!(f, "bridge"),
+            LinkKind::Loopback => write!(f, "loopback"),
+            LinkKind::Virtual => write!(f, "virtual"),
+            LinkKind::Other => write!(f, "other"),
+        }
+    }
+}
+
+// Traffic counters since the interface was created
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkCounters {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_dropped: u64,
+}
+
+// A network interface of the device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub name: String,
+    pub index: u32,
+    pub kind: LinkKind,
+    pub mac: Option<String>,
+    pub mtu: u32,
+    // administratively up
+    pub up: bool,
+    // RFC 2863 state such as up, down, dormant or unknown
+    pub operstate: String,
+    // None while the interface is down and the driver does not know
+    pub carrier: Option<bool>,
+    // Mbit/s, None when the driver does not report it
+    pub speed: Option<u32>,
+    // full or half
+    pub duplex: Option<String>,
+    pub counters: LinkCounters,
+    pub addresses: Vec<InterfaceAddress>,
+}
+
+fn read_value(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+fn read_number<T: std::str::FromStr>(path: &Path) -> Option<T> {
+    read_value(path)?.parse().ok()
+}
+
+fn unknown_interface(interface: &str) -> WirelessNetworkError {
+    WirelessNetworkError::new(
+        WirelessNetworkErrorCodes::UnknownInterface,
+        format!("no interface named {}", interface),
+    )
+}
+
+// Network interfaces under /sys/class/net
+#[derive(Debug, Clone)]
+pub struct Links {
+    sys_class_net: PathBuf,
+}
+
+impl Default for Links {
+    fn default() -> Self {
+        Self::new(SYS_CLASS_NET)
+    }
+}
+
+impl Links {
+    pub fn new<P: Into<PathBuf>>(sys_class_net: P) -> Self {
+        Links {
+            sys_class_net: sys_class_net.into(),
+        }
+    }
+
+    // Every interface sorted by index
+    pub fn list(&self) -> Result<Vec<Link>> {
+        trace!(task = "list_links", "init");
+        let entries = match fs::read_dir(&self.sys_class_net) {
+            Ok(entries) => entries,
+            Err(e) => {
+                trace_error!(
+                    task = "list_links",
+                    "unable to read {}: {}",
+                    self.sys_class_net.display(),
+                    e
+                );
+                bail!(WirelessNetworkError::new(
+                    WirelessNetworkErrorCodes::Unknown,
+                    format!("unable to read {}: {}", self.sys_class_net.display(), e),
+                ))
+            }
+        };
+        let mut links: Vec<Link> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| self.read_link(&entry.file_name().to_string_lossy()))
+            .collect();
+        links.sort_by_key(|link| (link.index, link.name.clone()));
+        Ok(links)
+    }
+
+    pub fn get(&self, interface: &str) -> Result<Link> {
+        match self.read_link(interface) {
+            Some(link) => Ok(link),
+            None => bail!(unknown_interface(interface)),
+        }
+    }
+
+    // Bring `interface` up or down, returns its new state
+    pub fn set_up(&self, interface: &str, up: bool) -> Result<Link> {
+        trace!(task = "set_link_state", "init");
+        self.get(interface)?;
+        if let Err(e) = netlink::set_link_up(interface, up) {
+            trace_error!(
+                task = "set_link_state",
+                "unable to set {} up {}: {}",
+                interface,
+                up,
+                e
+            );
+            bail!(WirelessNetworkError::new(
+                WirelessNetworkErrorCodes::UnableToSetInterfaceState,
+                format!(
+                    "unable to bring {} {}: {}",
+                    interface,
+                    if up { "up" } else { "down" },
+                    e
+                ),
+            ))
+        }
+        info!(task = "set_link_state", "{} up: {}", interface, up);
+        self.get(interface)
+    }
+
+    fn kind(&self, interface: &str) -> LinkKind {
+        let path = self.sys_class_net.join(interface);
+        let arp_type = read_number::<u32>(&path.join("type"));
+        if arp_type == Some(ARPHRD_LOOPBACK) {
+            return LinkKind::Loopback;
+        }
+        if is_wireless(&self.sys_class_net, interface) {
+            return LinkKind::Wireless;
+        }
+        if path.join("bridge").exists() {
+            return LinkKind::Bridge;
+        }
+        // gadget interfaces hang off the UDC's gadget device
+        let device = match fs::canonicalize(path.join("device")) {
+            Ok(device) => device,
+            Err(_) => return LinkKind::Virtual,
+        };
+        let gadget = device.components().any(|component| {
+            let component = component.as_os_str().to_string_lossy();
+            component == "gadget" || component.starts_with("gadget.")
+        });
+        match arp_type {
+            _ if gadget => LinkKind::UsbGadget,
+            Some(ARPHRD_ETHER) => LinkKind::Ethernet,
+            _ => LinkKind::Other,
+        }
+    }
+
+    fn read_link(&self, interface: &str) -> Option<Link> {
+        if !valid_interface_name(interface) {
+            return None;
+        }
+        let path = self.sys_class_net.join(interface);
+        let index = read_number(&path.join("ifindex"))?;
+        let statistics = path.join("statistics");
+        let counter = |name: &str| read_number(&statistics.join(name)).unwrap_or_default();
+        Some(Link {
+            name: interface.to_string(),
+            index,
+            kind: self.kind(interface),
+            mac: read_value(&path.join("address")).filter(|mac| !mac.is_empty()),
+            mtu: read_number(&path.join("mtu")).unwrap_or_default(),
+            up: interface_is_up(&self.sys_class_net, interface),
+            operstate: read_value(&path.join("operstate"))
+                .unwrap_or_else(|| String::from("unknown")),
+            // reading carrier, speed and duplex fails with EINVAL while down
+            carrier: read_number::<u8>(&path.join("carrier")).map(|carrier| carrier == 1),
+            speed: read_number::<i64>(&path.join("speed"))
+                .filter(|speed| *speed > 0)
+                .map(|speed| speed as u32),
+            duplex: read_value(&path.join("duplex")).filter(|duplex| duplex != "unknown"),
+            counters: LinkCounters {
+                rx_bytes: counter("rx_bytes"),
+                tx_bytes: counter("tx_bytes"),
+                rx_packets: counter("rx_packets"),
+                tx_packets: counter("tx_packets"),
+                rx_errors: counter("rx_errors"),
+                tx_errors: counter("tx_errors"),
+                rx_dropped: counter("rx_dropped"),
+                tx_dropped: counter("tx_dropped"),
+            },
+            addresses: netlink::interface_addresses(interface).unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn add_link(dir: &Path, name: &str, index: u32, arp_type: u32, files: &[(&str, &str)]) {
+        let path = dir.join(name);
+        fs::create_dir_all(path.join("statistics")).unwrap();
+        fs::write(path.join("ifindex"), format!("{}\n", index)).unwrap();
+        fs::write(path.join("type"), format!("{}\n", arp_type)).unwrap();
+        for (file, value) in files {
+            fs::write(path.join(file), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_links() {
+        let dir = TempDir::new("links").unwrap();
+        let net = dir.path().join("net");
+        let devices = dir.path().join("devices");
+        add_link(&net, "lo", 1, 772, &[("flags", "0x9"), ("mtu", "65536")]);
+        add_link(
+            &net,
+            "eth0",
+            2,
+            1,
+            &[
+                ("flags", "0x1003"),
+                ("address", "02:00:00:00:00:01"),
+                ("carrier", "1"),
+                ("speed", "1000"),
+                ("duplex", "full"),
+                ("operstate", "up"),
+                ("statistics/rx_bytes", "4096"),
+            ],
+        );
+        add_link(&net, "usb0", 4, 1, &[("flags", "0x1002"), ("speed", "-1")]);
+        add_link(&net, "br0", 3, 1, &[("flags", "0x1003")]);
+        add_link(&net, "veth0", 5, 1, &[]);
+        fs::create_dir(net.join("br0/bridge")).unwrap();
+        fs::create_dir_all(devices.join("platform/eth")).unwrap();
+        fs::create_dir_all(devices.join("platform/udc/gadget.0")).unwrap();
+        std::os::unix::fs::symlink(devices.join("platform/eth"), net.join("eth0/device")).unwrap();
+        std::os::unix::fs::symlink(
+            devices.join("platform/udc/gadget.0"),
+            net.join("usb0/device"),
+        )
+        .unwrap();
+
+        let links = Links::new(&net);
+        let list = links.list().unwrap();
+        let names: Vec<&str> = list.iter().map(|link| link.name.as_str()).collect();
+        assert_eq!(names, vec!["lo", "eth0", "br0", "usb0", "veth0"]);
+        let kinds: Vec<LinkKind> = list.iter().map(|link| link.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                LinkKind::Loopback,
+                LinkKind::Ethernet,
+                LinkKind::Bridge,
+                LinkKind::UsbGadget,
+                LinkKind::Virtual
+            ]
+        );
+
+        let eth0 = links.get("eth0").unwrap();
+        assert!(eth0.up);
+        assert_eq!(eth0.carrier, Some(true));
+        assert_eq!(eth0.speed, Some(1000));
+        assert_eq!(eth0.duplex.as_deref(), Some("full"));
+        assert_eq!(eth0.counters.rx_bytes, 4096);
+        assert_eq!(eth0.mac.as_deref(), Some("02:00:00:00:00:01"));
+
+        let usb0 = links.get("usb0").unwrap();
+        assert!(!usb0.up);
+        assert_eq!((usb0.carrier, usb0.speed), (None, None));
+        assert!(links.get("../eth0").is_err());
+        assert!(links.get("eth1").is_err());
+    }
+}
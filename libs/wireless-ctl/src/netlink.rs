@@ -0,0 +1,565 @@
+use anyhow::{bail, Result};
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tracing::{error as trace_error, trace};
+
+use crate::errors::{WirelessNetworkError, WirelessNetworkErrorCodes};
+
+const NLMSG_HEADER_LEN: usize = 16;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const IFADDRMSG_LEN: usize = 8;
+const IFINFOMSG_LEN: usize = 16;
+const RTMSG_LEN: usize = 12;
+const RECV_BUFFER_LEN: usize = 32 * 1024;
+
+static SEQUENCE: AtomicU32 = AtomicU32::new(1);
+
+fn netlink_error(message: String) -> WirelessNetworkError {
+    WirelessNetworkError::new(WirelessNetworkErrorCodes::UnableToQueryNetlink, message)
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+// A reply of the kernel, `payload` follows the netlink header
+#[derive(Debug)]
+pub(crate) struct NetlinkMessage {
+    pub msg_type: u16,
+    pub payload: Vec<u8>,
+}
+
+// NETLINK_ROUTE socket, one request at a time
+pub(crate) struct NetlinkSocket {
+    fd: OwnedFd,
+}
+
+impl NetlinkSocket {
+    pub fn open() -> Result<Self> {
+        // SAFETY: plain socket call, the descriptor is checked before use
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            let e = io::Error::last_os_error();
+            trace_error!(task = "netlink", "unable to open netlink socket: {}", e);
+            bail!(netlink_error(format!(
+                "unable to open netlink socket: {}",
+                e
+            )))
+        }
+        // SAFETY: the kernel handed us a new descriptor that nothing else owns
+        Ok(NetlinkSocket {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    // Send a `msg_type` request and collect the replies, up to NLMSG_DONE for
+    // dumps or up to the acknowledgement otherwise
+    pub fn request(
+        &self,
+        msg_type: u16,
+        flags: u16,
+        payload: &[u8],
+    ) -> Result<Vec<NetlinkMessage>> {
+        trace!(task = "netlink", "request {}", msg_type);
+        let dump = flags & libc::NLM_F_DUMP as u16 != 0;
+        let mut flags = flags | libc::NLM_F_REQUEST as u16;
+        if !dump {
+            flags |= libc::NLM_F_ACK as u16;
+        }
+        let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+
+        let mut message = Vec::with_capacity(NLMSG_HEADER_LEN + payload.len());
+        message.extend_from_slice(&((NLMSG_HEADER_LEN + payload.len()) as u32).to_ne_bytes());
+        message.extend_from_slice(&msg_type.to_ne_bytes());
+        message.extend_from_slice(&flags.to_ne_bytes());
+        message.extend_from_slice(&sequence.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(payload);
+        self.send(&message)?;
+
+        let mut replies = Vec::new();
+        let mut buffer = vec![0u8; RECV_BUFFER_LEN];
+        loop {
+            let len = self.recv(&mut buffer)?;
+            let mut offset = 0;
+            while offset + NLMSG_HEADER_LEN <= len {
+                let message_len = read_u32(&buffer, offset) as usize;
+                if message_len < NLMSG_HEADER_LEN || offset + message_len > len {
+                    bail!(netlink_error(String::from("truncated netlink message")))
+                }
+                let reply_type = read_u16(&buffer, offset + 4);
+                let reply_sequence = read_u32(&buffer, offset + 8);
+                let payload = &buffer[offset + NLMSG_HEADER_LEN..offset + message_len];
+                offset += align(message_len);
+                if reply_sequence != sequence {
+                    continue;
+                }
+                match reply_type {
+                    NLMSG_DONE => return Ok(replies),
+                    NLMSG_ERROR => {
+                        let errno = match payload.len() {
+                            len if len >= 4 => {
+                                i32::from_ne_bytes([payload[0], payload[1], payload[2], payload[3]])
+                            }
+                            _ => 0,
+                        };
+                        if errno == 0 {
+                            return Ok(replies);
+                        }
+                        let e = io::Error::from_raw_os_error(-errno);
+                        trace_error!(task = "netlink", "request {} failed: {}", msg_type, e);
+                        bail!(netlink_error(format!("netlink request failed: {}", e)))
+                    }
+                    _ => replies.push(NetlinkMessage {
+                        msg_type: reply_type,
+                        payload: payload.to_vec(),
+                    }),
+                }
+            }
+        }
+    }
+
+    fn send(&self, message: &[u8]) -> Result<()> {
+        // SAFETY: sockaddr_nl is plain data, all zeroes addresses the kernel
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        // SAFETY: `message` and `address` are valid for the lengths passed
+        let sent = unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                message.as_ptr() as *const libc::c_void,
+                message.len(),
+                0,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if sent < 0 {
+            bail!(netlink_error(format!(
+                "unable to send netlink request: {}",
+                io::Error::last_os_error()
+            )))
+        }
+        Ok(())
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> Result<usize> {
+        // SAFETY: the kernel writes at most `buffer.len()` bytes into `buffer`
+        let len = unsafe {
+            libc::recv(
+                self.fd.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                0,
+            )
+        };
+        if len < 0 {
+            bail!(netlink_error(format!(
+                "unable to receive netlink reply: {}",
+                io::Error::last_os_error()
+            )))
+        }
+        Ok(len as usize)
+    }
+}
+
+// Route attributes in `data` as (type, value) pairs
+pub(crate) fn attributes(data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attributes = Vec::new();
+    let mut offset = 0;
+    while offset + 4 <= data.len() {
+        let len = read_u16(data, offset) as usize;
+        if len < 4 || offset + len > data.len() {
+            break;
+        }
+        // the top bits flag nested and byte-order attributes
+        let kind = read_u16(data, offset + 2) & 0x3fff;
+        attributes.push((kind, &data[offset + 4..offset + len]));
+        offset += align(len);
+    }
+    attributes
+}
+
+pub(crate) fn push_attribute(buffer: &mut Vec<u8>, kind: u16, value: &[u8]) {
+    let len = 4 + value.len();
+    buffer.extend_from_slice(&(len as u16).to_ne_bytes());
+    buffer.extend_from_slice(&kind.to_ne_bytes());
+    buffer.extend_from_slice(value);
+    buffer.resize(buffer.len() + align(len) - len, 0);
+}
+
+pub(crate) fn ip_address(family: u8, value: &[u8]) -> Option<IpAddr> {
+    match (family as i32, value.len()) {
+        (libc::AF_INET, 4) => Some(IpAddr::V4(Ipv4Addr::new(
+            value[0], value[1], value[2], value[3],
+        ))),
+        (libc::AF_INET6, 16) => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(value);
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
+
+pub fn interface_index(interface: &str) -> Result<u32> {
+    let name = match CString::new(interface) {
+        Ok(name) => name,
+        Err(_) => bail!(WirelessNetworkError::new(
+            WirelessNetworkErrorCodes::UnknownInterface,
+            format!("invalid interface name {:?}", interface),
+        )),
+    };
+    // SAFETY: `name` is a valid nul-terminated string
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if index == 0 {
+        bail!(WirelessNetworkError::new(
+            WirelessNetworkErrorCodes::UnknownInterface,
+            format!("no interface named {}", interface),
+        ))
+    }
+    Ok(index)
+}
+
+pub fn interface_name(index: u32) -> Option<String> {
+    let mut name = [0 as libc::c_char; libc::IF_NAMESIZE];
+    // SAFETY: `name` has room for IF_NAMESIZE bytes as the call requires
+    let result = unsafe { libc::if_indextoname(index, name.as_mut_ptr()) };
+    if result.is_null() {
+        return None;
+    }
+    // SAFETY: on success `name` holds a nul-terminated string
+    let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+    Some(name.to_string_lossy().to_string())
+}
+
+// Address assigned to an interface with its prefix length
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceAddress {
+    pub address: IpAddr,
+    pub prefix_len: u8,
+}
+
+impl fmt::Display for InterfaceAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+// Bring `interface` administratively up or down
+pub fn set_link_up(interface: &str, up: bool) -> Result<()> {
+    trace!(task = "set_link_up", "init");
+    let index = interface_index(interface)?;
+    let flags: u32 = if up { libc::IFF_UP as u32 } else { 0 };
+    let mut request = vec![0u8; IFINFOMSG_LEN];
+    request[0] = libc::AF_UNSPEC as u8;
+    request[4..8].copy_from_slice(&(index as i32).to_ne_bytes());
+    request[8..12].copy_from_slice(&flags.to_ne_bytes());
+    // ifi_change, only IFF_UP is touched
+    request[12..16].copy_from_slice(&(libc::IFF_UP as u32).to_ne_bytes());
+    NetlinkSocket::open()?.request(libc::RTM_NEWLINK, 0, &request)?;
+    Ok(())
+}
+
+// `address/prefix_len`, a host prefix when the length is left out
+impl FromStr for InterfaceAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(address: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow::anyhow!(WirelessNetworkError::new(
+                WirelessNetworkErrorCodes::UnableToConfigureIp,
+                format!("invalid address {}, expected address/prefix", address),
+            ))
+        };
+        let (ip, prefix_len) = match address.trim().split_once('/') {
+            Some((ip, prefix_len)) => (ip, Some(prefix_len)),
+            None => (address.trim(), None),
+        };
+        let address: IpAddr = ip.parse().map_err(|_| invalid())?;
+        let max_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse().map_err(|_| invalid())?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            return Err(invalid());
+        }
+        Ok(InterfaceAddress {
+            address,
+            prefix_len,
+        })
+    }
+}
+
+// (interface index, address) of an RTM_NEWADDR payload
+fn parse_address(payload: &[u8]) -> Option<(u32, InterfaceAddress)> {
+    if payload.len() < IFADDRMSG_LEN {
+        return None;
+    }
+    let family = payload[0];
+    let prefix_len = payload[1];
+    let index = read_u32(payload, 4);
+    let attributes = attributes(&payload[IFADDRMSG_LEN..]);
+    // IFA_LOCAL is our end of point-to-point links, where IFA_ADDRESS is the peer
+    let address = [libc::IFA_LOCAL, libc::IFA_ADDRESS]
+        .into_iter()
+        .find_map(|kind| {
+            attributes
+                .iter()
+                .find(|(attribute, _)| *attribute == kind)
+                .and_then(|(_, value)| ip_address(family, value))
+        })?;
+    Some((
+        index,
+        InterfaceAddress {
+            address,
+            prefix_len,
+        },
+    ))
+}
+
+// IPv4 and IPv6 addresses of `interface`
+pub fn interface_addresses(interface: &str) -> Result<Vec<InterfaceAddress>> {
+    trace!(task = "interface_addresses", "init");
+    let index = interface_index(interface)?;
+    let mut request = vec![0u8; IFADDRMSG_LEN];
+    request[0] = libc::AF_UNSPEC as u8;
+    let replies =
+        NetlinkSocket::open()?.request(libc::RTM_GETADDR, libc::NLM_F_DUMP as u16, &request)?;
+    Ok(replies
+        .iter()
+        .filter(|reply| reply.msg_type == libc::RTM_NEWADDR)
+        .filter_map(|reply| parse_address(&reply.payload))
+        .filter(|(address_index, _)| *address_index == index)
+        .map(|(_, address)| address)
+        .collect())
+}
+
+fn address_request(interface: &str, address: &InterfaceAddress) -> Result<Vec<u8>> {
+    let index = interface_index(interface)?;
+    let (family, octets) = match address.address {
+        IpAddr::V4(address) => (libc::AF_INET, address.octets().to_vec()),
+        IpAddr::V6(address) => (libc::AF_INET6, address.octets().to_vec()),
+    };
+    let mut request = vec![family as u8, address.prefix_len, 0, libc::RT_SCOPE_UNIVERSE];
+    request.extend_from_slice(&index.to_ne_bytes());
+    push_attribute(&mut request, libc::IFA_LOCAL, &octets);
+    push_attribute(&mut request, libc::IFA_ADDRESS, &octets);
+    Ok(request)
+}
+
+// Assign `address` to `interface`, replacing it when already there
+pub fn add_address(interface: &str, address: &InterfaceAddress) -> Result<()> {
+    trace!(task = "add_address", "init");
+    let request = address_request(interface, address)?;
+    let flags = (libc::NLM_F_CREATE | libc::NLM_F_REPLACE) as u16;
+    NetlinkSocket::open()?.request(libc::RTM_NEWADDR, flags, &request)?;
+    Ok(())
+}
+
+pub fn remove_address(interface: &str, address: &InterfaceAddress) -> Result<()> {
+    trace!(task = "remove_address", "init");
+    let request = address_request(interface, address)?;
+    NetlinkSocket::open()?.request(libc::RTM_DELADDR, 0, &request)?;
+    Ok(())
+}
+
+// Route everything not covered by a more specific route through `gateway` on
+// `interface`, replacing the default route of the same family
+pub fn add_default_route(interface: &str, gateway: IpAddr) -> Result<()> {
+    trace!(task = "add_default_route", "init");
+    let index = interface_index(interface)?;
+    let (family, octets) = match gateway {
+        IpAddr::V4(gateway) => (libc::AF_INET, gateway.octets().to_vec()),
+        IpAddr::V6(gateway) => (libc::AF_INET6, gateway.octets().to_vec()),
+    };
+    let mut request = vec![
+        family as u8,
+        0,
+        0,
+        0,
+        libc::RT_TABLE_MAIN,
+        libc::RTPROT_STATIC,
+        libc::RT_SCOPE_UNIVERSE,
+        libc::RTN_UNICAST,
+    ];
+    request.extend_from_slice(&0u32.to_ne_bytes());
+    push_attribute(&mut request, libc::RTA_GATEWAY, &octets);
+    push_attribute(&mut request, libc::RTA_OIF, &index.to_ne_bytes());
+    let flags = (libc::NLM_F_CREATE | libc::NLM_F_REPLACE) as u16;
+    NetlinkSocket::open()?.request(libc::RTM_NEWROUTE, flags, &request)?;
+    Ok(())
+}
+
+// Route to everything not covered by a more specific one
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DefaultRoute {
+    pub gateway: Option<IpAddr>,
+    pub interface: Option<String>,
+    pub metric: u32,
+}
+
+// (output interface index, route) of an RTM_NEWROUTE payload that is a
+// default route of the main table
+fn parse_default_route(payload: &[u8]) -> Option<(Option<u32>, DefaultRoute)> {
+    if payload.len() < RTMSG_LEN {
+        return None;
+    }
+    let family = payload[0];
+    let destination_len = payload[1];
+    let mut table = payload[4] as u32;
+    let route_type = payload[7];
+    if destination_len != 0 || route_type != libc::RTN_UNICAST {
+        return None;
+    }
+    let mut gateway = None;
+    let mut output_index = None;
+    let mut metric = 0;
+    for (kind, value) in attributes(&payload[RTMSG_LEN..]) {
+        match kind {
+            libc::RTA_GATEWAY => gateway = ip_address(family, value),
+            libc::RTA_OIF if value.len() == 4 => output_index = Some(read_u32(value, 0)),
+            libc::RTA_PRIORITY if value.len() == 4 => metric = read_u32(value, 0),
+            libc::RTA_TABLE if value.len() == 4 => table = read_u32(value, 0),
+            _ => {}
+        }
+    }
+    if table != libc::RT_TABLE_MAIN as u32 {
+        return None;
+    }
+    Some((
+        output_index,
+        DefaultRoute {
+            gateway,
+            interface: None,
+            metric,
+        },
+    ))
+}
+
+// Default routes of the main table, lowest metric first
+pub fn default_routes() -> Result<Vec<DefaultRoute>> {
+    trace!(task = "default_routes", "init");
+    let mut request = vec![0u8; RTMSG_LEN];
+    request[0] = libc::AF_UNSPEC as u8;
+    let replies =
+        NetlinkSocket::open()?.request(libc::RTM_GETROUTE, libc::NLM_F_DUMP as u16, &request)?;
+    let mut routes: Vec<DefaultRoute> = replies
+        .iter()
+        .filter(|reply| reply.msg_type == libc::RTM_NEWROUTE)
+        .filter_map(|reply| parse_default_route(&reply.payload))
+        .map(|(output_index, route)| DefaultRoute {
+            interface: output_index.and_then(interface_name),
+            ..route
+        })
+        .collect();
+    routes.sort_by_key(|route| route.metric);
+    Ok(routes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_messages() {
+        let mut address = vec![libc::AF_INET as u8, 24, 0, 0];
+        address.extend_from_slice(&3u32.to_ne_bytes());
+        push_attribute(&mut address, libc::IFA_ADDRESS, &[192, 168, 1, 20]);
+        push_attribute(&mut address, libc::IFA_LABEL, b"wlan0\0");
+        assert_eq!(
+            parse_address(&address),
+            Some((
+                3,
+                InterfaceAddress {
+                    address: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20)),
+                    prefix_len: 24,
+                }
+            ))
+        );
+        assert_eq!(
+            parse_address(&address).unwrap().1.to_string(),
+            "192.168.1.20/24"
+        );
+
+        let mut route = vec![
+            libc::AF_INET as u8,
+            0,
+            0,
+            0,
+            libc::RT_TABLE_MAIN,
+            0,
+            0,
+            libc::RTN_UNICAST,
+        ];
+        route.extend_from_slice(&0u32.to_ne_bytes());
+        push_attribute(&mut route, libc::RTA_GATEWAY, &[192, 168, 1, 1]);
+        push_attribute(&mut route, libc::RTA_OIF, &3u32.to_ne_bytes());
+        push_attribute(&mut route, libc::RTA_PRIORITY, &600u32.to_ne_bytes());
+        assert_eq!(
+            parse_default_route(&route),
+            Some((
+                Some(3),
+                DefaultRoute {
+                    gateway: Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))),
+                    interface: None,
+                    metric: 600,
+                }
+            ))
+        );
+
+        // a subnet route is not a default route
+        route[1] = 24;
+        assert_eq!(parse_default_route(&route), None);
+    }
+
+    #[test]
+    fn test_loopback_addresses() {
+        let addresses = interface_addresses("lo").unwrap();
+        assert!(addresses.contains(&InterfaceAddress {
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            prefix_len: 8,
+        }));
+        assert!(interface_addresses("no-such-interface").is_err());
+
+        assert_eq!(
+            "fd00::5/64"
+                .parse::<InterfaceAddress>()
+                .unwrap()
+                .to_string(),
+            "fd00::5/64"
+        );
+        assert_eq!(
+            "10.0.0.2".parse::<InterfaceAddress>().unwrap().prefix_len,
+            32
+        );
+        assert!("10.0.0.2/33".parse::<InterfaceAddress>().is_err());
+    }
+}
@@ -0,0 +1,436 @@
+use anyhow::{bail, Result};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::errors::{WirelessNetworkError, WirelessNetworkErrorCodes};
+
+const MAX_SSID_LEN: usize = 32;
+const MIN_PASSPHRASE_LEN: usize = 8;
+const MAX_PASSPHRASE_LEN: usize = 63;
+// a raw WPA key is 64 hex digits instead of a passphrase
+const RAW_PSK_LEN: usize = 64;
+
+fn invalid_profile(message: String) -> WirelessNetworkError {
+    WirelessNetworkError::new(WirelessNetworkErrorCodes::InvalidNetworkProfile, message)
+}
+
+// Security a network advertises in the scan result flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityType {
+    Open,
+    // opportunistic wireless encryption, open without a password
+    Owe,
+    Wep,
+    WpaPsk,
+    Sae,
+    // WPA2/WPA3 transition mode
+    WpaPskSae,
+    Eap,
+}
+
+impl SecurityType {
+    // Security of flags such as `[WPA2-PSK+SAE-CCMP][ESS]`
+    pub fn from_flags(flags: &str) -> Self {
+        let psk = flags.contains("PSK");
+        let sae = flags.contains("SAE");
+        if flags.contains("EAP") {
+            SecurityType::Eap
+        } else if psk && sae {
+            SecurityType::WpaPskSae
+        } else if sae {
+            SecurityType::Sae
+        } else if psk {
+            SecurityType::WpaPsk
+        } else if flags.contains("[WEP") {
+            SecurityType::Wep
+        } else if flags.contains("OWE") && !flags.contains("OWE-TRANS") {
+            SecurityType::Owe
+        } else {
+            SecurityType::Open
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SecurityType::Open => "open",
+            SecurityType::Owe => "owe",
+            SecurityType::Wep => "wep",
+            SecurityType::WpaPsk => "wpa-psk",
+            SecurityType::Sae => "sae",
+            SecurityType::WpaPskSae => "wpa-psk-sae",
+            SecurityType::Eap => "eap",
+        }
+    }
+}
+
+impl fmt::Display for SecurityType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+// Credentials of an 802.1X network
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EapCredentials {
+    pub identity: String,
+    // outer identity sent in the clear, the real one stays inside the tunnel
+    pub anonymous_identity: Option<String>,
+    pub password: Option<String>,
+    // paths of PEM files on the device
+    pub ca_cert: Option<String>,
+    pub client_cert: Option<String>,
+    pub private_key: Option<String>,
+    pub private_key_password: Option<String>,
+    // the server certificate must be issued for this domain or a subdomain
+    pub domain_suffix_match: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Security {
+    Open,
+    WpaPsk { passphrase: String },
+    Sae { password: String },
+    WpaPskSae { passphrase: String },
+    // PEAP with MSCHAPv2 inside
+    Peap(EapCredentials),
+    // EAP-TLS with a client certificate
+    Tls(EapCredentials),
+}
+
+// Security names accepted by `Security::from_kind`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityKind {
+    Open,
+    WpaPsk,
+    Sae,
+    WpaPskSae,
+    Peap,
+    Tls,
+}
+
+impl FromStr for SecurityKind {
+    type Err = anyhow::Error;
+
+    fn from_str(kind: &str) -> Result<Self> {
+        match kind.trim().to_lowercase().as_str() {
+            "open" | "none" => Ok(SecurityKind::Open),
+            "wpa-psk" | "wpa2" | "psk" => Ok(SecurityKind::WpaPsk),
+            "sae" | "wpa3" => Ok(SecurityKind::Sae),
+            "wpa-psk-sae" | "wpa2-wpa3" => Ok(SecurityKind::WpaPskSae),
+            "peap" => Ok(SecurityKind::Peap),
+            "tls" | "eap-tls" => Ok(SecurityKind::Tls),
+            kind => bail!(invalid_profile(format!(
+                "unknown security {}, expected open, wpa-psk, sae, wpa-psk-sae, peap or tls",
+                kind
+            ))),
+        }
+    }
+}
+
+impl Security {
+    pub fn from_kind(kind: SecurityKind, passphrase: &str, eap: EapCredentials) -> Self {
+        let passphrase = passphrase.to_string();
+        match kind {
+            SecurityKind::Open => Security::Open,
+            SecurityKind::WpaPsk => Security::WpaPsk { passphrase },
+            SecurityKind::Sae => Security::Sae {
+                password: passphrase,
+            },
+            SecurityKind::WpaPskSae => Security::WpaPskSae { passphrase },
+            SecurityKind::Peap => Security::Peap(eap),
+            SecurityKind::Tls => Security::Tls(eap),
+        }
+    }
+
+    // Security to join a network advertising `security_type` with `passphrase`
+    pub fn for_type(security_type: SecurityType, passphrase: &str) -> Result<Self> {
+        let passphrase = passphrase.to_string();
+        match security_type {
+            SecurityType::Open | SecurityType::Owe => Ok(Security::Open),
+            SecurityType::WpaPsk => Ok(Security::WpaPsk { passphrase }),
+            SecurityType::Sae => Ok(Security::Sae {
+                password: passphrase,
+            }),
+            SecurityType::WpaPskSae => Ok(Security::WpaPskSae { passphrase }),
+            SecurityType::Wep => bail!(invalid_profile(String::from(
+                "WEP networks are not supported"
+            ))),
+            SecurityType::Eap => bail!(invalid_profile(String::from(
+                "enterprise networks need EAP credentials"
+            ))),
+        }
+    }
+}
+
+// Everything needed to join a network
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkProfile {
+    pub ssid: String,
+    pub security: Security,
+    // the access point does not broadcast its ssid and has to be probed
+    pub hidden: bool,
+}
+
+impl NetworkProfile {
+    pub fn new(ssid: &str, security: Security) -> Self {
+        NetworkProfile {
+            ssid: ssid.to_string(),
+            security,
+            hidden: false,
+        }
+    }
+
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.hidden = hidden;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        validate_ssid(&self.ssid)?;
+        match &self.security {
+            Security::Open => {}
+            Security::WpaPsk { passphrase } | Security::WpaPskSae { passphrase } => {
+                validate_passphrase(passphrase)?
+            }
+            Security::Sae { password } => {
+                if password.is_empty() {
+                    bail!(invalid_profile(String::from("password must not be empty")))
+                }
+                validate_string("password", password)?
+            }
+            Security::Peap(eap) => {
+                if eap.password.as_deref().unwrap_or_default().is_empty() {
+                    bail!(invalid_profile(String::from("PEAP needs a password")))
+                }
+                validate_eap(eap)?
+            }
+            Security::Tls(eap) => {
+                if eap.client_cert.is_none() || eap.private_key.is_none() {
+                    bail!(invalid_profile(String::from(
+                        "EAP-TLS needs a client certificate and private key"
+                    )))
+                }
+                validate_eap(eap)?
+            }
+        }
+        Ok(())
+    }
+
+    // `SET_NETWORK` parameters of the profile, values quoted as the supplicant expects
+    pub fn network_settings(&self) -> Vec<(&'static str, String)> {
+        let mut settings = vec![("ssid", hex_ssid(&self.ssid))];
+        match &self.security {
+            Security::Open => settings.push(("key_mgmt", String::from("NONE"))),
+            Security::WpaPsk { passphrase } => {
+                settings.push(("key_mgmt", String::from("WPA-PSK")));
+                settings.push(("psk", psk_value(passphrase)));
+            }
+            Security::Sae { password } => {
+                settings.push(("key_mgmt", String::from("SAE")));
+                settings.push(("sae_password", quote(password)));
+                // WPA3 requires protected management frames
+                settings.push(("ieee80211w", String::from("2")));
+            }
+            Security::WpaPskSae { passphrase } => {
+                settings.push(("key_mgmt", String::from("WPA-PSK SAE")));
+                settings.push(("psk", psk_value(passphrase)));
+                settings.push(("ieee80211w", String::from("1")));
+            }
+            Security::Peap(eap) => {
+                settings.push(("key_mgmt", String::from("WPA-EAP")));
+                settings.push(("eap", String::from("PEAP")));
+                settings.push(("phase2", quote("auth=MSCHAPV2")));
+                eap_settings(eap, &mut settings);
+            }
+            Security::Tls(eap) => {
+                settings.push(("key_mgmt", String::from("WPA-EAP")));
+                settings.push(("eap", String::from("TLS")));
+                eap_settings(eap, &mut settings);
+            }
+        }
+        if self.hidden {
+            settings.push(("scan_ssid", String::from("1")));
+        }
+        settings
+    }
+}
+
+// hex needs no escaping whatever bytes the ssid holds
+pub(crate) fn hex_ssid(ssid: &str) -> String {
+    ssid.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn validate_ssid(ssid: &str) -> Result<()> {
+    if ssid.is_empty() || ssid.len() > MAX_SSID_LEN {
+        bail!(invalid_profile(format!(
+            "ssid must be 1 to {} bytes long",
+            MAX_SSID_LEN
+        )))
+    }
+    Ok(())
+}
+
+pub(crate) fn quote(value: &str) -> String {
+    format!("\"{}\"", value)
+}
+
+fn is_raw_psk(passphrase: &str) -> bool {
+    passphrase.len() == RAW_PSK_LEN && passphrase.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+pub(crate) fn psk_value(passphrase: &str) -> String {
+    match is_raw_psk(passphrase) {
+        true => passphrase.to_string(),
+        false => quote(passphrase),
+    }
+}
+
+pub(crate) fn validate_passphrase(passphrase: &str) -> Result<()> {
+    if is_raw_psk(passphrase) {
+        return Ok(());
+    }
+    let printable = passphrase.chars().all(|c| (' '..='~').contains(&c));
+    if !printable || passphrase.len() < MIN_PASSPHRASE_LEN || passphrase.len() > MAX_PASSPHRASE_LEN
+    {
+        bail!(invalid_profile(format!(
+            "passphrase must be {} to {} printable ASCII characters",
+            MIN_PASSPHRASE_LEN, MAX_PASSPHRASE_LEN
+        )))
+    }
+    Ok(())
+}
+
+// values end up in a line based protocol
+fn validate_string(name: &str, value: &str) -> Result<()> {
+    if value.chars().any(|c| c.is_control()) {
+        bail!(invalid_profile(format!(
+            "{} must not contain control characters",
+            name
+        )))
+    }
+    Ok(())
+}
+
+fn validate_eap(eap: &EapCredentials) -> Result<()> {
+    if eap.identity.is_empty() {
+        bail!(invalid_profile(String::from("EAP needs an identity")))
+    }
+    validate_string("identity", &eap.identity)?;
+    for (name, value) in [
+        ("anonymous identity", &eap.anonymous_identity),
+        ("password", &eap.password),
+        ("ca certificate", &eap.ca_cert),
+        ("client certificate", &eap.client_cert),
+        ("private key", &eap.private_key),
+        ("private key password", &eap.private_key_password),
+        ("domain suffix", &eap.domain_suffix_match),
+    ] {
+        if let Some(value) = value {
+            validate_string(name, value)?;
+        }
+    }
+    Ok(())
+}
+
+fn eap_settings(eap: &EapCredentials, settings: &mut Vec<(&'static str, String)>) {
+    settings.push(("identity", quote(&eap.identity)));
+    for (key, value) in [
+        ("anonymous_identity", &eap.anonymous_identity),
+        ("password", &eap.password),
+        ("ca_cert", &eap.ca_cert),
+        ("client_cert", &eap.client_cert),
+        ("private_key", &eap.private_key),
+        ("private_key_passwd", &eap.private_key_password),
+        ("domain_suffix_match", &eap.domain_suffix_match),
+    ] {
+        if let Some(value) = value {
+            settings.push((key, quote(value)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_security_from_flags() {
+        assert_eq!(SecurityType::from_flags("[ESS]"), SecurityType::Open);
+        assert_eq!(
+            SecurityType::from_flags("[WPA2-PSK-CCMP][WPS][ESS]"),
+            SecurityType::WpaPsk
+        );
+        assert_eq!(
+            SecurityType::from_flags("[WPA2-PSK+SAE-CCMP][ESS]"),
+            SecurityType::WpaPskSae
+        );
+        assert_eq!(
+            SecurityType::from_flags("[WPA2-SAE-CCMP][ESS]"),
+            SecurityType::Sae
+        );
+        assert_eq!(
+            SecurityType::from_flags("[WPA2-EAP-CCMP][ESS]"),
+            SecurityType::Eap
+        );
+        assert_eq!(SecurityType::from_flags("[WEP][ESS]"), SecurityType::Wep);
+        assert_eq!(
+            SecurityType::from_flags("[ESS][OWE-TRANS]"),
+            SecurityType::Open
+        );
+    }
+
+    #[test]
+    fn test_network_settings() {
+        let profile = NetworkProfile::new(
+            "lab",
+            Security::Sae {
+                password: String::from("secret"),
+            },
+        )
+        .hidden(true);
+        profile.validate().unwrap();
+        assert_eq!(
+            profile.network_settings(),
+            vec![
+                ("ssid", String::from("6c6162")),
+                ("key_mgmt", String::from("SAE")),
+                ("sae_password", String::from("\"secret\"")),
+                ("ieee80211w", String::from("2")),
+                ("scan_ssid", String::from("1")),
+            ]
+        );
+
+        let eap = EapCredentials {
+            identity: String::from("jane@example.com"),
+            password: Some(String::from("hunter22")),
+            ca_cert: Some(String::from("/etc/ssl/certs/corp.pem")),
+            ..Default::default()
+        };
+        let settings = NetworkProfile::new("corp", Security::Peap(eap)).network_settings();
+        assert!(settings.contains(&("eap", String::from("PEAP"))));
+        assert!(settings.contains(&("phase2", String::from("\"auth=MSCHAPV2\""))));
+        assert!(settings.contains(&("ca_cert", String::from("\"/etc/ssl/certs/corp.pem\""))));
+    }
+
+    #[test]
+    fn test_validate() {
+        let psk = |passphrase: &str| {
+            NetworkProfile::new(
+                "home",
+                Security::WpaPsk {
+                    passphrase: passphrase.to_string(),
+                },
+            )
+        };
+        assert!(psk("short").validate().is_err());
+        assert!(psk("line\nbreak").validate().is_err());
+        assert!(psk(&"a".repeat(64)).validate().is_ok());
+        assert!(psk("long enough").validate().is_ok());
+        assert!(NetworkProfile::new("", Security::Open).validate().is_err());
+        assert!(
+            NetworkProfile::new("corp", Security::Tls(EapCredentials::default()))
+                .validate()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use anyhow::{bail, Result};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::{error as trace_error, info, trace};
+
+use crate::errors::{WirelessNetworkError, WirelessNetworkErrorCodes};
+
+pub const SYS_CLASS_RFKILL: &str = "/sys/class/rfkill";
+
+// Radios to block or unblock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RadioType {
+    All,
+    Wifi,
+    Bluetooth,
+}
+
+impl RadioType {
+    // whether a radio of rfkill `type` belongs to this one
+    pub fn matches(&self, kind: &str) -> bool {
+        match self {
+            RadioType::All => true,
+            RadioType::Wifi => kind == "wlan",
+            RadioType::Bluetooth => kind == "bluetooth",
+        }
+    }
+}
+
+impl fmt::Display for RadioType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RadioType::All => write!(f, "all"),
+            RadioType::Wifi => write!(f, "wifi"),
+            RadioType::Bluetooth => write!(f, "bluetooth"),
+        }
+    }
+}
+
+impl FromStr for RadioType {
+    type Err = anyhow::Error;
+
+    fn from_str(radio_type: &str) -> Result<Self> {
+        match radio_type.trim().to_lowercase().as_str() {
+            "all" => Ok(RadioType::All),
+            "wifi" | "wlan" => Ok(RadioType::Wifi),
+            "bluetooth" | "bt" => Ok(RadioType::Bluetooth),
+            _ => bail!(WirelessNetworkError::new(
+                WirelessNetworkErrorCodes::Unknown,
+                format!(
+                    "unknown radio {}, expected wifi, bluetooth or all",
+                    radio_type
+                ),
+            )),
+        }
+    }
+}
+
+// A radio the kernel can switch off
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Radio {
+    // rfkill0, rfkill1, ...
+    pub id: String,
+    // device of the radio such as phy0 or hci0
+    pub device: String,
+    // rfkill type such as wlan or bluetooth
+    pub kind: String,
+    // blocked by software, what `set_blocked` changes
+    pub soft_blocked: bool,
+    // blocked by a hardware switch, software cannot undo it
+    pub hard_blocked: bool,
+}
+
+impl Radio {
+    pub fn blocked(&self) -> bool {
+        self.soft_blocked || self.hard_blocked
+    }
+}
+
+// Radio switches under /sys/class/rfkill
+#[derive(Debug, Clone)]
+pub struct Rfkill {
+    class_dir: PathBuf,
+}
+
+impl Default for Rfkill {
+    fn default() -> Self {
+        Self::new(SYS_CLASS_RFKILL)
+    }
+}
+
+fn read_value(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+impl Rfkill {
+    pub fn new<P: Into<PathBuf>>(class_dir: P) -> Self {
+        Rfkill {
+            class_dir: class_dir.into(),
+        }
+    }
+
+    // Radios of the device sorted by id
+    pub fn list(&self) -> Result<Vec<Radio>> {
+        trace!(task = "rfkill_list", "init");
+        let entries = match fs::read_dir(&self.class_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                trace_error!(
+                    task = "rfkill_list",
+                    "unable to read {}: {}",
+                    self.class_dir.display(),
+                    e
+                );
+                bail!(WirelessNetworkError::new(
+                    WirelessNetworkErrorCodes::Unknown,
+                    format!("unable to read {}: {}", self.class_dir.display(), e),
+                ))
+            }
+        };
+        let mut radios: Vec<Radio> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                Some(Radio {
+                    id: entry.file_name().to_string_lossy().to_string(),
+                    device: read_value(&path.join("name")).unwrap_or_default(),
+                    kind: read_value(&path.join("type"))?,
+                    soft_blocked: read_value(&path.join("soft")).as_deref() == Some("1"),
+                    hard_blocked: read_value(&path.join("hard")).as_deref() == Some("1"),
+                })
+            })
+            .collect();
+        radios.sort_by_key(|radio| {
+            let index = radio.id.trim_start_matches("rfkill").parse::<u32>();
+            (index.unwrap_or(u32::MAX), radio.id.clone())
+        });
+        Ok(radios)
+    }
+
+    // Block or unblock every radio of `radio_type`, returns their new state
+    pub fn set_blocked(&self, radio_type: RadioType, blocked: bool) -> Result<Vec<Radio>> {
+        trace!(task = "rfkill_set_blocked", "init");
+        let code = match blocked {
+            true => WirelessNetworkErrorCodes::UnableToTurnOffWirelessNetwork,
+            false => WirelessNetworkErrorCodes::UnableToTurnOnWirelessNetwork,
+        };
+        let radios: Vec<Radio> = self
+            .list()?
+            .into_iter()
+            .filter(|radio| radio_type.matches(&radio.kind))
+            .collect();
+        if radios.is_empty() {
+            trace_error!(task = "rfkill_set_blocked", "no {} radio", radio_type);
+            bail!(WirelessNetworkError::new(
+                code,
+                format!("no {} radio found", radio_type)
+            ))
+        }
+
+        let soft = if blocked { "1" } else { "0" };
+        for radio in &radios {
+            let path = self.class_dir.join(&radio.id).join("soft");
+            if let Err(e) = fs::write(&path, soft) {
+                trace_error!(
+                    task = "rfkill_set_blocked",
+                    "unable to write {}: {}",
+                    path.display(),
+                    e
+                );
+                bail!(WirelessNetworkError::new(
+                    code,
+                    format!("unable to switch {} ({}): {}", radio.id, radio.kind, e),
+                ))
+            }
+            info!(
+                task = "rfkill_set_blocked",
+                "{} ({}) blocked: {}", radio.id, radio.kind, blocked
+            );
+        }
+
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|radio| radio_type.matches(&radio.kind))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn add_radio(dir: &Path, id: &str, name: &str, kind: &str, hard: &str) {
+        let path = dir.join(id);
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("name"), format!("{}\n", name)).unwrap();
+        fs::write(path.join("type"), format!("{}\n", kind)).unwrap();
+        fs::write(path.join("soft"), "0\n").unwrap();
+        fs::write(path.join("hard"), format!("{}\n", hard)).unwrap();
+    }
+
+    #[test]
+    fn test_rfkill() {
+        let dir = TempDir::new("rfkill").unwrap();
+        add_radio(dir.path(), "rfkill10", "hci0", "bluetooth", "0");
+        add_radio(dir.path(), "rfkill2", "phy0", "wlan", "0");
+        add_radio(dir.path(), "rfkill3", "phy1", "wlan", "1");
+        let rfkill = Rfkill::new(dir.path());
+
+        let radios = rfkill.list().unwrap();
+        let ids: Vec<&str> = radios.iter().map(|radio| radio.id.as_str()).collect();
+        assert_eq!(ids, vec!["rfkill2", "rfkill3", "rfkill10"]);
+        assert!(radios[1].hard_blocked && radios[1].blocked());
+
+        let wifi = rfkill.set_blocked(RadioType::Wifi, true).unwrap();
+        assert_eq!(wifi.len(), 2);
+        assert!(wifi.iter().all(|radio| radio.soft_blocked));
+        assert!(!rfkill.list().unwrap()[2].soft_blocked);
+
+        let all = rfkill.set_blocked(RadioType::All, false).unwrap();
+        assert!(all.iter().all(|radio| !radio.soft_blocked));
+        // software cannot lift a hardware block
+        assert!(all[1].hard_blocked);
+
+        assert_eq!("bt".parse::<RadioType>().unwrap(), RadioType::Bluetooth);
+        assert!(Rfkill::new(dir.path().join("missing")).list().is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use wifi_ctrl::sta::NetworkResult;
+
+// A network stored in the supplicant configuration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SavedNetwork {
+    pub network_id: usize,
+    pub ssid: String,
+    // higher priorities are joined first when several networks are in range
+    pub priority: i32,
+    // the supplicant joins the network by itself when it is in range
+    pub auto_connect: bool,
+    // the network of the current connection
+    pub current: bool,
+}
+
+impl SavedNetwork {
+    // Saved network of a `LIST_NETWORKS` entry, `priority` comes from `GET_NETWORK`
+    pub fn from_network(network: &NetworkResult, priority: i32) -> Self {
+        SavedNetwork {
+            network_id: network.network_id,
+            ssid: network.ssid.clone(),
+            priority,
+            auto_connect: !network.flags.contains("[DISABLED]"),
+            current: network.flags.contains("[CURRENT]"),
+        }
+    }
+}
+
+// Value of a `GET_NETWORK` reply, `None` when the field is not set
+pub fn network_value(reply: &str) -> Option<String> {
+    let value = reply.trim();
+    match value {
+        "" | "FAIL" => None,
+        value => Some(value.trim_matches('"').to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_saved_network() {
+        let network = NetworkResult {
+            network_id: 2,
+            ssid: String::from("office"),
+            flags: String::from("[CURRENT]"),
+        };
+        let saved = SavedNetwork::from_network(&network, 5);
+        assert!(saved.current);
+        assert!(saved.auto_connect);
+        assert_eq!(saved.priority, 5);
+
+        let disabled = NetworkResult {
+            flags: String::from("[DISABLED]"),
+            ..network
+        };
+        assert!(!SavedNetwork::from_network(&disabled, 0).auto_connect);
+
+        assert_eq!(network_value("\"lab\"\n").as_deref(), Some("lab"));
+        assert_eq!(network_value("WPA-PSK SAE").as_deref(), Some("WPA-PSK SAE"));
+        assert_eq!(network_value("FAIL\n"), None);
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::profile::SecurityType;
+use crate::saved::SavedNetwork;
+use crate::session::ScanResult;
+use crate::status::channel_from_frequency;
+
+// signal at and above which quality is reported as 100%
+const FULL_QUALITY_DBM: i32 = -50;
+// signal at and below which quality is reported as 0%
+const NO_QUALITY_DBM: i32 = -100;
+
+// Frequency band of a BSS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RadioBand {
+    TwoPointFourGhz,
+    FiveGhz,
+    SixGhz,
+}
+
+impl RadioBand {
+    // Band of a frequency in MHz
+    pub fn from_frequency(frequency: u32) -> Option<Self> {
+        match frequency {
+            2400..=2500 => Some(RadioBand::TwoPointFourGhz),
+            5150..=5895 => Some(RadioBand::FiveGhz),
+            5925..=7125 => Some(RadioBand::SixGhz),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RadioBand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RadioBand::TwoPointFourGhz => write!(f, "2.4 GHz"),
+            RadioBand::FiveGhz => write!(f, "5 GHz"),
+            RadioBand::SixGhz => write!(f, "6 GHz"),
+        }
+    }
+}
+
+// Signal in dBm as a 0-100 quality, linear between -100 and -50 dBm
+pub fn signal_quality(signal: i32) -> u8 {
+    let signal = signal.clamp(NO_QUALITY_DBM, FULL_QUALITY_DBM);
+    ((signal - NO_QUALITY_DBM) * 100 / (FULL_QUALITY_DBM - NO_QUALITY_DBM)) as u8
+}
+
+// One access point of a scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bss {
+    pub bssid: String,
+    // MHz
+    pub frequency: u32,
+    pub band: Option<RadioBand>,
+    pub channel: Option<u32>,
+    // dBm
+    pub signal: i32,
+    // percent
+    pub quality: u8,
+    pub security: SecurityType,
+    // the access point accepts WPS
+    pub wps: bool,
+    // infrastructure network, false for ad-hoc and mesh
+    pub ess: bool,
+    // flags as the supplicant reported them
+    pub flags: String,
+}
+
+impl Bss {
+    pub fn from_scan_result(result: &ScanResult) -> Self {
+        let frequency = result.frequency.trim().parse().unwrap_or_default();
+        let signal = result.signal as i32;
+        Bss {
+            bssid: result.mac.clone(),
+            frequency,
+            band: RadioBand::from_frequency(frequency),
+            channel: channel_from_frequency(frequency),
+            signal,
+            quality: signal_quality(signal),
+            security: SecurityType::from_flags(&result.flags),
+            wps: result.flags.contains("[WPS"),
+            ess: result.flags.contains("[ESS]"),
+            flags: result.flags.clone(),
+        }
+    }
+}
+
+// A network of a scan with its access points, the strongest first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannedNetwork {
+    pub ssid: String,
+    pub bss: Vec<Bss>,
+    // the network is in the supplicant configuration
+    pub saved: bool,
+    // the interface is connected to it
+    pub current: bool,
+}
+
+impl ScannedNetwork {
+    fn strongest(&self) -> &Bss {
+        &self.bss[0]
+    }
+
+    pub fn security(&self) -> SecurityType {
+        self.strongest().security
+    }
+
+    pub fn signal(&self) -> i32 {
+        self.strongest().signal
+    }
+
+    pub fn quality(&self) -> u8 {
+        self.strongest().quality
+    }
+
+    pub fn wps(&self) -> bool {
+        self.bss.iter().any(|bss| bss.wps)
+    }
+
+    // Bands the network is seen on, sorted
+    pub fn bands(&self) -> Vec<RadioBand> {
+        let mut bands: Vec<RadioBand> = self.bss.iter().filter_map(|bss| bss.band).collect();
+        bands.sort();
+        bands.dedup();
+        bands
+    }
+}
+
+// Group scan results by ssid, strongest networks first
+//
+// Hidden networks have no ssid to join them by and are left out.
+pub fn group_scan_results(results: &[ScanResult], saved: &[SavedNetwork]) -> Vec<ScannedNetwork> {
+    let mut networks: Vec<ScannedNetwork> = Vec::new();
+    let mut by_ssid: HashMap<&str, usize> = HashMap::new();
+    for result in results.iter().filter(|result| !result.name.is_empty()) {
+        let bss = Bss::from_scan_result(result);
+        match by_ssid.get(result.name.as_str()) {
+            Some(index) => networks[*index].bss.push(bss),
+            None => {
+                let saved = saved.iter().find(|network| network.ssid == result.name);
+                by_ssid.insert(&result.name, networks.len());
+                networks.push(ScannedNetwork {
+                    ssid: result.name.clone(),
+                    bss: vec![bss],
+                    saved: saved.is_some(),
+                    current: saved.map(|network| network.current).unwrap_or(false),
+                });
+            }
+        }
+    }
+    for network in networks.iter_mut() {
+        network.bss.sort_by_key(|bss| std::cmp::Reverse(bss.signal));
+    }
+    networks.sort_by(|a, b| {
+        b.current
+            .cmp(&a.current)
+            .then(b.signal().cmp(&a.signal()))
+            .then(a.ssid.cmp(&b.ssid))
+    });
+    networks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(mac: &str, frequency: &str, signal: isize, flags: &str, name: &str) -> ScanResult {
+        ScanResult {
+            mac: mac.to_string(),
+            frequency: frequency.to_string(),
+            signal,
+            flags: flags.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_group_scan_results() {
+        let results = vec![
+            result(
+                "aa:00:00:00:00:01",
+                "2437",
+                -70,
+                "[WPA2-PSK-CCMP][ESS]",
+                "office",
+            ),
+            result(
+                "aa:00:00:00:00:02",
+                "5180",
+                -55,
+                "[WPA2-PSK-CCMP][WPS][ESS]",
+                "office",
+            ),
+            result("bb:00:00:00:00:01", "2412", -40, "[ESS]", "cafe"),
+            result("cc:00:00:00:00:01", "5975", -80, "[SAE-CCMP][ESS]", "home"),
+            result("dd:00:00:00:00:01", "2462", -60, "[WPA2-PSK-CCMP][ESS]", ""),
+        ];
+        let saved = vec![SavedNetwork {
+            network_id: 0,
+            ssid: String::from("home"),
+            priority: 0,
+            auto_connect: true,
+            current: true,
+        }];
+        let networks = group_scan_results(&results, &saved);
+        let ssids: Vec<&str> = networks
+            .iter()
+            .map(|network| network.ssid.as_str())
+            .collect();
+        // the current network first, then by signal, hidden networks left out
+        assert_eq!(ssids, vec!["home", "cafe", "office"]);
+
+        let home = &networks[0];
+        assert!(home.saved && home.current);
+        assert_eq!(home.security(), SecurityType::Sae);
+        assert_eq!(home.bands(), vec![RadioBand::SixGhz]);
+        assert_eq!(home.bss[0].channel, Some(5));
+
+        let office = &networks[2];
+        assert!(!office.saved);
+        assert_eq!(office.bss[0].bssid, "aa:00:00:00:00:02");
+        assert_eq!(office.signal(), -55);
+        assert_eq!(office.quality(), 90);
+        assert!(office.wps() && office.bss[0].ess);
+        assert_eq!(
+            office.bands(),
+            vec![RadioBand::TwoPointFourGhz, RadioBand::FiveGhz]
+        );
+        assert_eq!(office.bss[1].channel, Some(6));
+
+        assert_eq!(signal_quality(-30), 100);
+        assert_eq!(signal_quality(-75), 50);
+        assert_eq!(signal_quality(-110), 0);
+    }
+}
@@ -0,0 +1,448 @@
+use anyhow::{bail, Result};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{error as trace_error, info, trace, warn};
+use wifi_ctrl::sta;
+pub use wifi_ctrl::sta::{NetworkResult, ScanResult, SelectResult};
+
+use crate::errors::{WirelessNetworkError, WirelessNetworkErrorCodes};
+use crate::interfaces::{DEFAULT_CONTROL_DIR, DEFAULT_INTERFACE};
+use crate::saved;
+use crate::wps::WpsEvent;
+
+// events kept for watchers that fall behind
+const EVENT_CAPACITY: usize = 32;
+// the supplicant runtime defers requests while it waits for the socket,
+// give up on them long before it does
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Event reported by wpa_supplicant
+#[derive(Debug, Clone)]
+pub enum WifiEvent {
+    Connected {
+        ssid: Option<String>,
+        bssid: Option<String>,
+    },
+    Disconnected,
+    ScanResults(Vec<ScanResult>),
+    // authentication or association was rejected, `reason` is the supplicant's
+    AuthFailure {
+        ssid: Option<String>,
+        reason: String,
+    },
+    WrongKey {
+        ssid: Option<String>,
+    },
+    NetworkNotFound {
+        ssid: Option<String>,
+    },
+    Wps(WpsEvent),
+}
+
+impl WifiEvent {
+    // Typed event for a raw supplicant message the runtime did not recognise,
+    // `ssid` is the network being connected when the message names none
+    pub fn from_message(message: &str, ssid: Option<&str>) -> Option<WifiEvent> {
+        let ssid = || field(message, "ssid").or_else(|| ssid.map(str::to_string));
+        if message.contains("CTRL-EVENT-SSID-TEMP-DISABLED") {
+            let reason = field(message, "reason").unwrap_or_default();
+            return Some(match reason.as_str() {
+                "WRONG_KEY" => WifiEvent::WrongKey { ssid: ssid() },
+                _ => WifiEvent::AuthFailure {
+                    ssid: ssid(),
+                    reason,
+                },
+            });
+        }
+        if message.contains("CTRL-EVENT-EAP-FAILURE") {
+            return Some(WifiEvent::AuthFailure {
+                ssid: ssid(),
+                reason: String::from("EAP_FAILURE"),
+            });
+        }
+        if let Some(event) = WpsEvent::from_message(message) {
+            return Some(WifiEvent::Wps(event));
+        }
+        for (event, reason) in [
+            ("CTRL-EVENT-ASSOC-REJECT", "ASSOC_REJECT"),
+            ("CTRL-EVENT-AUTH-REJECT", "AUTH_REJECT"),
+        ] {
+            if message.contains(event) {
+                let reason = match field(message, "status_code") {
+                    Some(status_code) => format!("{} status_code={}", reason, status_code),
+                    None => reason.to_string(),
+                };
+                return Some(WifiEvent::AuthFailure {
+                    ssid: ssid(),
+                    reason,
+                });
+            }
+        }
+        None
+    }
+}
+
+// `key=value` or `key="value"` field of a supplicant message
+pub(crate) fn field(message: &str, key: &str) -> Option<String> {
+    let start = message.find(&format!(" {}=", key))? + key.len() + 2;
+    let value = &message[start..];
+    match value.strip_prefix('"') {
+        Some(quoted) => quoted.find('"').map(|end| quoted[..end].to_string()),
+        None => value.split_whitespace().next().map(str::to_string),
+    }
+}
+
+pub type WifiEventReceiver = broadcast::Receiver<WifiEvent>;
+
+struct Station {
+    requester: sta::RequestClient,
+    runtime: JoinHandle<()>,
+}
+
+// One long-lived connection to wpa_supplicant. The connection is opened on the
+// first request and opened again when the supplicant went away, events keep
+// flowing to subscribers across reconnects.
+#[derive(Clone)]
+pub struct WifiSession {
+    socket_path: PathBuf,
+    station: Arc<Mutex<Option<Station>>>,
+    events: broadcast::Sender<WifiEvent>,
+    // ssid of the network being connected, failures are reported against it
+    connecting: Arc<StdMutex<Option<String>>>,
+}
+
+impl Default for WifiSession {
+    fn default() -> Self {
+        Self::new(Path::new(DEFAULT_CONTROL_DIR).join(DEFAULT_INTERFACE))
+    }
+}
+
+impl WifiSession {
+    pub fn new<P: Into<PathBuf>>(socket_path: P) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        WifiSession {
+            socket_path: socket_path.into(),
+            station: Arc::new(Mutex::new(None)),
+            events,
+            connecting: Arc::new(StdMutex::new(None)),
+        }
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    // Events from now on, starts the connection if needed
+    pub async fn subscribe(&self) -> Result<WifiEventReceiver> {
+        let events = self.events.subscribe();
+        self.requester().await?;
+        Ok(events)
+    }
+
+    async fn requester(&self) -> Result<sta::RequestClient> {
+        let mut station = self.station.lock().await;
+        if let Some(station) = station.as_ref() {
+            if !station.runtime.is_finished() {
+                return Ok(station.requester.clone());
+            }
+            info!(
+                task = "wifi_session",
+                "supplicant connection closed, reconnecting"
+            );
+        }
+        let started = self.start()?;
+        let requester = started.requester.clone();
+        *station = Some(started);
+        Ok(requester)
+    }
+
+    fn start(&self) -> Result<Station> {
+        trace!(task = "wifi_session", "init");
+        let mut setup = match sta::WifiSetup::new() {
+            Ok(setup) => setup,
+            Err(e) => {
+                trace_error!(
+                    task = "wifi_session",
+                    "unable to set up supplicant connection: {}",
+                    e
+                );
+                bail!(WirelessNetworkError::new(
+                    WirelessNetworkErrorCodes::UnableToReachSupplicant,
+                    format!("unable to set up supplicant connection: {}", e),
+                ))
+            }
+        };
+        setup.set_socket_path(&self.socket_path);
+
+        let broadcast = setup.get_broadcast_receiver();
+        let requester = setup.get_request_client();
+        let runtime = setup.complete();
+        let socket_path = self.socket_path.clone();
+        let runtime = tokio::spawn(async move {
+            if let Err(e) = runtime.run().await {
+                trace_error!(
+                    task = "wifi_session",
+                    "supplicant connection {} failed: {}",
+                    socket_path.display(),
+                    e
+                );
+            }
+        });
+        // the runtime fails when it has no one to broadcast to, this keeps a
+        // receiver alive for as long as the runtime runs
+        tokio::spawn(Self::forward_events(
+            broadcast,
+            requester.clone(),
+            self.events.clone(),
+            self.connecting.clone(),
+        ));
+        info!(
+            task = "wifi_session",
+            "connecting to supplicant at {}",
+            self.socket_path.display()
+        );
+        Ok(Station { requester, runtime })
+    }
+
+    async fn forward_events(
+        mut broadcast: sta::BroadcastReceiver,
+        requester: sta::RequestClient,
+        events: broadcast::Sender<WifiEvent>,
+        connecting: Arc<StdMutex<Option<String>>>,
+    ) {
+        loop {
+            let message = match broadcast.recv().await {
+                Ok(message) => message,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!(task = "wifi_events", "missed {} supplicant events", missed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let ssid = connecting.lock().ok().and_then(|ssid| ssid.clone());
+            let event = match message {
+                sta::Broadcast::Ready => None,
+                sta::Broadcast::Connected => {
+                    // the runtime drops the details of the event
+                    let status = requester.get_status().await.unwrap_or_default();
+                    Some(WifiEvent::Connected {
+                        ssid: status.get("ssid").cloned(),
+                        bssid: status.get("bssid").cloned(),
+                    })
+                }
+                sta::Broadcast::Disconnected => Some(WifiEvent::Disconnected),
+                sta::Broadcast::WrongPsk => Some(WifiEvent::WrongKey { ssid }),
+                sta::Broadcast::NetworkNotFound => Some(WifiEvent::NetworkNotFound { ssid }),
+                sta::Broadcast::Unknown(message) => {
+                    WifiEvent::from_message(&message, ssid.as_deref())
+                }
+            };
+            if let Some(event) = event {
+                info!(task = "wifi_events", "event: {:?}", event);
+                // nobody watching is fine
+                let _ = events.send(event);
+            }
+        }
+        trace!(task = "wifi_events", "supplicant events closed");
+    }
+
+    async fn request<T, F, R>(&self, task: &str, request: F) -> Result<T>
+    where
+        F: FnOnce(sta::RequestClient) -> R,
+        R: Future<Output = wifi_ctrl::Result<T>>,
+    {
+        let requester = self.requester().await?;
+        match tokio::time::timeout(REQUEST_TIMEOUT, request(requester)).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(e)) => {
+                trace_error!(task = task, "supplicant request failed: {}", e);
+                bail!(WirelessNetworkError::new(
+                    WirelessNetworkErrorCodes::UnableToReachSupplicant,
+                    format!("supplicant request failed: {}", e),
+                ))
+            }
+            Err(_) => {
+                trace_error!(task = task, "supplicant request timed out");
+                bail!(WirelessNetworkError::new(
+                    WirelessNetworkErrorCodes::UnableToReachSupplicant,
+                    format!(
+                        "no response from supplicant at {}",
+                        self.socket_path.display()
+                    ),
+                ))
+            }
+        }
+    }
+
+    pub async fn scan(&self) -> Result<Vec<ScanResult>> {
+        let results = self
+            .request(
+                "scan",
+                |requester| async move { requester.get_scan().await },
+            )
+            .await?
+            .to_vec();
+        let _ = self.events.send(WifiEvent::ScanResults(results.clone()));
+        Ok(results)
+    }
+
+    pub async fn networks(&self) -> Result<Vec<NetworkResult>> {
+        self.request("networks", |requester| async move {
+            requester.get_networks().await
+        })
+        .await
+    }
+
+    pub async fn status(&self) -> Result<sta::Status> {
+        self.request(
+            "status",
+            |requester| async move { requester.get_status().await },
+        )
+        .await
+    }
+
+    pub async fn add_network(&self) -> Result<usize> {
+        self.request("add_network", |requester| async move {
+            requester.add_network().await
+        })
+        .await
+    }
+
+    pub async fn set_network_ssid(&self, network_id: usize, ssid: &str) -> Result<()> {
+        let ssid = ssid.to_string();
+        self.request("set_network_ssid", |requester| async move {
+            requester.set_network_ssid(network_id, ssid).await
+        })
+        .await
+    }
+
+    pub async fn set_network_psk(&self, network_id: usize, psk: &str) -> Result<()> {
+        let psk = psk.to_string();
+        self.request("set_network_psk", |requester| async move {
+            requester.set_network_psk(network_id, psk).await
+        })
+        .await
+    }
+
+    // Set one `SET_NETWORK` parameter, `value` already quoted as the supplicant expects
+    pub async fn set_network(&self, network_id: usize, key: &str, value: &str) -> Result<()> {
+        let reply = self
+            .command(&format!("SET_NETWORK {} {} {}", network_id, key, value))
+            .await?;
+        if reply.trim() != "OK" {
+            // the value may be a secret, only the key is reported
+            trace_error!(task = "set_network", "supplicant rejected {}", key);
+            bail!(WirelessNetworkError::new(
+                WirelessNetworkErrorCodes::InvalidNetworkProfile,
+                format!("supplicant rejected {} of network {}", key, network_id),
+            ))
+        }
+        Ok(())
+    }
+
+    // Value of one network parameter, `None` when it is not set
+    pub async fn get_network(&self, network_id: usize, key: &str) -> Result<Option<String>> {
+        let reply = self
+            .command(&format!("GET_NETWORK {} {}", network_id, key))
+            .await?;
+        Ok(saved::network_value(&reply))
+    }
+
+    // Select network `network_id` named `ssid` and wait for the outcome
+    pub async fn select_network(&self, network_id: usize, ssid: &str) -> Result<SelectResult> {
+        if let Ok(mut connecting) = self.connecting.lock() {
+            *connecting = Some(ssid.to_string());
+        }
+        let result = self
+            .request("select_network", |requester| async move {
+                requester.select_network(network_id).await
+            })
+            .await;
+        if let Ok(mut connecting) = self.connecting.lock() {
+            *connecting = None;
+        }
+        result
+    }
+
+    pub async fn remove_network(&self, network_id: usize) -> Result<()> {
+        self.request("remove_network", |requester| async move {
+            requester.remove_network(network_id).await
+        })
+        .await
+    }
+
+    // Raw supplicant command, returns the reply
+    pub async fn command(&self, command: &str) -> Result<String> {
+        let command = command.to_string();
+        self.request("command", |requester| async move {
+            requester.send_custom(command).await
+        })
+        .await
+    }
+
+    // Close the connection, the next request opens a new one
+    pub async fn shutdown(&self) -> Result<()> {
+        if let Some(station) = self.station.lock().await.take() {
+            if let Err(e) = station.requester.shutdown().await {
+                trace_error!(task = "wifi_session", "unable to shut down: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_from_message() {
+        let event = WifiEvent::from_message(
+            "<3>CTRL-EVENT-SSID-TEMP-DISABLED id=0 ssid=\"home net\" auth_failures=1 duration=10 reason=WRONG_KEY",
+            None,
+        );
+        assert!(
+            matches!(event, Some(WifiEvent::WrongKey { ssid: Some(ssid) }) if ssid == "home net")
+        );
+
+        let event = WifiEvent::from_message(
+            "<3>CTRL-EVENT-SSID-TEMP-DISABLED id=1 ssid=\"office\" auth_failures=2 duration=20 reason=AUTH_FAILED",
+            None,
+        );
+        assert!(matches!(
+            event,
+            Some(WifiEvent::AuthFailure { ssid: Some(ssid), reason })
+                if ssid == "office" && reason == "AUTH_FAILED"
+        ));
+
+        let event = WifiEvent::from_message(
+            "<3>CTRL-EVENT-ASSOC-REJECT bssid=aa:bb:cc:dd:ee:ff status_code=17",
+            Some("office"),
+        );
+        assert!(matches!(
+            event,
+            Some(WifiEvent::AuthFailure { ssid: Some(ssid), reason })
+                if ssid == "office" && reason == "ASSOC_REJECT status_code=17"
+        ));
+
+        let event =
+            WifiEvent::from_message("<3>CTRL-EVENT-EAP-FAILURE EAP authentication failed", None);
+        assert!(matches!(
+            event,
+            Some(WifiEvent::AuthFailure { ssid: None, reason }) if reason == "EAP_FAILURE"
+        ));
+
+        assert!(matches!(
+            WifiEvent::from_message("<3>WPS-SUCCESS", None),
+            Some(WifiEvent::Wps(WpsEvent::Success))
+        ));
+
+        assert!(
+            WifiEvent::from_message("<3>CTRL-EVENT-BSS-ADDED 0 aa:bb:cc:dd:ee:ff", None).is_none()
+        );
+    }
+}
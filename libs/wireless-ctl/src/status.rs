@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use crate::netlink::{DefaultRoute, InterfaceAddress};
+
+// wpa_state once the connection is up
+pub const WPA_STATE_COMPLETED: &str = "COMPLETED";
+
+// Channel number of a frequency in MHz
+pub fn channel_from_frequency(frequency: u32) -> Option<u32> {
+    match frequency {
+        2484 => Some(14),
+        2412..=2472 => Some((frequency - 2407) / 5),
+        5160..=5885 => Some((frequency - 5000) / 5),
+        5955..=7115 => Some((frequency - 5950) / 5),
+        _ => None,
+    }
+}
+
+// State of a wireless interface as seen by wpa_supplicant and the kernel
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WifiStatus {
+    pub interface: String,
+    // whether the interface is up
+    pub enabled: bool,
+    // `None` when no supplicant runs on the interface
+    pub wpa_state: Option<String>,
+    pub ssid: Option<String>,
+    pub bssid: Option<String>,
+    // MHz
+    pub frequency: Option<u32>,
+    pub channel: Option<u32>,
+    // dBm
+    pub rssi: Option<i32>,
+    // Mbit/s
+    pub link_speed: Option<u32>,
+    pub key_mgmt: Option<String>,
+    pub ipv4_addresses: Vec<InterfaceAddress>,
+    pub ipv6_addresses: Vec<InterfaceAddress>,
+    pub default_route: Option<DefaultRoute>,
+}
+
+impl WifiStatus {
+    pub fn connected(&self) -> bool {
+        self.wpa_state.as_deref() == Some(WPA_STATE_COMPLETED)
+    }
+
+    // Fill in the fields of the supplicant `STATUS` reply
+    pub fn set_supplicant_status(&mut self, status: &HashMap<String, String>) {
+        let value = |key: &str| status.get(key).filter(|value| !value.is_empty()).cloned();
+        self.wpa_state = value("wpa_state");
+        self.ssid = value("ssid");
+        self.bssid = value("bssid");
+        self.frequency = value("freq").and_then(|freq| freq.parse().ok());
+        self.channel = self.frequency.and_then(channel_from_frequency);
+        self.key_mgmt = value("key_mgmt");
+    }
+
+    // Fill in the fields of the supplicant `SIGNAL_POLL` reply
+    pub fn set_signal_poll(&mut self, reply: &str) {
+        for line in reply.lines() {
+            match line.trim().split_once('=') {
+                Some(("RSSI", rssi)) => self.rssi = rssi.parse().ok(),
+                Some(("LINKSPEED", speed)) => self.link_speed = speed.parse().ok(),
+                Some(("FREQUENCY", freq)) if self.frequency.is_none() => {
+                    self.frequency = freq.parse().ok();
+                    self.channel = self.frequency.and_then(channel_from_frequency);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn set_addresses(&mut self, addresses: Vec<InterfaceAddress>) {
+        let (ipv4_addresses, ipv6_addresses) = addresses
+            .into_iter()
+            .partition(|address| address.address.is_ipv4());
+        self.ipv4_addresses = ipv4_addresses;
+        self.ipv6_addresses = ipv6_addresses;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status() {
+        assert_eq!(channel_from_frequency(2412), Some(1));
+        assert_eq!(channel_from_frequency(2484), Some(14));
+        assert_eq!(channel_from_frequency(5180), Some(36));
+        assert_eq!(channel_from_frequency(5955), Some(1));
+        assert_eq!(channel_from_frequency(900), None);
+
+        let supplicant: HashMap<String, String> = [
+            ("wpa_state", "COMPLETED"),
+            ("ssid", "office"),
+            ("bssid", "aa:bb:cc:dd:ee:ff"),
+            ("freq", "5180"),
+            ("key_mgmt", "WPA2-PSK"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+        let mut status = WifiStatus::default();
+        status.set_supplicant_status(&supplicant);
+        status.set_signal_poll("RSSI=-61\nLINKSPEED=433\nNOISE=9999\nFREQUENCY=5180\n");
+
+        assert!(status.connected());
+        assert_eq!(status.ssid.as_deref(), Some("office"));
+        assert_eq!(status.channel, Some(36));
+        assert_eq!(status.rssi, Some(-61));
+        assert_eq!(status.link_speed, Some(433));
+        assert_eq!(status.key_mgmt.as_deref(), Some("WPA2-PSK"));
+    }
+}
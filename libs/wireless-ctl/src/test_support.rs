@@ -0,0 +1,497 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UnixDatagram;
+use tokio::task::JoinHandle;
+
+// how often `wait_attached` looks for an event listener
+const ATTACH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+const ATTACH_TIMEOUT: Duration = Duration::from_secs(5);
+const FAKE_ADDRESS: &str = "02:00:00:00:00:01";
+
+// What SELECT_NETWORK leads to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectOutcome {
+    // the network is joined and CTRL-EVENT-CONNECTED sent
+    Connect,
+    // the passphrase is rejected with reason=WRONG_KEY
+    WrongKey,
+    // no access point of the network answers
+    NotFound,
+    // nothing happens, the caller times out
+    Silent,
+}
+
+// A network in the fake supplicant configuration, values as SET_NETWORK gave them
+// except the ssid which is kept as plain text
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FakeNetwork {
+    pub ssid: String,
+    pub settings: BTreeMap<String, String>,
+    pub disabled: bool,
+}
+
+// An access point the fake scan finds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FakeBss {
+    pub bssid: String,
+    // MHz
+    pub frequency: u32,
+    // dBm
+    pub signal: i32,
+    pub flags: String,
+    pub ssid: String,
+}
+
+struct FakeState {
+    scan_results: Vec<FakeBss>,
+    networks: BTreeMap<usize, FakeNetwork>,
+    next_network_id: usize,
+    current: Option<usize>,
+    select_outcome: SelectOutcome,
+    // replies to commands starting with the key, before the built-in ones
+    replies: Vec<(String, String)>,
+    commands: Vec<String>,
+    listeners: Vec<PathBuf>,
+    // events raised before anyone attached, sent on ATTACH
+    queued: Vec<String>,
+    // applied after the STATUS request that follows SELECT_NETWORK
+    pending_select: Option<usize>,
+}
+
+impl Default for FakeState {
+    fn default() -> Self {
+        FakeState {
+            scan_results: Vec::new(),
+            networks: BTreeMap::new(),
+            next_network_id: 0,
+            current: None,
+            select_outcome: SelectOutcome::Connect,
+            replies: Vec::new(),
+            commands: Vec::new(),
+            listeners: Vec::new(),
+            queued: Vec::new(),
+            pending_select: None,
+        }
+    }
+}
+
+// Stand-in for wpa_supplicant serving its control interface on a Unix datagram
+// socket, so the supplicant session can be driven without a radio. Must be
+// started inside a tokio runtime, the socket goes away when it is dropped.
+pub struct FakeSupplicant {
+    socket_path: PathBuf,
+    socket: Arc<UnixDatagram>,
+    state: Arc<Mutex<FakeState>>,
+    task: JoinHandle<()>,
+}
+
+impl FakeSupplicant {
+    // Serve the control socket of `interface` in `control_dir`
+    pub fn start(control_dir: &Path, interface: &str) -> io::Result<Self> {
+        let socket_path = control_dir.join(interface);
+        let socket = Arc::new(UnixDatagram::bind(&socket_path)?);
+        let state = Arc::new(Mutex::new(FakeState::default()));
+        let task = tokio::spawn(Self::serve(socket.clone(), state.clone()));
+        Ok(FakeSupplicant {
+            socket_path,
+            socket,
+            state,
+            task,
+        })
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, FakeState> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    pub fn add_scan_result(
+        &self,
+        bssid: &str,
+        frequency: u32,
+        signal: i32,
+        flags: &str,
+        ssid: &str,
+    ) {
+        self.state().scan_results.push(FakeBss {
+            bssid: bssid.to_string(),
+            frequency,
+            signal,
+            flags: flags.to_string(),
+            ssid: ssid.to_string(),
+        });
+    }
+
+    // A network already in the configuration, returns its id
+    pub fn add_network(&self, ssid: &str) -> usize {
+        let mut state = self.state();
+        let network_id = state.next_network_id;
+        state.next_network_id += 1;
+        state.networks.insert(
+            network_id,
+            FakeNetwork {
+                ssid: ssid.to_string(),
+                ..Default::default()
+            },
+        );
+        network_id
+    }
+
+    pub fn set_select_outcome(&self, outcome: SelectOutcome) {
+        self.state().select_outcome = outcome;
+    }
+
+    // Answer commands starting with `command` with `reply`
+    pub fn set_reply(&self, command: &str, reply: &str) {
+        self.state()
+            .replies
+            .insert(0, (command.to_string(), reply.to_string()));
+    }
+
+    pub fn network(&self, network_id: usize) -> Option<FakeNetwork> {
+        self.state().networks.get(&network_id).cloned()
+    }
+
+    pub fn networks(&self) -> BTreeMap<usize, FakeNetwork> {
+        self.state().networks.clone()
+    }
+
+    pub fn current_network(&self) -> Option<usize> {
+        self.state().current
+    }
+
+    // Every command received so far, oldest first
+    pub fn commands(&self) -> Vec<String> {
+        self.state().commands.clone()
+    }
+
+    // Wait until a client attached for events, `emit` reaches nobody before
+    pub async fn wait_attached(&self) -> bool {
+        let deadline = tokio::time::Instant::now() + ATTACH_TIMEOUT;
+        while self.state().listeners.is_empty() {
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(ATTACH_POLL_INTERVAL).await;
+        }
+        true
+    }
+
+    // Join `network_id` as the supplicant does by itself, after WPS or a roam
+    pub async fn connect(&self, network_id: usize) {
+        let events = {
+            let mut state = self.state();
+            let outcome = state.select_outcome;
+            state.select_outcome = SelectOutcome::Connect;
+            let events = state.select(network_id);
+            state.select_outcome = outcome;
+            events
+        };
+        for event in events {
+            self.emit(&event).await;
+        }
+    }
+
+    // Send `event` such as `CTRL-EVENT-DISCONNECTED` to the attached clients, it is
+    // held back until one attaches
+    pub async fn emit(&self, event: &str) {
+        Self::send_event(&self.socket, &self.state, event).await
+    }
+
+    async fn send_event(socket: &UnixDatagram, state: &Mutex<FakeState>, event: &str) {
+        let listeners = {
+            let mut state = match state.lock() {
+                Ok(state) => state,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            // the event socket of a client attaches after its first request went out
+            if state.listeners.is_empty() {
+                state.queued.push(event.to_string());
+                return;
+            }
+            state.listeners.clone()
+        };
+        let message = format!("<3>{}", event);
+        for listener in listeners {
+            let _ = socket.send_to(message.as_bytes(), &listener).await;
+        }
+    }
+
+    async fn serve(socket: Arc<UnixDatagram>, state: Arc<Mutex<FakeState>>) {
+        let mut buffer = vec![0u8; 4096];
+        loop {
+            let (len, peer) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(_) => break,
+            };
+            let peer = match peer.as_pathname() {
+                Some(peer) => peer.to_path_buf(),
+                // unbound clients cannot be answered
+                None => continue,
+            };
+            let command = String::from_utf8_lossy(&buffer[..len]).trim().to_string();
+            let (reply, events) = match state.lock() {
+                Ok(mut state) => state.handle(&command, &peer),
+                Err(poisoned) => poisoned.into_inner().handle(&command, &peer),
+            };
+            let _ = socket.send_to(reply.as_bytes(), &peer).await;
+            for event in events {
+                Self::send_event(&socket, &state, &event).await;
+            }
+        }
+    }
+}
+
+impl Drop for FakeSupplicant {
+    fn drop(&mut self) {
+        self.task.abort();
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
+}
+
+// the ssid of SET_NETWORK is quoted text or hex
+fn decode_ssid(value: &str) -> String {
+    if let Some(quoted) = value.strip_prefix('"') {
+        return quoted.trim_end_matches('"').to_string();
+    }
+    let bytes: Option<Vec<u8>> = (0..value.len())
+        .step_by(2)
+        .map(|i| {
+            value
+                .get(i..i + 2)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        })
+        .collect();
+    match bytes {
+        Some(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+        None => value.to_string(),
+    }
+}
+
+impl FakeState {
+    // Reply to `command` and the events to send after it
+    fn handle(&mut self, command: &str, peer: &Path) -> (String, Vec<String>) {
+        self.commands.push(command.to_string());
+        let ok = || String::from("OK");
+        let fail = || String::from("FAIL");
+        if let Some((_, reply)) = self
+            .replies
+            .iter()
+            .find(|(prefix, _)| command.starts_with(prefix.as_str()))
+        {
+            return (reply.clone(), Vec::new());
+        }
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let mut events = Vec::new();
+        let reply = match name {
+            "PING" => String::from("PONG"),
+            "ATTACH" => {
+                self.listeners.push(peer.to_path_buf());
+                events.append(&mut self.queued);
+                ok()
+            }
+            "DETACH" => {
+                self.listeners.retain(|listener| listener != peer);
+                ok()
+            }
+            "SCAN" => {
+                events.push(String::from("CTRL-EVENT-SCAN-STARTED "));
+                events.push(String::from("CTRL-EVENT-SCAN-RESULTS"));
+                ok()
+            }
+            "SCAN_RESULTS" => self.scan_results(),
+            "LIST_NETWORKS" => self.list_networks(),
+            "STATUS" => {
+                let status = self.status();
+                if let Some(network_id) = self.pending_select.take() {
+                    events.extend(self.select(network_id));
+                }
+                status
+            }
+            "ADD_NETWORK" => {
+                let network_id = self.next_network_id;
+                self.next_network_id += 1;
+                self.networks.insert(network_id, FakeNetwork::default());
+                network_id.to_string()
+            }
+            "SET_NETWORK" => {
+                let network_id = words.next().and_then(|id| id.parse().ok());
+                let key = words.next();
+                let value = words.collect::<Vec<&str>>().join(" ");
+                match (network_id.and_then(|id| self.networks.get_mut(&id)), key) {
+                    (Some(network), Some("ssid")) => {
+                        network.ssid = decode_ssid(&value);
+                        ok()
+                    }
+                    (Some(network), Some(key)) if !value.is_empty() => {
+                        network.settings.insert(key.to_string(), value);
+                        ok()
+                    }
+                    _ => fail(),
+                }
+            }
+            "GET_NETWORK" => {
+                let network_id = words.next().and_then(|id| id.parse().ok());
+                let key = words.next();
+                match (network_id.and_then(|id| self.networks.get(&id)), key) {
+                    (Some(network), Some("ssid")) => format!("\"{}\"", network.ssid),
+                    (Some(network), Some(key)) => {
+                        network.settings.get(key).cloned().unwrap_or_else(fail)
+                    }
+                    _ => fail(),
+                }
+            }
+            "REMOVE_NETWORK" | "ENABLE_NETWORK" | "DISABLE_NETWORK" => {
+                let ids: Vec<usize> = match words.next() {
+                    Some("all") => self.networks.keys().copied().collect(),
+                    Some(id) => id.parse().ok().into_iter().collect(),
+                    None => Vec::new(),
+                };
+                if ids.is_empty() || ids.iter().any(|id| !self.networks.contains_key(id)) {
+                    fail()
+                } else {
+                    for id in ids {
+                        match name {
+                            "REMOVE_NETWORK" => {
+                                self.networks.remove(&id);
+                                if self.current == Some(id) {
+                                    self.current = None;
+                                    events.push(disconnected());
+                                }
+                            }
+                            _ => {
+                                if let Some(network) = self.networks.get_mut(&id) {
+                                    network.disabled = name == "DISABLE_NETWORK";
+                                }
+                            }
+                        }
+                    }
+                    ok()
+                }
+            }
+            "SELECT_NETWORK" => match words.next().and_then(|id| id.parse().ok()) {
+                Some(network_id) if self.networks.contains_key(&network_id) => {
+                    // the real supplicant disables every other network
+                    for (id, network) in self.networks.iter_mut() {
+                        network.disabled = *id != network_id;
+                    }
+                    self.pending_select = Some(network_id);
+                    ok()
+                }
+                _ => fail(),
+            },
+            "DISCONNECT" => {
+                if self.current.take().is_some() {
+                    events.push(disconnected());
+                }
+                ok()
+            }
+            "RECONNECT" | "REASSOCIATE" | "SAVE_CONFIG" | "WPS_CANCEL" => ok(),
+            "WPS_PBC" => ok(),
+            // the PIN in use, generated when none was given
+            "WPS_PIN" => words.nth(1).unwrap_or("12345670").to_string(),
+            "SIGNAL_POLL" => match self.current_bss() {
+                Some(bss) => format!(
+                    "RSSI={}\nLINKSPEED=144\nNOISE=9999\nFREQUENCY={}\n",
+                    bss.signal, bss.frequency
+                ),
+                None => fail(),
+            },
+            _ => String::from("UNKNOWN COMMAND"),
+        };
+        (reply, events)
+    }
+
+    fn select(&mut self, network_id: usize) -> Vec<String> {
+        let ssid = self
+            .networks
+            .get(&network_id)
+            .map(|network| network.ssid.clone())
+            .unwrap_or_default();
+        match self.select_outcome {
+            SelectOutcome::Connect => {
+                self.current = Some(network_id);
+                let bssid = self
+                    .current_bss()
+                    .map(|bss| bss.bssid.clone())
+                    .unwrap_or_else(|| FAKE_ADDRESS.to_string());
+                vec![format!(
+                    "CTRL-EVENT-CONNECTED - Connection to {} completed [id={} id_str=]",
+                    bssid, network_id
+                )]
+            }
+            SelectOutcome::WrongKey => vec![format!(
+                "CTRL-EVENT-SSID-TEMP-DISABLED id={} ssid=\"{}\" auth_failures=1 duration=10 reason=WRONG_KEY",
+                network_id, ssid
+            )],
+            SelectOutcome::NotFound => vec![String::from("CTRL-EVENT-NETWORK-NOT-FOUND")],
+            SelectOutcome::Silent => Vec::new(),
+        }
+    }
+
+    fn current_bss(&self) -> Option<&FakeBss> {
+        let network = self.networks.get(&self.current?)?;
+        self.scan_results
+            .iter()
+            .filter(|bss| bss.ssid == network.ssid)
+            .max_by_key(|bss| bss.signal)
+    }
+
+    fn scan_results(&self) -> String {
+        let mut reply = String::from("bssid / frequency / signal level / flags / ssid\n");
+        for bss in &self.scan_results {
+            reply.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\n",
+                bss.bssid, bss.frequency, bss.signal, bss.flags, bss.ssid
+            ));
+        }
+        reply
+    }
+
+    fn list_networks(&self) -> String {
+        let mut reply = String::from("network id / ssid / bssid / flags\n");
+        for (id, network) in &self.networks {
+            let flags = match (self.current == Some(*id), network.disabled) {
+                (true, _) => "[CURRENT]",
+                (false, true) => "[DISABLED]",
+                (false, false) => "",
+            };
+            reply.push_str(&format!("{}\t{}\tany\t{}\n", id, network.ssid, flags));
+        }
+        reply
+    }
+
+    fn status(&self) -> String {
+        let network = self
+            .current
+            .and_then(|id| self.networks.get(&id).map(|network| (id, network)));
+        match network {
+            Some((id, network)) => {
+                let bss = self.current_bss();
+                format!(
+                    "bssid={}\nfreq={}\nssid={}\nid={}\nmode=station\npairwise_cipher=CCMP\nkey_mgmt=WPA2-PSK\nwpa_state=COMPLETED\naddress={}\n",
+                    bss.map(|bss| bss.bssid.as_str()).unwrap_or(FAKE_ADDRESS),
+                    bss.map(|bss| bss.frequency).unwrap_or(2412),
+                    network.ssid,
+                    id,
+                    FAKE_ADDRESS
+                )
+            }
+            None => format!("wpa_state=DISCONNECTED\naddress={}\n", FAKE_ADDRESS),
+        }
+    }
+}
+
+fn disconnected() -> String {
+    String::from("CTRL-EVENT-DISCONNECTED bssid=00:00:00:00:00:00 reason=3 locally_generated=1")
+}
@@ -0,0 +1,281 @@
+use anyhow::{bail, Result};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::errors::{WirelessNetworkError, WirelessNetworkErrorCodes};
+use crate::hotspot::HotspotConfig;
+use crate::profile::{validate_ssid, NetworkProfile, Security, SecurityType};
+
+const SCHEME: &str = "WIFI:";
+// characters escaped with a backslash in field values
+const SPECIAL_CHARS: [char; 5] = ['\\', ';', ',', '"', ':'];
+
+fn invalid_uri(message: String) -> WirelessNetworkError {
+    WirelessNetworkError::new(WirelessNetworkErrorCodes::InvalidNetworkProfile, message)
+}
+
+// A network in the `WIFI:T:WPA;S:ssid;P:pass;H:true;;` format phone cameras read
+// from QR codes, as Wi-Fi Easy Connect specifies it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WifiUri {
+    pub ssid: String,
+    // Open, Wep, WpaPsk, Sae or Eap
+    pub security: SecurityType,
+    pub password: Option<String>,
+    pub hidden: bool,
+}
+
+impl WifiUri {
+    // URI joining the hotspot of `config`
+    pub fn for_hotspot(config: &HotspotConfig) -> Self {
+        WifiUri {
+            ssid: config.ssid.clone(),
+            security: match config.passphrase {
+                Some(_) => SecurityType::WpaPsk,
+                None => SecurityType::Open,
+            },
+            password: config.passphrase.clone(),
+            hidden: false,
+        }
+    }
+
+    // `T:WPA` covers WPA2 and WPA3 transition networks alike, the scan results
+    // tell which one the access point runs
+    pub fn ambiguous_security(&self) -> bool {
+        self.security == SecurityType::WpaPsk
+    }
+
+    pub fn profile(&self) -> Result<NetworkProfile> {
+        let password = self.password.as_deref().unwrap_or_default();
+        let security = Security::for_type(self.security, password)?;
+        let profile = NetworkProfile::new(&self.ssid, security).hidden(self.hidden);
+        profile.validate()?;
+        Ok(profile)
+    }
+}
+
+// Split `T:WPA;S:ssid;;` into its fields, keeping escaped characters in values
+// and telling whether a value is wrapped in unescaped quotes
+fn fields(payload: &str) -> Result<Vec<(String, String, bool)>> {
+    let mut fields = Vec::new();
+    let mut chars = payload.chars();
+    loop {
+        let mut key = String::new();
+        let mut value = String::new();
+        let mut in_value = false;
+        let mut terminated = false;
+        // whether the first and the last character of the value are unescaped quotes
+        let mut opening_quote = false;
+        let mut closing_quote = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if in_value => match chars.next() {
+                    Some(escaped) => {
+                        value.push(escaped);
+                        closing_quote = false;
+                    }
+                    None => bail!(invalid_uri(String::from("URI ends in an escape"))),
+                },
+                ':' if !in_value => in_value = true,
+                ';' => {
+                    terminated = true;
+                    break;
+                }
+                c if in_value => {
+                    opening_quote |= value.is_empty() && c == '"';
+                    closing_quote = c == '"';
+                    value.push(c);
+                }
+                c => key.push(c),
+            }
+        }
+        if !terminated {
+            bail!(invalid_uri(String::from("URI does not end with ;;")))
+        }
+        // an empty field is the closing `;`
+        if key.is_empty() && !in_value {
+            return Ok(fields);
+        }
+        let quoted = opening_quote && closing_quote && value.chars().count() >= 2;
+        fields.push((key.trim().to_uppercase(), value, quoted));
+    }
+}
+
+// values that look like hex are quoted so readers take them as text, escaped
+// quotes are part of the value
+fn unquote(value: String, quoted: bool) -> String {
+    match quoted {
+        true => value[1..value.len() - 1].to_string(),
+        false => value,
+    }
+}
+
+// Escaped `value`, quoted when it looks like hex so a 64 digit passphrase is
+// not taken for a raw PSK
+fn quote(value: &str) -> String {
+    match value.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => format!("\"{}\"", escape(value)),
+        false => escape(value),
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        if SPECIAL_CHARS.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl FromStr for WifiUri {
+    type Err = anyhow::Error;
+
+    fn from_str(uri: &str) -> Result<Self> {
+        let uri = uri.trim();
+        let payload = match uri.get(..SCHEME.len()) {
+            Some(scheme) if scheme.eq_ignore_ascii_case(SCHEME) => &uri[SCHEME.len()..],
+            _ => bail!(invalid_uri(String::from("URI does not start with WIFI:"))),
+        };
+        let mut ssid = None;
+        let mut auth = String::new();
+        let mut password = None;
+        let mut hidden = false;
+        // transition disable, the network only accepts WPA3
+        let mut wpa3_only = false;
+        for (key, value, quoted) in fields(payload)? {
+            match key.as_str() {
+                "S" => ssid = Some(unquote(value, quoted)),
+                "T" => auth = value.trim().to_uppercase(),
+                "P" => {
+                    password = Some(unquote(value, quoted)).filter(|password| !password.is_empty())
+                }
+                "H" => hidden = value.trim().eq_ignore_ascii_case("true"),
+                "R" => wpa3_only = u32::from_str_radix(value.trim(), 16).unwrap_or(0) & 1 == 1,
+                // enterprise and Easy Connect fields the profile has no use for
+                _ => {}
+            }
+        }
+        let ssid = match ssid {
+            Some(ssid) => ssid,
+            None => bail!(invalid_uri(String::from("URI has no S: ssid field"))),
+        };
+        validate_ssid(&ssid)?;
+        let security = match auth.as_str() {
+            "" if password.is_none() => SecurityType::Open,
+            "" => SecurityType::WpaPsk,
+            "NOPASS" => SecurityType::Open,
+            "WEP" => SecurityType::Wep,
+            "WPA" | "WPA2" if wpa3_only => SecurityType::Sae,
+            "WPA" | "WPA2" => SecurityType::WpaPsk,
+            "SAE" | "WPA3" => SecurityType::Sae,
+            "WPA2-EAP" => SecurityType::Eap,
+            auth => bail!(invalid_uri(format!("unknown authentication type {}", auth))),
+        };
+        Ok(WifiUri {
+            ssid,
+            security,
+            password: match security {
+                SecurityType::Open => None,
+                _ => password,
+            },
+            hidden,
+        })
+    }
+}
+
+impl fmt::Display for WifiUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let auth = match self.security {
+            SecurityType::Open | SecurityType::Owe => "nopass",
+            SecurityType::Wep => "WEP",
+            SecurityType::WpaPsk | SecurityType::WpaPskSae => "WPA",
+            SecurityType::Sae => "SAE",
+            SecurityType::Eap => "WPA2-EAP",
+        };
+        write!(f, "{}T:{};S:{};", SCHEME, auth, quote(&self.ssid))?;
+        if let Some(password) = &self.password {
+            write!(f, "P:{};", quote(password))?;
+        }
+        if self.hidden {
+            write!(f, "H:true;")?;
+        }
+        write!(f, ";")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wifi_uri() {
+        let uri: WifiUri = r#"WIFI:S:shop\;floor 2;T:WPA;P:pa\:ss\\word;H:true;;"#
+            .parse()
+            .unwrap();
+        assert_eq!(uri.ssid, "shop;floor 2");
+        assert_eq!(uri.security, SecurityType::WpaPsk);
+        assert_eq!(uri.password.as_deref(), Some(r"pa:ss\word"));
+        assert!(uri.hidden && uri.ambiguous_security());
+        assert_eq!(
+            uri.to_string(),
+            r#"WIFI:T:WPA;S:shop\;floor 2;P:pa\:ss\\word;H:true;;"#
+        );
+        assert_eq!(uri.to_string().parse::<WifiUri>().unwrap(), uri);
+        let profile = uri.profile().unwrap();
+        assert!(profile.hidden);
+        assert_eq!(
+            profile.security,
+            Security::WpaPsk {
+                passphrase: String::from(r"pa:ss\word")
+            }
+        );
+
+        let uri: WifiUri = "wifi:T:nopass;S:\"CAFE\";;".parse().unwrap();
+        assert_eq!(uri.ssid, "CAFE");
+        assert_eq!(uri.security, SecurityType::Open);
+        assert_eq!(uri.to_string(), "WIFI:T:nopass;S:\"CAFE\";;");
+        // escaped quotes belong to the ssid
+        let uri: WifiUri = r#"WIFI:T:nopass;S:\"cafe\";;"#.parse().unwrap();
+        assert_eq!(uri.ssid, "\"cafe\"");
+        assert_eq!(uri.to_string(), r#"WIFI:T:nopass;S:\"cafe\";;"#);
+        assert_eq!(uri.to_string().parse::<WifiUri>().unwrap(), uri);
+        let uri: WifiUri = r#"WIFI:T:nopass;S:"\"CAFE\"";;"#.parse().unwrap();
+        assert_eq!(uri.ssid, "\"CAFE\"");
+
+        let passphrase = "0123456789abcdef".repeat(4);
+        let uri: WifiUri = format!("WIFI:T:WPA;S:home;P:\"{}\";;", passphrase)
+            .parse()
+            .unwrap();
+        assert_eq!(uri.password.as_deref(), Some(passphrase.as_str()));
+        assert_eq!(
+            uri.to_string(),
+            format!("WIFI:T:WPA;S:home;P:\"{}\";;", passphrase)
+        );
+        let uri: WifiUri = "WIFI:T:WPA2-EAP;S:corp;P:secret;;".parse().unwrap();
+        assert_eq!(uri.security, SecurityType::Eap);
+        assert_eq!(uri.to_string(), "WIFI:T:WPA2-EAP;S:corp;P:secret;;");
+
+        let uri: WifiUri = "WIFI:T:WPA;R:1;S:home;P:password1;;".parse().unwrap();
+        assert_eq!(uri.security, SecurityType::Sae);
+        let uri: WifiUri = "WIFI:T:WEP;S:old;P:12345;;".parse().unwrap();
+        assert!(uri.profile().is_err());
+
+        assert!("WIFI:T:WPA;P:password1;;".parse::<WifiUri>().is_err());
+        assert!("WIFI:S:home;T:WPA;P:password1".parse::<WifiUri>().is_err());
+        assert!("http://example.com".parse::<WifiUri>().is_err());
+        assert!("WIFI:S:home;T:WPA4;;".parse::<WifiUri>().is_err());
+
+        let hotspot = HotspotConfig {
+            ssid: String::from("mecha-setup"),
+            passphrase: Some(String::from("secret;123")),
+            ..Default::default()
+        };
+        assert_eq!(
+            WifiUri::for_hotspot(&hotspot).to_string(),
+            r"WIFI:T:WPA;S:mecha-setup;P:secret\;123;;"
+        );
+    }
+}
@@ -1,70 +1,142 @@
 use anyhow::{bail, Result};
-use std::process::Command;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
 use tracing::{error as trace_error, info, trace};
-use wifi_ctrl::sta::{self, NetworkResult, ScanResult};
+use wifi_ctrl::sta::{NetworkResult, ScanResult, SelectResult};
 
 use crate::errors::{WirelessNetworkError,WirelessNetworkErrorCodes};
+use crate::hotspot::{
+    self, HotspotClient, HotspotConfig, HotspotFiles, HotspotState, HOTSPOT_RUN_DIR,
+};
+use crate::interfaces::{self, DEFAULT_CONTROL_DIR, DEFAULT_INTERFACE, SYS_CLASS_NET};
+use crate::netlink::{self, InterfaceAddress};
+use crate::profile::{self, NetworkProfile, Security, SecurityType};
+use crate::saved::SavedNetwork;
+use crate::scan::{self, ScannedNetwork};
+use crate::session::{WifiEventReceiver, WifiSession};
+use crate::status::{WifiStatus, WPA_STATE_COMPLETED};
+use crate::wifi_uri::WifiUri;
+use crate::wps::{self, WpsMethod, WpsProgress};
 
-pub struct WirelessNetworkControl;
+#[derive(Clone)]
+pub struct WirelessNetworkControl {
+    interface: String,
+    sys_class_net: PathBuf,
+    run_dir: PathBuf,
+    session: WifiSession,
+}
+
+// how long the supplicant gets to bring an access point up
+const HOTSPOT_START_TIMEOUT: Duration = Duration::from_secs(20);
+const HOTSPOT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+impl Default for WirelessNetworkControl {
+    fn default() -> Self {
+        Self::for_interface(Path::new(DEFAULT_CONTROL_DIR), DEFAULT_INTERFACE)
+    }
+}
 
 impl WirelessNetworkControl {
     pub fn new() -> Self {
         trace!(task = "wireless network instance", "init");
-        Self
+        Self::default()
     }
 
-    pub async fn wireless_network_status() -> bool {
-        trace!(task = "wireless_network_status", "checking wireless network status");
-        let output = Command::new("ifconfig")
-            .output()
-            .expect("Failed to execute ifconfig command");
+    // control of `interface` through the supplicant socket in `control_dir`
+    pub fn for_interface(control_dir: &Path, interface: &str) -> Self {
+        Self::with_session(interface, WifiSession::new(control_dir.join(interface)))
+    }
+
+    // control through an existing supplicant session
+    pub fn with_session(interface: &str, session: WifiSession) -> Self {
+        Self {
+            interface: interface.to_string(),
+            sys_class_net: PathBuf::from(SYS_CLASS_NET),
+            run_dir: PathBuf::from(HOTSPOT_RUN_DIR),
+            session,
+        }
+    }
 
-        info!(task = "wireless_network_status", "stdout: {:?}", output.stdout);
-        let stdout = String::from_utf8(output.stdout).expect("Failed to convert stdout to string");
+    pub(crate) fn with_sys_class_net(mut self, sys_class_net: &Path) -> Self {
+        self.sys_class_net = sys_class_net.to_path_buf();
+        self
+    }
 
-        // Check if the stdout contains "wlan0"
-        stdout.contains("wlan0")
+    pub(crate) fn with_run_dir(mut self, run_dir: &Path) -> Self {
+        self.run_dir = run_dir.to_path_buf();
+        self
     }
 
-    pub async fn scan_wireless_network(&self) -> Result<Vec<ScanResult>> {
-        trace!(task = "scan_wireless_network", "init");
-        let mut setup = match sta::WifiSetup::new() {
-            Ok(setup) => {
-                info!(take = "wirelss_network_setup", "wireless network setup successful");
-                setup
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    pub fn session(&self) -> &WifiSession {
+        &self.session
+    }
+
+    // whether the wireless interface is up
+    pub async fn wireless_network_status(&self) -> bool {
+        trace!(task = "wireless_network_status", "checking wireless network status");
+        let up = interfaces::interface_is_up(&self.sys_class_net, &self.interface);
+        info!(task = "wireless_network_status", "{} up: {}", self.interface, up);
+        up
+    }
+
+    // state of the interface from the supplicant STATUS and SIGNAL_POLL and the kernel
+    pub async fn wifi_status(&self) -> Result<WifiStatus> {
+        trace!(task = "wifi_status", "init");
+        let mut status = WifiStatus {
+            interface: self.interface.clone(),
+            enabled: self.wireless_network_status().await,
+            ..Default::default()
+        };
+
+        //without a control socket there is no supplicant to ask
+        if self.session.socket_path().exists() {
+            match self.session.status().await {
+                Ok(supplicant) => status.set_supplicant_status(&supplicant),
+                Err(e) => {
+                    trace_error!(task = "wifi_status", "unable to get supplicant status: {}", e);
+                    bail!(WirelessNetworkError::new(
+                        WirelessNetworkErrorCodes::UnableToGetWirelessNetworkStatus,
+                        format!("unable to get wireless network status: {}", e),
+                    ))
+                }
             }
+            if status.connected() {
+                //signal is nice to have, the status stands without it
+                match self.session.command("SIGNAL_POLL").await {
+                    Ok(reply) => status.set_signal_poll(&reply),
+                    Err(e) => info!(task = "wifi_status", "unable to poll signal: {}", e),
+                }
+            }
+        }
+
+        //an interface without addresses is not an error
+        if let Ok(addresses) = netlink::interface_addresses(&self.interface) {
+            status.set_addresses(addresses);
+        }
+        // the route is informational, the link status is reported without it
+        status.default_route = match netlink::default_routes() {
+            Ok(routes) => routes.into_iter().next(),
             Err(e) => {
-                trace_error!(
-                    task = "scan_wireless_network",
-                    "unable to get wireless network status: {}",
-                    e
-                );
-                bail!(WirelessNetworkError::new(
-                    WirelessNetworkErrorCodes::UnableToGetWirelessNetworkStatus,
-                    format!("unable to get wireless network status: {}", e),
-                ))
+                trace!(task = "wifi_status", "unable to read the default route: {}", e);
+                None
             }
         };
 
-        let proposed_path = format!("/var/run/wpa_supplicant/wlan0");
-        setup.set_socket_path(proposed_path);
-
-        let broadcast = setup.get_broadcast_receiver();
-        let requester = setup.get_request_client();
-        let runtime = setup.complete();
+        info!(task = "wifi_status", "status: {:?}", status);
+        Ok(status)
+    }
 
-        let (_runtime, wireless_network_list, _broadcast) = tokio::join!(
-            async move {
-                if let Err(e) = runtime.run().await {
-                    trace_error!(task = "scan_wireless_network", "error: {}", e);
-                }
-            },
-            WirelessNetworkControl::wireless_network_list(requester),
-            WirelessNetworkControl::broadcast_listener(broadcast),
-        );
+    pub async fn scan_wireless_network(&self) -> Result<Vec<ScanResult>> {
+        trace!(task = "scan_wireless_network", "init");
 
-        //use wireless_network_list to get the list of all the wireless network networks or else return an error with matching error code
-        let wireless_network_list = match wireless_network_list {
+        //use the scan results to get the list of all the wireless network networks or else return an error with matching error code
+        let wireless_network_list = match self.session.scan().await {
             Ok(wireless_network_list) => {
                 info!(task = "scan_wireless_network", "wireless networks : {:?}", wireless_network_list);
                 wireless_network_list
@@ -85,51 +157,25 @@ impl WirelessNetworkControl {
         Ok(wireless_network_list)
     }
 
-    async fn wireless_network_list(requester: sta::RequestClient) -> Result<Vec<ScanResult>> {
-        trace!(task = "wireless_network_list", "requesting scan");
-        let scan = requester.get_scan().await?;
-        requester.shutdown().await?;
-        Ok(scan.to_vec())
-    }
-
-    pub async fn get_known_wireless_networks() -> Result<Vec<NetworkResult>> {
-        trace!(task = "get_known_wireless_networks", "starting wireless network connection");
-        let mut setup = match sta::WifiSetup::new() {
-            Ok(setup) => {
-                info!(task = "wifi_setup", "wireless network setup successful");
-                setup
-            }
+    // Scan results grouped by ssid with saved and current networks flagged
+    pub async fn scan_networks(&self) -> Result<Vec<ScannedNetwork>> {
+        let results = self.scan_wireless_network().await?;
+        //the scan stands without the saved networks
+        let saved = match self.saved_networks().await {
+            Ok(saved) => saved,
             Err(e) => {
-                trace_error!(
-                    task = "get_known_wireless_networks",
-                    "unable to get wireless network status: {}",
-                    e
-                );
-                bail!(WirelessNetworkError::new(
-                    WirelessNetworkErrorCodes::UnableToGetWirelessNetworkStatus,
-                    format!("unable to get wireless network status: {}", e),
-                ))
+                info!(task = "scan_networks", "unable to list saved networks: {}", e);
+                Vec::new()
             }
         };
-        let proposed_path = format!("/var/run/wpa_supplicant/wlan0");
-        setup.set_socket_path(proposed_path);
-
-        let broadcast = setup.get_broadcast_receiver();
-        let requester = setup.get_request_client();
-        let runtime = setup.complete();
+        Ok(scan::group_scan_results(&results, &saved))
+    }
 
-        let (_runtime, known_wireless_networks, _broadcast) = tokio::join!(
-            async move {
-                if let Err(e) = runtime.run().await {
-                    trace_error!(task = "get_known_wireless_networks", "error: {}", e);
-                }
-            },
-            WirelessNetworkControl::known_wireless_networks(requester),
-            WirelessNetworkControl::broadcast_listener(broadcast),
-        );
+    pub async fn get_known_wireless_networks(&self) -> Result<Vec<NetworkResult>> {
+        trace!(task = "get_known_wireless_networks", "requesting networks");
 
-        //use known_wireless_networks to get the list of all the known wireless network networks or else return an error with matching error code
-        let wireless_network_list = match known_wireless_networks {
+        //use known networks to get the list of all the known wireless network networks or else return an error with matching error code
+        let wireless_network_list = match self.session.networks().await {
             Ok(wireless_network_list) => {
                 info!(task = "get_known_wireless_networks", "wireless networks: {:?}", wireless_network_list);
                 wireless_network_list
@@ -150,20 +196,13 @@ impl WirelessNetworkControl {
         Ok(wireless_network_list)
     }
 
-    async fn known_wireless_networks(requester: sta::RequestClient) -> Result<Vec<NetworkResult>> {
-        trace!(task = "known_wireless_networks", "requesting networks");
-        let scan = requester.get_networks().await?;
-        requester.shutdown().await?;
-        Ok(scan)
-    }
-
-    // we need to write function that return the currnet wireless network name if it is connected to wireless network or else none, how we're going to do that is we use get_known_wireless_networks function to get the list of all the known wireless network networks and from that reult we can filter the list that has  "flags": "[CURRENT]" and return the ssid of that network or else return none
+    // current wireless network is the known network flagged "[CURRENT]", looked up in the scan results
     pub async fn current_wireless_network(&self) -> Result<ScanResult> {
-        let known_wifi_list = WirelessNetworkControl::get_known_wireless_networks().await?;
+        let known_wifi_list = self.get_known_wireless_networks().await?;
         let current_wifi = known_wifi_list.iter().find(|&x| x.flags == "[CURRENT]");
 
         //take ssid for current wireless network and find that in scan_networks list and return that network or else return an error with matching error code
-        let scan_wifi_list = WirelessNetworkControl::scan_wireless_network(&self).await?;
+        let scan_wifi_list = self.scan_wireless_network().await?;
         let current_wifi = current_wifi
             .map(|x| {
                 scan_wifi_list
@@ -185,82 +224,263 @@ impl WirelessNetworkControl {
         }
     }
 
-    pub async fn connect_wireless_network(ssid: &str, psk: &str) -> Result<()> {
+    // Join `ssid`, a known network as saved, a new one with the security it advertises
+    pub async fn connect_wireless_network(&self, ssid: &str, psk: &str) -> Result<()> {
         trace!(
             task = "connect_wireless_network",
             "starting wireless network connection"
         );
 
-        let mut setup = match sta::WifiSetup::new() {
-            Ok(setup) => {
-                info!(task = "wifi_setup", "wireless network setup successful");
-                setup
+        //if ssid is in known networks, use that network id to connect else create new network
+        match self.known_network_id(ssid).await? {
+            Some(network_id) => {
+                info!("network id: {}", network_id);
+                self.select_wireless_network(network_id, ssid).await
             }
-            Err(e) => {
-                trace_error!(
-                    task = "connect_wireless_network",
-                    "unable to get wireless network status: {}",
-                    e
-                );
-                bail!(WirelessNetworkError::new(
-                    WirelessNetworkErrorCodes::UnableToGetWirelessNetworkStatus,
-                    format!("unable to get wireless network status: {}", e),
-                ))
+            None => {
+                let profile = self.resolve_profile(ssid, psk, false).await?;
+                self.connect_profile(&profile).await
             }
+        }
+    }
+
+    // Profile for `ssid` with the security found in the scan results, hidden networks
+    // are not in them and are taken as open without a passphrase and WPA-PSK with one
+    pub async fn resolve_profile(
+        &self,
+        ssid: &str,
+        passphrase: &str,
+        hidden: bool,
+    ) -> Result<NetworkProfile> {
+        trace!(task = "resolve_profile", "init");
+        let mut security_type = None;
+        if !hidden {
+            match self.session.scan().await {
+                Ok(results) => {
+                    security_type = results
+                        .iter()
+                        .find(|result| result.name == ssid)
+                        .map(|result| SecurityType::from_flags(&result.flags));
+                }
+                Err(e) => {
+                    trace_error!(task = "resolve_profile", "unable to scan: {}", e);
+                }
+            }
+        }
+        let security_type = match security_type {
+            Some(security_type) => security_type,
+            None if passphrase.is_empty() => SecurityType::Open,
+            None => SecurityType::WpaPsk,
         };
+        info!(task = "resolve_profile", "{} uses {}", ssid, security_type);
+        let security = Security::for_type(security_type, passphrase)?;
+        Ok(NetworkProfile::new(ssid, security).hidden(hidden))
+    }
 
-        let proposed_path = format!("/var/run/wpa_supplicant/wlan0");
-        setup.set_socket_path(proposed_path);
+    // Save `profile`, replacing the settings of a known network of the same ssid, and
+    // join it
+    pub async fn connect_profile(&self, profile: &NetworkProfile) -> Result<()> {
+        trace!(task = "connect_profile", "init");
+        let network_id = self.store_profile(profile).await?;
+        self.select_wireless_network(network_id, &profile.ssid).await
+    }
 
-        let broadcast = setup.get_broadcast_receiver();
-        let requester = setup.get_request_client();
-        let runtime = setup.complete();
+    // Join the network of a `WIFI:` URI such as a QR code holds, returns the profile
+    // it was joined with
+    pub async fn connect_uri(&self, uri: &str) -> Result<NetworkProfile> {
+        trace!(task = "connect_uri", "init");
+        let uri: WifiUri = uri.parse()?;
+        let profile = if uri.ambiguous_security() && !uri.hidden {
+            let password = uri.password.as_deref().unwrap_or_default();
+            self.resolve_profile(&uri.ssid, password, false).await?
+        } else {
+            uri.profile()?
+        };
+        self.connect_profile(&profile).await?;
+        info!(task = "connect_uri", "joined {} from a WIFI: URI", profile.ssid);
+        Ok(profile)
+    }
 
-        let (_runtime, connect_wireless_network_list, _broadcast) = tokio::join!(
-            async move {
-                if let Err(e) = runtime.run().await {
-                    trace_error!(task = "connect_wireless_network", "error: {}", e);
-                }
-            },
-            WirelessNetworkControl::connect_wireless_network_list(requester, &ssid, &psk),
-            WirelessNetworkControl::broadcast_listener(broadcast),
-        );
+    // Add `profile` to the supplicant, or update the known network of the same ssid
+    async fn store_profile(&self, profile: &NetworkProfile) -> Result<usize> {
+        profile.validate()?;
 
-        let wireless_network_list = match connect_wireless_network_list {
-            Ok(wireless_network_list) => {
-                info!(
-                    task = "connect_wireless_network",
-                    "wireless networks : {:?}", wireless_network_list
-                );
-                wireless_network_list
+        let (network_id, added) = match self.known_network_id(&profile.ssid).await? {
+            Some(network_id) => (network_id, false),
+            None => (self.add_wireless_network().await?, true),
+        };
+
+        let applied = self
+            .apply_settings(network_id, profile.network_settings())
+            .await;
+        if let Err(e) = applied {
+            //do not leave a half configured network behind
+            if added {
+                if let Err(e) = self.session.remove_network(network_id).await {
+                    trace_error!(
+                        task = "store_profile",
+                        "unable to remove network {}: {}",
+                        network_id,
+                        e
+                    );
+                }
             }
-            Err(e) => {
-                trace_error!(
-                    task = "connect_wireless_network",
-                    "unable to get wireless network status: {}",
-                    e
-                );
+            return Err(e);
+        }
+        Ok(network_id)
+    }
+
+    // Save `profile` without joining it, the supplicant joins it by itself when
+    // `auto_connect` is set and it is in range
+    pub async fn save_network(
+        &self,
+        profile: &NetworkProfile,
+        priority: Option<i32>,
+        auto_connect: bool,
+    ) -> Result<usize> {
+        trace!(task = "save_network", "init");
+        let network_id = self.store_profile(profile).await?;
+        if let Some(priority) = priority {
+            self.session
+                .set_network(network_id, "priority", &priority.to_string())
+                .await?;
+        }
+        self.set_network_enabled(network_id, auto_connect).await?;
+        self.save_config().await?;
+        info!(task = "save_network", "saved {} as {}", profile.ssid, network_id);
+        Ok(network_id)
+    }
+
+    // Networks in the supplicant configuration
+    pub async fn saved_networks(&self) -> Result<Vec<SavedNetwork>> {
+        trace!(task = "saved_networks", "init");
+        let networks = self.session.networks().await?;
+        let mut saved = Vec::with_capacity(networks.len());
+        for network in &networks {
+            let priority = self
+                .session
+                .get_network(network.network_id, "priority")
+                .await?
+                .and_then(|priority| priority.parse().ok())
+                .unwrap_or_default();
+            saved.push(SavedNetwork::from_network(network, priority));
+        }
+        Ok(saved)
+    }
+
+    // Id of the saved network `ssid`, an error when there is none
+    async fn saved_network_id(&self, ssid: &str) -> Result<usize> {
+        match self.known_network_id(ssid).await? {
+            Some(network_id) => Ok(network_id),
+            None => {
+                trace_error!(task = "saved_network", "{} is not saved", ssid);
                 bail!(WirelessNetworkError::new(
-                    WirelessNetworkErrorCodes::UnableToConnectToWirelessNetwork,
-                    format!("unable to connect to wireless network {}", e),
+                    WirelessNetworkErrorCodes::NoWirelessNetworkFound,
+                    format!("no saved network {}", ssid),
                 ))
             }
+        }
+    }
+
+    // Replace the passphrase of the saved network `ssid`
+    pub async fn update_network_passphrase(&self, ssid: &str, passphrase: &str) -> Result<()> {
+        trace!(task = "update_network_passphrase", "init");
+        let network_id = self.saved_network_id(ssid).await?;
+        let key_mgmt = self
+            .session
+            .get_network(network_id, "key_mgmt")
+            .await?
+            .unwrap_or_default();
+        //transition mode networks take the WPA2 passphrase for both
+        let (key, value) = if key_mgmt.contains("WPA-PSK") {
+            profile::validate_passphrase(passphrase)?;
+            ("psk", profile::psk_value(passphrase))
+        } else if key_mgmt.contains("SAE") {
+            ("sae_password", profile::quote(passphrase))
+        } else {
+            trace_error!(task = "update_network_passphrase", "{} has no passphrase", ssid);
+            bail!(WirelessNetworkError::new(
+                WirelessNetworkErrorCodes::InvalidNetworkProfile,
+                format!("{} uses {} and has no passphrase", ssid, key_mgmt),
+            ))
         };
+        self.session.set_network(network_id, key, &value).await?;
+        self.save_config().await
+    }
 
-        Ok(wireless_network_list)
+    pub async fn set_network_priority(&self, ssid: &str, priority: i32) -> Result<()> {
+        trace!(task = "set_network_priority", "init");
+        let network_id = self.saved_network_id(ssid).await?;
+        self.session
+            .set_network(network_id, "priority", &priority.to_string())
+            .await?;
+        self.save_config().await
+    }
+
+    // Let the supplicant join `ssid` by itself or only when asked to
+    pub async fn set_auto_connect(&self, ssid: &str, auto_connect: bool) -> Result<()> {
+        trace!(task = "set_auto_connect", "init");
+        let network_id = self.saved_network_id(ssid).await?;
+        self.set_network_enabled(network_id, auto_connect).await?;
+        self.save_config().await
+    }
+
+    // Forget the saved network `ssid`
+    pub async fn remove_saved_network(&self, ssid: &str) -> Result<()> {
+        trace!(task = "remove_saved_network", "init");
+        let network_id = self.saved_network_id(ssid).await?;
+        self.remove_wireless_network(network_id).await?;
+        self.save_config().await
     }
 
-    async fn connect_wireless_network_list(requester: sta::RequestClient, ssid: &str, psk: &str) -> Result<()> {
-        trace!(task = "connect_wireless_network_list", "requesting networks");
+    async fn set_network_enabled(&self, network_id: usize, enabled: bool) -> Result<()> {
+        let command = match enabled {
+            true => format!("ENABLE_NETWORK {}", network_id),
+            false => format!("DISABLE_NETWORK {}", network_id),
+        };
+        self.supplicant_command(
+            "set_network_enabled",
+            &command,
+            WirelessNetworkErrorCodes::UnableToSaveNetwork,
+        )
+        .await
+    }
+
+    // Enable `network_ids` again after SELECT_NETWORK disabled them, failures are
+    // only logged
+    async fn enable_networks(&self, task: &str, network_ids: &[usize]) {
+        for network_id in network_ids {
+            let command = format!("ENABLE_NETWORK {}", network_id);
+            if let Err(e) = self
+                .supplicant_command(task, &command, WirelessNetworkErrorCodes::Unknown)
+                .await
+            {
+                info!(task = task, "unable to enable network {}: {}", network_id, e);
+            }
+        }
+    }
+
+    // Write the networks to the supplicant configuration file
+    pub async fn save_config(&self) -> Result<()> {
+        //the supplicant refuses unless its configuration has update_config=1
+        self.supplicant_command(
+            "save_config",
+            "SAVE_CONFIG",
+            WirelessNetworkErrorCodes::UnableToSaveNetwork,
+        )
+        .await
+    }
+
+    async fn known_network_id(&self, ssid: &str) -> Result<Option<usize>> {
         //handle networks or else return an error with matching error code
-        let networks = match requester.get_networks().await {
+        let networks = match self.session.networks().await {
             Ok(networks) => {
-                info!(task = "connect_wireless_network_list", "networks: {:?}", networks);
+                info!(task = "connect_wireless_network", "networks: {:?}", networks);
                 networks
             }
             Err(e) => {
                 trace_error!(
-                    task = "connect_wireless_network_list",
+                    task = "connect_wireless_network",
                     "unable to get wireless network status: {}",
                     e
                 );
@@ -270,27 +490,53 @@ impl WirelessNetworkControl {
                 ))
             }
         };
+        Ok(networks
+            .iter()
+            .find(|network| network.ssid == ssid)
+            .map(|network| network.network_id))
+    }
 
-        //if ssid is in known networks, use that network id to connect else create new network id
-        for network in networks {
-            if network.ssid == ssid {
-                info!("network id: {}", network.network_id);
-                requester.select_network(network.network_id).await?;
-                requester.shutdown().await?;
-                return Ok(());
-            }
+    async fn apply_settings(
+        &self,
+        network_id: usize,
+        settings: Vec<(&'static str, String)>,
+    ) -> Result<()> {
+        //key_mgmt and eap first, the supplicant checks the later fields against them
+        for (key, value) in settings {
+            self.session.set_network(network_id, key, &value).await?;
         }
+        Ok(())
+    }
 
-        //if ssid is not in known networks, create new network id and connect to it or else return an error with matching error code
-        let network_id = match requester.add_network().await {
-            Ok(network_id) => {
-                info!(task = "connect_wireless_network_list", "network id: {}", network_id);
-                network_id
+    //select the network and report why the connection failed
+    async fn select_wireless_network(&self, network_id: usize, ssid: &str) -> Result<()> {
+        //SELECT_NETWORK disables every other network, the ones set to auto connect are
+        //enabled again afterwards so a later SAVE_CONFIG does not persist them disabled
+        let auto_connect: Vec<usize> = match self.session.networks().await {
+            Ok(networks) => networks
+                .iter()
+                .filter(|network| network.network_id != network_id)
+                .filter(|network| !network.flags.contains("[DISABLED]"))
+                .map(|network| network.network_id)
+                .collect(),
+            Err(e) => {
+                trace_error!(
+                    task = "connect_wireless_network",
+                    "unable to list networks: {}",
+                    e
+                );
+                Vec::new()
             }
+        };
+        let result = self.session.select_network(network_id, ssid).await;
+        self.enable_networks("connect_wireless_network", &auto_connect).await;
+
+        let result = match result {
+            Ok(result) => result,
             Err(e) => {
                 trace_error!(
-                    task = "connect_wireless_network_list",
-                    "unable to get wireless network status: {}",
+                    task = "connect_wireless_network",
+                    "unable to select network: {}",
                     e
                 );
                 bail!(WirelessNetworkError::new(
@@ -300,26 +546,47 @@ impl WirelessNetworkControl {
             }
         };
 
-        //set network ssid
-        requester
-            .set_network_ssid(network_id, ssid.to_string())
-            .await?;
-
-        //set network psk
-        requester
-            .set_network_psk(network_id, psk.to_string())
-            .await?;
+        let (code, message) = match result {
+            SelectResult::Success | SelectResult::AlreadyConnected => {
+                info!(task = "connect_wireless_network", "connected to {}: {}", ssid, result);
+                return Ok(());
+            }
+            SelectResult::WrongPsk => (
+                WirelessNetworkErrorCodes::WrongKey,
+                format!("wrong password for {}", ssid),
+            ),
+            SelectResult::NotFound => (
+                WirelessNetworkErrorCodes::NoWirelessNetworkFound,
+                format!("wireless network {} not found", ssid),
+            ),
+            SelectResult::Timeout => (
+                WirelessNetworkErrorCodes::UnableToConnectToWirelessNetwork,
+                format!("timed out connecting to {}", ssid),
+            ),
+            SelectResult::PendingSelect => (
+                WirelessNetworkErrorCodes::UnableToConnectToWirelessNetwork,
+                String::from("another connection is in progress"),
+            ),
+            SelectResult::InvalidNetworkId => (
+                WirelessNetworkErrorCodes::UnableToConnectToWirelessNetwork,
+                format!("invalid network id {}", network_id),
+            ),
+        };
+        trace_error!(task = "connect_wireless_network", "{}", message);
+        bail!(WirelessNetworkError::new(code, message))
+    }
 
-        //select newly created network id or else return an error with matching error code
-        let _ = match requester.select_network(network_id).await {
-            Ok(_) => {
-                info!(task = "connect_wireless_network_list", "connect to selected network");
-                ()
+    async fn add_wireless_network(&self) -> Result<usize> {
+        //create new network id or else return an error with matching error code
+        match self.session.add_network().await {
+            Ok(network_id) => {
+                info!(task = "add_wireless_network", "network id: {}", network_id);
+                Ok(network_id)
             }
             Err(e) => {
                 trace_error!(
-                    task = "connect_wireless_network_list",
-                    "unable to get wireless network status: {}",
+                    task = "add_wireless_network",
+                    "unable to add wireless network: {}",
                     e
                 );
                 bail!(WirelessNetworkError::new(
@@ -327,87 +594,457 @@ impl WirelessNetworkControl {
                     format!("unable to connect to wireless network {}", e),
                 ))
             }
-        };
-
-        requester.shutdown().await?;
-        Ok(())
+        }
     }
 
     // remove wireless network from known networks using network id
-    pub async fn remove_wireless_network(network_id: usize) -> Result<()> {
+    pub async fn remove_wireless_network(&self, network_id: usize) -> Result<()> {
         trace!(task = "remove_wireless_network", "removing wireless network");
 
-        let mut setup = match sta::WifiSetup::new() {
-            Ok(setup) => {
-                info!(task = "remove_wireless_network", "wireless network setup successful");
-                setup
+        //remove the wireless network or else return an error with matching error code
+        match self.session.remove_network(network_id).await {
+            Ok(()) => {
+                info!(task = "remove_wireless_network", "removed wireless network {}", network_id);
+                Ok(())
             }
             Err(e) => {
                 trace_error!(
                     task = "remove_wireless_network",
-                    "unable to get wireless network status: {}",
+                    "unable to remove wireless network: {}",
                     e
                 );
                 bail!(WirelessNetworkError::new(
-                    WirelessNetworkErrorCodes::UnableToGetWirelessNetworkStatus,
-                    format!("unable to get wireless network status: {}", e),
+                    WirelessNetworkErrorCodes::UnableToRemoveWirelessNetwork,
+                    format!("unable to remove wireless network {}", e),
                 ))
             }
-        };
+        }
+    }
 
-        let proposed_path = format!("/var/run/wpa_supplicant/wlan0");
-        setup.set_socket_path(proposed_path);
+    // Supplicant command that replies OK
+    async fn supplicant_command(
+        &self,
+        task: &str,
+        command: &str,
+        code: WirelessNetworkErrorCodes,
+    ) -> Result<()> {
+        let reply = self.session.command(command).await?;
+        if reply.trim() != "OK" {
+            trace_error!(task = task, "{} failed: {}", command, reply.trim());
+            bail!(WirelessNetworkError::new(
+                code,
+                format!("{} failed: {}", command, reply.trim()),
+            ))
+        }
+        Ok(())
+    }
 
-        let broadcast = setup.get_broadcast_receiver();
-        let requester = setup.get_request_client();
-        let runtime = setup.complete();
+    // Drop the current connection, the network stays saved and is not joined again
+    // until `reconnect_wireless_network`
+    pub async fn disconnect_wireless_network(&self) -> Result<()> {
+        trace!(task = "disconnect_wireless_network", "init");
+        self.supplicant_command(
+            "disconnect_wireless_network",
+            "DISCONNECT",
+            WirelessNetworkErrorCodes::UnableToDisconnectWirelessNetwork,
+        )
+        .await?;
+        info!(task = "disconnect_wireless_network", "{} disconnected", self.interface);
+        Ok(())
+    }
 
-        let (_runtime, remove_network, _broadcast) = tokio::join!(
-            async move {
-                if let Err(e) = runtime.run().await {
-                    trace_error!(task = "remove_wireless_network", "error: {}", e);
-                }
-            },
-            WirelessNetworkControl::remove_network(requester, network_id),
-            WirelessNetworkControl::broadcast_listener(broadcast),
-        );
+    // Join the saved networks again after a disconnect
+    pub async fn reconnect_wireless_network(&self) -> Result<()> {
+        trace!(task = "reconnect_wireless_network", "init");
+        self.supplicant_command(
+            "reconnect_wireless_network",
+            "RECONNECT",
+            WirelessNetworkErrorCodes::UnableToConnectToWirelessNetwork,
+        )
+        .await?;
+        info!(task = "reconnect_wireless_network", "{} reconnecting", self.interface);
+        Ok(())
+    }
 
-        //use remove_network to remove the wireless network or else return an error with matching error code
-        let wireless_network_list = match remove_network {
-            Ok(wireless_network_list) => {
-                info!(
-                    task = "remove_wireless_network",
-                    "wireless network list: {:?}", wireless_network_list
-                );
-                wireless_network_list
-            }
+    fn hotspot_files(&self) -> HotspotFiles {
+        HotspotFiles::new(&self.run_dir, &self.interface)
+    }
+
+    pub fn hotspot_active(&self) -> bool {
+        self.hotspot_files().state.exists()
+    }
+
+    // Turn the interface into an access point, saved networks stay as they are and
+    // are joined again once the hotspot stops
+    pub async fn start_hotspot(&self, config: &HotspotConfig) -> Result<InterfaceAddress> {
+        trace!(task = "start_hotspot", "init");
+        config.validate()?;
+        let settings = config.network_settings()?;
+        let files = self.hotspot_files();
+
+        //a running hotspot is replaced
+        if self.hotspot_active() {
+            self.stop_hotspot().await?;
+        }
+
+        let enabled_networks = match self.session.networks().await {
+            Ok(networks) => networks
+                .iter()
+                .filter(|network| !network.flags.contains("[DISABLED]"))
+                .map(|network| network.network_id)
+                .collect(),
             Err(e) => {
-                trace_error!(
-                    task = "remove_wireless_network",
-                    "unable to get wireless network status: {}",
-                    e
-                );
+                trace_error!(task = "start_hotspot", "unable to list networks: {}", e);
                 bail!(WirelessNetworkError::new(
-                    WirelessNetworkErrorCodes::UnableToRemoveWirelessNetwork,
-                    format!("unable to remove wireless network {}", e),
+                    WirelessNetworkErrorCodes::UnableToStartHotspot,
+                    format!("unable to start hotspot: {}", e),
                 ))
             }
         };
-        Ok(wireless_network_list)
+
+        let network_id = self.add_wireless_network().await?;
+        let state = HotspotState {
+            network_id,
+            address: config.interface_address(),
+            enabled_networks,
+        };
+        //saved before anything changes so stop_hotspot can undo a half started hotspot
+        let saved = match self.apply_settings(network_id, settings).await {
+            Ok(()) => state.save(&files.state),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = saved {
+            if let Err(e) = self.session.remove_network(network_id).await {
+                trace_error!(task = "start_hotspot", "unable to remove network: {}", e);
+            }
+            return Err(e);
+        }
+
+        if let Err(e) = self.enable_hotspot(config, &state, &files).await {
+            if let Err(e) = self.stop_hotspot().await {
+                trace_error!(task = "start_hotspot", "unable to stop hotspot: {}", e);
+            }
+            return Err(e);
+        }
+
+        info!(
+            task = "start_hotspot",
+            "hotspot {} up on {} at {}",
+            config.ssid,
+            self.interface,
+            state.address
+        );
+        Ok(state.address)
     }
 
-    async fn remove_network(requester: sta::RequestClient, network_id: usize) -> Result<()> {
-        trace!(task = "remove_network", "removing wireless network");
-        requester.remove_network(network_id).await?;
-        requester.shutdown().await?;
+    async fn enable_hotspot(
+        &self,
+        config: &HotspotConfig,
+        state: &HotspotState,
+        files: &HotspotFiles,
+    ) -> Result<()> {
+        self.supplicant_command(
+            "start_hotspot",
+            &format!("SELECT_NETWORK {}", state.network_id),
+            WirelessNetworkErrorCodes::UnableToStartHotspot,
+        )
+        .await?;
+
+        //the supplicant reports the access point as a completed connection
+        let deadline = tokio::time::Instant::now() + HOTSPOT_START_TIMEOUT;
+        loop {
+            let status = self.session.status().await?;
+            let mode = status.get("mode").map(String::as_str);
+            let wpa_state = status.get("wpa_state").map(String::as_str);
+            if mode == Some("AP") && wpa_state == Some(WPA_STATE_COMPLETED) {
+                break;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                trace_error!(task = "start_hotspot", "access point did not come up");
+                bail!(WirelessNetworkError::new(
+                    WirelessNetworkErrorCodes::UnableToStartHotspot,
+                    format!("access point did not come up on {}", self.interface),
+                ))
+            }
+            tokio::time::sleep(HOTSPOT_POLL_INTERVAL).await;
+        }
+
+        netlink::add_address(&self.interface, &state.address)?;
+
+        if let Some(dnsmasq) = &config.dnsmasq {
+            //leases of an earlier hotspot are stale
+            let _ = fs::remove_file(&files.leases);
+            hotspot::start_dnsmasq(dnsmasq, &config.dnsmasq_args(&self.interface, files))?;
+        }
         Ok(())
     }
 
-    async fn broadcast_listener(mut broadcast_receiver: sta::BroadcastReceiver) -> Result<()> {
-        trace!(task = "broadcast_listener", "listening for broadcasts");
-        while let Ok(broadcast) = broadcast_receiver.recv().await {
-            info!("Broadcast: {:?}", broadcast);
+    // Switch back to station mode and rejoin the saved networks
+    pub async fn stop_hotspot(&self) -> Result<()> {
+        trace!(task = "stop_hotspot", "init");
+        let files = self.hotspot_files();
+        let state = match HotspotState::load(&files.state) {
+            Some(state) => state,
+            None => {
+                trace_error!(task = "stop_hotspot", "no hotspot on {}", self.interface);
+                bail!(WirelessNetworkError::new(
+                    WirelessNetworkErrorCodes::HotspotNotRunning,
+                    format!("no hotspot on {}", self.interface),
+                ))
+            }
+        };
+
+        hotspot::stop_dnsmasq(&files.pid);
+        //the hotspot may have failed before the address was added
+        if let Err(e) = netlink::remove_address(&self.interface, &state.address) {
+            info!(task = "stop_hotspot", "unable to remove {}: {}", state.address, e);
+        }
+        self.remove_wireless_network(state.network_id).await?;
+
+        self.enable_networks("stop_hotspot", &state.enabled_networks).await;
+        if let Err(e) = self.session.command("REASSOCIATE").await {
+            info!(task = "stop_hotspot", "unable to reassociate: {}", e);
+        }
+
+        if let Err(e) = fs::remove_file(&files.state) {
+            trace_error!(task = "stop_hotspot", "unable to remove hotspot state: {}", e);
         }
+        info!(task = "stop_hotspot", "hotspot on {} stopped", self.interface);
         Ok(())
     }
+
+    // Stations associated with the hotspot, with the addresses dnsmasq leased them
+    pub async fn hotspot_clients(&self) -> Result<Vec<HotspotClient>> {
+        trace!(task = "hotspot_clients", "init");
+        let files = self.hotspot_files();
+        if !self.hotspot_active() {
+            bail!(WirelessNetworkError::new(
+                WirelessNetworkErrorCodes::HotspotNotRunning,
+                format!("no hotspot on {}", self.interface),
+            ))
+        }
+        let leases = fs::read_to_string(&files.leases)
+            .map(|leases| hotspot::parse_leases(&leases))
+            .unwrap_or_default();
+
+        let mut clients = Vec::new();
+        let mut reply = self.session.command("STA-FIRST").await?;
+        while let Some(mut client) = hotspot::parse_station(&reply) {
+            if let Some((ip, hostname)) = leases.get(&client.mac) {
+                client.ip = Some(*ip);
+                client.hostname = hostname.clone();
+            }
+            reply = self
+                .session
+                .command(&format!("STA-NEXT {}", client.mac))
+                .await?;
+            clients.push(client);
+        }
+        info!(task = "hotspot_clients", "clients: {:?}", clients);
+        Ok(clients)
+    }
+
+    // Join a network with WPS push-button or PIN, steps are reported on the returned
+    // receiver until the interface connected or WPS failed, WPS is cancelled if no
+    // access point answers within `timeout`
+    pub async fn connect_wps(
+        &self,
+        method: &WpsMethod,
+        bssid: Option<&str>,
+        timeout: Duration,
+    ) -> Result<mpsc::Receiver<WpsProgress>> {
+        trace!(task = "connect_wps", "init");
+        if let WpsMethod::Pin(Some(pin)) = method {
+            wps::validate_pin(pin)?;
+        }
+        //subscribed before starting so no event is missed
+        let mut events = self.session.subscribe().await?;
+        let command = method.command(bssid);
+        let reply = self.session.command(&command).await?;
+        let reply = reply.trim().to_string();
+        if reply.is_empty() || reply.starts_with("FAIL") {
+            trace_error!(task = "connect_wps", "{} failed: {}", command, reply);
+            bail!(WirelessNetworkError::new(
+                WirelessNetworkErrorCodes::UnableToConnectWps,
+                format!("{} failed: {}", command, reply),
+            ))
+        }
+        //WPS_PIN replies with the PIN in use, WPS_PBC with OK
+        let pin = match method {
+            WpsMethod::PushButton => None,
+            WpsMethod::Pin(_) => Some(reply),
+        };
+        info!(task = "connect_wps", "WPS started on {}", self.interface);
+
+        let (sender, receiver) = mpsc::channel(16);
+        let _ = sender.send(WpsProgress::Started { pin }).await;
+        let session = self.session.clone();
+        tokio::spawn(async move {
+            let last = wps::wait_for_connection(&mut events, &sender, timeout).await;
+            match &last {
+                WpsProgress::Failed { reason } => {
+                    trace_error!(task = "connect_wps", "WPS failed: {}", reason);
+                    if let Err(e) = session.command("WPS_CANCEL").await {
+                        info!(task = "connect_wps", "unable to cancel WPS: {}", e);
+                    }
+                }
+                _ => info!(task = "connect_wps", "{}", last),
+            }
+            let _ = sender.send(last).await;
+        });
+        Ok(receiver)
+    }
+
+    // events of the supplicant from now on, connects, disconnects, scans and failures
+    pub async fn watch_events(&self) -> Result<WifiEventReceiver> {
+        trace!(task = "watch_events", "init");
+        self.session.subscribe().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::WifiEvent;
+    use crate::test_support::{FakeSupplicant, SelectOutcome};
+    use tempdir::TempDir;
+
+    fn fake_control(dir: &TempDir) -> (FakeSupplicant, WirelessNetworkControl) {
+        let supplicant = FakeSupplicant::start(dir.path(), "wlan0").unwrap();
+        supplicant.add_scan_result(
+            "aa:00:00:00:00:01",
+            2437,
+            -60,
+            "[WPA2-PSK-CCMP][ESS]",
+            "shop",
+        );
+        supplicant.add_scan_result(
+            "aa:00:00:00:00:02",
+            5180,
+            -50,
+            "[WPA2-PSK-CCMP][ESS]",
+            "shop",
+        );
+        supplicant.add_scan_result("bb:00:00:00:00:01", 2412, -70, "[ESS]", "guest");
+        let control = WirelessNetworkControl::for_interface(dir.path(), "wlan0");
+        (supplicant, control)
+    }
+
+    #[tokio::test]
+    async fn test_connect_profile() {
+        let dir = TempDir::new("supplicant").unwrap();
+        let (supplicant, wifi) = fake_control(&dir);
+
+        let networks = wifi.scan_networks().await.unwrap();
+        let ssids: Vec<&str> = networks
+            .iter()
+            .map(|network| network.ssid.as_str())
+            .collect();
+        assert_eq!(ssids, vec!["shop", "guest"]);
+        assert_eq!(networks[0].bss.len(), 2);
+
+        let profile = wifi
+            .resolve_profile("shop", "password1", false)
+            .await
+            .unwrap();
+        assert_eq!(
+            profile.security,
+            Security::WpaPsk {
+                passphrase: String::from("password1")
+            }
+        );
+        wifi.connect_profile(&profile).await.unwrap();
+        let network = supplicant.network(0).unwrap();
+        assert_eq!(network.ssid, "shop");
+        assert_eq!(
+            network.settings.get("key_mgmt").map(String::as_str),
+            Some("WPA-PSK")
+        );
+        assert_eq!(
+            network.settings.get("psk").map(String::as_str),
+            Some("\"password1\"")
+        );
+        assert_eq!(supplicant.current_network(), Some(0));
+
+        //joining another network keeps the saved ones enabled
+        let profile = wifi.resolve_profile("guest", "", false).await.unwrap();
+        wifi.connect_profile(&profile).await.unwrap();
+        wifi.save_config().await.unwrap();
+        assert_eq!(supplicant.current_network(), Some(1));
+        assert!(!supplicant.network(0).unwrap().disabled);
+        let saved = wifi.saved_networks().await.unwrap();
+        assert_eq!((saved[1].ssid.as_str(), saved[1].current), ("guest", true));
+        assert!(saved[0].auto_connect);
+
+        supplicant.set_select_outcome(SelectOutcome::WrongKey);
+        let profile = NetworkProfile::new(
+            "office",
+            Security::WpaPsk {
+                passphrase: String::from("password2"),
+            },
+        );
+        let err = wifi.connect_profile(&profile).await.unwrap_err();
+        let err = err.downcast_ref::<WirelessNetworkError>().unwrap();
+        assert!(matches!(err.code, WirelessNetworkErrorCodes::WrongKey));
+
+        let saved = wifi.saved_networks().await.unwrap();
+        assert_eq!(saved.len(), 3);
+        assert_eq!((saved[1].ssid.as_str(), saved[1].current), ("guest", true));
+        assert!(saved.iter().all(|network| network.auto_connect));
+
+        wifi.remove_saved_network("office").await.unwrap();
+        assert_eq!(supplicant.networks().len(), 2);
+        assert!(supplicant.networks().values().all(|network| !network.disabled));
+        assert!(supplicant.commands().contains(&String::from("SAVE_CONFIG")));
+    }
+
+    #[tokio::test]
+    async fn test_connect_wps() {
+        let dir = TempDir::new("supplicant").unwrap();
+        let (supplicant, wifi) = fake_control(&dir);
+
+        let mut events = wifi.watch_events().await.unwrap();
+        let mut progress = wifi
+            .connect_wps(&WpsMethod::Pin(None), None, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(
+            progress.recv().await,
+            Some(WpsProgress::Started {
+                pin: Some(String::from("12345670"))
+            })
+        );
+        assert!(supplicant.wait_attached().await);
+
+        supplicant.emit("WPS-CRED-RECEIVED").await;
+        supplicant.emit("WPS-SUCCESS").await;
+        //the supplicant adds the network WPS handed out and joins it
+        let network_id = supplicant.add_network("shop");
+        supplicant.connect(network_id).await;
+
+        let mut steps = Vec::new();
+        while let Some(step) = progress.recv().await {
+            steps.push(step);
+        }
+        assert_eq!(
+            steps,
+            vec![
+                WpsProgress::CredentialsReceived,
+                WpsProgress::Succeeded,
+                WpsProgress::Connected {
+                    ssid: Some(String::from("shop"))
+                },
+            ]
+        );
+        assert!(matches!(events.recv().await, Ok(WifiEvent::Wps(_))));
+
+        assert!(wifi
+            .connect_wps(
+                &WpsMethod::Pin(Some(String::from("12345678"))),
+                None,
+                Duration::from_secs(5)
+            )
+            .await
+            .is_err());
+    }
 }
@@ -0,0 +1,348 @@
+use anyhow::{bail, Result};
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::Instant;
+
+use crate::errors::{WirelessNetworkError, WirelessNetworkErrorCodes};
+use crate::session::{field, WifiEvent, WifiEventReceiver};
+
+// the supplicant gives up on WPS after the two minute walk time
+pub const DEFAULT_WPS_TIMEOUT: Duration = Duration::from_secs(120);
+// how long to wait for the association once the credentials arrived
+pub const WPS_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How the access point is told to hand out its credentials
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WpsMethod {
+    // the button on the router is pressed
+    PushButton,
+    // `pin` entered in the router's web interface, None lets the supplicant
+    // generate one
+    Pin(Option<String>),
+}
+
+impl WpsMethod {
+    // Control interface command starting WPS, with any access point when
+    // `bssid` is None
+    pub fn command(&self, bssid: Option<&str>) -> String {
+        match self {
+            WpsMethod::PushButton => match bssid {
+                Some(bssid) => format!("WPS_PBC {}", bssid),
+                None => String::from("WPS_PBC"),
+            },
+            WpsMethod::Pin(pin) => {
+                let command = format!("WPS_PIN {}", bssid.unwrap_or("any"));
+                match pin {
+                    Some(pin) => format!("{} {}", command, pin),
+                    None => command,
+                }
+            }
+        }
+    }
+}
+
+// Checksum digit of the first seven digits of an eight digit PIN
+fn pin_checksum(pin: u32) -> u32 {
+    let mut pin = pin;
+    let mut accum = 0;
+    while pin > 0 {
+        accum += 3 * (pin % 10);
+        pin /= 10;
+        accum += pin % 10;
+        pin /= 10;
+    }
+    (10 - accum % 10) % 10
+}
+
+// Eight digits with a valid checksum, or four digits which carry none
+pub fn validate_pin(pin: &str) -> Result<()> {
+    let digits = !pin.is_empty() && pin.chars().all(|c| c.is_ascii_digit());
+    let valid = match (digits, pin.len()) {
+        (true, 4) => true,
+        (true, 8) => {
+            let value: u32 = pin.parse().unwrap_or_default();
+            pin_checksum(value / 10) == value % 10
+        }
+        _ => false,
+    };
+    if !valid {
+        bail!(WirelessNetworkError::new(
+            WirelessNetworkErrorCodes::InvalidWpsPin,
+            format!("{} is not a valid WPS PIN", pin),
+        ))
+    }
+    Ok(())
+}
+
+// WPS progress reported by wpa_supplicant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WpsEvent {
+    // the supplicant is looking for an access point in WPS mode
+    Active,
+    CredentialsReceived,
+    Success,
+    // `reason` describes the WPS configuration error
+    Failure { reason: String },
+    // no access point answered within the walk time
+    Timeout,
+    // several access points are in push-button mode at once
+    Overlap,
+}
+
+impl WpsEvent {
+    pub fn from_message(message: &str) -> Option<WpsEvent> {
+        if message.contains("WPS-PBC-ACTIVE") || message.contains("WPS-PIN-ACTIVE") {
+            return Some(WpsEvent::Active);
+        }
+        if message.contains("WPS-CRED-RECEIVED") {
+            return Some(WpsEvent::CredentialsReceived);
+        }
+        if message.contains("WPS-SUCCESS") {
+            return Some(WpsEvent::Success);
+        }
+        if message.contains("WPS-FAIL") {
+            let config_error = field(message, "config_error").and_then(|e| e.parse().ok());
+            return Some(WpsEvent::Failure {
+                reason: config_error_reason(config_error.unwrap_or_default()),
+            });
+        }
+        if message.contains("WPS-TIMEOUT") {
+            return Some(WpsEvent::Timeout);
+        }
+        if message.contains("WPS-OVERLAP-DETECTED") {
+            return Some(WpsEvent::Overlap);
+        }
+        None
+    }
+}
+
+// Configuration error of a WPS-FAIL event as the WPS specification names it
+fn config_error_reason(config_error: u32) -> String {
+    match config_error {
+        5 => String::from("signal too weak"),
+        6 => String::from("network authentication failed"),
+        7 => String::from("network association failed"),
+        12 => String::from("multiple push-button sessions detected"),
+        13 => String::from("rogue activity suspected"),
+        14 => String::from("access point busy"),
+        15 => String::from("WPS setup locked on the access point"),
+        16 | 17 => String::from("access point did not answer in time"),
+        18 => String::from("wrong PIN"),
+        0 => String::from("WPS failed"),
+        error => format!("WPS failed with config_error={}", error),
+    }
+}
+
+// Step of a WPS connection reported to the caller, the last one is either
+// Connected or Failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WpsProgress {
+    // WPS started, `pin` is the one to enter on the router for the PIN method
+    Started { pin: Option<String> },
+    Active,
+    CredentialsReceived,
+    // WPS finished, the supplicant connects with the new credentials
+    Succeeded,
+    Connected { ssid: Option<String> },
+    Failed { reason: String },
+}
+
+impl WpsProgress {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            WpsProgress::Connected { .. } | WpsProgress::Failed { .. }
+        )
+    }
+}
+
+impl fmt::Display for WpsProgress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WpsProgress::Started { pin: Some(pin) } => {
+                write!(f, "WPS started, enter PIN {} on the access point", pin)
+            }
+            WpsProgress::Started { pin: None } => write!(f, "WPS started"),
+            WpsProgress::Active => write!(f, "waiting for the access point"),
+            WpsProgress::CredentialsReceived => write!(f, "credentials received"),
+            WpsProgress::Succeeded => write!(f, "WPS succeeded, connecting"),
+            WpsProgress::Connected { ssid: Some(ssid) } => write!(f, "connected to {}", ssid),
+            WpsProgress::Connected { ssid: None } => write!(f, "connected"),
+            WpsProgress::Failed { reason } => write!(f, "WPS failed: {}", reason),
+        }
+    }
+}
+
+impl From<WpsEvent> for WpsProgress {
+    fn from(event: WpsEvent) -> Self {
+        match event {
+            WpsEvent::Active => WpsProgress::Active,
+            WpsEvent::CredentialsReceived => WpsProgress::CredentialsReceived,
+            WpsEvent::Success => WpsProgress::Succeeded,
+            WpsEvent::Failure { reason } => WpsProgress::Failed { reason },
+            WpsEvent::Timeout => WpsProgress::Failed {
+                reason: String::from("no access point in WPS mode found"),
+            },
+            WpsEvent::Overlap => WpsProgress::Failed {
+                reason: String::from(
+                    "several access points are in push-button mode, try again or use a PIN",
+                ),
+            },
+        }
+    }
+}
+
+// Report WPS events on `progress` until the interface connected with the
+// credentials or WPS failed, the final step is returned rather than sent
+pub async fn wait_for_connection(
+    events: &mut WifiEventReceiver,
+    progress: &mpsc::Sender<WpsProgress>,
+    timeout: Duration,
+) -> WpsProgress {
+    let mut deadline = Instant::now() + timeout;
+    let mut provisioned = false;
+    loop {
+        let event = match tokio::time::timeout_at(deadline, events.recv()).await {
+            Ok(Ok(event)) => event,
+            Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+            Ok(Err(broadcast::error::RecvError::Closed)) => {
+                return WpsProgress::Failed {
+                    reason: String::from("supplicant events closed"),
+                }
+            }
+            Err(_) if provisioned => {
+                return WpsProgress::Failed {
+                    reason: String::from("unable to connect with the received credentials"),
+                }
+            }
+            Err(_) => {
+                return WpsProgress::Failed {
+                    reason: format!("no answer within {} seconds", timeout.as_secs()),
+                }
+            }
+        };
+        match event {
+            WifiEvent::Wps(event) => {
+                let step = WpsProgress::from(event);
+                if step.is_finished() {
+                    return step;
+                }
+                if matches!(
+                    step,
+                    WpsProgress::CredentialsReceived | WpsProgress::Succeeded
+                ) && !provisioned
+                {
+                    provisioned = true;
+                    deadline = Instant::now() + WPS_CONNECT_TIMEOUT;
+                }
+                // the receiver going away does not stop WPS
+                let _ = progress.send(step).await;
+            }
+            // the association WPS runs over is not reported as connected
+            WifiEvent::Connected { ssid, .. } if provisioned => {
+                return WpsProgress::Connected { ssid }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wps() {
+        assert!(validate_pin("12345670").is_ok());
+        assert!(validate_pin("12345678").is_err());
+        assert!(validate_pin("1234").is_ok());
+        assert!(validate_pin("1234567a").is_err());
+        assert!(validate_pin("").is_err());
+
+        assert_eq!(WpsMethod::PushButton.command(None), "WPS_PBC");
+        assert_eq!(
+            WpsMethod::PushButton.command(Some("aa:bb:cc:dd:ee:ff")),
+            "WPS_PBC aa:bb:cc:dd:ee:ff"
+        );
+        assert_eq!(WpsMethod::Pin(None).command(None), "WPS_PIN any");
+        assert_eq!(
+            WpsMethod::Pin(Some(String::from("12345670"))).command(Some("aa:bb:cc:dd:ee:ff")),
+            "WPS_PIN aa:bb:cc:dd:ee:ff 12345670"
+        );
+
+        assert_eq!(
+            WpsEvent::from_message("<3>WPS-PBC-ACTIVE"),
+            Some(WpsEvent::Active)
+        );
+        assert_eq!(
+            WpsEvent::from_message("<3>WPS-CRED-RECEIVED"),
+            Some(WpsEvent::CredentialsReceived)
+        );
+        assert_eq!(
+            WpsEvent::from_message("<3>WPS-FAIL msg=8 config_error=18 reason=0"),
+            Some(WpsEvent::Failure {
+                reason: String::from("wrong PIN")
+            })
+        );
+        assert_eq!(
+            WpsEvent::from_message("<3>WPS-OVERLAP-DETECTED"),
+            Some(WpsEvent::Overlap)
+        );
+        assert_eq!(WpsEvent::from_message("<3>WPS-AP-AVAILABLE-PBC"), None);
+        assert!(WpsProgress::from(WpsEvent::Timeout).is_finished());
+        assert!(!WpsProgress::from(WpsEvent::Success).is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_connection() {
+        let (events, mut receiver) = broadcast::channel(8);
+        let (sender, mut progress) = mpsc::channel(8);
+        events.send(WifiEvent::Wps(WpsEvent::Active)).unwrap();
+        // connections before the credentials arrived are not WPS's
+        events
+            .send(WifiEvent::Connected {
+                ssid: Some(String::from("other")),
+                bssid: None,
+            })
+            .unwrap();
+        events
+            .send(WifiEvent::Wps(WpsEvent::CredentialsReceived))
+            .unwrap();
+        events.send(WifiEvent::Wps(WpsEvent::Success)).unwrap();
+        events
+            .send(WifiEvent::Connected {
+                ssid: Some(String::from("shop")),
+                bssid: None,
+            })
+            .unwrap();
+        let last = wait_for_connection(&mut receiver, &sender, DEFAULT_WPS_TIMEOUT).await;
+        assert_eq!(
+            last,
+            WpsProgress::Connected {
+                ssid: Some(String::from("shop"))
+            }
+        );
+        drop(sender);
+        let mut steps = Vec::new();
+        while let Some(step) = progress.recv().await {
+            steps.push(step);
+        }
+        assert_eq!(
+            steps,
+            vec![
+                WpsProgress::Active,
+                WpsProgress::CredentialsReceived,
+                WpsProgress::Succeeded
+            ]
+        );
+
+        events.send(WifiEvent::Wps(WpsEvent::Overlap)).unwrap();
+        let (sender, _progress) = mpsc::channel(8);
+        let last = wait_for_connection(&mut receiver, &sender, DEFAULT_WPS_TIMEOUT).await;
+        assert!(matches!(last, WpsProgress::Failed { .. }));
+
+        let last = wait_for_connection(&mut receiver, &sender, Duration::from_millis(10)).await;
+        assert!(matches!(last, WpsProgress::Failed { reason } if reason.contains("no answer")));
+    }
+}
@@ -9,6 +9,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let trustzone_ctrl = "./proto/trustzone_ctrl.proto";
     let battery_ctrl = "./proto/battery_ctrl.proto";
     let bluetooth_manager = "./proto/bluetooth_manager.proto";
+    let adc_manager = "./proto/adc_manager.proto";
 
     tonic_build::configure().build_server(true).compile(
         &[
@@ -22,6 +23,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             trustzone_ctrl,
             battery_ctrl,
             bluetooth_manager,
+            adc_manager,
         ],
         &[
             "./proto/network_manager",
@@ -34,6 +36,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "./proto/trustzone_ctrl",
             "./proto/battery_ctrl",
             "./proto/bluetooth_manager",
+            "./proto/adc_manager",
         ],
     )?;
     Ok(())
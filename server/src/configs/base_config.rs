@@ -1,4 +1,6 @@
+use mecha_network_ctl::connectivity::{ConnectivityConfig, DEFAULT_CHECK_INTERVAL};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BaseConfig {
@@ -17,6 +19,10 @@ pub struct Interfaces {
     pub motion_sensor: Gyroscope,
     pub led: Led,
     pub battery: Battery,
+    #[serde(default)]
+    pub adc: Adc,
+    #[serde(default)]
+    pub wifi: Wifi,
 }
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct Display {
@@ -28,6 +34,40 @@ pub struct Gyroscope {
     pub x_axis: String,
     pub y_axis: String,
     pub z_axis: String,
+    // IIO trigger used for buffered sampling, e.g. `iio-trig-hrtimer0`
+    #[serde(default)]
+    pub trigger: Option<String>,
+    #[serde(default)]
+    pub detection: MotionDetection,
+    // directory of the per-device calibration files
+    #[serde(default)]
+    pub calibration_dir: Option<String>,
+    // applied at startup, the driver's defaults are kept when unset
+    #[serde(default)]
+    pub sampling_frequency: Option<f64>,
+    #[serde(default)]
+    pub oversampling_ratio: Option<u32>,
+}
+
+// Defaults for WatchMotion, threshold is in the unit of the axes (rad/s for a gyroscope)
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MotionDetection {
+    pub threshold: f64,
+    pub high_pass_cutoff_hz: f64,
+    pub debounce_ms: u64,
+    pub quiet_period_ms: u64,
+}
+
+impl Default for MotionDetection {
+    fn default() -> Self {
+        MotionDetection {
+            threshold: 0.35,
+            high_pass_cutoff_hz: 0.5,
+            debounce_ms: 50,
+            quiet_period_ms: 2000,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
@@ -35,6 +75,44 @@ pub struct Led {
     pub red_led: String,
     pub green_led: String,
     pub blue_led: String,
+    // brightness path of a multicolor led, used instead of the three leds above
+    #[serde(default)]
+    pub multicolor_led: Option<String>,
+    // system states shown on the leds, highest priority active state wins
+    #[serde(default)]
+    pub indicators: Vec<LedIndicator>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LedIndicator {
+    pub state: String,
+    pub priority: u32,
+    pub color: String,
+    #[serde(default)]
+    pub pattern: LedIndicatorPattern,
+    #[serde(default = "default_blink_ms")]
+    pub on_ms: u64,
+    #[serde(default = "default_blink_ms")]
+    pub off_ms: u64,
+    #[serde(default = "default_breathe_period_ms")]
+    pub period_ms: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum LedIndicatorPattern {
+    #[default]
+    Solid,
+    Blink,
+    Breathe,
+}
+
+fn default_blink_ms() -> u64 {
+    500
+}
+
+fn default_breathe_period_ms() -> u64 {
+    2000
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
@@ -42,3 +120,83 @@ pub struct Battery {
     pub device: String,
     pub current: String,
 }
+
+// IIO voltage inputs the carrier board routes analog sensors to
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct Adc {
+    #[serde(default)]
+    pub channel_1: Option<String>,
+    #[serde(default)]
+    pub channel_2: Option<String>,
+    // `sampling_frequency` attribute of the adc
+    #[serde(default)]
+    pub sampling_frequency: Option<String>,
+}
+
+impl Adc {
+    pub fn channels(&self) -> Vec<&str> {
+        [&self.channel_1, &self.channel_2]
+            .into_iter()
+            .filter_map(|channel| channel.as_deref())
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct Wifi {
+    // interface used when none is given, the first wireless one otherwise
+    #[serde(default)]
+    pub interface: Option<String>,
+    // directory of the wpa_supplicant control sockets
+    #[serde(default)]
+    pub control_dir: Option<String>,
+    // dnsmasq serving DHCP on hotspots, `dnsmasq` from the PATH when not set
+    #[serde(default)]
+    pub dnsmasq: Option<String>,
+    // networkd or netlink, who applies IP configuration, networkd when not set
+    #[serde(default)]
+    pub ip_backend: Option<String>,
+    #[serde(default)]
+    pub connectivity: ConnectivityCheck,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConnectivityCheck {
+    // plain http url answering `expected_status` when the internet is reachable
+    pub probe_url: String,
+    pub expected_status: u16,
+    // name resolved to test DNS, the host of `probe_url` when not set
+    pub dns_host: Option<String>,
+    // host:port pairs tried for TCP reachability
+    pub tcp_endpoints: Vec<String>,
+    pub interval_secs: u64,
+    pub timeout_secs: u64,
+}
+
+impl Default for ConnectivityCheck {
+    fn default() -> Self {
+        let defaults = ConnectivityConfig::default();
+        ConnectivityCheck {
+            probe_url: defaults.probe_url,
+            expected_status: defaults.expected_status,
+            dns_host: defaults.dns_host,
+            tcp_endpoints: defaults.tcp_endpoints,
+            interval_secs: DEFAULT_CHECK_INTERVAL.as_secs(),
+            timeout_secs: defaults.timeout.as_secs(),
+        }
+    }
+}
+
+impl ConnectivityCheck {
+    pub fn config(&self) -> ConnectivityConfig {
+        ConnectivityConfig {
+            probe_url: self.probe_url.clone(),
+            expected_status: self.expected_status,
+            dns_host: self.dns_host.clone(),
+            tcp_endpoints: self.tcp_endpoints.clone(),
+            // a zero timeout is rejected by the socket calls
+            timeout: Duration::from_secs(self.timeout_secs.max(1)),
+        }
+    }
+}
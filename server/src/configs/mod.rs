@@ -1,2 +1,2 @@
 mod base_config;
-pub use base_config::BaseConfig;
\ No newline at end of file
+pub use base_config::{BaseConfig, LedIndicator, LedIndicatorPattern};
\ No newline at end of file
@@ -0,0 +1,172 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use mecha_battery_ctl::{Battery, BatteryControl, PowerSupplyInfo};
+use mecha_led_ctl::StatusIndicator;
+use mecha_network_ctl::connectivity::{Connectivity, ConnectivityMonitor};
+use mecha_network_ctl::interfaces::WirelessInterfaces;
+use mecha_network_ctl::session::WifiEvent;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tracing::{error as trace_error, info};
+
+// States driven by the server itself, the other configured states such as
+// `error` or `bluetooth_pairing` are raised by clients through the led service
+const NETWORK_CONNECTED_INDICATOR: &str = "network_connected";
+const NETWORK_DISCONNECTED_INDICATOR: &str = "network_disconnected";
+const BATTERY_LOW_INDICATOR: &str = "battery_low";
+const BATTERY_CHARGING_INDICATOR: &str = "battery_charging";
+
+// capacity in percent at or below which a discharging battery is low
+const BATTERY_LOW_CAPACITY: u8 = 15;
+const BATTERY_POLL_INTERVAL: Duration = Duration::from_secs(30);
+// wait before subscribing again when the supplicant is not running
+const WIFI_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+// Raise or clear a state, failures are only logged so a broken led does not
+// stop the task driving it
+async fn show(indicator: &StatusIndicator, name: &str, active: bool) {
+    if !indicator.is_configured(name) {
+        return;
+    }
+    let result = match active {
+        true => indicator.raise(name).await,
+        false => indicator.clear(name).await,
+    };
+    if let Err(e) = result {
+        trace_error!(
+            task = "indicators",
+            "unable to update indicator {}: {}",
+            name,
+            e
+        );
+    }
+}
+
+async fn show_network(indicator: &StatusIndicator, connected: bool) {
+    show(indicator, NETWORK_CONNECTED_INDICATOR, connected).await;
+    show(indicator, NETWORK_DISCONNECTED_INDICATOR, !connected).await;
+}
+
+// Follow the connectivity checks, anything with a default route counts as connected
+pub fn watch_connectivity(
+    indicator: Arc<StatusIndicator>,
+    monitor: &ConnectivityMonitor,
+) -> JoinHandle<()> {
+    let mut reports = monitor.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match reports.recv().await {
+                Ok(report) => {
+                    show_network(&indicator, report.state != Connectivity::None).await;
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+// Follow the supplicant of the default interface, which reports a lost or new
+// association before the next connectivity check
+pub fn watch_wifi(
+    indicator: Arc<StatusIndicator>,
+    interfaces: WirelessInterfaces,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let events = match interfaces.control(None) {
+                Ok(wifi) => wifi.watch_events().await,
+                Err(e) => Err(e),
+            };
+            let mut events = match events {
+                Ok(events) => events,
+                Err(e) => {
+                    info!(task = "indicators", "unable to watch wifi events: {}", e);
+                    tokio::time::sleep(WIFI_RETRY_INTERVAL).await;
+                    continue;
+                }
+            };
+            loop {
+                match events.recv().await {
+                    Ok(WifiEvent::Connected { .. }) => show_network(&indicator, true).await,
+                    Ok(WifiEvent::Disconnected) => show_network(&indicator, false).await,
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                }
+            }
+            tokio::time::sleep(WIFI_RETRY_INTERVAL).await;
+        }
+    })
+}
+
+// Whether the battery is low and whether it is charging
+fn battery_states(info: &BatteryControl) -> (bool, bool) {
+    let charging = info.status == "Charging";
+    let low = !charging
+        && (info.capacity <= BATTERY_LOW_CAPACITY
+            || matches!(info.capacity_level.as_str(), "Low" | "Critical"));
+    (low, charging)
+}
+
+// Poll the battery and show when it is low or charging
+pub fn watch_battery(indicator: Arc<StatusIndicator>, battery: Battery) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            match battery.info() {
+                Ok(info) if info.present => {
+                    let (low, charging) = battery_states(&info);
+                    show(&indicator, BATTERY_LOW_INDICATOR, low).await;
+                    show(&indicator, BATTERY_CHARGING_INDICATOR, charging).await;
+                }
+                Ok(_) => {}
+                Err(e) => info!(task = "indicators", "unable to read the battery: {}", e),
+            }
+            tokio::time::sleep(BATTERY_POLL_INTERVAL).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn battery(status: &str, capacity: u8, capacity_level: &str) -> BatteryControl {
+        BatteryControl {
+            name: String::from("bq27441-0"),
+            r#type: String::from("Battery"),
+            status: String::from(status),
+            present: true,
+            voltage_now: 0,
+            current_now: 0,
+            capacity,
+            capacity_level: String::from(capacity_level),
+            temp: 0,
+            technology: String::new(),
+            charge_full: 0,
+            charge_now: 0,
+            charge_full_design: 0,
+            manufacturer: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_battery_states() {
+        assert_eq!(
+            battery_states(&battery("Discharging", 80, "Normal")),
+            (false, false)
+        );
+        assert_eq!(
+            battery_states(&battery("Discharging", 10, "Normal")),
+            (true, false)
+        );
+        assert_eq!(
+            battery_states(&battery("Discharging", 30, "Critical")),
+            (true, false)
+        );
+        assert_eq!(
+            battery_states(&battery("Charging", 10, "Low")),
+            (false, true)
+        );
+    }
+}
@@ -1,19 +1,35 @@
 use anyhow::Result;
+use mecha_adc_ctl::AdcControl;
 use mecha_cpu_governor_ctl::CpuGovernanceCtl;
-use mecha_led_ctl::LedControl;
+use mecha_led_ctl::{IndicatorOutput, IndicatorState, LedControl, LedPattern, RgbColor};
 use mecha_metrics_ctl::DeviceMetricsCtl;
-use mecha_motion_sensor_ctl::MotionSensorControl;
+use mecha_motion_sensor_ctl::{
+    BufferConfig, MotionDetectorConfig, MotionSensorControl, CALIBRATION_DIR,
+};
+use mecha_network_ctl::connectivity::{ConnectivityChecker, ConnectivityMonitor};
+use mecha_network_ctl::hotspot::HotspotConfig;
+use mecha_network_ctl::interfaces::{WirelessInterfaces, DEFAULT_CONTROL_DIR};
+use mecha_network_ctl::ipconfig::{IpBackend, IpConfigurator};
+use mecha_network_ctl::links::Links;
+use mecha_network_ctl::rfkill::Rfkill;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::PathBuf;
+use std::time::Duration;
 use std::{fs::File, io::BufReader};
-use tracing::{info, Level};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tracing::{error as trace_error, info, Level};
 use tracing_subscriber;
 
 use tonic::transport::Server;
 
 mod configs;
-use crate::configs::BaseConfig;
+use crate::configs::{BaseConfig, LedIndicator, LedIndicatorPattern};
+
+mod indicators;
 
 mod services;
+use crate::services::{AdcManager, AdcServiceServer};
 use crate::services::{Battery, BatteryControl, PowerSupplyServiceServer};
 use crate::services::{Bluetooth, BluetoothServiceServer};
 use crate::services::{CpuCtlService, CpuGovernorCtlServiceServer};
@@ -23,8 +39,46 @@ use crate::services::{LedctlManager, LedctlServiceServer};
 use crate::services::{MotionSensorControlServiceServer, MotionSensorManager};
 use crate::services::{NetworkManager, NetworkManagerServiceServer};
 
+//indicator state shown while the server starts up
+const BOOTING_INDICATOR: &str = "booting";
+
+//convert the configured led indicators into indicator states
+fn indicator_states(indicators: &[LedIndicator]) -> Result<Vec<IndicatorState>> {
+    indicators
+        .iter()
+        .map(|indicator| {
+            let color = indicator.color.parse::<RgbColor>()?;
+            let output = match indicator.pattern {
+                LedIndicatorPattern::Solid => IndicatorOutput::Solid(color),
+                LedIndicatorPattern::Blink => IndicatorOutput::Pattern(LedPattern::Blink {
+                    color,
+                    on: Duration::from_millis(indicator.on_ms),
+                    off: Duration::from_millis(indicator.off_ms),
+                }),
+                LedIndicatorPattern::Breathe => IndicatorOutput::Pattern(LedPattern::Breathe {
+                    color,
+                    period: Duration::from_millis(indicator.period_ms),
+                }),
+            };
+            Ok(IndicatorState::new(
+                &indicator.state,
+                indicator.priority,
+                output,
+            ))
+        })
+        .collect()
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let subscriber = tracing_subscriber::fmt()
+        // filter spans/events with level TRACE or higher.
+        .with_max_level(Level::TRACE)
+        // build but do not install the subscriber.
+        .finish();
+
+    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+
     let profile_file = File::open("/home/jack/mecha/rust/mecha-ctl/server/Config.yml")
         .expect("Failed to open config file");
     let reader = BufReader::new(profile_file);
@@ -45,8 +99,43 @@ async fn main() -> Result<()> {
         power_supply: battery,
     };
 
+    //backend applying ip configuration
+    let ip_backend = match &config.interfaces.wifi.ip_backend {
+        Some(ip_backend) => ip_backend.parse::<IpBackend>()?,
+        None => IpBackend::Networkd,
+    };
+
+    //internet connectivity check
+    let connectivity = &config.interfaces.wifi.connectivity;
+    let connectivity_monitor =
+        ConnectivityMonitor::new(ConnectivityChecker::new(connectivity.config())?);
+
+    //wireless interfaces, shared with the network indicator
+    let interfaces = WirelessInterfaces::new(
+        config
+            .interfaces
+            .wifi
+            .control_dir
+            .as_deref()
+            .unwrap_or(DEFAULT_CONTROL_DIR),
+        config.interfaces.wifi.interface.as_deref(),
+    );
+
     //network manager service
-    let network_service = NetworkManager::default();
+    let network_service = NetworkManager {
+        interfaces: interfaces.clone(),
+        hotspot: match &config.interfaces.wifi.dnsmasq {
+            Some(dnsmasq) => HotspotConfig {
+                dnsmasq: Some(PathBuf::from(dnsmasq)),
+                ..Default::default()
+            },
+            None => HotspotConfig::default(),
+        },
+        rfkill: Rfkill::default(),
+        ip_config: IpConfigurator::new(ip_backend),
+        links: Links::default(),
+        connectivity: connectivity_monitor.clone(),
+    };
 
     //device info service
     let device_info = DeviceInfoCtl::default();
@@ -63,16 +152,27 @@ async fn main() -> Result<()> {
     };
 
     //led manager service
-    let led_service = LedControl::new(
-        config.interfaces.led.red_led.as_str(),
-        config.interfaces.led.green_led.as_str(),
-        config.interfaces.led.blue_led.as_str(),
-    );
+    let led_service = match &config.interfaces.led.multicolor_led {
+        Some(multicolor_led) => LedControl::new_multicolor(multicolor_led.as_str()),
+        None => LedControl::new(
+            config.interfaces.led.red_led.as_str(),
+            config.interfaces.led.green_led.as_str(),
+            config.interfaces.led.blue_led.as_str(),
+        ),
+    };
 
     //device led service
-    let led_ctl = LedctlManager {
-        led_ctl: led_service,
-    };
+    let led_ctl = LedctlManager::new(
+        led_service,
+        indicator_states(&config.interfaces.led.indicators)?,
+    );
+    let indicator = led_ctl.indicator.clone();
+    //a failing led must not keep the server from starting
+    if indicator.is_configured(BOOTING_INDICATOR) {
+        if let Err(e) = indicator.raise(BOOTING_INDICATOR).await {
+            trace_error!(task = "main", "unable to show the booting indicator: {}", e);
+        }
+    }
 
     //motion sensor
     let motion_sensor = MotionSensorControl::new(
@@ -81,25 +181,85 @@ async fn main() -> Result<()> {
         config.interfaces.motion_sensor.z_axis.as_str(),
     );
 
+    //apply the saved calibration of the motion sensor
+    let calibration_dir = PathBuf::from(
+        config
+            .interfaces
+            .motion_sensor
+            .calibration_dir
+            .as_deref()
+            .unwrap_or(CALIBRATION_DIR),
+    );
+    if let Err(e) = motion_sensor.load_calibration(&calibration_dir) {
+        trace_error!(task = "main", "unable to load motion sensor calibration: {}", e);
+    }
+    if let Some(frequency) = config.interfaces.motion_sensor.sampling_frequency {
+        if let Err(e) = motion_sensor.set_sampling_frequency(frequency) {
+            trace_error!(task = "main", "unable to set motion sensor sampling frequency: {}", e);
+        }
+    }
+    if let Some(ratio) = config.interfaces.motion_sensor.oversampling_ratio {
+        if let Err(e) = motion_sensor.set_oversampling_ratio(ratio) {
+            trace_error!(task = "main", "unable to set motion sensor oversampling ratio: {}", e);
+        }
+    }
+
     //motion sensor service
+    let detection = &config.interfaces.motion_sensor.detection;
     let motion_senso_service = MotionSensorManager {
         motion_sensor: motion_sensor,
+        detector_config: MotionDetectorConfig {
+            threshold: detection.threshold,
+            high_pass_cutoff_hz: detection.high_pass_cutoff_hz,
+            debounce: Duration::from_millis(detection.debounce_ms),
+            quiet_period: Duration::from_millis(detection.quiet_period_ms),
+            ..Default::default()
+        },
+        buffer_config: BufferConfig {
+            trigger: config.interfaces.motion_sensor.trigger.clone(),
+            ..Default::default()
+        },
+        calibration_dir,
+        ..Default::default()
     };
 
-    println!("Mecha Edge Server listening on {}", addr);
-
-    let subscriber = tracing_subscriber::fmt()
-        // filter spans/events with level TRACE or higher.
-        .with_max_level(Level::TRACE)
-        // build but do not install the subscriber.
-        .finish();
+    //adc service
+    let adc_service = AdcManager {
+        adc: AdcControl::new(
+            &config.interfaces.adc.channels(),
+            config.interfaces.adc.sampling_frequency.as_deref(),
+        ),
+        ..Default::default()
+    };
 
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    //bind before leaving the booting state so it is only cleared once clients can connect
+    let listener = TcpListener::bind(addr).await?;
+    println!("Mecha Edge Server listening on {}", addr);
     info!(
         task = "mecha_grpc_tracer",
         result = "success",
         "grpc server started"
     );
+    if indicator.is_configured(BOOTING_INDICATOR) {
+        if let Err(e) = indicator.clear(BOOTING_INDICATOR).await {
+            trace_error!(task = "main", "unable to clear the booting indicator: {}", e);
+        }
+    }
+
+    //keep the network and battery indicator states up to date
+    indicators::watch_connectivity(indicator.clone(), &connectivity_monitor);
+    indicators::watch_wifi(indicator.clone(), interfaces);
+    indicators::watch_battery(
+        indicator.clone(),
+        Battery {
+            path: config.interfaces.battery.device.clone(),
+            currnet_now: config.interfaces.battery.current.clone(),
+        },
+    );
+
+    //check connectivity in the background for the lifetime of the server
+    connectivity_monitor.start(Duration::from_secs(connectivity.interval_secs.max(1)));
+
     Server::builder()
         .add_service(PowerSupplyServiceServer::new(power_supply))
         .add_service(NetworkManagerServiceServer::new(network_service))
@@ -109,7 +269,8 @@ async fn main() -> Result<()> {
         .add_service(CpuGovernorCtlServiceServer::new(cpu_ctl))
         .add_service(LedctlServiceServer::new(led_ctl))
         .add_service(MotionSensorControlServiceServer::new(motion_senso_service))
-        .serve(addr)
+        .add_service(AdcServiceServer::new(adc_service))
+        .serve_with_incoming(TcpListenerStream::new(listener))
         .await?;
 
     Ok(())
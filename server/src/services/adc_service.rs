@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+pub use mecha_adc_ctl::{AdcControl, AdcReading, AdcWindow};
+
+// Upper bounds of a requested window, a read blocks for the whole window
+const MAX_WINDOW_SAMPLES: u32 = 1000;
+const MAX_WINDOW_INTERVAL_MS: u32 = 60_000;
+
+#[derive(Default)]
+pub struct AdcManager {
+    pub adc: AdcControl,
+    // used for fields left at 0 in requests
+    pub window: AdcWindow,
+}
+
+#[allow(non_snake_case)]
+pub mod adc {
+    tonic::include_proto!("adc");
+}
+
+pub use adc::{
+    adc_service_server::{AdcService, AdcServiceServer},
+    AdcChannel as AdcChannelProto, AdcReading as AdcReadingProto, Empty, ListChannelsResponse,
+    ReadChannelRequest, SamplingFrequency, StreamReadingsRequest,
+};
+
+fn reading_to_proto(reading: AdcReading) -> AdcReadingProto {
+    AdcReadingProto {
+        channel: reading.channel,
+        millivolts: reading.millivolts,
+        min_millivolts: reading.min_millivolts,
+        max_millivolts: reading.max_millivolts,
+        samples: reading.samples as u32,
+        timestamp_ns: reading.timestamp,
+    }
+}
+
+impl AdcManager {
+    fn window(&self, samples: u32, interval_ms: u32) -> Result<AdcWindow, String> {
+        if samples > MAX_WINDOW_SAMPLES {
+            return Err(format!("samples must be at most {}", MAX_WINDOW_SAMPLES));
+        }
+        if interval_ms > MAX_WINDOW_INTERVAL_MS {
+            return Err(format!(
+                "interval_ms must be at most {}",
+                MAX_WINDOW_INTERVAL_MS
+            ));
+        }
+        Ok(AdcWindow {
+            samples: match samples {
+                0 => self.window.samples,
+                samples => samples as usize,
+            },
+            interval: match interval_ms {
+                0 => self.window.interval,
+                interval_ms => Duration::from_millis(interval_ms.into()),
+            },
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl AdcService for AdcManager {
+    type StreamReadingsStream = ReceiverStream<Result<AdcReadingProto, Status>>;
+
+    async fn list_channels(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<ListChannelsResponse>, Status> {
+        match self.adc.channels() {
+            Ok(channels) => Ok(Response::new(ListChannelsResponse {
+                channels: channels
+                    .iter()
+                    .map(|channel| AdcChannelProto {
+                        name: channel.name().to_string(),
+                        path: channel.path().display().to_string(),
+                        scale: channel.scale(),
+                    })
+                    .collect(),
+            })),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn read_channel(
+        &self,
+        request: Request<ReadChannelRequest>,
+    ) -> Result<Response<AdcReadingProto>, Status> {
+        let request = request.into_inner();
+        let window = self
+            .window(request.samples, request.interval_ms)
+            .map_err(Status::invalid_argument)?;
+        let adc = self.adc.clone();
+        // averaging sleeps between samples
+        let reading =
+            tokio::task::spawn_blocking(move || adc.read(&request.channel, &window)).await;
+        match reading {
+            Ok(Ok(reading)) => Ok(Response::new(reading_to_proto(reading))),
+            Ok(Err(err)) => Err(Status::invalid_argument(err.to_string())),
+            Err(err) => Err(Status::internal(err.to_string())),
+        }
+    }
+
+    async fn stream_readings(
+        &self,
+        request: Request<StreamReadingsRequest>,
+    ) -> Result<Response<Self::StreamReadingsStream>, Status> {
+        let request = request.into_inner();
+        let window = self
+            .window(request.samples, request.interval_ms)
+            .map_err(Status::invalid_argument)?;
+        let readings = match self.adc.stream(&request.channels, &window) {
+            Ok(readings) => readings,
+            Err(err) => return Err(Status::invalid_argument(err.to_string())),
+        };
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut readings = readings;
+            while let Some(reading) = readings.next().await {
+                let reading = match reading {
+                    Ok(reading) => Ok(reading_to_proto(reading)),
+                    Err(err) => Err(Status::internal(err.to_string())),
+                };
+                // the client went away, dropping `readings` stops reading
+                if sender.send(reading).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn get_sampling_frequency(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<SamplingFrequency>, Status> {
+        match self.adc.sampling_frequency() {
+            Ok(frequency) => Ok(Response::new(SamplingFrequency { frequency })),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn set_sampling_frequency(
+        &self,
+        request: Request<SamplingFrequency>,
+    ) -> Result<Response<Empty>, Status> {
+        let frequency = request.into_inner().frequency;
+        match self.adc.set_sampling_frequency(frequency) {
+            Ok(_) => Ok(Response::new(Empty {})),
+            Err(err) => Err(Status::invalid_argument(err.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window() {
+        let adc = AdcManager::default();
+        assert_eq!(adc.window(0, 0).unwrap(), AdcWindow::default());
+        let window = adc
+            .window(MAX_WINDOW_SAMPLES, MAX_WINDOW_INTERVAL_MS)
+            .unwrap();
+        assert_eq!(window.samples, 1000);
+        assert_eq!(window.interval, Duration::from_secs(60));
+        assert!(adc.window(MAX_WINDOW_SAMPLES + 1, 0).is_err());
+        assert!(adc.window(0, MAX_WINDOW_INTERVAL_MS + 1).is_err());
+    }
+}
@@ -1,6 +1,14 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
 use tonic::{Request, Response, Status};
 
-pub use mecha_led_ctl::{LedColor, LedControl, LedctlError, LedctlErrorCodes};
+pub use mecha_led_ctl::{
+    list_leds, IndicatorState, Keyframe, LedColor, LedControl, LedDevice, LedInfo, LedPattern,
+    LedPatternEngine, LedTrigger, LedctlError, LedctlErrorCodes, PatternBackend, RgbColor,
+    StatusIndicator,
+};
 
 #[allow(non_snake_case)]
 pub mod ledmanager {
@@ -8,12 +16,140 @@ pub mod ledmanager {
 }
 
 pub use ledmanager::{
+    led_color_value::Value as LedColorValueProto,
+    led_pattern_request::Pattern as LedPatternProto,
+    led_trigger_request::Options as LedTriggerOptions,
     ledctl_service_server::{LedctlService, LedctlServiceServer},
-    Empty, LedColor as LedColorProto,
+    Empty, IndicatorRequest, IndicatorResponse, LedColor as LedColorProto, LedColorValue,
+    LedInfo as LedInfoProto, LedListResponse, LedPatternRequest, LedPatternResponse, LedRgb,
+    LedTriggerRequest,
 };
 
 pub struct LedctlManager {
-    pub led_ctl: LedControl,
+    pub led_pattern: Arc<LedPatternEngine>,
+    // shared with other services so they can assert system states
+    pub indicator: Arc<StatusIndicator>,
+}
+
+impl LedctlManager {
+    pub fn new(led_ctl: LedControl, indicator_states: Vec<IndicatorState>) -> Self {
+        let led_pattern = Arc::new(LedPatternEngine::new(Arc::new(led_ctl)));
+        LedctlManager {
+            indicator: Arc::new(StatusIndicator::new(led_pattern.clone(), indicator_states)),
+            led_pattern,
+        }
+    }
+
+    // Direct led writes are refused while an indicator state owns the leds
+    async fn indicator_shown_status(&self) -> Option<Status> {
+        self.indicator.shown().await.map(|state| {
+            Status::failed_precondition(format!("led is showing indicator state {}", state))
+        })
+    }
+}
+
+// Convert a proto color to the led brightness values, rejecting values above 255
+fn rgb_from_proto(color: Option<LedRgb>) -> Result<RgbColor> {
+    let color = color.unwrap_or_default();
+    let channel = |value: u32| {
+        u8::try_from(value).map_err(|_| anyhow!("led brightness {} is out of range 0-255", value))
+    };
+    Ok(RgbColor::new(
+        channel(color.red)?,
+        channel(color.green)?,
+        channel(color.blue)?,
+    ))
+}
+
+fn color_from_proto(color: Option<LedColorValueProto>) -> Result<RgbColor> {
+    match color {
+        Some(LedColorValueProto::Rgb(rgb)) => rgb_from_proto(Some(rgb)),
+        Some(LedColorValueProto::Hex(hex)) => {
+            // hex is accepted with or without the leading `#`
+            format!("#{}", hex.trim_start_matches('#')).parse::<RgbColor>()
+        }
+        Some(LedColorValueProto::Name(name)) => name.parse::<RgbColor>(),
+        None => Err(anyhow!("led color is missing")),
+    }
+}
+
+fn pattern_from_proto(pattern: Option<LedPatternProto>) -> Result<LedPattern> {
+    let pattern = match pattern {
+        Some(LedPatternProto::Blink(blink)) => LedPattern::Blink {
+            color: rgb_from_proto(blink.color)?,
+            on: Duration::from_millis(blink.on_ms.into()),
+            off: Duration::from_millis(blink.off_ms.into()),
+        },
+        Some(LedPatternProto::Breathe(breathe)) => LedPattern::Breathe {
+            color: rgb_from_proto(breathe.color)?,
+            period: Duration::from_millis(breathe.period_ms.into()),
+        },
+        Some(LedPatternProto::ColorCycle(cycle)) => LedPattern::ColorCycle {
+            colors: cycle
+                .colors
+                .into_iter()
+                .map(|color| rgb_from_proto(Some(color)))
+                .collect::<Result<_>>()?,
+            interval: Duration::from_millis(cycle.interval_ms.into()),
+        },
+        Some(LedPatternProto::Sequence(sequence)) => LedPattern::Sequence {
+            keyframes: sequence
+                .keyframes
+                .into_iter()
+                .map(|keyframe| {
+                    Ok(Keyframe::new(
+                        rgb_from_proto(keyframe.color)?,
+                        Duration::from_millis(keyframe.duration_ms.into()),
+                    ))
+                })
+                .collect::<Result<_>>()?,
+        },
+        None => return Err(anyhow!("led pattern is missing")),
+    };
+    Ok(pattern)
+}
+
+fn trigger_from_proto(trigger: &str, options: Option<LedTriggerOptions>) -> Result<LedTrigger> {
+    let trigger = match (trigger, options) {
+        ("none", _) => LedTrigger::None,
+        ("default-on", _) => LedTrigger::DefaultOn,
+        ("heartbeat", _) => LedTrigger::Heartbeat,
+        ("timer", Some(LedTriggerOptions::Timer(timer))) => LedTrigger::Timer {
+            delay_on: timer.delay_on_ms,
+            delay_off: timer.delay_off_ms,
+        },
+        ("netdev", Some(LedTriggerOptions::Netdev(netdev))) => {
+            if netdev.device_name.is_empty() {
+                return Err(anyhow!("netdev trigger requires a device name"));
+            }
+            LedTrigger::Netdev {
+                device: netdev.device_name,
+                link: netdev.link,
+                rx: netdev.rx,
+                tx: netdev.tx,
+                interval: (netdev.interval_ms != 0).then_some(netdev.interval_ms),
+            }
+        }
+        ("timer", _) | ("netdev", _) => {
+            return Err(anyhow!("{} trigger options are missing", trigger))
+        }
+        ("", _) => return Err(anyhow!("led trigger is missing")),
+        (name, _) => LedTrigger::Other(name.to_string()),
+    };
+    Ok(trigger)
+}
+
+fn led_info_to_proto(led: LedInfo) -> LedInfoProto {
+    LedInfoProto {
+        name: led.name,
+        device: led.device.unwrap_or_default(),
+        color: led.color.unwrap_or_default(),
+        function: led.function.unwrap_or_default(),
+        brightness: led.brightness,
+        max_brightness: led.max_brightness,
+        trigger: led.trigger.unwrap_or_default(),
+        available_triggers: led.available_triggers,
+    }
 }
 
 #[tonic::async_trait]
@@ -26,14 +162,20 @@ impl LedctlService for LedctlManager {
         let green = colors.green;
         let blue = colors.blue;
 
-        // Convert the boolean values to u8
-        let red_value = if red { 1 } else { 0 };
-        let green_value = if green { 1 } else { 0 };
-        let blue_value = if blue { 1 } else { 0 };
+        // Convert the boolean values to full brightness
+        let red_value = if red { u8::MAX } else { 0 };
+        let green_value = if green { u8::MAX } else { 0 };
+        let blue_value = if blue { u8::MAX } else { 0 };
+
+        if let Some(status) = self.indicator_shown_status().await {
+            return Err(status);
+        }
 
+        // a static color replaces any running pattern
         match self
-            .led_ctl
-            .set_led(red_value as u8, green_value as u8, blue_value as u8)
+            .led_pattern
+            .set_color(RgbColor::new(red_value, green_value, blue_value))
+            .await
         {
             Ok(_) => Ok(Response::new(Empty {})),
             Err(err) => Err(Status::from_error(err.into())),
@@ -43,22 +185,192 @@ impl LedctlService for LedctlManager {
     async fn clear_led(&self, request: Request<LedColorProto>) -> Result<Response<Empty>, Status> {
         let colors = request.into_inner();
 
-        // Extract the red, green, and blue values
-        let red = colors.red;
-        let green = colors.green;
-        let blue = colors.blue;
+        // Only the selected colors are switched off, the others keep their brightness
+        let channels: Vec<LedColor> = [
+            (colors.red, LedColor::Red),
+            (colors.green, LedColor::Green),
+            (colors.blue, LedColor::Blue),
+        ]
+        .into_iter()
+        .filter(|(selected, _)| *selected)
+        .map(|(_, color)| color)
+        .collect();
 
-        // Convert the boolean values to u8
-        let red_value = if red { 1 } else { 0 };
-        let green_value = if green { 1 } else { 0 };
-        let blue_value = if blue { 1 } else { 0 };
+        if let Some(status) = self.indicator_shown_status().await {
+            return Err(status);
+        }
 
-        match self
-            .led_ctl
-            .set_led(red_value as u8, green_value as u8, blue_value as u8)
-        {
+        match self.led_pattern.clear_channels(&channels).await {
+            Ok(_) => Ok(Response::new(Empty {})),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn set_color(&self, request: Request<LedColorValue>) -> Result<Response<Empty>, Status> {
+        let color = match color_from_proto(request.into_inner().value) {
+            Ok(color) => color,
+            Err(err) => return Err(Status::invalid_argument(err.to_string())),
+        };
+
+        if let Some(status) = self.indicator_shown_status().await {
+            return Err(status);
+        }
+
+        match self.led_pattern.set_color(color).await {
+            Ok(_) => Ok(Response::new(Empty {})),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn set_pattern(
+        &self,
+        request: Request<LedPatternRequest>,
+    ) -> Result<Response<LedPatternResponse>, Status> {
+        let request = request.into_inner();
+        let pattern = match pattern_from_proto(request.pattern) {
+            Ok(pattern) => pattern,
+            Err(err) => return Err(Status::invalid_argument(err.to_string())),
+        };
+
+        // 0 repeats plays the pattern until it is stopped or replaced
+        let repeat = match request.repeat {
+            0 => None,
+            repeat => Some(repeat),
+        };
+
+        if let Some(status) = self.indicator_shown_status().await {
+            return Err(status);
+        }
+
+        match self.led_pattern.start(pattern, repeat).await {
+            Ok(backend) => Ok(Response::new(LedPatternResponse {
+                offloaded: backend == PatternBackend::Kernel,
+            })),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn stop_pattern(&self, _request: Request<Empty>) -> Result<Response<Empty>, Status> {
+        if let Some(status) = self.indicator_shown_status().await {
+            return Err(status);
+        }
+
+        match self.led_pattern.stop().await {
+            Ok(_) => Ok(Response::new(Empty {})),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn raise_indicator(
+        &self,
+        request: Request<IndicatorRequest>,
+    ) -> Result<Response<IndicatorResponse>, Status> {
+        let state = request.into_inner().state;
+        if !self.indicator.is_configured(&state) {
+            return Err(Status::not_found(format!(
+                "indicator state {} is not configured",
+                state
+            )));
+        }
+
+        match self.indicator.raise(&state).await {
+            Ok(shown_state) => Ok(Response::new(IndicatorResponse {
+                shown_state: shown_state.unwrap_or_default(),
+            })),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn clear_indicator(
+        &self,
+        request: Request<IndicatorRequest>,
+    ) -> Result<Response<IndicatorResponse>, Status> {
+        let state = request.into_inner().state;
+        if !self.indicator.is_configured(&state) {
+            return Err(Status::not_found(format!(
+                "indicator state {} is not configured",
+                state
+            )));
+        }
+
+        match self.indicator.clear(&state).await {
+            Ok(shown_state) => Ok(Response::new(IndicatorResponse {
+                shown_state: shown_state.unwrap_or_default(),
+            })),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn list_leds(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<LedListResponse>, Status> {
+        match list_leds() {
+            Ok(leds) => Ok(Response::new(LedListResponse {
+                leds: leds.into_iter().map(led_info_to_proto).collect(),
+            })),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn set_trigger(
+        &self,
+        request: Request<LedTriggerRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let request = request.into_inner();
+        // led names are looked up under /sys/class/leds, reject anything resembling a path
+        if request.led.is_empty() || request.led.contains('/') || request.led.starts_with('.') {
+            return Err(Status::invalid_argument(format!(
+                "invalid led name {}",
+                request.led
+            )));
+        }
+        let trigger = match trigger_from_proto(&request.trigger, request.options) {
+            Ok(trigger) => trigger,
+            Err(err) => return Err(Status::invalid_argument(err.to_string())),
+        };
+
+        match LedDevice::new(&request.led).set_trigger(&trigger) {
             Ok(_) => Ok(Response::new(Empty {})),
             Err(err) => Err(Status::from_error(err.into())),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn test_clear_led() {
+        let tmp_dir = TempDir::new("led").unwrap();
+        let paths: Vec<String> = ["red", "green", "blue"]
+            .iter()
+            .map(|name| tmp_dir.path().join(name).to_str().unwrap().to_string())
+            .collect();
+        let led_ctl = LedctlManager::new(LedControl::new(&paths[0], &paths[1], &paths[2]), vec![]);
+
+        led_ctl
+            .set_led(Request::new(LedColorProto {
+                red: true,
+                green: true,
+                blue: true,
+            }))
+            .await
+            .unwrap();
+        led_ctl
+            .clear_led(Request::new(LedColorProto {
+                red: true,
+                green: false,
+                blue: true,
+            }))
+            .await
+            .unwrap();
+
+        assert_eq!(fs::read_to_string(&paths[0]).unwrap(), "0");
+        assert_eq!(fs::read_to_string(&paths[1]).unwrap(), "255");
+        assert_eq!(fs::read_to_string(&paths[2]).unwrap(), "0");
+    }
+}
@@ -22,6 +22,9 @@ pub use metrics_service::{DeviceMetricsService, MetricsServiceServer};
 mod cpu_ctl_service;
 pub use cpu_ctl_service::{CpuCtlService, CpuGovernorCtlServiceServer};
 
+mod adc_service;
+pub use adc_service::{AdcManager, AdcServiceServer};
+
 mod motion_sensor_service;
 pub use motion_sensor_service::{
     MotionSensorControlService, MotionSensorControlServiceServer, MotionSensorManager,
@@ -1,10 +1,32 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 
-pub use mecha_motion_sensor_ctl::MotionSensorControl;
+pub use mecha_motion_sensor_ctl::{
+    AvailableValues, BufferConfig, Calibration, CalibrationConfig, CalibrationPose, Calibrator,
+    FusionConfig, Gesture, GestureConfig, GestureEvent, MotionDetectorConfig, MotionEvent,
+    MotionEventKind, MotionEventSource, MotionSensorControl, Orientation, OrientationUpdate,
+    PowerControl, Vector3,
+};
 
 #[derive(Default)]
 pub struct MotionSensorManager {
     pub motion_sensor: MotionSensorControl,
+    // defaults for WatchMotion requests
+    pub detector_config: MotionDetectorConfig,
+    pub buffer_config: BufferConfig,
+    pub fusion_config: FusionConfig,
+    pub gesture_config: GestureConfig,
+    pub calibration_dir: PathBuf,
+    pub calibration_config: CalibrationConfig,
+    // calibration in progress, driven step by step through Calibrate
+    pub calibrator: Arc<Mutex<Option<Calibrator>>>,
 }
 
 #[allow(non_snake_case)]
@@ -16,11 +38,192 @@ pub use motionsensor::{
     motion_sensor_control_service_server::{
         MotionSensorControlService, MotionSensorControlServiceServer,
     },
-    DetectEventResponse, Empty, ReadValueResponse,
+    Attribute, AvailableRange, CalibrateRequest, CalibrateResponse,
+    CalibrationPose as CalibrationPoseProto, CalibrationResult, CalibrationStep,
+    DetectEventResponse, Empty, GestureEvent as GestureEventProto, GestureKind,
+    ListAttributesResponse, MotionEvent as MotionEventProto,
+    MotionEventKind as MotionEventKindProto, OrientationKind,
+    OrientationUpdate as OrientationUpdateProto, PowerControl as PowerControlProto,
+    ReadChannelsResponse, ReadValueResponse, SetOversamplingRatioRequest, SetPowerControlRequest,
+    SetSamplingFrequencyRequest, TemperatureReading, Vector3 as Vector3Proto, WatchMotionRequest,
+    WatchOrientationRequest,
 };
 
+fn event_to_proto(event: MotionEvent) -> MotionEventProto {
+    let kind = match event.kind {
+        MotionEventKind::Started => MotionEventKindProto::MotionStarted,
+        MotionEventKind::Stopped => MotionEventKindProto::MotionStopped,
+    };
+    MotionEventProto {
+        kind: kind as i32,
+        hardware: event.source == MotionEventSource::Hardware,
+        timestamp_ns: event.timestamp,
+        magnitude: event.magnitude,
+    }
+}
+
+fn orientation_to_proto(update: OrientationUpdate) -> OrientationUpdateProto {
+    let orientation = match update.orientation {
+        Orientation::Portrait => OrientationKind::Portrait,
+        Orientation::PortraitUpsideDown => OrientationKind::PortraitUpsideDown,
+        Orientation::LandscapeLeft => OrientationKind::LandscapeLeft,
+        Orientation::LandscapeRight => OrientationKind::LandscapeRight,
+        Orientation::FlatUp => OrientationKind::FlatUp,
+        Orientation::FlatDown => OrientationKind::FlatDown,
+    };
+    OrientationUpdateProto {
+        roll: update.attitude.roll,
+        pitch: update.attitude.pitch,
+        yaw: update.attitude.yaw,
+        orientation: orientation as i32,
+        orientation_changed: update.orientation_changed,
+        timestamp_ns: update.timestamp,
+    }
+}
+
+fn gesture_to_proto(event: GestureEvent) -> GestureEventProto {
+    let gesture = match event.gesture {
+        Gesture::Tap => GestureKind::Tap,
+        Gesture::Shake => GestureKind::Shake,
+        Gesture::FreeFall => GestureKind::FreeFall,
+    };
+    GestureEventProto {
+        gesture: gesture as i32,
+        timestamp_ns: event.timestamp,
+    }
+}
+
+fn pose_from_proto(pose: CalibrationPoseProto) -> CalibrationPose {
+    match pose {
+        CalibrationPoseProto::ZUp => CalibrationPose::ZUp,
+        CalibrationPoseProto::ZDown => CalibrationPose::ZDown,
+        CalibrationPoseProto::XUp => CalibrationPose::XUp,
+        CalibrationPoseProto::XDown => CalibrationPose::XDown,
+        CalibrationPoseProto::YUp => CalibrationPose::YUp,
+        CalibrationPoseProto::YDown => CalibrationPose::YDown,
+    }
+}
+
+fn pose_to_proto(pose: CalibrationPose) -> CalibrationPoseProto {
+    match pose {
+        CalibrationPose::ZUp => CalibrationPoseProto::ZUp,
+        CalibrationPose::ZDown => CalibrationPoseProto::ZDown,
+        CalibrationPose::XUp => CalibrationPoseProto::XUp,
+        CalibrationPose::XDown => CalibrationPoseProto::XDown,
+        CalibrationPose::YUp => CalibrationPoseProto::YUp,
+        CalibrationPose::YDown => CalibrationPoseProto::YDown,
+    }
+}
+
+// Run one calibration step, measuring blocks while samples are collected
+fn calibrate_step(
+    motion_sensor: &MotionSensorControl,
+    calibrator: &Mutex<Option<Calibrator>>,
+    config: &CalibrationConfig,
+    directory: &Path,
+    step: CalibrationStep,
+    request: CalibrateRequest,
+) -> Result<CalibrateResponse> {
+    let mut calibrator = calibrator
+        .lock()
+        .map_err(|_| anyhow!("calibration state is unavailable"))?;
+    let mut response = CalibrateResponse::default();
+
+    match step {
+        CalibrationStep::Unspecified => return Err(anyhow!("calibration step is missing")),
+        CalibrationStep::CalibrationStart => {
+            *calibrator = Some(motion_sensor.calibrator(config)?);
+        }
+        CalibrationStep::CalibrationCancel => {
+            *calibrator = None;
+            motion_sensor.set_calibration(Calibration::default());
+            motion_sensor.load_calibration(directory)?;
+        }
+        CalibrationStep::CalibrationGyroBias => {
+            let active = calibrator
+                .as_mut()
+                .ok_or_else(|| anyhow!("calibration has not been started"))?;
+            response.measured = Some(vector_to_proto(active.measure_gyro_bias()?));
+        }
+        CalibrationStep::CalibrationMeasurePose => {
+            let pose = CalibrationPoseProto::from_i32(request.pose)
+                .ok_or_else(|| anyhow!("unknown calibration pose {}", request.pose))?;
+            let active = calibrator
+                .as_mut()
+                .ok_or_else(|| anyhow!("calibration has not been started"))?;
+            response.measured = Some(vector_to_proto(active.measure_pose(pose_from_proto(pose))?));
+        }
+        CalibrationStep::CalibrationFinish => {
+            let active = calibrator
+                .as_ref()
+                .ok_or_else(|| anyhow!("calibration has not been started"))?;
+            let calibration = active.finish()?;
+            motion_sensor.set_calibration(calibration.clone());
+            let path = motion_sensor.save_calibration(directory)?;
+            *calibrator = None;
+            response.result = Some(CalibrationResult {
+                accel_offset: Some(vector_to_proto(calibration.accel_offset)),
+                accel_scale: Some(vector_to_proto(calibration.accel_scale)),
+                gyro_bias: Some(vector_to_proto(calibration.gyro_bias)),
+                path: path.display().to_string(),
+            });
+        }
+    }
+
+    if let Some(active) = calibrator.as_ref() {
+        response.gyro_bias_pending = active.needs_gyro_bias();
+        response.remaining_poses = active
+            .remaining_poses()
+            .into_iter()
+            .map(|pose| pose_to_proto(pose) as i32)
+            .collect();
+    }
+    Ok(response)
+}
+
+fn list_attributes(motion_sensor: &MotionSensorControl) -> Result<ListAttributesResponse> {
+    let mut attributes = Vec::new();
+    for attribute in motion_sensor.attributes()? {
+        let mut proto = Attribute {
+            name: attribute.name.clone(),
+            value: attribute.read()?,
+            ..Default::default()
+        };
+        match attribute.available()? {
+            Some(AvailableValues::List(values)) => proto.available = values,
+            Some(AvailableValues::Range { min, step, max }) => {
+                proto.available_range = Some(AvailableRange { min, step, max })
+            }
+            None => {}
+        }
+        attributes.push(proto);
+    }
+    // not every device supports runtime power management
+    let power_control = match motion_sensor.power_control() {
+        Ok(PowerControl::Auto) => PowerControlProto::PowerAuto,
+        Ok(PowerControl::On) => PowerControlProto::PowerOn,
+        Err(_) => PowerControlProto::Unspecified,
+    };
+    Ok(ListAttributesResponse {
+        attributes,
+        power_control: power_control as i32,
+    })
+}
+
+fn vector_to_proto(vector: Vector3) -> Vector3Proto {
+    Vector3Proto {
+        x: vector.x,
+        y: vector.y,
+        z: vector.z,
+    }
+}
+
 #[tonic::async_trait]
 impl MotionSensorControlService for MotionSensorManager {
+    type WatchMotionStream = ReceiverStream<Result<MotionEventProto, Status>>;
+    type WatchOrientationStream = ReceiverStream<Result<OrientationUpdateProto, Status>>;
+    type WatchGesturesStream = ReceiverStream<Result<GestureEventProto, Status>>;
+
     async fn read_value(
         &self,
         _request: Request<Empty>,
@@ -47,4 +250,203 @@ impl MotionSensorControlService for MotionSensorManager {
             Err(err) => Err(Status::from_error(err.into())),
         }
     }
+
+    async fn read_channels(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<ReadChannelsResponse>, Status> {
+        let device = match self.motion_sensor.device() {
+            Ok(device) => device,
+            Err(err) => return Err(Status::from_error(err.into())),
+        };
+
+        match device.read_all() {
+            Ok(readings) => Ok(Response::new(ReadChannelsResponse {
+                device_name: device.name().unwrap_or_default().to_string(),
+                accel: readings.accel.map(vector_to_proto),
+                anglvel: readings.anglvel.map(vector_to_proto),
+                magn: readings.magn.map(vector_to_proto),
+                temp: readings.temp.map(|celsius| TemperatureReading { celsius }),
+            })),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn watch_motion(
+        &self,
+        request: Request<WatchMotionRequest>,
+    ) -> Result<Response<Self::WatchMotionStream>, Status> {
+        let request = request.into_inner();
+        let mut config = self.detector_config.clone();
+        if request.threshold > 0.0 {
+            config.threshold = request.threshold;
+        }
+        if request.high_pass_cutoff_hz > 0.0 {
+            config.high_pass_cutoff_hz = request.high_pass_cutoff_hz;
+        }
+        if request.debounce_ms > 0 {
+            config.debounce = Duration::from_millis(request.debounce_ms.into());
+        }
+        if request.quiet_period_ms > 0 {
+            config.quiet_period = Duration::from_millis(request.quiet_period_ms.into());
+        }
+
+        let events = match self
+            .motion_sensor
+            .watch_motion(&config, &self.buffer_config)
+        {
+            Ok(events) => events,
+            Err(err) => return Err(Status::from_error(err.into())),
+        };
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut events = events;
+            while let Some(event) = events.next().await {
+                let event = match event {
+                    Ok(event) => Ok(event_to_proto(event)),
+                    Err(err) => Err(Status::internal(err.to_string())),
+                };
+                // the client went away, dropping `events` stops watching
+                if sender.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn watch_orientation(
+        &self,
+        request: Request<WatchOrientationRequest>,
+    ) -> Result<Response<Self::WatchOrientationStream>, Status> {
+        let changes_only = request.into_inner().changes_only;
+        let updates = match self.motion_sensor.watch_orientation(&self.fusion_config) {
+            Ok(updates) => updates,
+            Err(err) => return Err(Status::from_error(err.into())),
+        };
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut updates = updates;
+            while let Some(update) = updates.next().await {
+                let update = match update {
+                    Ok(update) if changes_only && !update.orientation_changed => continue,
+                    Ok(update) => Ok(orientation_to_proto(update)),
+                    Err(err) => Err(Status::internal(err.to_string())),
+                };
+                if sender.send(update).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn watch_gestures(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::WatchGesturesStream>, Status> {
+        let gestures = match self.motion_sensor.watch_gestures(&self.gesture_config) {
+            Ok(gestures) => gestures,
+            Err(err) => return Err(Status::from_error(err.into())),
+        };
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut gestures = gestures;
+            while let Some(event) = gestures.next().await {
+                let event = match event {
+                    Ok(event) => Ok(gesture_to_proto(event)),
+                    Err(err) => Err(Status::internal(err.to_string())),
+                };
+                if sender.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn calibrate(
+        &self,
+        request: Request<CalibrateRequest>,
+    ) -> Result<Response<CalibrateResponse>, Status> {
+        let request = request.into_inner();
+        let step = match CalibrationStep::from_i32(request.step) {
+            Some(CalibrationStep::Unspecified) | None => {
+                return Err(Status::invalid_argument(format!(
+                    "unknown calibration step {}",
+                    request.step
+                )))
+            }
+            Some(step) => step,
+        };
+        let motion_sensor = self.motion_sensor.clone();
+        let calibrator = self.calibrator.clone();
+        let config = self.calibration_config.clone();
+        let directory = self.calibration_dir.clone();
+        let response = tokio::task::spawn_blocking(move || {
+            calibrate_step(
+                &motion_sensor,
+                &calibrator,
+                &config,
+                &directory,
+                step,
+                request,
+            )
+        })
+        .await;
+        match response {
+            Ok(Ok(response)) => Ok(Response::new(response)),
+            Ok(Err(err)) => Err(Status::failed_precondition(err.to_string())),
+            Err(err) => Err(Status::internal(err.to_string())),
+        }
+    }
+
+    async fn list_attributes(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<ListAttributesResponse>, Status> {
+        match list_attributes(&self.motion_sensor) {
+            Ok(response) => Ok(Response::new(response)),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn set_sampling_frequency(
+        &self,
+        request: Request<SetSamplingFrequencyRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let frequency = request.into_inner().frequency;
+        match self.motion_sensor.set_sampling_frequency(frequency) {
+            Ok(_) => Ok(Response::new(Empty {})),
+            Err(err) => Err(Status::invalid_argument(err.to_string())),
+        }
+    }
+
+    async fn set_oversampling_ratio(
+        &self,
+        request: Request<SetOversamplingRatioRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let ratio = request.into_inner().ratio;
+        match self.motion_sensor.set_oversampling_ratio(ratio) {
+            Ok(_) => Ok(Response::new(Empty {})),
+            Err(err) => Err(Status::invalid_argument(err.to_string())),
+        }
+    }
+
+    async fn set_power_control(
+        &self,
+        request: Request<SetPowerControlRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let power_control = match PowerControlProto::from_i32(request.into_inner().power_control) {
+            Some(PowerControlProto::PowerAuto) => PowerControl::Auto,
+            Some(PowerControlProto::PowerOn) => PowerControl::On,
+            Some(PowerControlProto::Unspecified) | None => {
+                return Err(Status::invalid_argument("unknown power control"))
+            }
+        };
+        match self.motion_sensor.set_power_control(power_control) {
+            Ok(_) => Ok(Response::new(Empty {})),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
 }
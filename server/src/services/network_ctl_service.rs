@@ -1,13 +1,60 @@
+use mecha_network_ctl::connectivity::{
+    Connectivity, ConnectivityMonitor, ConnectivityReport as WifiConnectivityReport,
+};
+use mecha_network_ctl::hotspot::{Band, HotspotClient as WifiHotspotClient, HotspotConfig};
+use mecha_network_ctl::interfaces::WirelessInterfaces;
+use mecha_network_ctl::ipconfig::{
+    DhcpLease as IpDhcpLease, IpConfig, IpConfigurator, IpMethod as IpConfigMethod, IpStatus,
+};
+use mecha_network_ctl::links::{Link, LinkKind, Links};
+use mecha_network_ctl::netlink::InterfaceAddress;
+use mecha_network_ctl::profile::{self, NetworkProfile, Security, SecurityKind};
+use mecha_network_ctl::rfkill::{Radio as RfkillRadio, RadioType as RfkillRadioType, Rfkill};
+use mecha_network_ctl::scan::ScannedNetwork;
+use mecha_network_ctl::session::{ScanResult as WifiScanResult, WifiEvent};
+use mecha_network_ctl::status::WifiStatus;
+use mecha_network_ctl::wifi_uri::WifiUri;
 use mecha_network_ctl::wireless_network::WirelessNetworkControl;
+use mecha_network_ctl::wps::{
+    WpsMethod as WifiWpsMethod, WpsProgress as WifiWpsProgress, DEFAULT_WPS_TIMEOUT,
+};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 
+// owns the supplicant sessions of the wireless interfaces, shared by every request
 #[derive(Default)]
-pub struct NetworkManager {}
+pub struct NetworkManager {
+    pub interfaces: WirelessInterfaces,
+    // settings of hotspots that requests leave out
+    pub hotspot: HotspotConfig,
+    pub rfkill: Rfkill,
+    // layer 3 configuration of every interface, not only the wireless ones
+    pub ip_config: IpConfigurator,
+    pub links: Links,
+    // periodic internet check, started by the server
+    pub connectivity: ConnectivityMonitor,
+}
 
 const NETWORK_CONNECT_SUCCESS_MESSAGE: &str = "WiFi connection successful";
 const NETWORK_CONNECT_FAILURE_MESSAGE: &str = "WiFi connection failed";
 const NETWORK_REMOVAL_SUCCESS_MESSAGE: &str = "WiFi network removed successfully";
 const NETWORK_REMOVAL_FAILURE_MESSAGE: &str = "WiFi network removal failed";
+const NETWORK_DISCONNECT_SUCCESS_MESSAGE: &str = "WiFi disconnected";
+const NETWORK_DISCONNECT_FAILURE_MESSAGE: &str = "WiFi disconnect failed";
+const NETWORK_RECONNECT_SUCCESS_MESSAGE: &str = "WiFi reconnecting";
+const NETWORK_RECONNECT_FAILURE_MESSAGE: &str = "WiFi reconnect failed";
+const NETWORK_SAVE_SUCCESS_MESSAGE: &str = "WiFi network saved";
+const NETWORK_SAVE_FAILURE_MESSAGE: &str = "WiFi network save failed";
+const NETWORK_UPDATE_SUCCESS_MESSAGE: &str = "WiFi network updated";
+const NETWORK_UPDATE_FAILURE_MESSAGE: &str = "WiFi network update failed";
+const HOTSPOT_START_SUCCESS_MESSAGE: &str = "Hotspot started";
+const HOTSPOT_START_FAILURE_MESSAGE: &str = "Hotspot start failed";
+const HOTSPOT_STOP_SUCCESS_MESSAGE: &str = "Hotspot stopped";
+const HOTSPOT_STOP_FAILURE_MESSAGE: &str = "Hotspot stop failed";
+const IP_CONFIG_SUCCESS_MESSAGE: &str = "IP configuration applied";
+const IP_CONFIG_FAILURE_MESSAGE: &str = "IP configuration failed";
 
 #[allow(non_snake_case)]
 pub mod networkmanager {
@@ -16,12 +63,346 @@ pub mod networkmanager {
 
 pub use networkmanager::{
     network_manager_service_server::{NetworkManagerService, NetworkManagerServiceServer},
-    Empty, NetworkResult, RemoveNetworkRequest, RemoveNetworkResponse, ScanResult, ScanResults,
-    WifiConnectRequest, WifiConnectResponse, WifiStatusResponse,
+    AutoConnectRequest, Bss, ConnectivityReport, ConnectivityState, DhcpLease, EapCredentials,
+    Empty, HotspotClient, HotspotClients, HotspotRequest, HotspotResponse, InterfaceCounters,
+    InterfaceKind, InterfaceRequest, InterfaceStateRequest, IpConfigRequest, IpConfigResponse,
+    IpConfigStatus, IpMethod, NetworkInterface, NetworkInterfaces, NetworkPriorityRequest,
+    NetworkResult, Radio, RadioRequest, RadioType, Radios, RemoveNetworkRequest,
+    RemoveNetworkResponse, SaveNetworkRequest, SavedNetwork, SavedNetworkRequest,
+    SavedNetworkResponse, SavedNetworks, ScanResult, ScanResultV2, ScanResults, ScanResultsV2,
+    UpdatePassphraseRequest, WifiBand, WifiConnectRequest, WifiConnectResponse,
+    WifiEvent as WifiEventProto, WifiEventKind, WifiSecurity, WifiStatusResponse, WifiUriRequest,
+    WirelessInterfaces as WirelessInterfacesProto, WpsMethod, WpsProgress, WpsRequest, WpsStage,
 };
 
 use self::networkmanager::NetworkResults;
 
+fn scan_result_to_proto(wifi: WifiScanResult) -> ScanResult {
+    ScanResult {
+        mac: wifi.mac,
+        frequency: wifi.frequency,
+        signal: wifi.signal as i32,
+        flags: wifi.flags,
+        name: wifi.name,
+    }
+}
+
+fn scanned_network_to_proto(network: ScannedNetwork) -> ScanResultV2 {
+    let bss = network
+        .bss
+        .iter()
+        .map(|bss| Bss {
+            bssid: bss.bssid.clone(),
+            frequency: bss.frequency,
+            band: bss.band.map(|band| band.to_string()).unwrap_or_default(),
+            channel: bss.channel.unwrap_or_default(),
+            signal: bss.signal,
+            quality: bss.quality as u32,
+            wps: bss.wps,
+            ess: bss.ess,
+            flags: bss.flags.clone(),
+        })
+        .collect();
+    ScanResultV2 {
+        security: network.security().to_string(),
+        signal: network.signal(),
+        quality: network.quality() as u32,
+        bands: network
+            .bands()
+            .iter()
+            .map(|band| band.to_string())
+            .collect(),
+        wps: network.wps(),
+        saved: network.saved,
+        current: network.current,
+        bss,
+        ssid: network.ssid,
+    }
+}
+
+fn status_to_proto(status: WifiStatus) -> WifiStatusResponse {
+    let connected = status.connected();
+    let addresses = |addresses: Vec<InterfaceAddress>| {
+        addresses
+            .iter()
+            .map(|address| address.to_string())
+            .collect()
+    };
+    let default_route = status.default_route.unwrap_or_default();
+    WifiStatusResponse {
+        wifi_on: status.enabled,
+        interface: status.interface,
+        wpa_state: status.wpa_state.unwrap_or_default(),
+        connected,
+        ssid: status.ssid.unwrap_or_default(),
+        bssid: status.bssid.unwrap_or_default(),
+        frequency: status.frequency.unwrap_or_default(),
+        channel: status.channel.unwrap_or_default(),
+        rssi: status.rssi.unwrap_or_default(),
+        link_speed: status.link_speed.unwrap_or_default(),
+        key_mgmt: status.key_mgmt.unwrap_or_default(),
+        ipv4_addresses: addresses(status.ipv4_addresses),
+        ipv6_addresses: addresses(status.ipv6_addresses),
+        gateway: default_route
+            .gateway
+            .map(|gateway| gateway.to_string())
+            .unwrap_or_default(),
+        default_route_interface: default_route.interface.unwrap_or_default(),
+    }
+}
+
+fn event_to_proto(event: WifiEvent) -> WifiEventProto {
+    let mut proto = WifiEventProto::default();
+    let kind = match event {
+        WifiEvent::Connected { ssid, bssid } => {
+            proto.ssid = ssid.unwrap_or_default();
+            proto.bssid = bssid.unwrap_or_default();
+            WifiEventKind::WifiConnected
+        }
+        WifiEvent::Disconnected => WifiEventKind::WifiDisconnected,
+        WifiEvent::ScanResults(results) => {
+            proto.scan_results = results.into_iter().map(scan_result_to_proto).collect();
+            WifiEventKind::WifiScanResults
+        }
+        WifiEvent::AuthFailure { ssid, reason } => {
+            proto.ssid = ssid.unwrap_or_default();
+            proto.reason = reason;
+            WifiEventKind::WifiAuthFailure
+        }
+        WifiEvent::WrongKey { ssid } => {
+            proto.ssid = ssid.unwrap_or_default();
+            WifiEventKind::WifiWrongKey
+        }
+        WifiEvent::NetworkNotFound { ssid } => {
+            proto.ssid = ssid.unwrap_or_default();
+            WifiEventKind::WifiNetworkNotFound
+        }
+        WifiEvent::Wps(event) => {
+            proto.reason = WifiWpsProgress::from(event).to_string();
+            WifiEventKind::WifiWps
+        }
+    };
+    proto.set_kind(kind);
+    proto
+}
+
+fn eap_from_proto(eap: Option<EapCredentials>) -> profile::EapCredentials {
+    let eap = eap.unwrap_or_default();
+    let optional = |value: String| Some(value).filter(|value| !value.is_empty());
+    profile::EapCredentials {
+        identity: eap.identity,
+        anonymous_identity: optional(eap.anonymous_identity),
+        password: optional(eap.password),
+        ca_cert: optional(eap.ca_cert),
+        client_cert: optional(eap.client_cert),
+        private_key: optional(eap.private_key),
+        private_key_password: optional(eap.private_key_password),
+        domain_suffix_match: optional(eap.domain_suffix_match),
+    }
+}
+
+// `None` when the security is taken from the scan results
+fn profile_from_proto(
+    ssid: &str,
+    psk: &str,
+    security: i32,
+    hidden: bool,
+    eap: Option<EapCredentials>,
+) -> Option<NetworkProfile> {
+    let kind = match WifiSecurity::from_i32(security).unwrap_or(WifiSecurity::SecurityAuto) {
+        WifiSecurity::SecurityAuto => return None,
+        WifiSecurity::SecurityOpen => SecurityKind::Open,
+        WifiSecurity::SecurityWpaPsk => SecurityKind::WpaPsk,
+        WifiSecurity::SecuritySae => SecurityKind::Sae,
+        WifiSecurity::SecurityWpaPskSae => SecurityKind::WpaPskSae,
+        WifiSecurity::SecurityEapPeap => SecurityKind::Peap,
+        WifiSecurity::SecurityEapTls => SecurityKind::Tls,
+    };
+    let security = Security::from_kind(kind, psk, eap_from_proto(eap));
+    Some(NetworkProfile::new(ssid, security).hidden(hidden))
+}
+
+fn radio_to_proto(radio: RfkillRadio) -> Radio {
+    Radio {
+        id: radio.id,
+        device: radio.device,
+        kind: radio.kind,
+        soft_blocked: radio.soft_blocked,
+        hard_blocked: radio.hard_blocked,
+    }
+}
+
+fn hotspot_from_proto(request: &HotspotRequest, defaults: &HotspotConfig) -> HotspotConfig {
+    let band = match WifiBand::from_i32(request.band).unwrap_or(WifiBand::Band24Ghz) {
+        WifiBand::Band24Ghz => Band::TwoPointFourGhz,
+        WifiBand::Band5Ghz => Band::FiveGhz,
+    };
+    let mut config = defaults.clone();
+    if !request.ssid.is_empty() {
+        config.ssid = request.ssid.clone();
+    }
+    config.passphrase = Some(request.passphrase.clone()).filter(|psk| !psk.is_empty());
+    config.band = band;
+    config.channel = match request.channel {
+        0 => band.default_channel(),
+        channel => channel,
+    };
+    config.captive = !request.dhcp_only;
+    config
+}
+
+fn ip_config_from_proto(request: &IpConfigRequest) -> anyhow::Result<IpConfig> {
+    let method = match IpMethod::from_i32(request.method).unwrap_or(IpMethod::Dhcp) {
+        IpMethod::Dhcp => IpConfigMethod::Dhcp,
+        IpMethod::Static => IpConfigMethod::Static,
+    };
+    IpConfig::from_args(
+        method,
+        &request.addresses,
+        &request.gateways,
+        &request.dns,
+        &request.search_domains,
+    )
+}
+
+fn lease_to_proto(lease: IpDhcpLease) -> DhcpLease {
+    let to_string = |ip: Option<std::net::IpAddr>| ip.map(|ip| ip.to_string()).unwrap_or_default();
+    DhcpLease {
+        address: lease
+            .address
+            .map(|address| address.to_string())
+            .unwrap_or_default(),
+        router: to_string(lease.router),
+        server: to_string(lease.server),
+        dns: lease.dns.iter().map(|dns| dns.to_string()).collect(),
+        domain: lease.domain.unwrap_or_default(),
+        lifetime: lease.lifetime.unwrap_or_default(),
+    }
+}
+
+fn ip_status_to_proto(status: IpStatus) -> IpConfigStatus {
+    let mut proto = IpConfigStatus {
+        interface: status.interface,
+        managed: status.method.is_some(),
+        addresses: status
+            .addresses
+            .iter()
+            .map(|address| address.to_string())
+            .collect(),
+        gateways: status.gateways.iter().map(|ip| ip.to_string()).collect(),
+        dns: status.dns.iter().map(|ip| ip.to_string()).collect(),
+        lease: status.lease.map(lease_to_proto),
+        ..Default::default()
+    };
+    proto.set_method(match status.method {
+        Some(IpConfigMethod::Static) => IpMethod::Static,
+        _ => IpMethod::Dhcp,
+    });
+    proto
+}
+
+fn link_to_proto(link: Link) -> NetworkInterface {
+    let kind = match link.kind {
+        LinkKind::Ethernet => InterfaceKind::InterfaceEthernet,
+        LinkKind::Wireless => InterfaceKind::InterfaceWireless,
+        LinkKind::UsbGadget => InterfaceKind::InterfaceUsbGadget,
+        LinkKind::Bridge => InterfaceKind::InterfaceBridge,
+        LinkKind::Loopback => InterfaceKind::InterfaceLoopback,
+        LinkKind::Virtual => InterfaceKind::InterfaceVirtual,
+        LinkKind::Other => InterfaceKind::InterfaceOther,
+    };
+    let counters = link.counters;
+    let mut proto = NetworkInterface {
+        name: link.name,
+        index: link.index,
+        mac: link.mac.unwrap_or_default(),
+        mtu: link.mtu,
+        up: link.up,
+        operstate: link.operstate,
+        carrier: link.carrier.unwrap_or_default(),
+        speed: link.speed.unwrap_or_default(),
+        duplex: link.duplex.unwrap_or_default(),
+        counters: Some(InterfaceCounters {
+            rx_bytes: counters.rx_bytes,
+            tx_bytes: counters.tx_bytes,
+            rx_packets: counters.rx_packets,
+            tx_packets: counters.tx_packets,
+            rx_errors: counters.rx_errors,
+            tx_errors: counters.tx_errors,
+            rx_dropped: counters.rx_dropped,
+            tx_dropped: counters.tx_dropped,
+        }),
+        addresses: link
+            .addresses
+            .iter()
+            .map(|address| address.to_string())
+            .collect(),
+        ..Default::default()
+    };
+    proto.set_kind(kind);
+    proto
+}
+
+fn wps_progress_to_proto(progress: WifiWpsProgress) -> WpsProgress {
+    let mut proto = WpsProgress::default();
+    let stage = match progress {
+        WifiWpsProgress::Started { pin } => {
+            proto.pin = pin.unwrap_or_default();
+            WpsStage::WpsStarted
+        }
+        WifiWpsProgress::Active => WpsStage::WpsActive,
+        WifiWpsProgress::CredentialsReceived => WpsStage::WpsCredentialsReceived,
+        WifiWpsProgress::Succeeded => WpsStage::WpsSucceeded,
+        WifiWpsProgress::Connected { ssid } => {
+            proto.ssid = ssid.unwrap_or_default();
+            WpsStage::WpsConnected
+        }
+        WifiWpsProgress::Failed { reason } => {
+            proto.reason = reason;
+            WpsStage::WpsFailed
+        }
+    };
+    proto.set_stage(stage);
+    proto
+}
+
+fn connectivity_to_proto(report: WifiConnectivityReport) -> ConnectivityReport {
+    let state = match report.state {
+        Connectivity::None => ConnectivityState::ConnectivityNone,
+        Connectivity::LocalOnly => ConnectivityState::ConnectivityLocalOnly,
+        Connectivity::DnsFailure => ConnectivityState::ConnectivityDnsFailure,
+        Connectivity::CaptivePortal => ConnectivityState::ConnectivityCaptivePortal,
+        Connectivity::Full => ConnectivityState::ConnectivityFull,
+    };
+    let mut proto = ConnectivityReport {
+        default_route: report.default_route,
+        dns_resolved: report.dns_resolved,
+        tcp_reachable: report.tcp_reachable,
+        http_status: report
+            .http
+            .as_ref()
+            .map(|http| http.status as u32)
+            .unwrap_or_default(),
+        portal_url: report.portal_url().unwrap_or_default().to_string(),
+        latency_ms: report
+            .latency
+            .map(|latency| latency.as_millis() as u32)
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+    proto.set_state(state);
+    proto
+}
+
+fn hotspot_client_to_proto(client: WifiHotspotClient) -> HotspotClient {
+    HotspotClient {
+        mac: client.mac,
+        ip: client.ip.map(|ip| ip.to_string()).unwrap_or_default(),
+        hostname: client.hostname.unwrap_or_default(),
+        connected_time: client.connected_time.unwrap_or_default(),
+    }
+}
+
 trait ResponseMessage {
     fn set_success(&mut self, success: bool);
     fn set_message(&mut self, message: String);
@@ -47,10 +428,53 @@ impl ResponseMessage for RemoveNetworkResponse {
     }
 }
 
+impl ResponseMessage for HotspotResponse {
+    fn set_success(&mut self, success: bool) {
+        self.success = success;
+    }
+
+    fn set_message(&mut self, message: String) {
+        self.message = message;
+    }
+}
+
+impl ResponseMessage for SavedNetworkResponse {
+    fn set_success(&mut self, success: bool) {
+        self.success = success;
+    }
+
+    fn set_message(&mut self, message: String) {
+        self.message = message;
+    }
+}
+
+impl ResponseMessage for IpConfigResponse {
+    fn set_success(&mut self, success: bool) {
+        self.success = success;
+    }
+
+    fn set_message(&mut self, message: String) {
+        self.message = message;
+    }
+}
+
 impl NetworkManager {
+    // control of the requested interface, the default one when `interface` is empty
+    fn wifi(&self, interface: &str) -> anyhow::Result<WirelessNetworkControl> {
+        self.interfaces.control(Some(interface))
+    }
+
+    // any interface for IP configuration, the default wireless one when empty
+    fn ip_interface(&self, interface: &str) -> String {
+        match interface {
+            "" => self.interfaces.default_interface(),
+            interface => interface.to_string(),
+        }
+    }
+
     fn handle_response<T: ResponseMessage>(
         &self,
-        result: Result<(), &str>,
+        result: Result<(), String>,
         response: &mut T,
         success_message: &str,
         failure_message: &str,
@@ -60,48 +484,136 @@ impl NetworkManager {
                 response.set_success(true);
                 response.set_message(success_message.to_string());
             }
-            Err(_) => {
+            Err(reason) => {
                 response.set_success(false);
-                response.set_message(failure_message.to_string());
+                response.set_message(format!("{}: {}", failure_message, reason));
             }
         }
     }
 
-    async fn connect_to_wifi(&self, ssid: &str, psk: &str) -> Result<(), &str> {
-        let connect_wireless_network_list =
-            WirelessNetworkControl::connect_wireless_network(ssid, psk).await;
+    async fn connect_to_wifi(&self, request: &WifiConnectRequest) -> Result<(), String> {
+        let wifi = self
+            .wifi(&request.interface)
+            .map_err(|err| err.to_string())?;
+        let profile = profile_from_proto(
+            &request.ssid,
+            &request.psk,
+            request.security,
+            request.hidden,
+            request.eap.clone(),
+        );
+        let connect_wireless_network_list = match profile {
+            Some(profile) => wifi.connect_profile(&profile).await,
+            None if request.hidden => {
+                match wifi
+                    .resolve_profile(&request.ssid, &request.psk, true)
+                    .await
+                {
+                    Ok(profile) => wifi.connect_profile(&profile).await,
+                    Err(err) => Err(err),
+                }
+            }
+            None => {
+                wifi.connect_wireless_network(&request.ssid, &request.psk)
+                    .await
+            }
+        };
 
         match connect_wireless_network_list {
             Ok(_) => Ok(()),
-            Err(_) => Err(NETWORK_CONNECT_FAILURE_MESSAGE),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    async fn save_wifi_network(&self, request: &SaveNetworkRequest) -> anyhow::Result<usize> {
+        let wifi = self.wifi(&request.interface)?;
+        let profile = profile_from_proto(
+            &request.ssid,
+            &request.psk,
+            request.security,
+            request.hidden,
+            request.eap.clone(),
+        );
+        let profile = match profile {
+            Some(profile) => profile,
+            None => {
+                wifi.resolve_profile(&request.ssid, &request.psk, request.hidden)
+                    .await?
+            }
+        };
+        wifi.save_network(&profile, Some(request.priority), !request.manual_connect)
+            .await
+    }
+
+    fn saved_network_response(
+        &self,
+        result: anyhow::Result<()>,
+        success_message: &str,
+        failure_message: &str,
+    ) -> Response<SavedNetworkResponse> {
+        let mut saved_network_response = SavedNetworkResponse::default();
+        self.handle_response(
+            result.map_err(|err| err.to_string()),
+            &mut saved_network_response,
+            success_message,
+            failure_message,
+        );
+        Response::new(saved_network_response)
+    }
+
+    // Drop the connection to `network_id`, leaving it saved
+    async fn disconnect_wifi_network(
+        &self,
+        interface: &str,
+        network_id: usize,
+    ) -> Result<(), String> {
+        let wifi = self.wifi(interface).map_err(|err| err.to_string())?;
+        let saved = wifi.saved_networks().await.map_err(|err| err.to_string())?;
+        match saved
+            .iter()
+            .find(|network| network.network_id == network_id)
+        {
+            Some(network) if network.current => wifi
+                .disconnect_wireless_network()
+                .await
+                .map_err(|err| err.to_string()),
+            Some(network) => Err(format!("wifi network {} is not connected", network.ssid)),
+            None => Err(format!("no saved wifi network {}", network_id)),
         }
     }
 
-    async fn remove_wifi_network(&self, network_id: usize) -> Result<(), &str> {
-        let remove_network = WirelessNetworkControl::remove_wireless_network(network_id).await;
+    async fn remove_wifi_network(&self, interface: &str, network_id: usize) -> Result<(), String> {
+        let wifi = self.wifi(interface).map_err(|err| err.to_string())?;
+        let remove_network = wifi.remove_wireless_network(network_id).await;
 
         match remove_network {
             Ok(_) => Ok(()),
-            Err(_) => Err(NETWORK_REMOVAL_FAILURE_MESSAGE),
+            Err(err) => Err(err.to_string()),
         }
     }
 }
 
 #[tonic::async_trait]
 impl NetworkManagerService for NetworkManager {
+    type WatchWifiEventsStream = ReceiverStream<Result<WifiEventProto, Status>>;
+    type WatchConnectivityStream = ReceiverStream<Result<ConnectivityReport, Status>>;
+    type ConnectWpsStream = ReceiverStream<Result<WpsProgress, Status>>;
+
     async fn scan_wireless_network(
         &self,
-        _request: Request<Empty>,
+        request: Request<InterfaceRequest>,
     ) -> Result<Response<ScanResults>, Status> {
         // Implement your async get_wifi logic here
         let mut scan_results = ScanResults::default();
+        let wifi = self
+            .wifi(&request.into_inner().interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
 
         log::info!("Starting All Wifi List Function");
-        let wifi_service = WirelessNetworkControl::new();
 
         //get wifi list from mecha_edge_sdk
         // Attempt to get the wifi list from mecha_edge_sdk and handle errors.
-        let wireless_network_list = match wifi_service.scan_wireless_network().await {
+        let wireless_network_list = match wifi.scan_wireless_network().await {
             Ok(wireless_network_list) => wireless_network_list,
             Err(err) => {
                 // Convert the error into a gRPC Status and return it.
@@ -110,14 +622,7 @@ impl NetworkManagerService for NetworkManager {
         };
         //add wifi list to scan_results
         for wifi in wireless_network_list {
-            let scan_result = ScanResult {
-                mac: wifi.mac,
-                frequency: wifi.frequency,
-                signal: wifi.signal as i32,
-                flags: wifi.flags,
-                name: wifi.name,
-            };
-            scan_results.results.push(scan_result);
+            scan_results.results.push(scan_result_to_proto(wifi));
         }
 
         Ok(Response::new(scan_results))
@@ -131,8 +636,7 @@ impl NetworkManagerService for NetworkManager {
         let request_data = request.into_inner();
 
         self.handle_response(
-            self.connect_to_wifi(&request_data.ssid, &request_data.psk)
-                .await,
+            self.connect_to_wifi(&request_data).await,
             &mut wifi_connect_response,
             NETWORK_CONNECT_SUCCESS_MESSAGE,
             NETWORK_CONNECT_FAILURE_MESSAGE,
@@ -141,15 +645,59 @@ impl NetworkManagerService for NetworkManager {
         Ok(Response::new(wifi_connect_response))
     }
 
+    async fn join_wifi_uri(
+        &self,
+        request: Request<WifiUriRequest>,
+    ) -> Result<Response<WifiConnectResponse>, Status> {
+        let mut wifi_connect_response = WifiConnectResponse::default();
+        let request_data = request.into_inner();
+
+        let result = match self.wifi(&request_data.interface) {
+            Ok(wifi) => wifi
+                .connect_uri(&request_data.uri)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        self.handle_response(
+            result,
+            &mut wifi_connect_response,
+            NETWORK_CONNECT_SUCCESS_MESSAGE,
+            NETWORK_CONNECT_FAILURE_MESSAGE,
+        );
+
+        Ok(Response::new(wifi_connect_response))
+    }
+
+    // the old name of RemoveWirelessNetwork, it never disconnected
     async fn disconnect_wireless_network(
         &self,
         request: Request<RemoveNetworkRequest>,
+    ) -> Result<Response<RemoveNetworkResponse>, Status> {
+        let mut disconnect_network_response = RemoveNetworkResponse::default();
+        let request_data = request.into_inner();
+
+        self.handle_response(
+            self.disconnect_wifi_network(&request_data.interface, request_data.network_id as usize)
+                .await,
+            &mut disconnect_network_response,
+            NETWORK_DISCONNECT_SUCCESS_MESSAGE,
+            NETWORK_DISCONNECT_FAILURE_MESSAGE,
+        );
+
+        Ok(Response::new(disconnect_network_response))
+    }
+
+    async fn remove_wireless_network(
+        &self,
+        request: Request<RemoveNetworkRequest>,
     ) -> Result<Response<RemoveNetworkResponse>, Status> {
         let mut remove_network_response = RemoveNetworkResponse::default();
         let request_data = request.into_inner();
 
         self.handle_response(
-            self.remove_wifi_network(request_data.network_id as usize)
+            self.remove_wifi_network(&request_data.interface, request_data.network_id as usize)
                 .await,
             &mut remove_network_response,
             NETWORK_REMOVAL_SUCCESS_MESSAGE,
@@ -159,23 +707,40 @@ impl NetworkManagerService for NetworkManager {
         Ok(Response::new(remove_network_response))
     }
 
+    async fn scan_wireless_network_v2(
+        &self,
+        request: Request<InterfaceRequest>,
+    ) -> Result<Response<ScanResultsV2>, Status> {
+        let wifi = self
+            .wifi(&request.into_inner().interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        match wifi.scan_networks().await {
+            Ok(networks) => Ok(Response::new(ScanResultsV2 {
+                networks: networks.into_iter().map(scanned_network_to_proto).collect(),
+            })),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
     async fn scan_known_wireless_network(
         &self,
-        _request: Request<Empty>,
+        request: Request<InterfaceRequest>,
     ) -> Result<Response<NetworkResults>, Status> {
         // Implement your async get_known_wifi logic here
         let mut scan_results = NetworkResults::default();
+        let wifi = self
+            .wifi(&request.into_inner().interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
         log::info!("Starting Known Wifi List Function");
 
         //get wifi list from mecha_edge_sdk
-        let wireless_network_list =
-            match WirelessNetworkControl::get_known_wireless_networks().await {
-                Ok(wireless_network_list) => wireless_network_list,
-                Err(err) => {
-                    // Convert the error into a gRPC Status and return it.
-                    return Err(Status::from_error(err.into()));
-                }
-            };
+        let wireless_network_list = match wifi.get_known_wireless_networks().await {
+            Ok(wireless_network_list) => wireless_network_list,
+            Err(err) => {
+                // Convert the error into a gRPC Status and return it.
+                return Err(Status::from_error(err.into()));
+            }
+        };
         //add wifi list to scan_results
         for wifi in wireless_network_list {
             let scan_result = NetworkResult {
@@ -191,23 +756,29 @@ impl NetworkManagerService for NetworkManager {
 
     async fn get_wifi_status(
         &self,
-        _request: Request<Empty>,
+        request: Request<InterfaceRequest>,
     ) -> Result<Response<WifiStatusResponse>, Status> {
         // Implement your logic to check Wi-Fi status here
-        let wifi_on = WirelessNetworkControl::wireless_network_status().await; // This should return true if Wi-Fi is on, false otherwise.
-
-        let wifi_status_response = WifiStatusResponse { wifi_on };
+        let wifi = self
+            .wifi(&request.into_inner().interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let status = match wifi.wifi_status().await {
+            Ok(status) => status,
+            Err(err) => return Err(Status::from_error(err.into())),
+        };
 
-        Ok(Response::new(wifi_status_response))
+        Ok(Response::new(status_to_proto(status)))
     }
 
     async fn get_current_network(
         &self,
-        _request: Request<Empty>,
+        request: Request<InterfaceRequest>,
     ) -> Result<Response<ScanResult>, Status> {
         // Implement your logic to get current Wi-Fi network here
-        let wifi_service = WirelessNetworkControl::new();
-        let current_network = match wifi_service.current_wireless_network().await {
+        let wifi = self
+            .wifi(&request.into_inner().interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let current_network = match wifi.current_wireless_network().await {
             Ok(current_network) => current_network,
             Err(err) => {
                 // Convert the error into a gRPC Status and return it.
@@ -215,14 +786,541 @@ impl NetworkManagerService for NetworkManager {
             }
         };
 
-        let scan_result = ScanResult {
-            mac: current_network.mac,
-            frequency: current_network.frequency,
-            signal: current_network.signal as i32,
-            flags: current_network.flags,
-            name: current_network.name,
+        Ok(Response::new(scan_result_to_proto(current_network)))
+    }
+
+    async fn watch_wifi_events(
+        &self,
+        request: Request<InterfaceRequest>,
+    ) -> Result<Response<Self::WatchWifiEventsStream>, Status> {
+        let wifi = self
+            .wifi(&request.into_inner().interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let mut events = match wifi.watch_events().await {
+            Ok(events) => events,
+            Err(err) => return Err(Status::from_error(err.into())),
+        };
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event_to_proto(event),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        log::warn!("wifi event watcher missed {} events", missed);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                // the client went away
+                if sender.send(Ok(event)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn connect_wps(
+        &self,
+        request: Request<WpsRequest>,
+    ) -> Result<Response<Self::ConnectWpsStream>, Status> {
+        let request_data = request.into_inner();
+        let wifi = self
+            .wifi(&request_data.interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let method = match WpsMethod::from_i32(request_data.method) {
+            Some(WpsMethod::WpsPin) => {
+                WifiWpsMethod::Pin(Some(request_data.pin).filter(|pin| !pin.is_empty()))
+            }
+            _ => WifiWpsMethod::PushButton,
+        };
+        let bssid = Some(request_data.bssid.as_str()).filter(|bssid| !bssid.is_empty());
+        let timeout = match request_data.timeout {
+            0 => DEFAULT_WPS_TIMEOUT,
+            timeout => Duration::from_secs(timeout as u64),
+        };
+        let mut progress = match wifi.connect_wps(&method, bssid, timeout).await {
+            Ok(progress) => progress,
+            Err(err) => return Err(Status::from_error(err.into())),
+        };
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(async move {
+            while let Some(step) = progress.recv().await {
+                // WPS carries on when the client went away
+                if sender.send(Ok(wps_progress_to_proto(step))).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn start_hotspot(
+        &self,
+        request: Request<HotspotRequest>,
+    ) -> Result<Response<HotspotResponse>, Status> {
+        let request_data = request.into_inner();
+        let wifi = self
+            .wifi(&request_data.interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let config = hotspot_from_proto(&request_data, &self.hotspot);
+        let mut hotspot_response = HotspotResponse::default();
+
+        let result = match wifi.start_hotspot(&config).await {
+            Ok(address) => {
+                hotspot_response.address = address.to_string();
+                hotspot_response.uri = WifiUri::for_hotspot(&config).to_string();
+                Ok(())
+            }
+            Err(err) => Err(err.to_string()),
+        };
+        self.handle_response(
+            result,
+            &mut hotspot_response,
+            HOTSPOT_START_SUCCESS_MESSAGE,
+            HOTSPOT_START_FAILURE_MESSAGE,
+        );
+
+        Ok(Response::new(hotspot_response))
+    }
+
+    async fn stop_hotspot(
+        &self,
+        request: Request<InterfaceRequest>,
+    ) -> Result<Response<HotspotResponse>, Status> {
+        let wifi = self
+            .wifi(&request.into_inner().interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let mut hotspot_response = HotspotResponse::default();
+
+        self.handle_response(
+            wifi.stop_hotspot().await.map_err(|err| err.to_string()),
+            &mut hotspot_response,
+            HOTSPOT_STOP_SUCCESS_MESSAGE,
+            HOTSPOT_STOP_FAILURE_MESSAGE,
+        );
+
+        Ok(Response::new(hotspot_response))
+    }
+
+    async fn list_clients(
+        &self,
+        request: Request<InterfaceRequest>,
+    ) -> Result<Response<HotspotClients>, Status> {
+        let wifi = self
+            .wifi(&request.into_inner().interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        match wifi.hotspot_clients().await {
+            Ok(clients) => Ok(Response::new(HotspotClients {
+                clients: clients.into_iter().map(hotspot_client_to_proto).collect(),
+            })),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn add_wireless_network(
+        &self,
+        request: Request<SaveNetworkRequest>,
+    ) -> Result<Response<SavedNetworkResponse>, Status> {
+        let request_data = request.into_inner();
+        let (result, network_id) = match self.save_wifi_network(&request_data).await {
+            Ok(network_id) => (Ok(()), network_id as i32),
+            Err(err) => (Err(err), 0),
+        };
+        let mut response = self.saved_network_response(
+            result,
+            NETWORK_SAVE_SUCCESS_MESSAGE,
+            NETWORK_SAVE_FAILURE_MESSAGE,
+        );
+        response.get_mut().network_id = network_id;
+        Ok(response)
+    }
+
+    async fn list_saved_networks(
+        &self,
+        request: Request<InterfaceRequest>,
+    ) -> Result<Response<SavedNetworks>, Status> {
+        let wifi = self
+            .wifi(&request.into_inner().interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let saved_networks = match wifi.saved_networks().await {
+            Ok(saved_networks) => saved_networks,
+            Err(err) => return Err(Status::from_error(err.into())),
         };
+        let networks = saved_networks
+            .into_iter()
+            .map(|network| SavedNetwork {
+                network_id: network.network_id as i32,
+                ssid: network.ssid,
+                priority: network.priority,
+                auto_connect: network.auto_connect,
+                current: network.current,
+            })
+            .collect();
+        Ok(Response::new(SavedNetworks { networks }))
+    }
+
+    async fn update_network_passphrase(
+        &self,
+        request: Request<UpdatePassphraseRequest>,
+    ) -> Result<Response<SavedNetworkResponse>, Status> {
+        let request_data = request.into_inner();
+        let wifi = self
+            .wifi(&request_data.interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let result = wifi
+            .update_network_passphrase(&request_data.ssid, &request_data.psk)
+            .await;
+        Ok(self.saved_network_response(
+            result,
+            NETWORK_UPDATE_SUCCESS_MESSAGE,
+            NETWORK_UPDATE_FAILURE_MESSAGE,
+        ))
+    }
+
+    async fn set_network_priority(
+        &self,
+        request: Request<NetworkPriorityRequest>,
+    ) -> Result<Response<SavedNetworkResponse>, Status> {
+        let request_data = request.into_inner();
+        let wifi = self
+            .wifi(&request_data.interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let result = wifi
+            .set_network_priority(&request_data.ssid, request_data.priority)
+            .await;
+        Ok(self.saved_network_response(
+            result,
+            NETWORK_UPDATE_SUCCESS_MESSAGE,
+            NETWORK_UPDATE_FAILURE_MESSAGE,
+        ))
+    }
+
+    async fn set_auto_connect(
+        &self,
+        request: Request<AutoConnectRequest>,
+    ) -> Result<Response<SavedNetworkResponse>, Status> {
+        let request_data = request.into_inner();
+        let wifi = self
+            .wifi(&request_data.interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let result = wifi
+            .set_auto_connect(&request_data.ssid, request_data.enabled)
+            .await;
+        Ok(self.saved_network_response(
+            result,
+            NETWORK_UPDATE_SUCCESS_MESSAGE,
+            NETWORK_UPDATE_FAILURE_MESSAGE,
+        ))
+    }
+
+    async fn remove_saved_network(
+        &self,
+        request: Request<SavedNetworkRequest>,
+    ) -> Result<Response<SavedNetworkResponse>, Status> {
+        let request_data = request.into_inner();
+        let wifi = self
+            .wifi(&request_data.interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let result = wifi.remove_saved_network(&request_data.ssid).await;
+        Ok(self.saved_network_response(
+            result,
+            NETWORK_REMOVAL_SUCCESS_MESSAGE,
+            NETWORK_REMOVAL_FAILURE_MESSAGE,
+        ))
+    }
+
+    async fn disconnect_wifi(
+        &self,
+        request: Request<InterfaceRequest>,
+    ) -> Result<Response<WifiConnectResponse>, Status> {
+        let wifi = self
+            .wifi(&request.into_inner().interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let mut wifi_connect_response = WifiConnectResponse::default();
+
+        self.handle_response(
+            wifi.disconnect_wireless_network()
+                .await
+                .map_err(|err| err.to_string()),
+            &mut wifi_connect_response,
+            NETWORK_DISCONNECT_SUCCESS_MESSAGE,
+            NETWORK_DISCONNECT_FAILURE_MESSAGE,
+        );
+
+        Ok(Response::new(wifi_connect_response))
+    }
+
+    async fn reconnect_wifi(
+        &self,
+        request: Request<InterfaceRequest>,
+    ) -> Result<Response<WifiConnectResponse>, Status> {
+        let wifi = self
+            .wifi(&request.into_inner().interface)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let mut wifi_connect_response = WifiConnectResponse::default();
+
+        self.handle_response(
+            wifi.reconnect_wireless_network()
+                .await
+                .map_err(|err| err.to_string()),
+            &mut wifi_connect_response,
+            NETWORK_RECONNECT_SUCCESS_MESSAGE,
+            NETWORK_RECONNECT_FAILURE_MESSAGE,
+        );
+
+        Ok(Response::new(wifi_connect_response))
+    }
+
+    async fn list_radios(&self, _request: Request<Empty>) -> Result<Response<Radios>, Status> {
+        match self.rfkill.list() {
+            Ok(radios) => Ok(Response::new(Radios {
+                radios: radios.into_iter().map(radio_to_proto).collect(),
+            })),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn set_radio_blocked(
+        &self,
+        request: Request<RadioRequest>,
+    ) -> Result<Response<Radios>, Status> {
+        let request_data = request.into_inner();
+        let radio_type = match RadioType::from_i32(request_data.radio) {
+            Some(RadioType::RadioAll) => RfkillRadioType::All,
+            Some(RadioType::RadioWifi) => RfkillRadioType::Wifi,
+            Some(RadioType::RadioBluetooth) => RfkillRadioType::Bluetooth,
+            Some(RadioType::RadioUnspecified) | None => {
+                return Err(Status::invalid_argument(format!(
+                    "unknown radio type {}",
+                    request_data.radio
+                )))
+            }
+        };
+        match self.rfkill.set_blocked(radio_type, request_data.blocked) {
+            Ok(radios) => Ok(Response::new(Radios {
+                radios: radios.into_iter().map(radio_to_proto).collect(),
+            })),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn set_ip_config(
+        &self,
+        request: Request<IpConfigRequest>,
+    ) -> Result<Response<IpConfigResponse>, Status> {
+        let request_data = request.into_inner();
+        let interface = self.ip_interface(&request_data.interface);
+        let result = ip_config_from_proto(&request_data)
+            .and_then(|config| self.ip_config.apply(&interface, &config));
+        let mut ip_config_response = IpConfigResponse::default();
+
+        self.handle_response(
+            result.map_err(|err| err.to_string()),
+            &mut ip_config_response,
+            IP_CONFIG_SUCCESS_MESSAGE,
+            IP_CONFIG_FAILURE_MESSAGE,
+        );
+
+        Ok(Response::new(ip_config_response))
+    }
+
+    async fn get_ip_config(
+        &self,
+        request: Request<InterfaceRequest>,
+    ) -> Result<Response<IpConfigStatus>, Status> {
+        let interface = self.ip_interface(&request.into_inner().interface);
+        match self.ip_config.status(&interface) {
+            Ok(status) => Ok(Response::new(ip_status_to_proto(status))),
+            Err(err) => Err(Status::invalid_argument(err.to_string())),
+        }
+    }
+
+    async fn list_interfaces(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<NetworkInterfaces>, Status> {
+        match self.links.list() {
+            Ok(links) => Ok(Response::new(NetworkInterfaces {
+                interfaces: links.into_iter().map(link_to_proto).collect(),
+            })),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn set_interface_state(
+        &self,
+        request: Request<InterfaceStateRequest>,
+    ) -> Result<Response<NetworkInterface>, Status> {
+        let request_data = request.into_inner();
+        match self.links.set_up(&request_data.interface, request_data.up) {
+            Ok(link) => Ok(Response::new(link_to_proto(link))),
+            Err(err) => Err(Status::failed_precondition(err.to_string())),
+        }
+    }
+
+    async fn check_connectivity(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<ConnectivityReport>, Status> {
+        match self.connectivity.check().await {
+            Ok(report) => Ok(Response::new(connectivity_to_proto(report))),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+
+    async fn watch_connectivity(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::WatchConnectivityStream>, Status> {
+        let mut reports = self.connectivity.subscribe();
+        let (sender, receiver) = mpsc::channel(16);
+        // the current state first, changes after it
+        if let Some(report) = self.connectivity.last_report() {
+            let _ = sender.send(Ok(connectivity_to_proto(report))).await;
+        }
+        tokio::spawn(async move {
+            loop {
+                let report = match reports.recv().await {
+                    Ok(report) => connectivity_to_proto(report),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        log::warn!("connectivity watcher missed {} reports", missed);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                // the client went away
+                if sender.send(Ok(report)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn list_wireless_interfaces(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<WirelessInterfacesProto>, Status> {
+        match self.interfaces.list() {
+            Ok(interfaces) => Ok(Response::new(WirelessInterfacesProto {
+                interfaces,
+                default_interface: self.interfaces.default_interface(),
+            })),
+            Err(err) => Err(Status::from_error(err.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mecha_network_ctl::test_support::FakeSupplicant;
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn test_network_manager() {
+        let dir = TempDir::new("network_manager").unwrap();
+        let supplicant = FakeSupplicant::start(dir.path(), "wlan0").unwrap();
+        supplicant.add_scan_result(
+            "aa:00:00:00:00:01",
+            2437,
+            -60,
+            "[WPA2-PSK-CCMP][WPS][ESS]",
+            "shop",
+        );
+        supplicant.add_scan_result("bb:00:00:00:00:01", 5180, -70, "[ESS]", "guest");
+        let manager = NetworkManager {
+            interfaces: WirelessInterfaces::new(dir.path(), Some("wlan0"))
+                .with_sys_class_net(dir.path().join("net"))
+                .with_run_dir(dir.path().join("run")),
+            ..Default::default()
+        };
+        let interface = || InterfaceRequest {
+            interface: String::from("wlan0"),
+        };
+
+        let scan = manager
+            .scan_wireless_network_v2(Request::new(interface()))
+            .await
+            .unwrap()
+            .into_inner();
+        let ssids: Vec<&str> = scan
+            .networks
+            .iter()
+            .map(|network| network.ssid.as_str())
+            .collect();
+        assert_eq!(ssids, vec!["shop", "guest"]);
+        assert!(scan.networks[0].wps && !scan.networks[0].saved);
+
+        let response = manager
+            .connect_wireless_network(Request::new(WifiConnectRequest {
+                interface: String::from("wlan0"),
+                ssid: String::from("shop"),
+                psk: String::from("password1"),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.success, "{}", response.message);
+
+        let saved = manager
+            .list_saved_networks(Request::new(interface()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(saved.networks.len(), 1);
+        assert_eq!(saved.networks[0].ssid, "shop");
+        assert!(saved.networks[0].current);
+
+        let response = manager
+            .join_wifi_uri(Request::new(WifiUriRequest {
+                interface: String::from("wlan0"),
+                uri: String::from("WIFI:T:nopass;S:guest;;"),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.success, "{}", response.message);
+        assert_eq!(supplicant.current_network(), Some(1));
+
+        // disconnecting keeps the network saved
+        let disconnect = |network_id| RemoveNetworkRequest {
+            interface: String::from("wlan0"),
+            network_id,
+        };
+        let response = manager
+            .disconnect_wireless_network(Request::new(disconnect(1)))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.success, "{}", response.message);
+        assert_eq!(supplicant.current_network(), None);
+        assert_eq!(supplicant.networks().len(), 2);
+        let response = manager
+            .disconnect_wireless_network(Request::new(disconnect(0)))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!response.success);
+
+        let status = manager
+            .set_radio_blocked(Request::new(RadioRequest::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
 
-        Ok(Response::new(scan_result))
+        // only known wireless interfaces reach a supplicant
+        let response = manager
+            .connect_wireless_network(Request::new(WifiConnectRequest {
+                interface: String::from("eth0"),
+                ssid: String::from("shop"),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!response.success);
     }
 }